@@ -0,0 +1,20 @@
+//! Build script that captures the git hash of the source the application is built from.
+
+use std::process::Command;
+
+fn main() {
+    // Fall back to "unknown" when not building from a git repository (or git is unavailable).
+    let hash = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_string())
+        .unwrap_or_else(|| String::from("unknown"));
+    println!("cargo:rustc-env=GIT_HASH={}", hash);
+
+    // Only rerun when the checked out commit changes.
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs/heads");
+}
@@ -0,0 +1,1684 @@
+//! Module for reading and interpreting the accelerometer.
+
+use core::convert::Infallible;
+use core::fmt::{self, Write};
+use hal::prelude::_embedded_hal_blocking_spi_Transfer as Transfer;
+use hal::prelude::_embedded_hal_digital_v2_OutputPin as OutputPin;
+use heapless::{consts::U32, Vec};
+
+use crate::led_ring::PWM_STEPS;
+use crate::serial::write_hex;
+use crate::spi_bus::{BusError, SpiBus};
+
+/// The address of the register that identifies the device.
+const WHO_AM_I: u8 = 0x0F;
+
+/// The identification of the LIS3DSH accelerometer (as read from [`WHO_AM_I`]).
+const WHO_AM_I_LIS3DSH: u8 = 0x3F;
+
+/// The address of the control register that enables the axes and sets the data rate.
+const CTRL_REG: u8 = 0x20;
+
+/// The control register value that enables the X, Y and Z axes.
+const CTRL_REG_ENABLE_XYZ: u8 = 0b0100_0111;
+
+/// The address of the control register that sets the full-scale range.
+const CTRL_REG5: u8 = 0x24;
+
+/// The bits of the control register that set the full-scale range.
+const CTRL_REG5_FSCALE_SHIFT: u8 = 3;
+
+/// The address of the control register that enables the FIFO.
+const CTRL_REG6: u8 = 0x25;
+
+/// The control register bit that enables the FIFO.
+const CTRL_REG6_FIFO_EN: u8 = 1 << 6;
+
+/// The control register bit that enables address auto-increment for multi-byte reads.
+const CTRL_REG6_ADD_INC: u8 = 1 << 4;
+
+/// The address of the FIFO control register that sets the FIFO mode.
+const FIFO_CTRL_REG: u8 = 0x2E;
+
+/// The FIFO control register value for stream mode (the oldest samples are discarded when
+/// the FIFO is full).
+const FIFO_CTRL_STREAM: u8 = 0b010 << 5;
+
+/// The FIFO control register value for bypass mode (the FIFO is not used).
+const FIFO_CTRL_BYPASS: u8 = 0;
+
+/// The address of the FIFO source register that reports the number of stored samples.
+const FIFO_SRC_REG: u8 = 0x2F;
+
+/// The bits of the FIFO source register with the number of stored samples.
+const FIFO_SRC_SAMPLES: u8 = 0x1F;
+
+/// The address of the status register that reports which axes have new data.
+const STATUS: u8 = 0x27;
+
+/// The bits of the status register that report new data for the X and Y axes (XDA and YDA).
+const STATUS_XY_DA: u8 = 0b0000_0011;
+
+/// The address of the control register of state machine 1.
+const CTRL_REG1: u8 = 0x21;
+
+/// The control register bit that enables state machine 1.
+const CTRL_REG1_SM1_EN: u8 = 1 << 0;
+
+/// The control register bit that routes the interrupt of state machine 1 to the INT2 pin.
+const CTRL_REG1_SM1_PIN: u8 = 1 << 3;
+
+/// The address of the control register of the interrupt pins.
+const CTRL_REG3: u8 = 0x23;
+
+/// The control register bit that makes the interrupt pins active high.
+const CTRL_REG3_IEA: u8 = 1 << 6;
+
+/// The control register bit that enables the INT2 pin.
+const CTRL_REG3_INT2_EN: u8 = 1 << 4;
+
+/// The address of the first threshold register of state machine 1.
+const THRS1_1: u8 = 0x57;
+
+/// The address of the first two instructions of the program of state machine 1.
+const ST1_1: u8 = 0x40;
+
+/// The program of state machine 1 that detects motion: wait until any enabled axis exceeds
+/// [`THRS1_1`] (GNTH1), then trigger the interrupt and start over (CONT).
+const MOTION_PROGRAM: [u8; 2] = [0x05, 0x11];
+
+/// The addresses of the axis masks of state machine 1.
+const MASK1_B: u8 = 0x59;
+const MASK1_A: u8 = 0x5A;
+
+/// The axis mask that enables the positive and negative X and Y axes (the Z axis always
+/// measures gravity).
+const MASK_XY: u8 = 0b1111_0000;
+
+/// The address of the settings register of state machine 1.
+const SETT1: u8 = 0x5B;
+
+/// The settings register bit that lets the CONT instruction trigger the interrupt.
+const SETT1_SITR: u8 = 1 << 0;
+
+/// The address of the output register of state machine 1; reading it clears the (latched)
+/// interrupt.
+const OUTS1: u8 = 0x5F;
+
+/// The SPI transfer that reads a sample of the X and Y acceleration from the FIFO.
+///
+/// The read starts at the OUT_X_L register and auto-increments the address; only the high bytes
+/// (OUT_X_H and OUT_Y_H) are used.
+const READ_SAMPLE: [u8; 5] = [READ_BIT | 0x28, 0x0, 0x0, 0x0, 0x0];
+
+/// The bit in the address byte of a transfer that indicates a read.
+const READ_BIT: u8 = 1 << 7;
+
+/// The highest register address of the accelerometer.
+pub const MAX_REGISTER: u8 = 0x7F;
+
+/// The acceleration (in the unit of the readings) that is shown at full duty, which is about 1 g.
+pub const FULL_TILT: u8 = 64;
+
+/// The margin (in the unit of the readings) by which the magnitude has to drop below the
+/// threshold of the number of LEDs lit by the tilt meter before fewer LEDs are lit.
+pub const METER_HYSTERESIS: u16 = 4;
+
+/// The magnitude of a reading (in the unit of the current range) at which it counts as
+/// saturated, so that auto-ranging steps up to a larger range.
+pub const AUTO_RANGE_SATURATION: u8 = 120;
+
+/// The magnitude of a reading (in the unit of the current range) below which it counts as
+/// small, so that auto-ranging steps down to a smaller range.
+///
+/// This is well below half of [`AUTO_RANGE_SATURATION`], so that a reading that steps down
+/// does not saturate in the smaller range right away.
+pub const AUTO_RANGE_SMALL: u8 = 48;
+
+/// The number of recent measurements the shake detector looks at.
+pub const SHAKE_WINDOW: usize = 8;
+
+/// The change in acceleration (in the unit of the readings) between two measurements that
+/// counts as a jolt for the shake detector, which is about 0.5 g.
+pub const SHAKE_THRESHOLD: u8 = FULL_TILT / 2;
+
+/// The number of times jolts have to change direction within the window to count as a shake.
+pub const SHAKE_REVERSALS: usize = 3;
+
+/// The SPI transfer that reads the X and Y acceleration.
+///
+/// The first byte is the read command that starts at the OUT_X_H register and auto-increments
+/// the address (which `init` enables), the others are placeholders for the read values:
+/// OUT_X_H, OUT_Y_L (which is skipped) and OUT_Y_H.
+pub const READ_XY: [u8; 4] = [READ_BIT | 0x29, 0x0, 0x0, 0x0];
+
+/// Decodes the X and Y acceleration from the result of the [`READ_XY`] transfer.
+pub fn decode_xy(result: &[u8]) -> (i8, i8) {
+    (result[1] as i8, result[3] as i8)
+}
+
+/// Writes all bytes of the result of the [`READ_XY`] transfer in hexadecimal, followed by the
+/// X and Y acceleration decoded from them (without line terminator).
+///
+/// The first byte is whatever the device shifted out while receiving the read command, and the
+/// third byte is that of the skipped register.
+pub fn write_raw_xy<W: Write>(writer: &mut W, result: &[u8; 4]) -> fmt::Result {
+    let (acc_x, acc_y) = decode_xy(result);
+
+    write_hex(writer, result)?;
+    write!(writer, " (x {}, y {})", acc_x, acc_y)
+}
+
+/// Returns the average of the samples of the X and Y acceleration (rounded towards zero).
+///
+/// Returns `None` if there are no samples.
+pub fn average_xy(samples: &[(i8, i8)]) -> Option<(i8, i8)> {
+    if samples.is_empty() {
+        return None;
+    }
+
+    let (sum_x, sum_y) = samples
+        .iter()
+        .fold((0i32, 0i32), |(sum_x, sum_y), (acc_x, acc_y)| {
+            (sum_x + *acc_x as i32, sum_y + *acc_y as i32)
+        });
+    let count = samples.len() as i32;
+
+    Some(((sum_x / count) as i8, (sum_y / count) as i8))
+}
+
+/// The error that occurs when accessing a register that may not be accessed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RegisterError {
+    /// The register address is out of range.
+    OutOfRange,
+    /// The register is reserved and may not be accessed.
+    Reserved,
+    /// The register is read-only and may not be written to.
+    ReadOnly,
+}
+
+impl RegisterError {
+    /// Returns a short description of the error.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RegisterError::OutOfRange => "out of range",
+            RegisterError::Reserved => "reserved",
+            RegisterError::ReadOnly => "read-only",
+        }
+    }
+}
+
+/// The error that occurs when initializing the accelerometer fails.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum InitError<E> {
+    /// The SPI transfer failed.
+    Transfer(E),
+    /// The device does not identify as the expected accelerometer (with the given
+    /// identification instead), e.g. because it does not respond at all.
+    UnknownDevice(u8),
+}
+
+impl<E> InitError<E> {
+    /// Returns a short description of the error.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InitError::Transfer(_) => "transfer failed",
+            InitError::UnknownDevice(_) => "unknown device",
+        }
+    }
+}
+
+/// The access allowed to an accelerometer register.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Access {
+    /// The register is reserved.
+    Reserved,
+    /// The register can only be read.
+    ReadOnly,
+    /// The register can be read and written.
+    ReadWrite,
+}
+
+/// Returns the value of the threshold register of the state machines for the motion threshold
+/// in mg at the given full-scale range.
+///
+/// The threshold is compared against the upper byte of the readings, so one unit is 1/128th
+/// of the range (15.625 mg at ±2 g).  The threshold is rounded up to the next unit and clamped
+/// to the valid range of 1 to 127 units, so that noise never counts as motion.
+pub fn motion_threshold(threshold_mg: u16, scale: Scale) -> u8 {
+    let unit_ug = 2_000_000 / 128 * scale.factor() as u32;
+    let units = (threshold_mg as u32 * 1000 + unit_ug - 1) / unit_ug;
+
+    units.clamp(1, 127) as u8
+}
+
+/// Returns the access allowed to the register with the given address.
+///
+/// This follows the register map of the LIS3DSH.
+fn access(address: u8) -> Result<Access, RegisterError> {
+    let access = match address {
+        0x00..=0x0B | 0x26 | 0x30..=0x3F | 0x58 | 0x78 => Access::Reserved,
+        0x0C..=0x0F | 0x18..=0x1A | 0x27..=0x2D | 0x2F | 0x5C..=0x5F | 0x7C..=0x7F => {
+            Access::ReadOnly
+        }
+        0x10..=0x17
+        | 0x1B..=0x25
+        | 0x2E
+        | 0x40..=0x57
+        | 0x59..=0x5B
+        | 0x60..=0x77
+        | 0x79..=0x7B => Access::ReadWrite,
+        _ => return Err(RegisterError::OutOfRange),
+    };
+
+    Ok(access)
+}
+
+/// Checks whether the register with the given address may be read.
+pub fn check_read(address: u8) -> Result<(), RegisterError> {
+    match access(address)? {
+        Access::Reserved => Err(RegisterError::Reserved),
+        Access::ReadOnly | Access::ReadWrite => Ok(()),
+    }
+}
+
+/// Checks whether the register with the given address may be written to.
+pub fn check_write(address: u8) -> Result<(), RegisterError> {
+    match access(address)? {
+        Access::Reserved => Err(RegisterError::Reserved),
+        Access::ReadOnly => Err(RegisterError::ReadOnly),
+        Access::ReadWrite => Ok(()),
+    }
+}
+
+/// The accelerometer.
+///
+/// The accelerometer is accessed via SPI and selected using a chip select output pin, which is
+/// held low during transfers.  Each transfer is a transaction on the SPI bus (see
+/// [`SpiBus::transaction`]), which the accelerometer does not share with other devices on this
+/// board.
+pub struct Accelerometer<SPI, CS> {
+    /// The SPI bus the accelerometer is connected to.
+    spi: SpiBus<SPI>,
+    /// The chip select output of the accelerometer.
+    cs: CS,
+    /// The state of auto-ranging (if enabled).
+    auto_range: Option<AutoRange>,
+    /// The threshold of the motion interrupt (if enabled).
+    motion_interrupt: Option<u8>,
+}
+
+impl<SPI, CS, E> Accelerometer<SPI, CS>
+where
+    SPI: Transfer<u8, Error = E>,
+    CS: OutputPin<Error = Infallible>,
+{
+    /// Sets up the accelerometer using the SPI bus and chip select output.
+    pub fn from(spi: SPI, mut cs: CS) -> Accelerometer<SPI, CS> {
+        cs.set_high().unwrap();
+
+        Accelerometer {
+            spi: SpiBus::new(spi),
+            cs,
+            auto_range: None,
+            motion_interrupt: None,
+        }
+    }
+
+    /// Initializes the accelerometer by checking its identification, enabling all axes and
+    /// enabling address auto-increment (for [`READ_XY`]).
+    pub fn init(&mut self) -> Result<(), InitError<BusError<E>>> {
+        let identity = self.identify().map_err(InitError::Transfer)?;
+        if identity != WHO_AM_I_LIS3DSH {
+            return Err(InitError::UnknownDevice(identity));
+        }
+        self.write_register(CTRL_REG, CTRL_REG_ENABLE_XYZ)
+            .and_then(|()| self.write_register(CTRL_REG6, CTRL_REG6_ADD_INC))
+            .map_err(InitError::Transfer)
+    }
+
+    /// Reads the identification of the device.
+    pub fn identify(&mut self) -> Result<u8, BusError<E>> {
+        self.read_register(WHO_AM_I)
+    }
+
+    /// Returns whether the device identifies as the expected accelerometer.
+    pub fn is_present(&mut self) -> Result<bool, BusError<E>> {
+        Ok(self.identify()? == WHO_AM_I_LIS3DSH)
+    }
+
+    /// Reads the X and Y acceleration.
+    ///
+    /// If auto-ranging is enabled, the reading is rescaled to the unit of the smallest range.
+    pub fn read_xy(&mut self) -> Result<(i8, i8), BusError<E>> {
+        let result = self.read_xy_raw()?;
+
+        self.auto_scale(decode_xy(&result))
+    }
+
+    /// Returns whether a new sample of the X and Y acceleration is available, i.e. the data
+    /// rate produced one since they were last read.
+    ///
+    /// This is the status behind the data-ready (DRDY) signal, which can only be routed to the
+    /// INT1 pin; see the board module for why it is polled instead.
+    pub fn is_data_ready(&mut self) -> Result<bool, BusError<E>> {
+        let status = self.read_register(STATUS)?;
+
+        Ok(status & STATUS_XY_DA == STATUS_XY_DA)
+    }
+
+    /// Reads the X and Y acceleration, returning all bytes of the [`READ_XY`] transfer as
+    /// received (without rescaling for auto-ranging).
+    pub fn read_xy_raw(&mut self) -> Result<[u8; 4], BusError<E>> {
+        let mut result = READ_XY;
+        self.transfer(&mut result)?;
+
+        Ok(result)
+    }
+
+    /// Sets the full-scale range.
+    pub fn set_scale(&mut self, scale: Scale) -> Result<(), BusError<E>> {
+        self.write_register(CTRL_REG5, scale.bits() << CTRL_REG5_FSCALE_SHIFT)
+    }
+
+    /// Returns the current full-scale range.
+    pub fn scale(&self) -> Scale {
+        self.auto_range().unwrap_or(Scale::G2)
+    }
+
+    /// Returns the current full-scale range if auto-ranging is enabled.
+    pub fn auto_range(&self) -> Option<Scale> {
+        self.auto_range.as_ref().map(AutoRange::scale)
+    }
+
+    /// Enables or disables auto-ranging.
+    ///
+    /// Both start from the smallest range, which is kept when auto-ranging is disabled.  If
+    /// setting the range fails, auto-ranging is left as it was.
+    pub fn enable_auto_range(&mut self, enable: bool) -> Result<(), BusError<E>> {
+        self.set_scale(Scale::G2)?;
+        self.auto_range = if enable { Some(AutoRange::new()) } else { None };
+        Ok(())
+    }
+
+    /// Rescales the reading if auto-ranging is enabled, and switches to another range if
+    /// the reading calls for it.
+    fn auto_scale(&mut self, (acc_x, acc_y): (i8, i8)) -> Result<(i8, i8), BusError<E>> {
+        let (scaled, scale) = match self.auto_range.as_mut() {
+            Some(auto_range) => (
+                auto_range.rescale(acc_x, acc_y),
+                auto_range.update(acc_x, acc_y),
+            ),
+            None => return Ok((acc_x, acc_y)),
+        };
+        if let Some(scale) = scale {
+            self.set_scale(scale)?;
+        }
+
+        Ok(scaled)
+    }
+
+    /// Enables or disables the FIFO (in stream mode).
+    pub fn enable_fifo(&mut self, enable: bool) -> Result<(), BusError<E>> {
+        if enable {
+            self.write_register(CTRL_REG6, CTRL_REG6_FIFO_EN | CTRL_REG6_ADD_INC)?;
+            self.write_register(FIFO_CTRL_REG, FIFO_CTRL_STREAM)
+        } else {
+            self.write_register(FIFO_CTRL_REG, FIFO_CTRL_BYPASS)?;
+            self.write_register(CTRL_REG6, CTRL_REG6_ADD_INC)
+        }
+    }
+
+    /// Enables the motion interrupt on the INT2 pin with the given threshold (see
+    /// [`motion_threshold`]), or disables it.
+    ///
+    /// State machine 1 is programmed to raise the interrupt when the X or Y acceleration
+    /// exceeds the threshold in either direction.  The interrupt is latched until it is
+    /// disabled or enabled again, which also clears it.
+    pub fn enable_motion_interrupt(&mut self, threshold: Option<u8>) -> Result<(), BusError<E>> {
+        self.motion_interrupt = threshold;
+        match threshold {
+            Some(threshold) => {
+                self.write_register(CTRL_REG1, 0)?;
+                self.read_register(OUTS1)?;
+                self.write_register(THRS1_1, threshold)?;
+                self.write_register(ST1_1, MOTION_PROGRAM[0])?;
+                self.write_register(ST1_1 + 1, MOTION_PROGRAM[1])?;
+                self.write_register(MASK1_B, MASK_XY)?;
+                self.write_register(MASK1_A, MASK_XY)?;
+                self.write_register(SETT1, SETT1_SITR)?;
+                self.write_register(CTRL_REG3, CTRL_REG3_IEA | CTRL_REG3_INT2_EN)?;
+                self.write_register(CTRL_REG1, CTRL_REG1_SM1_EN | CTRL_REG1_SM1_PIN)
+            }
+            None => {
+                self.write_register(CTRL_REG1, 0)?;
+                self.write_register(CTRL_REG3, 0)?;
+                self.read_register(OUTS1).map(|_| ())
+            }
+        }
+    }
+
+    /// Returns the threshold of the motion interrupt if it is enabled.
+    pub fn motion_interrupt(&self) -> Option<u8> {
+        self.motion_interrupt
+    }
+
+    /// Returns the number of samples stored in the FIFO.
+    pub fn fifo_samples(&mut self) -> Result<usize, BusError<E>> {
+        let source = self.read_register(FIFO_SRC_REG)?;
+
+        Ok((source & FIFO_SRC_SAMPLES) as usize)
+    }
+
+    /// Reads all samples stored in the FIFO and returns the average X and Y acceleration.
+    ///
+    /// If the FIFO is empty (or not enabled), the current acceleration is read instead.  If
+    /// auto-ranging is enabled, the average is rescaled like a single reading; right after
+    /// switching to another range, the FIFO may still contain samples of the previous range.
+    pub fn read_xy_fifo(&mut self) -> Result<(i8, i8), BusError<E>> {
+        let count = self.fifo_samples()?;
+        let mut samples = Vec::<(i8, i8), U32>::new();
+        for _ in 0..count.min(samples.capacity()) {
+            let mut commands = READ_SAMPLE;
+            let result = self.transfer(&mut commands)?;
+            samples.push((result[2] as i8, result[4] as i8)).unwrap();
+        }
+
+        match average_xy(&samples) {
+            Some(average) => self.auto_scale(average),
+            None => self.read_xy(),
+        }
+    }
+
+    /// Reads the register with the given address.
+    ///
+    /// The address is not checked, see [`check_read`].
+    pub fn read_register(&mut self, address: u8) -> Result<u8, BusError<E>> {
+        let mut commands = [READ_BIT | address, 0x0];
+        let result = self.transfer(&mut commands)?;
+
+        Ok(result[1])
+    }
+
+    /// Writes the value to the register with the given address.
+    ///
+    /// The address is not checked, see [`check_write`].
+    pub fn write_register(&mut self, address: u8, value: u8) -> Result<(), BusError<E>> {
+        self.transfer(&mut [address, value])?;
+
+        Ok(())
+    }
+
+    /// Performs a transfer while the accelerometer is selected.
+    fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], BusError<E>> {
+        self.spi.transfer(&mut self.cs, words)
+    }
+}
+
+/// The full-scale range of the accelerometer.
+///
+/// Only the ranges that are a power of two times the smallest range are used, so that readings
+/// are easily rescaled.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Scale {
+    /// ±2 g, the default range.
+    G2,
+    /// ±4 g.
+    G4,
+    /// ±8 g.
+    G8,
+    /// ±16 g.
+    G16,
+}
+
+impl Scale {
+    /// Returns the value of the full-scale bits of the control register for the range.
+    fn bits(self) -> u8 {
+        match self {
+            Scale::G2 => 0b000,
+            Scale::G4 => 0b001,
+            Scale::G8 => 0b011,
+            Scale::G16 => 0b100,
+        }
+    }
+
+    /// Returns the range in g.
+    pub fn as_g(self) -> u8 {
+        2 * self.factor()
+    }
+
+    /// Returns how many times larger the range is than the smallest range.
+    fn factor(self) -> u8 {
+        match self {
+            Scale::G2 => 1,
+            Scale::G4 => 2,
+            Scale::G8 => 4,
+            Scale::G16 => 8,
+        }
+    }
+
+    /// Returns the next larger range (if any).
+    fn up(self) -> Option<Scale> {
+        match self {
+            Scale::G2 => Some(Scale::G4),
+            Scale::G4 => Some(Scale::G8),
+            Scale::G8 => Some(Scale::G16),
+            Scale::G16 => None,
+        }
+    }
+
+    /// Returns the next smaller range (if any).
+    fn down(self) -> Option<Scale> {
+        match self {
+            Scale::G2 => None,
+            Scale::G4 => Some(Scale::G2),
+            Scale::G8 => Some(Scale::G4),
+            Scale::G16 => Some(Scale::G8),
+        }
+    }
+}
+
+/// The state machine that selects the full-scale range from the readings.
+///
+/// A saturated reading steps up to the next larger range, a reading where both axes are small
+/// steps down to the next smaller range, so that the readings have the best resolution that
+/// fits them.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct AutoRange {
+    /// The current range.
+    scale: Scale,
+}
+
+impl AutoRange {
+    /// Creates the state machine, starting from the smallest range.
+    pub const fn new() -> AutoRange {
+        AutoRange { scale: Scale::G2 }
+    }
+
+    /// Returns the current range.
+    pub fn scale(&self) -> Scale {
+        self.scale
+    }
+
+    /// Rescales a reading of the current range to the unit of the smallest range.
+    ///
+    /// Readings beyond the smallest range are clamped, since they do not fit the unit.
+    pub fn rescale(&self, acc_x: i8, acc_y: i8) -> (i8, i8) {
+        let rescale = |acc: i8| {
+            let acc = acc as i16 * self.scale.factor() as i16;
+            acc.clamp(i8::MIN as i16, i8::MAX as i16) as i8
+        };
+
+        (rescale(acc_x), rescale(acc_y))
+    }
+
+    /// Updates the state with a reading of the current range and returns the new range if it
+    /// changes.
+    pub fn update(&mut self, acc_x: i8, acc_y: i8) -> Option<Scale> {
+        let largest = acc_x.unsigned_abs().max(acc_y.unsigned_abs());
+        let scale = if largest >= AUTO_RANGE_SATURATION {
+            self.scale.up()
+        } else if largest < AUTO_RANGE_SMALL {
+            self.scale.down()
+        } else {
+            None
+        }?;
+
+        self.scale = scale;
+        Some(scale)
+    }
+}
+
+impl Default for AutoRange {
+    fn default() -> AutoRange {
+        AutoRange::new()
+    }
+}
+
+/// Which axes of the accelerometer are flipped (negated).
+///
+/// This can be used to correct the readings when the board is mounted face-down.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct AxisFlip {
+    /// Whether the X axis is flipped.
+    pub x: bool,
+    /// Whether the Y axis is flipped.
+    pub y: bool,
+}
+
+impl AxisFlip {
+    /// No axes are flipped.
+    pub const NONE: AxisFlip = AxisFlip { x: false, y: false };
+
+    /// Returns the axis flip with the given name.
+    pub fn from_name(name: &[u8]) -> Option<AxisFlip> {
+        match name {
+            b"none" => Some(AxisFlip::NONE),
+            b"x" => Some(AxisFlip { x: true, y: false }),
+            b"y" => Some(AxisFlip { x: false, y: true }),
+            b"xy" => Some(AxisFlip { x: true, y: true }),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the axis flip (as used by the serial interface).
+    pub fn as_name(&self) -> &'static str {
+        match (self.x, self.y) {
+            (false, false) => "none",
+            (true, false) => "x",
+            (false, true) => "y",
+            (true, true) => "xy",
+        }
+    }
+
+    /// Applies the flip to the X and Y acceleration.
+    ///
+    /// Since the range of the acceleration is asymmetric, the lowest value is flipped to the
+    /// highest value.
+    pub fn apply(&self, acc_x: i8, acc_y: i8) -> (i8, i8) {
+        let acc_x = if self.x {
+            acc_x.saturating_neg()
+        } else {
+            acc_x
+        };
+        let acc_y = if self.y {
+            acc_y.saturating_neg()
+        } else {
+            acc_y
+        };
+
+        (acc_x, acc_y)
+    }
+}
+
+/// Orients the X and Y acceleration (as measured, minus the calibration offset) to the axes of
+/// the LED ring.
+///
+/// The transforms are applied in a fixed order: first the axes are swapped (if enabled), for a
+/// board mounted rotated by 90 degrees, and then the flip is applied to the swapped axes, so
+/// that the flipped axes are always those of the LED ring.
+pub fn orient(acc_x: i8, acc_y: i8, swap: bool, flip: AxisFlip) -> (i8, i8) {
+    let (acc_x, acc_y) = if swap { (acc_y, acc_x) } else { (acc_x, acc_y) };
+
+    flip.apply(acc_x, acc_y)
+}
+
+/// Returns the magnitude of the X and Y acceleration, i.e. `sqrt(x * x + y * y)`.
+///
+/// The result is rounded down.  No floating point arithmetic is used and the calculation cannot
+/// overflow.
+pub fn magnitude(acc_x: i8, acc_y: i8) -> u16 {
+    let (acc_x, acc_y) = (acc_x as i32, acc_y as i32);
+
+    isqrt((acc_x * acc_x + acc_y * acc_y) as u32) as u16
+}
+
+/// Returns the integer square root (rounded down) of the value.
+///
+/// The bits of the root are determined one by one, starting with the most significant bit.
+fn isqrt(value: u32) -> u32 {
+    let mut root = 0;
+    let mut bit = 1 << 15;
+    while bit > 0 {
+        let candidate = root | bit;
+        if candidate * candidate <= value {
+            root = candidate;
+        }
+        bit >>= 1;
+    }
+
+    root
+}
+
+/// Returns which LEDs should be on for the given X and Y acceleration.
+///
+/// The LEDs on the sides of the board that are pointing down are on.  The directions are
+/// ordered the same as the LEDs in the ring: `[east, south, west, north]`.
+pub fn directions_from_accel(acc_x: i8, acc_y: i8) -> [bool; 4] {
+    [acc_y < 0, acc_x < 0, acc_y > 0, acc_x > 0]
+}
+
+/// The characters for the directions, ordered the same as the LEDs in the ring.
+const DIRECTION_CHARS: [char; 4] = ['E', 'S', 'W', 'N'];
+
+/// Returns a character for the direction of the tilt for the given X and Y acceleration: `N`,
+/// `S`, `E` or `W`, or `.` when the board is level.
+///
+/// Of the sides that are pointing down (see [`directions_from_accel`]), the one on the axis
+/// with the largest acceleration is used; on a tie, the X axis (north/south) wins.
+pub fn arrow_from_accel(acc_x: i8, acc_y: i8) -> char {
+    let directions = directions_from_accel(acc_x, acc_y);
+    let sides = if (acc_x as i16).abs() >= (acc_y as i16).abs() {
+        [1, 3]
+    } else {
+        [0, 2]
+    };
+
+    sides
+        .iter()
+        .find(|side| directions[**side])
+        .map_or('.', |side| DIRECTION_CHARS[*side])
+}
+
+/// Returns which LEDs should be on to show the downhill direction for the given X and Y
+/// acceleration, i.e. the direction the board would roll toward.
+///
+/// The direction of the acceleration vector is divided into eight sectors of 45 degrees: when it
+/// points within 22.5 degrees of an LED, only that LED is on, otherwise the pair of LEDs it
+/// points in between is on.  No LEDs are on when the board is level.  The directions are ordered
+/// the same as the LEDs in the ring: `[east, south, west, north]`.
+pub fn downhill_from_accel(acc_x: i8, acc_y: i8) -> [bool; 4] {
+    let (abs_x, abs_y) = ((acc_x as i16).abs(), (acc_y as i16).abs());
+    // The tangent of 22.5 degrees is about 5/12.
+    let x_only = abs_y * 12 < abs_x * 5;
+    let y_only = abs_x * 12 < abs_y * 5;
+
+    let mut directions = [false; 4];
+    if !y_only {
+        directions[3] = acc_x > 0;
+        directions[1] = acc_x < 0;
+    }
+    if !x_only {
+        directions[2] = acc_y > 0;
+        directions[0] = acc_y < 0;
+    }
+
+    directions
+}
+
+/// Returns the PWM duty (up to [`PWM_STEPS`]) for the acceleration in one direction.
+///
+/// The duty is proportional to the acceleration, reaching the full duty at [`FULL_TILT`].
+/// Accelerations in the opposite direction (negative values) give a zero duty.
+pub fn tilt_duty(acc: i16) -> u8 {
+    let acc = acc.clamp(0, FULL_TILT as i16) as u16;
+
+    (acc * PWM_STEPS as u16 / FULL_TILT as u16) as u8
+}
+
+/// Returns the PWM duty of each LED for the given X and Y acceleration.
+///
+/// This is the analog counterpart of [`directions_from_accel`]: the further the board is tilted
+/// towards a side, the brighter the LED on that side.
+pub fn duties_from_accel(acc_x: i8, acc_y: i8) -> [u8; 4] {
+    let (acc_x, acc_y) = (acc_x as i16, acc_y as i16);
+
+    [
+        tilt_duty(-acc_y),
+        tilt_duty(-acc_x),
+        tilt_duty(acc_y),
+        tilt_duty(acc_x),
+    ]
+}
+
+/// Returns the magnitude of the acceleration from which the tilt meter lights the given number
+/// of LEDs.
+///
+/// The thresholds are evenly spread up to [`FULL_TILT`], where all four LEDs are lit.
+pub fn meter_threshold(count: u8) -> u16 {
+    count as u16 * FULL_TILT as u16 / 4
+}
+
+/// Returns the number of LEDs (0-4) the tilt meter lights for the magnitude of the
+/// acceleration, given the number of LEDs that were lit before.
+///
+/// A count is reached once the magnitude reaches its threshold, but it is only left for a lower
+/// count once the magnitude drops below the threshold by more than [`METER_HYSTERESIS`], so that
+/// the meter does not flicker for a magnitude close to a threshold.  A level board always lights
+/// no LEDs.
+pub fn meter_count(magnitude: u16, previous: u8) -> u8 {
+    let count = |magnitude| {
+        (0..=4)
+            .rev()
+            .find(|count| magnitude >= meter_threshold(*count))
+            .unwrap_or(0)
+    };
+
+    previous.clamp(count(magnitude), count(magnitude + METER_HYSTERESIS))
+}
+
+/// Returns which LEDs should be on for the tilt meter lighting the given number of LEDs, i.e.
+/// the first LEDs in the order of the ring.
+pub fn meter_pattern(count: u8) -> [bool; 4] {
+    let mut pattern = [false; 4];
+    for (index, on) in pattern.iter_mut().enumerate() {
+        *on = index < count as usize;
+    }
+
+    pattern
+}
+
+/// Returns whether the measurements (oldest first) show that the board is being shaken.
+///
+/// A change in acceleration along an axis of at least [`SHAKE_THRESHOLD`] between two
+/// measurements is a jolt; smaller changes are ignored.  The board is shaken if the jolts along
+/// either axis change direction at least [`SHAKE_REVERSALS`] times, i.e. the acceleration
+/// rapidly alternates instead of changing once (e.g. when the board is tilted).
+pub fn is_shake(samples: &[(i8, i8)]) -> bool {
+    let reversals = |axis: fn(&(i8, i8)) -> i8| {
+        let mut previous = 0;
+        let mut reversals = 0;
+        for pair in samples.windows(2) {
+            let change = axis(&pair[1]) as i16 - axis(&pair[0]) as i16;
+            if change.abs() < SHAKE_THRESHOLD as i16 {
+                continue;
+            }
+            if change.signum() == -previous {
+                reversals += 1;
+            }
+            previous = change.signum();
+        }
+
+        reversals
+    };
+
+    reversals(|sample| sample.0) >= SHAKE_REVERSALS
+        || reversals(|sample| sample.1) >= SHAKE_REVERSALS
+}
+
+/// Detects shaking of the board from the most recent measurements.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ShakeDetector {
+    /// The most recent measurements (oldest first).
+    samples: [(i8, i8); SHAKE_WINDOW],
+    /// The number of measurements.
+    len: usize,
+}
+
+impl ShakeDetector {
+    /// Sets up a shake detector without measurements.
+    pub const fn new() -> ShakeDetector {
+        ShakeDetector {
+            samples: [(0, 0); SHAKE_WINDOW],
+            len: 0,
+        }
+    }
+
+    /// Forgets all measurements.
+    pub fn reset(&mut self) {
+        self.len = 0;
+    }
+
+    /// Adds a measurement and returns whether the board is being shaken, dropping the oldest
+    /// measurement if the window is full.
+    ///
+    /// The measurements are forgotten after a shake, so that one shake is only detected once.
+    pub fn push(&mut self, acc_x: i8, acc_y: i8) -> bool {
+        if self.len == SHAKE_WINDOW {
+            self.samples.copy_within(1.., 0);
+            self.len -= 1;
+        }
+        self.samples[self.len] = (acc_x, acc_y);
+        self.len += 1;
+
+        let shake = is_shake(&self.samples[..self.len]);
+        if shake {
+            self.reset();
+        }
+
+        shake
+    }
+}
+
+impl Default for ShakeDetector {
+    fn default() -> ShakeDetector {
+        ShakeDetector::new()
+    }
+}
+
+/// Returns the period (in cycles) between steps of an animation for the magnitude of the
+/// acceleration, where more tilt gives a shorter period (i.e. a faster animation).
+///
+/// The period decreases linearly from the slowest period for a level board to the fastest
+/// period at [`FULL_TILT`]; a larger magnitude is clamped.  The fastest period should not be
+/// longer than the slowest period, otherwise the slowest period is always returned.
+pub fn tilt_period(magnitude: u16, slowest: u32, fastest: u32) -> u32 {
+    let tilt = magnitude.min(FULL_TILT as u16) as u64;
+    let range = slowest.saturating_sub(fastest) as u64;
+
+    slowest - (range * tilt / FULL_TILT as u64) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        arrow_from_accel, average_xy, check_read, check_write, decode_xy, directions_from_accel,
+        downhill_from_accel, duties_from_accel, is_shake, isqrt, magnitude, meter_count,
+        meter_pattern, meter_threshold, motion_threshold, orient, tilt_duty, tilt_period,
+        write_raw_xy, Accelerometer, AutoRange, AxisFlip, BusError, InitError, RegisterError,
+        Scale, ShakeDetector, AUTO_RANGE_SATURATION, AUTO_RANGE_SMALL, FULL_TILT, METER_HYSTERESIS,
+        PWM_STEPS, READ_XY, SHAKE_REVERSALS, SHAKE_WINDOW, WHO_AM_I_LIS3DSH,
+    };
+    use crate::mock::{MockOutputPin, MockSpi};
+    use crate::spi_bus::Selected;
+
+    #[test]
+    fn decode() {
+        assert_eq!(decode_xy(&[0xff, 0x00, 0x12, 0x00]), (0, 0));
+        assert_eq!(decode_xy(&[0xff, 0x05, 0x12, 0xfb]), (5, -5));
+        assert_eq!(decode_xy(&[0xff, 0x80, 0x12, 0x7f]), (-128, 127));
+    }
+
+    #[test]
+    fn register_checks() {
+        // WHO_AM_I
+        assert_eq!(check_read(0x0F), Ok(()));
+        assert_eq!(check_write(0x0F), Err(RegisterError::ReadOnly));
+        // CTRL_REG4
+        assert_eq!(check_read(0x20), Ok(()));
+        assert_eq!(check_write(0x20), Ok(()));
+        // OUT_X_L and OUT_Z_H
+        assert_eq!(check_write(0x28), Err(RegisterError::ReadOnly));
+        assert_eq!(check_write(0x2D), Err(RegisterError::ReadOnly));
+        // FIFO_CTRL
+        assert_eq!(check_write(0x2E), Ok(()));
+        // Reserved registers
+        for address in [0x00, 0x0B, 0x26, 0x30, 0x3F, 0x58, 0x78].iter() {
+            assert_eq!(check_read(*address), Err(RegisterError::Reserved));
+            assert_eq!(check_write(*address), Err(RegisterError::Reserved));
+        }
+        // Out of range
+        assert_eq!(check_read(0x7F), Ok(()));
+        assert_eq!(check_read(0x80), Err(RegisterError::OutOfRange));
+        assert_eq!(check_write(0xFF), Err(RegisterError::OutOfRange));
+    }
+
+    #[test]
+    fn accel_init() {
+        let mut accel = Accelerometer::from(MockSpi::default(), MockOutputPin::default());
+        accel.spi.spi_mut().reply = WHO_AM_I_LIS3DSH;
+        assert_eq!(accel.init(), Ok(()));
+        assert_eq!(
+            accel.spi.spi_mut().written,
+            vec![0x8F, 0x00, 0x20, 0x47, 0x25, 0x10]
+        );
+        assert!(accel.cs.state);
+
+        // A device that does not respond reads as all ones; the axes are not enabled.
+        let mut accel = Accelerometer::from(MockSpi::default(), MockOutputPin::default());
+        accel.spi.spi_mut().reply = 0xFF;
+        assert_eq!(accel.init(), Err(InitError::UnknownDevice(0xFF)));
+        assert_eq!(accel.spi.spi_mut().written, vec![0x8F, 0x00]);
+        assert!(accel.cs.state);
+
+        let mut accel = Accelerometer::from(MockSpi::default(), MockOutputPin::default());
+        accel.spi.spi_mut().fail = true;
+        assert_eq!(
+            accel.init(),
+            Err(InitError::Transfer(BusError::Transfer(())))
+        );
+        assert!(accel.cs.state);
+
+        assert_eq!(accel.is_present(), Err(BusError::Transfer(())));
+        accel.spi.spi_mut().fail = false;
+        accel.spi.spi_mut().reply = WHO_AM_I_LIS3DSH;
+        assert_eq!(accel.is_present(), Ok(true));
+        accel.spi.spi_mut().reply = 0x00;
+        assert_eq!(accel.is_present(), Ok(false));
+
+        assert_eq!(InitError::Transfer(()).as_str(), "transfer failed");
+        assert_eq!(InitError::<()>::UnknownDevice(0).as_str(), "unknown device");
+    }
+
+    #[test]
+    fn accel_registers() {
+        let mut accel = Accelerometer::from(MockSpi::default(), MockOutputPin::default());
+        assert!(accel.cs.state);
+
+        accel.spi.spi_mut().reply = 0x3F;
+        assert_eq!(accel.read_register(0x0F), Ok(0x3F));
+        accel.write_register(0x20, 0x47).unwrap();
+        assert_eq!(accel.spi.spi_mut().written, vec![0x8F, 0x00, 0x20, 0x47]);
+        assert!(accel.cs.state);
+        assert_eq!(accel.cs.writes, 5);
+    }
+
+    #[test]
+    fn accel_data_ready() {
+        let mut accel = Accelerometer::from(MockSpi::default(), MockOutputPin::default());
+        assert_eq!(accel.is_data_ready(), Ok(false));
+        assert_eq!(accel.spi.spi_mut().written, vec![0xA7, 0x00]);
+
+        // Both X and Y need new data; the Z axis and the overrun bits are ignored.
+        for (status, ready) in [(0x03, true), (0xFF, true), (0x01, false), (0xFC, false)].iter() {
+            accel.spi.spi_mut().reply = *status;
+            assert_eq!(accel.is_data_ready(), Ok(*ready), "status {:#04x}", status);
+        }
+
+        accel.spi.spi_mut().fail = true;
+        assert_eq!(accel.is_data_ready(), Err(BusError::Transfer(())));
+    }
+
+    #[test]
+    fn motion_threshold_units() {
+        // One unit is 15.625 mg at ±2 g; partial units are rounded up.
+        assert_eq!(motion_threshold(125, Scale::G2), 8);
+        assert_eq!(motion_threshold(126, Scale::G2), 9);
+        assert_eq!(motion_threshold(1000, Scale::G2), 64);
+        // The unit doubles with the range.
+        assert_eq!(motion_threshold(125, Scale::G4), 4);
+        assert_eq!(motion_threshold(1000, Scale::G8), 16);
+        assert_eq!(motion_threshold(1000, Scale::G16), 8);
+        // The threshold is clamped to the valid range.
+        assert_eq!(motion_threshold(0, Scale::G2), 1);
+        assert_eq!(motion_threshold(1, Scale::G16), 1);
+        assert_eq!(motion_threshold(2000, Scale::G2), 127);
+        assert_eq!(motion_threshold(u16::MAX, Scale::G16), 127);
+    }
+
+    #[test]
+    fn accel_motion_interrupt() {
+        let mut accel = Accelerometer::from(MockSpi::default(), MockOutputPin::default());
+        assert_eq!(accel.scale(), Scale::G2);
+        assert_eq!(accel.motion_interrupt(), None);
+        accel.enable_motion_interrupt(Some(8)).unwrap();
+        assert_eq!(accel.motion_interrupt(), Some(8));
+        assert_eq!(
+            accel.spi.spi_mut().written,
+            vec![
+                0x21, 0x00, 0xDF, 0x00, 0x57, 0x08, 0x40, 0x05, 0x41, 0x11, 0x59, 0xF0, 0x5A, 0xF0,
+                0x5B, 0x01, 0x23, 0x50, 0x21, 0x09
+            ]
+        );
+
+        // Enabling and disabling read the output register to clear a latched interrupt.
+        accel.spi.spi_mut().written.clear();
+        accel.enable_motion_interrupt(None).unwrap();
+        assert_eq!(accel.motion_interrupt(), None);
+        assert_eq!(
+            accel.spi.spi_mut().written,
+            vec![0x21, 0x00, 0x23, 0x00, 0xDF, 0x00]
+        );
+    }
+
+    #[test]
+    fn accel_deselect() {
+        let mut cs = MockOutputPin::default();
+        {
+            let _selected = Selected::new(&mut cs);
+        }
+        assert!(cs.state);
+        assert_eq!(cs.writes, 2);
+
+        // The accelerometer is deselected after a successful transfer.
+        let mut accel = Accelerometer::from(MockSpi::default(), MockOutputPin::default());
+        assert_eq!(accel.read_xy(), Ok((0, 0)));
+        assert!(accel.cs.state);
+        assert_eq!(accel.cs.writes, 3);
+
+        // The accelerometer is also deselected after a failing transfer.
+        accel.spi.spi_mut().fail = true;
+        assert_eq!(accel.read_xy(), Err(BusError::Transfer(())));
+        assert!(accel.cs.state);
+        assert_eq!(accel.cs.writes, 5);
+        assert_eq!(
+            accel.write_register(0x20, 0x47),
+            Err(BusError::Transfer(()))
+        );
+        assert!(accel.cs.state);
+        assert_eq!(accel.cs.writes, 7);
+    }
+
+    #[test]
+    fn accel_read_raw() {
+        let mut accel = Accelerometer::from(MockSpi::default(), MockOutputPin::default());
+        accel.spi.spi_mut().reply = 0x05;
+        assert_eq!(accel.read_xy_raw(), Ok([0xA9, 0x05, 0x05, 0x05]));
+        assert_eq!(accel.spi.spi_mut().written, READ_XY.to_vec());
+        assert!(accel.cs.state);
+        assert_eq!(accel.cs.writes, 3);
+
+        accel.spi.spi_mut().fail = true;
+        assert_eq!(accel.read_xy_raw(), Err(BusError::Transfer(())));
+        assert!(accel.cs.state);
+        assert_eq!(accel.cs.writes, 5);
+    }
+
+    #[test]
+    fn raw_xy_format() {
+        let mut text = String::new();
+        write_raw_xy(&mut text, &[0xff, 0x05, 0x12, 0xfb]).unwrap();
+        assert_eq!(text, "ff 05 12 fb (x 5, y -5)");
+
+        let mut text = String::new();
+        write_raw_xy(&mut text, &[0x00, 0x80, 0x00, 0x7f]).unwrap();
+        assert_eq!(text, "00 80 00 7f (x -128, y 127)");
+
+        let mut text = String::new();
+        write_raw_xy(&mut text, &[0xff; 4]).unwrap();
+        assert_eq!(text, "ff ff ff ff (x -1, y -1)");
+    }
+
+    #[test]
+    fn scales() {
+        assert_eq!(Scale::G2.as_g(), 2);
+        assert_eq!(Scale::G4.as_g(), 4);
+        assert_eq!(Scale::G8.as_g(), 8);
+        assert_eq!(Scale::G16.as_g(), 16);
+        assert_eq!(Scale::G2.down(), None);
+        assert_eq!(Scale::G16.up(), None);
+        assert_eq!(Scale::G4.up().and_then(Scale::down), Some(Scale::G4));
+    }
+
+    #[test]
+    fn auto_range_transitions() {
+        let mut auto_range = AutoRange::new();
+        assert_eq!(auto_range.scale(), Scale::G2);
+
+        // Saturated readings (on either axis, in either direction) step up one range at a time,
+        // up to the largest range.
+        assert_eq!(auto_range.update(127, 0), Some(Scale::G4));
+        assert_eq!(auto_range.update(0, -128), Some(Scale::G8));
+        assert_eq!(
+            auto_range.update(AUTO_RANGE_SATURATION as i8, 0),
+            Some(Scale::G16)
+        );
+        assert_eq!(auto_range.update(127, 127), None);
+        assert_eq!(auto_range.scale(), Scale::G16);
+
+        // Readings in between keep the range.
+        assert_eq!(auto_range.update(AUTO_RANGE_SMALL as i8, 0), None);
+        assert_eq!(auto_range.update(100, -60), None);
+        assert_eq!(auto_range.scale(), Scale::G16);
+
+        // Small readings (on both axes) step down one range at a time, down to the smallest.
+        let small = AUTO_RANGE_SMALL as i8 - 1;
+        assert_eq!(auto_range.update(small, -small), Some(Scale::G8));
+        assert_eq!(auto_range.update(0, 0), Some(Scale::G4));
+        assert_eq!(auto_range.update(-small, 0), Some(Scale::G2));
+        assert_eq!(auto_range.update(0, 0), None);
+        assert_eq!(auto_range.scale(), Scale::G2);
+    }
+
+    #[test]
+    fn auto_range_no_oscillation() {
+        // A reading just below saturation in the larger range fits the smaller range after
+        // stepping down, and a reading that just steps down does not saturate it.
+        let largest = (AUTO_RANGE_SMALL - 1) as i16 * 2;
+        assert!(largest < AUTO_RANGE_SATURATION as i16);
+
+        let mut auto_range = AutoRange::new();
+        auto_range.update(127, 0);
+        assert_eq!(
+            auto_range.update(AUTO_RANGE_SMALL as i8 - 1, 0),
+            Some(Scale::G2)
+        );
+        assert_eq!(auto_range.update(largest as i8, 0), None);
+    }
+
+    #[test]
+    fn auto_range_rescale() {
+        let mut auto_range = AutoRange::new();
+        assert_eq!(auto_range.rescale(64, -64), (64, -64));
+
+        auto_range.update(127, 0);
+        assert_eq!(auto_range.rescale(32, -32), (64, -64));
+        auto_range.update(127, 0);
+        assert_eq!(auto_range.rescale(16, -16), (64, -64));
+
+        // Readings beyond the smallest range are clamped.
+        assert_eq!(auto_range.rescale(100, -100), (127, -128));
+    }
+
+    #[test]
+    fn accel_auto_range() {
+        let mut accel = Accelerometer::from(MockSpi::default(), MockOutputPin::default());
+        assert_eq!(accel.auto_range(), None);
+
+        // Enabling auto-ranging starts from the smallest range.
+        accel.enable_auto_range(true).unwrap();
+        assert_eq!(accel.auto_range(), Some(Scale::G2));
+        assert_eq!(accel.spi.spi_mut().written, vec![0x24, 0x00]);
+
+        // A saturated reading steps up to the next range.
+        accel.spi.spi_mut().written.clear();
+        accel.spi.spi_mut().reply = 0x7F;
+        assert_eq!(accel.read_xy(), Ok((127, 127)));
+        assert_eq!(accel.auto_range(), Some(Scale::G4));
+        assert_eq!(
+            accel.spi.spi_mut().written,
+            vec![0xA9, 0x00, 0x00, 0x00, 0x24, 0x08]
+        );
+
+        // Subsequent readings are rescaled.
+        accel.spi.spi_mut().written.clear();
+        accel.spi.spi_mut().reply = 0x30;
+        assert_eq!(accel.read_xy(), Ok((96, 96)));
+        assert_eq!(accel.auto_range(), Some(Scale::G4));
+        assert_eq!(accel.spi.spi_mut().written, vec![0xA9, 0x00, 0x00, 0x00]);
+
+        // Disabling auto-ranging returns to the smallest range.
+        accel.spi.spi_mut().written.clear();
+        accel.enable_auto_range(false).unwrap();
+        assert_eq!(accel.auto_range(), None);
+        assert_eq!(accel.spi.spi_mut().written, vec![0x24, 0x00]);
+        assert_eq!(accel.read_xy(), Ok((48, 48)));
+
+        // If setting the range fails, auto-ranging is left as it was.
+        accel.spi.spi_mut().fail = true;
+        assert_eq!(accel.enable_auto_range(true), Err(BusError::Transfer(())));
+        assert_eq!(accel.auto_range(), None);
+    }
+
+    #[test]
+    fn accel_read_xy() {
+        let mut accel = Accelerometer::from(MockSpi::default(), MockOutputPin::default());
+
+        accel.spi.spi_mut().reply = 0xFE;
+        assert_eq!(accel.read_xy(), Ok((-2, -2)));
+        assert_eq!(accel.spi.spi_mut().written, vec![0xA9, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn accel_fifo() {
+        let mut accel = Accelerometer::from(MockSpi::default(), MockOutputPin::default());
+
+        accel.enable_fifo(true).unwrap();
+        accel.enable_fifo(false).unwrap();
+        assert_eq!(
+            accel.spi.spi_mut().written,
+            vec![0x25, 0x50, 0x2E, 0x40, 0x2E, 0x00, 0x25, 0x10]
+        );
+
+        // The FIFO reports two samples (and the samples are -2).
+        accel.spi.spi_mut().written.clear();
+        accel.spi.spi_mut().reply = 0xE2;
+        assert_eq!(accel.fifo_samples(), Ok(2));
+        assert_eq!(accel.read_xy_fifo(), Ok((-30, -30)));
+        assert_eq!(
+            &accel.spi.spi_mut().written[2..],
+            &[0xAF, 0x00, 0xA8, 0, 0, 0, 0, 0xA8, 0, 0, 0, 0]
+        );
+
+        // An empty FIFO falls back to a single read.
+        accel.spi.spi_mut().written.clear();
+        accel.spi.spi_mut().reply = 0x20;
+        assert_eq!(accel.read_xy_fifo(), Ok((32, 32)));
+        assert_eq!(
+            accel.spi.spi_mut().written,
+            vec![0xAF, 0x00, 0xA9, 0x00, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn average() {
+        assert_eq!(average_xy(&[]), None);
+        assert_eq!(average_xy(&[(5, -5)]), Some((5, -5)));
+        assert_eq!(average_xy(&[(1, -1), (2, -2)]), Some((1, -1)));
+        assert_eq!(average_xy(&[(10, 0), (20, -30), (30, 3)]), Some((20, -9)));
+
+        // A full FIFO of extreme samples does not overflow.
+        assert_eq!(average_xy(&[(-128, 127); 32]), Some((-128, 127)));
+        let mut samples = [(-128, -128); 32];
+        samples[0] = (127, 127);
+        assert_eq!(average_xy(&samples), Some((-120, -120)));
+    }
+
+    #[test]
+    fn axis_flip_names() {
+        for name in ["none", "x", "y", "xy"].iter() {
+            let flip = AxisFlip::from_name(name.as_bytes()).unwrap();
+            assert_eq!(flip.as_name(), *name);
+        }
+        assert_eq!(AxisFlip::from_name(b"z"), None);
+        assert_eq!(AxisFlip::default(), AxisFlip::NONE);
+    }
+
+    #[test]
+    fn axis_flip_apply() {
+        let flip = AxisFlip::from_name(b"none").unwrap();
+        assert_eq!(flip.apply(10, -20), (10, -20));
+        let flip = AxisFlip::from_name(b"x").unwrap();
+        assert_eq!(flip.apply(10, -20), (-10, -20));
+        let flip = AxisFlip::from_name(b"y").unwrap();
+        assert_eq!(flip.apply(10, -20), (10, 20));
+        let flip = AxisFlip::from_name(b"xy").unwrap();
+        assert_eq!(flip.apply(10, -20), (-10, 20));
+
+        // The extremes do not overflow.
+        assert_eq!(flip.apply(-128, 127), (127, -127));
+        assert_eq!(flip.apply(0, 0), (0, 0));
+    }
+
+    #[test]
+    fn axis_flip_directions() {
+        // The board is tilted towards the east (and north) side.
+        let (acc_x, acc_y) = (-3, 5);
+        let directions = [false, true, true, false];
+        assert_eq!(directions_from_accel(acc_x, acc_y), directions);
+
+        // When mounted face-down with the X axis flipped, east and west are mirrored.
+        let flip = AxisFlip { x: true, y: false };
+        let (acc_x, acc_y) = flip.apply(acc_x, acc_y);
+        assert_eq!(
+            directions_from_accel(acc_x, acc_y),
+            [false, false, true, true]
+        );
+    }
+
+    #[test]
+    fn axis_swap_orient() {
+        assert_eq!(orient(10, -20, false, AxisFlip::NONE), (10, -20));
+        assert_eq!(orient(10, -20, true, AxisFlip::NONE), (-20, 10));
+
+        // The flip applies to the swapped axes.
+        let flip = AxisFlip { x: true, y: false };
+        assert_eq!(orient(10, -20, false, flip), (-10, -20));
+        assert_eq!(orient(10, -20, true, flip), (20, 10));
+        let flip = AxisFlip { x: false, y: true };
+        assert_eq!(orient(10, -20, true, flip), (-20, -10));
+        let flip = AxisFlip { x: true, y: true };
+        assert_eq!(orient(-128, 127, true, flip), (-127, 127));
+
+        // Swapping and flipping one axis turns the board a quarter: a tilt towards the east
+        // becomes a tilt towards the north.
+        assert_eq!(directions_from_accel(0, -5), [true, false, false, false]);
+        let (acc_x, acc_y) = orient(0, -5, true, AxisFlip { x: true, y: false });
+        assert_eq!(
+            directions_from_accel(acc_x, acc_y),
+            [false, false, false, true]
+        );
+    }
+
+    #[test]
+    fn integer_sqrt() {
+        assert_eq!(isqrt(0), 0);
+        assert_eq!(isqrt(1), 1);
+        assert_eq!(isqrt(3), 1);
+        assert_eq!(isqrt(4), 2);
+        assert_eq!(isqrt(32768), 181);
+        assert_eq!(isqrt(65535), 255);
+        assert_eq!(isqrt(65536), 256);
+        assert_eq!(isqrt(u32::MAX), 65535);
+    }
+
+    #[test]
+    fn magnitudes() {
+        assert_eq!(magnitude(0, 0), 0);
+        assert_eq!(magnitude(3, 4), 5);
+        assert_eq!(magnitude(-3, -4), 5);
+        assert_eq!(magnitude(127, 0), 127);
+        assert_eq!(magnitude(0, -128), 128);
+        assert_eq!(magnitude(-128, -128), 181);
+    }
+
+    #[test]
+    fn magnitudes_full_range() {
+        for acc_x in i8::MIN..=i8::MAX {
+            for acc_y in i8::MIN..=i8::MAX {
+                let exact = (acc_x as f64).hypot(acc_y as f64);
+                let result = magnitude(acc_x, acc_y);
+                assert_eq!(result, exact.floor() as u16, "x = {}, y = {}", acc_x, acc_y);
+            }
+        }
+    }
+
+    #[test]
+    fn tilt_periods() {
+        assert_eq!(tilt_period(0, 8000, 1000), 8000);
+        assert_eq!(tilt_period(FULL_TILT as u16 / 2, 8000, 1000), 4500);
+        assert_eq!(tilt_period(FULL_TILT as u16, 8000, 1000), 1000);
+        assert_eq!(tilt_period(magnitude(i8::MIN, i8::MIN), 8000, 1000), 1000);
+        assert_eq!(tilt_period(u16::MAX, 8000, 1000), 1000);
+
+        // Large periods do not overflow.
+        assert_eq!(tilt_period(FULL_TILT as u16, u32::MAX, 0), 0);
+        // A fastest period that is longer than the slowest period is ignored.
+        assert_eq!(tilt_period(FULL_TILT as u16, 1000, 8000), 1000);
+
+        // The period never leaves the range and only decreases with the magnitude.
+        let mut prev = 8000;
+        for magnitude in 0..=u8::MAX as u16 {
+            let period = tilt_period(magnitude, 8000, 1000);
+            assert!((1000..=8000).contains(&period));
+            assert!(period <= prev);
+            prev = period;
+        }
+    }
+
+    #[test]
+    fn tilt_duties() {
+        assert_eq!(tilt_duty(0), 0);
+        assert_eq!(tilt_duty(-1), 0);
+        assert_eq!(tilt_duty(i8::MIN as i16), 0);
+        assert_eq!(tilt_duty(FULL_TILT as i16 / 2), PWM_STEPS / 2);
+        assert_eq!(tilt_duty(FULL_TILT as i16), PWM_STEPS);
+        assert_eq!(tilt_duty(i8::MAX as i16), PWM_STEPS);
+        assert_eq!(tilt_duty(-(i8::MIN as i16)), PWM_STEPS);
+
+        // The duty never exceeds the PWM range and only increases with the acceleration.
+        let mut prev = 0;
+        for acc in i8::MIN as i16..=-(i8::MIN as i16) {
+            let duty = tilt_duty(acc);
+            assert!(duty <= PWM_STEPS);
+            assert!(duty >= prev);
+            prev = duty;
+        }
+    }
+
+    #[test]
+    fn duties() {
+        assert_eq!(duties_from_accel(0, 0), [0; 4]);
+        let half = PWM_STEPS / 2;
+        assert_eq!(duties_from_accel(32, -128), [PWM_STEPS, 0, 0, half]);
+        assert_eq!(duties_from_accel(-128, 127), [0, PWM_STEPS, PWM_STEPS, 0]);
+
+        // The LEDs that are on match the digital display for large enough accelerations.
+        for (acc_x, acc_y) in [(10, -20), (-64, 64), (100, 5)].iter() {
+            let on = duties_from_accel(*acc_x, *acc_y).map(|duty| duty > 0);
+            assert_eq!(on, directions_from_accel(*acc_x, *acc_y));
+        }
+    }
+
+    #[test]
+    fn directions() {
+        assert_eq!(directions_from_accel(0, 0), [false, false, false, false]);
+        assert_eq!(directions_from_accel(10, 0), [false, false, false, true]);
+        assert_eq!(directions_from_accel(-10, 0), [false, true, false, false]);
+        assert_eq!(directions_from_accel(0, 10), [false, false, true, false]);
+        assert_eq!(directions_from_accel(0, -10), [true, false, false, false]);
+        assert_eq!(directions_from_accel(-3, -3), [true, true, false, false]);
+    }
+
+    #[test]
+    fn meter_thresholds() {
+        assert_eq!(meter_threshold(0), 0);
+        assert_eq!(meter_threshold(1), 16);
+        assert_eq!(meter_threshold(2), 32);
+        assert_eq!(meter_threshold(3), 48);
+        assert_eq!(meter_threshold(4), FULL_TILT as u16);
+    }
+
+    #[test]
+    fn meter_counts() {
+        // Going up, each count is reached at its threshold.
+        assert_eq!(meter_count(0, 0), 0);
+        assert_eq!(meter_count(15, 0), 0);
+        assert_eq!(meter_count(16, 0), 1);
+        assert_eq!(meter_count(31, 1), 1);
+        assert_eq!(meter_count(32, 1), 2);
+        assert_eq!(meter_count(48, 2), 3);
+        assert_eq!(meter_count(64, 3), 4);
+        assert_eq!(meter_count(181, 4), 4);
+        // Larger jumps are followed right away.
+        assert_eq!(meter_count(50, 0), 3);
+        assert_eq!(meter_count(10, 4), 0);
+
+        // Going down, a count is kept until the magnitude is clearly below its threshold.
+        assert_eq!(meter_count(32, 2), 2);
+        assert_eq!(meter_count(32 - METER_HYSTERESIS, 2), 2);
+        assert_eq!(meter_count(32 - METER_HYSTERESIS - 1, 2), 1);
+        assert_eq!(meter_count(64 - METER_HYSTERESIS, 4), 4);
+        assert_eq!(meter_count(64 - METER_HYSTERESIS - 1, 4), 3);
+        assert_eq!(meter_count(16 - METER_HYSTERESIS, 1), 1);
+        assert_eq!(meter_count(16 - METER_HYSTERESIS - 1, 1), 0);
+
+        // A level board lights no LEDs, regardless of the previous count.
+        for previous in 0..=4 {
+            assert_eq!(meter_count(0, previous), 0);
+        }
+
+        // Around a threshold, the count does not flicker.
+        let mut count = 0;
+        for (magnitude, expected) in [
+            (16, 1),
+            (15, 1),
+            (13, 1),
+            (12, 1),
+            (11, 0),
+            (15, 0),
+            (16, 1),
+        ]
+        .iter()
+        {
+            count = meter_count(*magnitude, count);
+            assert_eq!(count, *expected, "(magnitude {})", magnitude);
+        }
+    }
+
+    #[test]
+    fn meter_patterns() {
+        assert_eq!(meter_pattern(0), [false; 4]);
+        assert_eq!(meter_pattern(1), [true, false, false, false]);
+        assert_eq!(meter_pattern(3), [true, true, true, false]);
+        assert_eq!(meter_pattern(4), [true; 4]);
+    }
+
+    #[test]
+    fn shakes() {
+        // Too few measurements, a board at rest and a board being tilted.
+        assert!(!is_shake(&[]));
+        assert!(!is_shake(&[(0, 0)]));
+        assert!(!is_shake(&[(5, -3); SHAKE_WINDOW]));
+        assert!(!is_shake(&[(0, 0), (20, 0), (40, 0), (64, 0), (64, 0)]));
+        assert!(!is_shake(&[(0, 0), (64, 0), (64, 0), (0, 0), (0, 0)]));
+
+        // Rapidly alternating acceleration along either axis.
+        assert!(is_shake(&[(0, 0), (60, 0), (-10, 0), (50, 0), (0, 0)]));
+        assert!(is_shake(&[(0, 10), (0, -40), (0, 30), (3, -20), (0, 40)]));
+
+        // The acceleration has to alternate often enough.
+        assert!(!is_shake(&[(0, 0), (60, 0), (-10, 0), (50, 0)]));
+        assert_eq!(SHAKE_REVERSALS, 3);
+
+        // Jittering (changes below the threshold) does not count, nor interrupts a shake.
+        assert!(!is_shake(&[
+            (0, 0),
+            (20, 0),
+            (-10, 0),
+            (20, 0),
+            (-10, 0),
+            (20, 0)
+        ]));
+        assert!(is_shake(&[
+            (0, 0),
+            (60, 0),
+            (50, 0),
+            (-10, 0),
+            (50, 0),
+            (0, 0)
+        ]));
+
+        // Changes at the extremes do not overflow.
+        assert!(is_shake(&[
+            (127, 0),
+            (-128, 0),
+            (127, 0),
+            (-128, 0),
+            (127, 0)
+        ]));
+    }
+
+    #[test]
+    fn shake_detector() {
+        let mut detector = ShakeDetector::new();
+        assert_eq!(detector, ShakeDetector::default());
+
+        // A board at rest fills the window without being detected as shaken.
+        for _ in 0..2 * SHAKE_WINDOW {
+            assert!(!detector.push(0, 0));
+        }
+
+        // A shake is detected once, as soon as the acceleration has alternated enough.
+        let shake = [(60, 0), (-10, 0), (50, 0), (0, 0)];
+        let detected: Vec<bool> = shake.iter().map(|(x, y)| detector.push(*x, *y)).collect();
+        assert_eq!(detected, [false, false, false, true]);
+        assert!(!detector.push(60, 0));
+        assert!(!detector.push(0, 0));
+
+        // Jolts that have dropped out of the window no longer count.
+        let mut detector = ShakeDetector::new();
+        for (x, y) in [(0, 0), (60, 0), (0, 0)].iter() {
+            assert!(!detector.push(*x, *y));
+        }
+        for _ in 0..SHAKE_WINDOW {
+            assert!(!detector.push(0, 0));
+        }
+        assert!(!detector.push(60, 0));
+        assert!(!detector.push(0, 0));
+
+        // Resetting forgets the measurements.
+        let mut detector = ShakeDetector::new();
+        for (x, y) in [(0, 0), (60, 0), (0, 0)].iter() {
+            assert!(!detector.push(*x, *y));
+        }
+        detector.reset();
+        assert!(!detector.push(60, 0));
+    }
+
+    #[test]
+    fn arrows() {
+        assert_eq!(arrow_from_accel(0, 0), '.');
+        assert_eq!(arrow_from_accel(10, 0), 'N');
+        assert_eq!(arrow_from_accel(-10, 0), 'S');
+        assert_eq!(arrow_from_accel(0, 10), 'W');
+        assert_eq!(arrow_from_accel(0, -10), 'E');
+        assert_eq!(arrow_from_accel(i8::MIN, 0), 'S');
+
+        // The axis with the largest acceleration wins, or the X axis on a tie.
+        assert_eq!(arrow_from_accel(10, -3), 'N');
+        assert_eq!(arrow_from_accel(-3, 10), 'W');
+        assert_eq!(arrow_from_accel(-3, -3), 'S');
+        assert_eq!(arrow_from_accel(i8::MIN, i8::MAX), 'S');
+    }
+
+    #[test]
+    fn downhill_directions() {
+        assert_eq!(downhill_from_accel(0, 0), [false; 4]);
+
+        // Close to an axis, only a single LED is on.
+        assert_eq!(downhill_from_accel(10, 0), [false, false, false, true]);
+        assert_eq!(downhill_from_accel(-10, 2), [false, true, false, false]);
+        assert_eq!(downhill_from_accel(4, 20), [false, false, true, false]);
+        assert_eq!(downhill_from_accel(0, -10), [true, false, false, false]);
+        assert_eq!(downhill_from_accel(1, 0), [false, false, false, true]);
+
+        // In between two axes, the pair is on.
+        assert_eq!(downhill_from_accel(-3, -3), [true, true, false, false]);
+        assert_eq!(downhill_from_accel(10, 5), [false, false, true, true]);
+        assert_eq!(downhill_from_accel(5, -10), [true, false, false, true]);
+
+        // Extreme values do not overflow.
+        assert_eq!(
+            downhill_from_accel(i8::MIN, i8::MIN),
+            [true, true, false, false]
+        );
+        assert_eq!(downhill_from_accel(i8::MAX, 0), [false, false, false, true]);
+
+        // There is always one LED or a pair of neighbouring LEDs on for a tilted board.
+        for acc_x in i8::MIN..=i8::MAX {
+            for acc_y in i8::MIN..=i8::MAX {
+                let directions = downhill_from_accel(acc_x, acc_y);
+                let lit = directions.iter().filter(|on| **on).count();
+                if acc_x == 0 && acc_y == 0 {
+                    assert_eq!(lit, 0);
+                } else {
+                    assert!(lit == 1 || lit == 2, "x = {}, y = {}", acc_x, acc_y);
+                    assert!(directions != [true, false, true, false]);
+                    assert!(directions != [false, true, false, true]);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,136 @@
+//! Module for acknowledging by blinking all LEDs of the LED ring.
+
+use crate::led_ring::{Direction, Mode};
+
+/// A step of the acknowledgment blinking.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AckStep {
+    /// Turn all LEDs on.
+    On,
+    /// Turn all LEDs off.
+    Off,
+    /// The blinking is done; restore the given mode, direction and LED states.
+    Restore {
+        mode: Mode,
+        direction: Direction,
+        states: [bool; 4],
+    },
+}
+
+/// An acknowledgment that blinks all LEDs a number of times.
+///
+/// The mode, direction and LED states of the LED ring from before the blinking are kept so that
+/// they can be restored afterwards.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Ack {
+    /// The number of remaining steps (two per blink).
+    remaining: u16,
+    /// The mode to restore.
+    mode: Mode,
+    /// The cycle direction to restore.
+    direction: Direction,
+    /// The LED states to restore.
+    states: [bool; 4],
+}
+
+impl Ack {
+    /// Sets up an acknowledgment that blinks the given number of times and then restores the
+    /// given mode, direction and LED states.
+    pub fn new(blinks: u8, mode: Mode, direction: Direction, states: [bool; 4]) -> Ack {
+        Ack {
+            remaining: 2 * blinks as u16,
+            mode,
+            direction,
+            states,
+        }
+    }
+
+    /// Returns the number of blinks that remain (including a blink in progress).
+    pub fn remaining_blinks(&self) -> u8 {
+        ((self.remaining + 1) / 2) as u8
+    }
+
+    /// Sets the number of blinks that remain, keeping the state to restore.
+    pub fn set_remaining_blinks(&mut self, blinks: u8) {
+        self.remaining = 2 * blinks as u16;
+    }
+
+    /// Advances the acknowledgment one step and returns what to do.
+    ///
+    /// Each blink consists of an on and an off step; after the last blink the state to restore
+    /// is returned (repeatedly).
+    pub fn advance(&mut self) -> AckStep {
+        if self.remaining == 0 {
+            return AckStep::Restore {
+                mode: self.mode,
+                direction: self.direction,
+                states: self.states,
+            };
+        }
+
+        self.remaining -= 1;
+        if self.remaining % 2 == 1 {
+            AckStep::On
+        } else {
+            AckStep::Off
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Ack, AckStep, Direction, Mode};
+
+    #[test]
+    fn ack_countdown() {
+        let states = [true, true, false, false];
+        let mut ack = Ack::new(2, Mode::Cycle, Direction::CounterClockwise, states);
+
+        assert_eq!(ack.remaining_blinks(), 2);
+        assert_eq!(ack.advance(), AckStep::On);
+        assert_eq!(ack.remaining_blinks(), 2);
+        assert_eq!(ack.advance(), AckStep::Off);
+        assert_eq!(ack.remaining_blinks(), 1);
+        assert_eq!(ack.advance(), AckStep::On);
+        assert_eq!(ack.advance(), AckStep::Off);
+        assert_eq!(ack.remaining_blinks(), 0);
+
+        let restore = AckStep::Restore {
+            mode: Mode::Cycle,
+            direction: Direction::CounterClockwise,
+            states,
+        };
+        assert_eq!(ack.advance(), restore);
+        assert_eq!(ack.advance(), restore);
+    }
+
+    #[test]
+    fn ack_restart() {
+        let mut ack = Ack::new(1, Mode::Chase, Direction::Clockwise, [false; 4]);
+
+        assert_eq!(ack.advance(), AckStep::On);
+        ack.set_remaining_blinks(1);
+        assert_eq!(ack.remaining_blinks(), 1);
+        assert_eq!(ack.advance(), AckStep::On);
+        assert_eq!(ack.advance(), AckStep::Off);
+        assert_eq!(
+            ack.advance(),
+            AckStep::Restore {
+                mode: Mode::Chase,
+                direction: Direction::Clockwise,
+                states: [false; 4]
+            }
+        );
+    }
+
+    #[test]
+    fn ack_max_blinks() {
+        let mut ack = Ack::new(u8::MAX, Mode::Off, Direction::Clockwise, [false; 4]);
+
+        for _ in 0..u8::MAX {
+            assert_eq!(ack.advance(), AckStep::On);
+            assert_eq!(ack.advance(), AckStep::Off);
+        }
+        assert_eq!(ack.remaining_blinks(), 0);
+    }
+}
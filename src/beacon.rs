@@ -0,0 +1,170 @@
+//! Module for the identity beacon that is sent via the serial interface at startup.
+//!
+//! The beacon is a single line of space-separated `key=value` fields that identifies the
+//! firmware and the board, followed by a CRC so that a flasher can check that it received the
+//! line intact:
+//!
+//! ```text
+//! beacon name=stm32f4disc-demo version=0.1.0 git=1a2b3c4 uid=0123456789abcdef01234567 crc=6733f123
+//! ```
+//!
+//! The unique ID of the device is given as 24 hexadecimal digits (its 12 bytes in order of
+//! increasing address).  The CRC is the CRC-32 (as used by Ethernet and zlib) of all bytes of
+//! the line before ` crc=`, given as 8 hexadecimal digits.
+
+use core::fmt::{self, Write};
+
+/// The length of the unique ID of the device (in bytes).
+pub const UID_LEN: usize = 12;
+
+/// The (reflected) polynomial of the CRC-32.
+const CRC32_POLYNOMIAL: u32 = 0xEDB8_8320;
+
+/// A running CRC-32 computation.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Crc32(u32);
+
+impl Crc32 {
+    /// Starts a CRC-32 computation.
+    pub const fn new() -> Crc32 {
+        Crc32(u32::MAX)
+    }
+
+    /// Updates the CRC with the bytes.
+    pub fn update(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u32;
+            for _ in 0..8 {
+                let mask = (self.0 & 1).wrapping_neg();
+                self.0 = (self.0 >> 1) ^ (CRC32_POLYNOMIAL & mask);
+            }
+        }
+    }
+
+    /// Returns the CRC of the bytes so far.
+    pub fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Crc32 {
+        Crc32::new()
+    }
+}
+
+/// Returns the CRC-32 of the bytes.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(bytes);
+    crc.finish()
+}
+
+/// A writer that computes the CRC-32 of everything that is written to the inner writer.
+struct CrcWriter<'a, W> {
+    /// The inner writer.
+    inner: &'a mut W,
+    /// The CRC of the bytes written so far.
+    crc: Crc32,
+}
+
+impl<'a, W: Write> Write for CrcWriter<'a, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.crc.update(s.as_bytes());
+        self.inner.write_str(s)
+    }
+}
+
+/// The identity of the firmware and the board.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Identity<'a> {
+    /// The name of the application.
+    pub name: &'a str,
+    /// The version of the application.
+    pub version: &'a str,
+    /// The git hash the application is built from.
+    pub git_hash: &'a str,
+    /// The unique ID of the device.
+    pub uid: [u8; UID_LEN],
+}
+
+/// Writes the beacon line for the identity (without line terminator).
+pub fn write_beacon<W: Write>(writer: &mut W, identity: &Identity) -> fmt::Result {
+    let mut crc_writer = CrcWriter {
+        inner: writer,
+        crc: Crc32::new(),
+    };
+    write!(
+        crc_writer,
+        "beacon name={} version={} git={} uid=",
+        identity.name, identity.version, identity.git_hash
+    )?;
+    for byte in identity.uid.iter() {
+        write!(crc_writer, "{:02x}", byte)?;
+    }
+
+    let crc = crc_writer.crc.finish();
+    write!(writer, " crc={:08x}", crc)
+}
+
+/// Returns the unique ID of the device, as programmed into the system memory at the factory.
+#[cfg(not(test))]
+pub fn unique_id() -> [u8; UID_LEN] {
+    /// The address of the unique ID (see the reference manual of the STM32F4).
+    const UID_ADDRESS: usize = 0x1FFF_7A10;
+
+    let mut uid = [0; UID_LEN];
+    for (offset, byte) in uid.iter_mut().enumerate() {
+        // NOTE(unsafe) The unique ID is a read-only region of memory that is always present.
+        *byte = unsafe { core::ptr::read_volatile((UID_ADDRESS + offset) as *const u8) };
+    }
+
+    uid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc32, write_beacon, Crc32, Identity};
+
+    #[test]
+    fn crc32_check() {
+        assert_eq!(crc32(b""), 0);
+        // The check value of CRC-32.
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(
+            crc32(b"The quick brown fox jumps over the lazy dog"),
+            0x414F_A339
+        );
+
+        // Computing in parts gives the same result.
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"");
+        crc.update(b"56789");
+        assert_eq!(crc.finish(), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn beacon_format() {
+        let identity = Identity {
+            name: "stm32f4disc-demo",
+            version: "0.1.0",
+            git_hash: "1a2b3c4",
+            uid: [
+                0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, 0x01, 0x23, 0x45, 0x67,
+            ],
+        };
+        let mut line = String::new();
+        write_beacon(&mut line, &identity).unwrap();
+
+        let (fields, crc) = line.split_at(line.find(" crc=").unwrap());
+        assert_eq!(
+            fields,
+            "beacon name=stm32f4disc-demo version=0.1.0 git=1a2b3c4 \
+             uid=0123456789abcdef01234567"
+        );
+        assert_eq!(crc, format!(" crc={:08x}", crc32(fields.as_bytes())));
+        assert_eq!(crc, " crc=6733f123");
+        assert_eq!(crc.len(), 13);
+    }
+}
@@ -0,0 +1,233 @@
+//! Module with the configuration of the pins the LEDs of the ring and the user button are
+//! connected to.
+//!
+//! On the STM32F4DISCOVERY board, the green, orange, red and blue LEDs are connected to pins
+//! PD12 to PD15.  For boards that route the LEDs differently, this module is the only place
+//! that needs to be changed (both the application and the SOS panic handler use it).
+//!
+//! The pin assignment is fixed at compile time, and all four LEDs have to be connected to pins
+//! of the same GPIO port, because the LED ring stores them as pins of a single (downgraded)
+//! type.  When changing the port, [`LedPort`], [`Led`] and [`led_pins`] have to be changed
+//! together, as well as the port peripheral that is passed to [`led_pins`].
+//!
+//! With the `pwm-timer` feature, the LEDs are dimmed by the hardware PWM of timer TIM4 instead
+//! of by switching the pins in software.  Channels 1 to 4 of TIM4 are connected to PD12 to
+//! PD15 (alternate function AF2), so the LEDs keep their order around the ring:
+//!
+//! | LED    | Pin  | Channel  |
+//! |--------|------|----------|
+//! | green  | PD12 | TIM4_CH1 |
+//! | orange | PD13 | TIM4_CH2 |
+//! | red    | PD14 | TIM4_CH3 |
+//! | blue   | PD15 | TIM4_CH4 |
+//!
+//! For boards that route the LEDs to other pins, [`LedTimer`] and [`led_channels`] have to be
+//! changed to a timer (and alternate function) whose channels are available on those pins.
+//!
+//! The user button is connected to PA0, which triggers the EXTI0 interrupt.  The EXTI line is
+//! the number of the pin, so the interrupt the button handler is bound to in the application
+//! (`binds = EXTI0`) has to be changed along with [`UserButton`], [`user_button`] and
+//! [`USER_BUTTON_INTERRUPT`]: pins 1 to 4 have their own interrupts (EXTI1 to EXTI4), whereas
+//! pins 5 to 9 and 10 to 15 share the EXTI9_5 and EXTI15_10 interrupts.  For a pin on another
+//! port than GPIOA, the EXTI line also has to be routed to that port via the SYSCFG peripheral
+//! (`make_interrupt_source`), which requires its clock to be enabled.
+//!
+//! The interrupt pins of the accelerometer are connected to PE0 (INT1) and PE1 (INT2).  Since
+//! EXTI0 is taken by the user button, the motion interrupt that wakes the board up is routed
+//! to INT2, which triggers the EXTI1 interrupt once [`accel_interrupt`] routed its EXTI line to
+//! GPIOE.  The data-ready (DRDY) signal of the accelerometer can only be routed to INT1, so it
+//! cannot trigger an interrupt as long as the user button is on EXTI0 (each EXTI line can only
+//! be routed to one port); instead, the data-ready status is read before polling a sample.
+
+use hal::gpio::{
+    gpioa, gpiod, gpioe, Alternate, Edge, ExtiPin, Floating, GpioExt, Input, Output, PushPull, AF2,
+};
+use hal::rcc::Clocks;
+use hal::stm32::{self, EXTI, SYSCFG};
+
+use crate::led_ring::{LedOutput, LedOutputState, PWM_STEPS};
+
+/// The GPIO port the LEDs are connected to.
+pub type LedPort = stm32::GPIOD;
+
+/// The type of the pins of the LEDs (configured as push-pull output).
+pub type Led = gpiod::PD<Output<PushPull>>;
+
+/// Sets up the pins of the LEDs of the ring in order (going around the ring clockwise,
+/// starting at the top).
+///
+/// This enables the clock of the GPIO port.
+pub fn led_pins(port: LedPort) -> [Led; 4] {
+    let gpio = port.split();
+
+    [
+        gpio.pd12.into_push_pull_output().downgrade(),
+        gpio.pd13.into_push_pull_output().downgrade(),
+        gpio.pd14.into_push_pull_output().downgrade(),
+        gpio.pd15.into_push_pull_output().downgrade(),
+    ]
+}
+
+/// The timer whose channels drive the LEDs with the `pwm-timer` feature.
+pub type LedTimer = stm32::TIM4;
+
+/// The frequency of the hardware PWM of the LEDs (in hertz).
+///
+/// This is well above the frequency of the software PWM, since the timer does not need any
+/// interrupts to switch the outputs.
+pub const LED_PWM_FREQUENCY_HZ: u32 = 1_000;
+
+/// A channel of the LED timer driving the pin of an LED (configured as alternate function).
+///
+/// The channel is lit for the part of the PWM period given by its capture/compare register:
+/// the output is high while the counter is below it, so 0 switches the LED off and
+/// [`PWM_STEPS`] switches it on during the whole period.
+pub struct LedChannel {
+    /// The index of the channel (0 for channel 1).
+    index: u8,
+    /// The pin of the LED, only kept to reserve it for the channel.
+    _pin: gpiod::PD<Alternate<AF2>>,
+}
+
+impl LedChannel {
+    /// Returns the capture/compare value of the channel.
+    fn compare(&self) -> u16 {
+        // NOTE(unsafe) Only reads the capture/compare register of this channel.
+        let timer = unsafe { &*LedTimer::ptr() };
+        match self.index {
+            0 => timer.ccr1.read().ccr().bits(),
+            1 => timer.ccr2.read().ccr().bits(),
+            2 => timer.ccr3.read().ccr().bits(),
+            _ => timer.ccr4.read().ccr().bits(),
+        }
+    }
+
+    /// Sets the capture/compare value of the channel.
+    fn set_compare(&mut self, value: u16) {
+        // NOTE(unsafe) Only writes the capture/compare register of this channel (each channel
+        // is owned by a single LED), which does not affect the other channels.
+        let timer = unsafe { &*LedTimer::ptr() };
+        match self.index {
+            0 => timer.ccr1.write(|w| w.ccr().bits(value)),
+            1 => timer.ccr2.write(|w| w.ccr().bits(value)),
+            2 => timer.ccr3.write(|w| w.ccr().bits(value)),
+            _ => timer.ccr4.write(|w| w.ccr().bits(value)),
+        }
+    }
+}
+
+impl LedOutput for LedChannel {
+    const HARDWARE_PWM: bool = true;
+
+    fn set_level(&mut self, high: bool) {
+        self.set_duty(if high { PWM_STEPS } else { 0 });
+    }
+
+    fn set_duty(&mut self, high_steps: u8) {
+        self.set_compare(high_steps.min(PWM_STEPS) as u16);
+    }
+}
+
+impl LedOutputState for LedChannel {
+    fn is_high(&self) -> bool {
+        self.compare() > 0
+    }
+}
+
+/// Sets up the channels of the LED timer driving the pins of the LEDs of the ring in order
+/// (see [`led_pins`]), with all LEDs off.
+///
+/// This enables the clock of the GPIO port and starts the timer counting [`PWM_STEPS`] steps per
+/// PWM period at [`LED_PWM_FREQUENCY_HZ`].  The clock of the timer has to be enabled (in
+/// `RCC_APB1ENR`) beforehand.
+pub fn led_channels(port: LedPort, timer: LedTimer, clocks: Clocks) -> [LedChannel; 4] {
+    // The timers on APB1 run at twice its clock if it is divided.
+    let timer_clock = match clocks.ppre1() {
+        1 => clocks.pclk1().0,
+        _ => 2 * clocks.pclk1().0,
+    };
+    let prescaler = timer_clock / (LED_PWM_FREQUENCY_HZ * PWM_STEPS as u32) - 1;
+    timer.psc.write(|w| w.psc().bits(prescaler as u16));
+    timer.arr.write(|w| w.arr().bits(PWM_STEPS as u16 - 1));
+
+    // PWM mode 1 (high while the counter is below the compare value), with the compare values
+    // only taking effect at the start of the next period to avoid glitches.
+    timer.ccmr1_output().modify(|_, w| {
+        w.oc1m().pwm_mode1().oc1pe().enabled();
+        w.oc2m().pwm_mode1().oc2pe().enabled()
+    });
+    timer.ccmr2_output().modify(|_, w| {
+        w.oc3m().pwm_mode1().oc3pe().enabled();
+        w.oc4m().pwm_mode1().oc4pe().enabled()
+    });
+    timer.ccr1.write(|w| w.ccr().bits(0));
+    timer.ccr2.write(|w| w.ccr().bits(0));
+    timer.ccr3.write(|w| w.ccr().bits(0));
+    timer.ccr4.write(|w| w.ccr().bits(0));
+    timer.ccer.modify(|_, w| {
+        w.cc1e().set_bit();
+        w.cc2e().set_bit();
+        w.cc3e().set_bit();
+        w.cc4e().set_bit()
+    });
+    // Load the prescaler and compare values before starting the timer.
+    timer.egr.write(|w| w.ug().set_bit());
+    timer.cr1.modify(|_, w| w.arpe().set_bit().cen().set_bit());
+
+    let gpio = port.split();
+    [
+        LedChannel {
+            index: 0,
+            _pin: gpio.pd12.into_alternate_af2().downgrade(),
+        },
+        LedChannel {
+            index: 1,
+            _pin: gpio.pd13.into_alternate_af2().downgrade(),
+        },
+        LedChannel {
+            index: 2,
+            _pin: gpio.pd14.into_alternate_af2().downgrade(),
+        },
+        LedChannel {
+            index: 3,
+            _pin: gpio.pd15.into_alternate_af2().downgrade(),
+        },
+    ]
+}
+
+/// The type of the pin of the user button (configured as floating input).
+pub type UserButton = gpioa::PA0<Input<Floating>>;
+
+/// The interrupt triggered by the EXTI line of the user button.
+pub const USER_BUTTON_INTERRUPT: stm32::Interrupt = stm32::Interrupt::EXTI0;
+
+/// Sets up the pin of the user button and its EXTI line to trigger an interrupt on both edges.
+///
+/// Both edges are used to keep track of whether the button is held.
+pub fn user_button<MODE>(pin: gpioa::PA0<MODE>, exti: &mut EXTI) -> UserButton {
+    let mut button = pin.into_floating_input();
+    button.enable_interrupt(exti);
+    button.trigger_on_edge(exti, Edge::RISING_FALLING);
+
+    button
+}
+
+/// The type of the pin of the accelerometer interrupt INT2 (configured as floating input).
+pub type AccelInterrupt = gpioe::PE1<Input<Floating>>;
+
+/// Sets up the pin of the accelerometer interrupt INT2 and its EXTI line to trigger an
+/// interrupt on the rising edge (the interrupt is active high).
+///
+/// The clock of the SYSCFG peripheral has to be enabled to route the EXTI line to GPIOE.
+pub fn accel_interrupt<MODE>(
+    pin: gpioe::PE1<MODE>,
+    exti: &mut EXTI,
+    syscfg: &mut SYSCFG,
+) -> AccelInterrupt {
+    let mut interrupt = pin.into_floating_input();
+    interrupt.make_interrupt_source(syscfg);
+    interrupt.trigger_on_edge(exti, Edge::RISING);
+    interrupt.enable_interrupt(exti);
+
+    interrupt
+}
@@ -0,0 +1,98 @@
+//! Module for letting the LED ring breathe, i.e. smoothly fade in and out.
+
+/// The number of steps of a full breath.
+pub const BREATHE_STEPS: usize = 32;
+
+/// The brightness level at each step of a breath.
+///
+/// The levels follow a raised cosine (`255 * (1 - cos(2πi/32)) / 2`, rounded) so that the
+/// brightness changes slowly near the dimmest and the brightest point and fastest in between.
+/// The curve is symmetric: the fade out is the fade in reversed.
+const LEVELS: [u8; BREATHE_STEPS] = [
+    0, 2, 10, 21, 37, 57, 79, 103, 128, 152, 176, 198, 218, 234, 245, 253, 255, 253, 245, 234, 218,
+    198, 176, 152, 128, 103, 79, 57, 37, 21, 10, 2,
+];
+
+/// A breath of the LED ring.
+///
+/// The breath continuously steps through the brightness curve, starting at zero brightness.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Breathe {
+    /// The current step in the brightness curve.
+    step: usize,
+}
+
+impl Breathe {
+    /// Sets up a breath starting at zero brightness.
+    pub fn new() -> Breathe {
+        Breathe { step: 0 }
+    }
+
+    /// Returns the current brightness level.
+    pub fn level(&self) -> u8 {
+        LEVELS[self.step]
+    }
+
+    /// Advances the breath one step and returns the new brightness level.
+    ///
+    /// After the last step the breath starts over at zero brightness.
+    pub fn advance(&mut self) -> u8 {
+        self.step = (self.step + 1) % BREATHE_STEPS;
+        self.level()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Breathe, BREATHE_STEPS, LEVELS};
+
+    #[test]
+    fn breathe_init() {
+        let breathe = Breathe::new();
+
+        assert_eq!(breathe.level(), 0);
+        assert_eq!(breathe, Breathe::default());
+    }
+
+    #[test]
+    fn breathe_curve() {
+        assert_eq!(LEVELS.iter().min(), Some(&0));
+        assert_eq!(LEVELS.iter().max(), Some(&u8::MAX));
+        assert_eq!(LEVELS[BREATHE_STEPS / 2], u8::MAX);
+
+        // The fade out mirrors the fade in.
+        for step in 1..BREATHE_STEPS {
+            assert_eq!(LEVELS[step], LEVELS[BREATHE_STEPS - step]);
+        }
+
+        // The brightness only rises during the first half and only falls during the second.
+        for step in 0..BREATHE_STEPS / 2 {
+            assert!(LEVELS[step] < LEVELS[step + 1]);
+        }
+    }
+
+    #[test]
+    fn breathe_smooth() {
+        let mut breathe = Breathe::new();
+
+        // The steepest slope of the curve is π/32 of full brightness per step (about 25).
+        let mut prev = breathe.level();
+        for _ in 0..2 * BREATHE_STEPS {
+            let level = breathe.advance();
+            let diff = (level as i16 - prev as i16).abs();
+            assert!(diff > 0 && diff <= 25, "{} -> {}", prev, level);
+            prev = level;
+        }
+    }
+
+    #[test]
+    fn breathe_periodic() {
+        let mut breathe = Breathe::new();
+
+        let first: Vec<u8> = (0..BREATHE_STEPS).map(|_| breathe.advance()).collect();
+        let second: Vec<u8> = (0..BREATHE_STEPS).map(|_| breathe.advance()).collect();
+        assert_eq!(first, second);
+        assert_eq!(breathe.level(), 0);
+        assert_eq!(first[BREATHE_STEPS - 1], 0);
+    }
+}
@@ -0,0 +1,237 @@
+//! Module for reading the user button.
+
+use core::convert::Infallible;
+use hal::prelude::_embedded_hal_digital_v2_InputPin as InputPin;
+
+use crate::command::Command;
+use crate::led_ring::Mode;
+
+/// The time (in ms) after an edge of a button at which its state is read, by which time a
+/// bouncing contact has settled.
+pub const DEBOUNCE_MS: u32 = 20;
+
+/// The action performed when the user button is pressed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ButtonAction {
+    /// Reverse the direction of the current mode.
+    Direction,
+    /// Switch to the next mode.
+    Mode,
+}
+
+impl ButtonAction {
+    /// Returns the button action with the given name.
+    pub fn from_name(name: &[u8]) -> Option<ButtonAction> {
+        match name {
+            b"direction" => Some(ButtonAction::Direction),
+            b"mode" => Some(ButtonAction::Mode),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the button action (as used by the serial interface).
+    pub fn as_name(&self) -> &'static str {
+        match self {
+            ButtonAction::Direction => "direction",
+            ButtonAction::Mode => "mode",
+        }
+    }
+
+    /// Returns the command to give for the action.
+    ///
+    /// The command is relative to the state of the LED ring when it runs rather than when the
+    /// button is pressed, so that a press combined with commands given via the serial interface
+    /// at the same time acts on the mode those commands leave behind (see [`next_mode`]).
+    pub fn command(&self) -> Command {
+        match self {
+            ButtonAction::Direction => Command::Flip,
+            ButtonAction::Mode => Command::NextMode,
+        }
+    }
+}
+
+/// Returns the mode that follows the given mode when switching to the next mode.
+///
+/// The modes are switched in the order they are listed to the user, skipping off so that
+/// pressing the button never turns all LEDs off, and skipping accelerometer mode if the
+/// accelerometer is unavailable.
+pub fn next_mode(mode: Mode, accel_ok: bool) -> Mode {
+    let next = Mode::ALL[(mode.index() + 1) % Mode::ALL.len()];
+    match next {
+        Mode::Off => Mode::ALL[1],
+        Mode::Accelerometer if !accel_ok => next_mode(next, accel_ok),
+        _ => next,
+    }
+}
+
+/// Returns whether the button connected to the input pin is pressed (i.e. the pin is high).
+///
+/// The pin is read once, so while the contact is bouncing the state can be either; it is only
+/// reliable once the contact has settled, i.e. `DEBOUNCE_MS` after the last edge.
+pub fn is_pressed<PIN>(pin: &PIN) -> bool
+where
+    PIN: InputPin<Error = Infallible>,
+{
+    pin.is_high().unwrap()
+}
+
+/// Returns whether the state of a button read `DEBOUNCE_MS` after an edge is a new press,
+/// given whether the button was held at the previous read.
+///
+/// A bouncing contact triggers several edges in a row, and the settled state read after them
+/// can be the same as before (e.g. when the edges fit in one debounce window).  Only the
+/// transition from released to pressed counts as a press, so that a press is neither missed
+/// nor counted twice.
+pub fn is_new_press(was_held: bool, pressed: bool) -> bool {
+    pressed && !was_held
+}
+
+/// Returns the name of the state of a button (as used by the serial interface).
+pub fn state_name(pressed: bool) -> &'static str {
+    if pressed {
+        "pressed"
+    } else {
+        "released"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        is_new_press, is_pressed, next_mode, state_name, ButtonAction, Command, Infallible,
+        InputPin, Mode,
+    };
+    use std::cell::Cell;
+
+    /// Mock input pin that returns the states of a pattern in turn.
+    struct MockInputPin {
+        pattern: Vec<bool>,
+        index: Cell<usize>,
+    }
+
+    impl MockInputPin {
+        fn new(pattern: &[bool]) -> MockInputPin {
+            MockInputPin {
+                pattern: pattern.to_vec(),
+                index: Cell::new(0),
+            }
+        }
+    }
+
+    impl InputPin for MockInputPin {
+        type Error = Infallible;
+
+        fn is_high(&self) -> Result<bool, Self::Error> {
+            let index = self.index.get();
+            self.index.set(index + 1);
+            Ok(self.pattern[index % self.pattern.len()])
+        }
+
+        fn is_low(&self) -> Result<bool, Self::Error> {
+            self.is_high().map(|high| !high)
+        }
+    }
+
+    #[test]
+    fn button_pressed() {
+        assert!(is_pressed(&MockInputPin::new(&[true])));
+        assert!(!is_pressed(&MockInputPin::new(&[false])));
+
+        // The pin is read once, at the time of the call.
+        let pin = MockInputPin::new(&[true, false]);
+        assert!(is_pressed(&pin));
+        assert!(!is_pressed(&pin));
+        assert_eq!(pin.index.get(), 2);
+    }
+
+    #[test]
+    fn new_presses() {
+        assert!(is_new_press(false, true));
+        assert!(!is_new_press(true, true));
+        assert!(!is_new_press(false, false));
+        assert!(!is_new_press(true, false));
+    }
+
+    /// Returns the number of presses for the settled states read after a series of edges.
+    fn count_presses(states: &[bool]) -> usize {
+        let mut held = false;
+        states
+            .iter()
+            .filter(|pressed| {
+                let was_held = held;
+                held = **pressed;
+                is_new_press(was_held, **pressed)
+            })
+            .count()
+    }
+
+    #[test]
+    fn press_count_rapid_edges() {
+        // A clean press and release.
+        assert_eq!(count_presses(&[true, false]), 1);
+        // Bouncing over more than one debounce window: the settled state is read repeatedly.
+        assert_eq!(count_presses(&[true, true, true, false, false]), 1);
+        // Released for longer than the debounce window in between is a new press.
+        assert_eq!(count_presses(&[true, false, true, false]), 2);
+        // Rapid presses are all counted.
+        assert_eq!(count_presses(&[true, false, true, false, true, false]), 3);
+        // An edge that is missed (e.g. the release) does not result in a double press.
+        assert_eq!(count_presses(&[true, true, false]), 1);
+        // Releasing without having seen the press does not count.
+        assert_eq!(count_presses(&[false, false]), 0);
+    }
+
+    #[test]
+    fn button_action_names() {
+        for action in [ButtonAction::Direction, ButtonAction::Mode].iter() {
+            assert_eq!(
+                ButtonAction::from_name(action.as_name().as_bytes()),
+                Some(*action)
+            );
+        }
+        assert_eq!(ButtonAction::from_name(b"flip"), None);
+        assert_eq!(ButtonAction::from_name(b""), None);
+    }
+
+    #[test]
+    fn button_action_commands() {
+        // Neither command depends on the mode at the time of the press.
+        assert_eq!(ButtonAction::Direction.command(), Command::Flip);
+        assert_eq!(ButtonAction::Mode.command(), Command::NextMode);
+    }
+
+    #[test]
+    fn button_next_mode() {
+        // Switching modes goes through all modes in order, except off.
+        let mut mode = Mode::Off;
+        let mut modes = Vec::new();
+        for _ in 0..Mode::ALL.len() {
+            mode = next_mode(mode, true);
+            modes.push(mode);
+        }
+        assert_eq!(
+            modes,
+            [
+                Mode::Cycle,
+                Mode::Accelerometer,
+                Mode::Ramp,
+                Mode::Chase,
+                Mode::Breathe,
+                Mode::Sparkle,
+                Mode::Counter,
+                Mode::Cycle
+            ]
+        );
+
+        // Without accelerometer, accelerometer mode is skipped as well.
+        assert_eq!(next_mode(Mode::Cycle, false), Mode::Ramp);
+        assert_eq!(next_mode(Mode::Counter, false), Mode::Cycle);
+        assert_eq!(next_mode(Mode::Accelerometer, false), Mode::Ramp);
+    }
+
+    #[test]
+    fn state_names() {
+        assert_eq!(state_name(true), "pressed");
+        assert_eq!(state_name(false), "released");
+    }
+}
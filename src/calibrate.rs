@@ -0,0 +1,209 @@
+//! Module for calibrating the accelerometer.
+//!
+//! The calibration starts with a countdown, blinking all LEDs, that gives the user time to put
+//! the board down flat.  Then a number of samples is taken, whose average is the offset of the
+//! accelerometer, i.e. what it reads when the board is level.
+
+use crate::led_ring::Mode;
+
+/// The number of steps of the countdown (alternately all LEDs on and off).
+pub const COUNTDOWN_STEPS: u8 = 6;
+
+/// The number of samples that are averaged into the offset.
+pub const CALIBRATION_SAMPLES: u8 = 8;
+
+/// A step of the calibration.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CalibrationStep {
+    /// Count down, showing the given LED states.
+    Countdown([bool; 4]),
+    /// Take a sample, see [`Calibration::add_sample`].
+    Sample,
+    /// The calibration is done; use the given offset and restore the given mode and LED states.
+    Done {
+        offset: (i8, i8),
+        mode: Mode,
+        states: [bool; 4],
+    },
+}
+
+/// A calibration that counts down and then averages samples into the offset.
+///
+/// The mode and LED states of the LED ring from before the calibration are kept so that they
+/// can be restored afterwards.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Calibration {
+    /// The number of remaining steps of the countdown.
+    countdown: u8,
+    /// The number of samples taken.
+    samples: u8,
+    /// The sum of the X and Y acceleration of the samples taken.
+    sum: (i16, i16),
+    /// The mode to restore.
+    mode: Mode,
+    /// The LED states to restore.
+    states: [bool; 4],
+}
+
+impl Calibration {
+    /// Sets up a calibration that restores the given mode and LED states when done.
+    pub fn new(mode: Mode, states: [bool; 4]) -> Calibration {
+        Calibration {
+            countdown: COUNTDOWN_STEPS,
+            samples: 0,
+            sum: (0, 0),
+            mode,
+            states,
+        }
+    }
+
+    /// Returns whether the countdown is over, i.e. samples are being taken.
+    pub fn is_sampling(&self) -> bool {
+        self.countdown == 0
+    }
+
+    /// Restarts the countdown, forgetting the samples taken, but keeping the state to restore.
+    pub fn restart(&mut self) {
+        *self = Calibration::new(self.mode, self.states);
+    }
+
+    /// Adds a sample of the X and Y acceleration (as read without offset).
+    ///
+    /// Samples are ignored during the countdown and once enough samples have been taken.
+    pub fn add_sample(&mut self, acc_x: i8, acc_y: i8) {
+        if self.is_sampling() && self.samples < CALIBRATION_SAMPLES {
+            self.sum.0 += acc_x as i16;
+            self.sum.1 += acc_y as i16;
+            self.samples += 1;
+        }
+    }
+
+    /// Advances the calibration one step and returns what to do.
+    ///
+    /// The countdown starts with all LEDs on and ends with all LEDs off.  Then a sample is
+    /// requested until enough samples have been added, after which the offset and the state
+    /// to restore are returned (repeatedly).
+    pub fn advance(&mut self) -> CalibrationStep {
+        if self.countdown > 0 {
+            self.countdown -= 1;
+            return CalibrationStep::Countdown([self.countdown % 2 == 1; 4]);
+        }
+
+        if self.samples < CALIBRATION_SAMPLES {
+            return CalibrationStep::Sample;
+        }
+
+        let count = self.samples as i16;
+        CalibrationStep::Done {
+            offset: ((self.sum.0 / count) as i8, (self.sum.1 / count) as i8),
+            mode: self.mode,
+            states: self.states,
+        }
+    }
+}
+
+/// Returns the X and Y acceleration corrected by the offset.
+pub fn calibrated(acc_x: i8, acc_y: i8, offset: (i8, i8)) -> (i8, i8) {
+    (
+        acc_x.saturating_sub(offset.0),
+        acc_y.saturating_sub(offset.1),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        calibrated, Calibration, CalibrationStep, Mode, CALIBRATION_SAMPLES, COUNTDOWN_STEPS,
+    };
+
+    #[test]
+    fn calibration_progression() {
+        let states = [true, false, true, false];
+        let mut calibration = Calibration::new(Mode::Cycle, states);
+
+        // The countdown blinks all LEDs, ending with them off.
+        for step in 0..COUNTDOWN_STEPS {
+            assert!(!calibration.is_sampling());
+            let on = step % 2 == 0;
+            assert_eq!(calibration.advance(), CalibrationStep::Countdown([on; 4]));
+        }
+        assert!(calibration.is_sampling());
+
+        // Samples are requested until enough have been added.
+        for sample in 0..CALIBRATION_SAMPLES as i8 {
+            assert_eq!(calibration.advance(), CalibrationStep::Sample);
+            assert_eq!(calibration.advance(), CalibrationStep::Sample);
+            calibration.add_sample(3 + sample % 2, -5);
+        }
+
+        let done = CalibrationStep::Done {
+            offset: (3, -5),
+            mode: Mode::Cycle,
+            states,
+        };
+        assert_eq!(calibration.advance(), done);
+        assert_eq!(calibration.advance(), done);
+
+        // Further samples are ignored.
+        calibration.add_sample(100, 100);
+        assert_eq!(calibration.advance(), done);
+    }
+
+    #[test]
+    fn calibration_countdown_ignores_samples() {
+        let mut calibration = Calibration::new(Mode::Off, [false; 4]);
+
+        calibration.add_sample(100, 100);
+        for _ in 0..COUNTDOWN_STEPS {
+            calibration.advance();
+            calibration.add_sample(100, 100);
+        }
+        for _ in 1..CALIBRATION_SAMPLES {
+            calibration.add_sample(-2, 1);
+        }
+        assert_eq!(
+            calibration.advance(),
+            CalibrationStep::Done {
+                offset: (10, 13),
+                mode: Mode::Off,
+                states: [false; 4]
+            }
+        );
+    }
+
+    #[test]
+    fn calibration_restart() {
+        let mut calibration = Calibration::new(Mode::Accelerometer, [false; 4]);
+
+        for _ in 0..COUNTDOWN_STEPS {
+            calibration.advance();
+        }
+        calibration.add_sample(10, 10);
+        calibration.restart();
+        assert!(!calibration.is_sampling());
+
+        let countdown = core::iter::repeat_with(|| calibration.advance())
+            .take_while(|step| *step != CalibrationStep::Sample)
+            .count();
+        assert_eq!(countdown, COUNTDOWN_STEPS as usize);
+        for _ in 0..CALIBRATION_SAMPLES {
+            calibration.add_sample(1, 2);
+        }
+        assert_eq!(
+            calibration.advance(),
+            CalibrationStep::Done {
+                offset: (1, 2),
+                mode: Mode::Accelerometer,
+                states: [false; 4]
+            }
+        );
+    }
+
+    #[test]
+    fn calibrated_readings() {
+        assert_eq!(calibrated(10, -10, (0, 0)), (10, -10));
+        assert_eq!(calibrated(10, -10, (3, -5)), (7, -5));
+        // The corrected readings saturate.
+        assert_eq!(calibrated(-128, 127, (10, -10)), (-128, 127));
+    }
+}
@@ -0,0 +1,194 @@
+//! Module for chasing two independent dots around the LED ring.
+
+use crate::led_ring::Direction;
+
+/// The default number of ticks between the steps of each of the two dots.
+pub const DEFAULT_RATES: [u8; 2] = [2, 3];
+
+/// The number of LEDs in the ring the dots move around.
+const NUM_LEDS: usize = 4;
+
+/// Two dots chasing each other around the LED ring.
+///
+/// Each dot moves one position after its own number of ticks (its rate), so that the dots
+/// move at different speeds and create a beat pattern.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Chase {
+    /// The positions (LED indices) of the dots.
+    positions: [usize; 2],
+    /// The number of ticks between steps of each dot.
+    rates: [u8; 2],
+    /// The number of ticks since the last step of each dot.
+    ticks: [u8; 2],
+}
+
+impl Chase {
+    /// Sets up a chase with the dots at opposite positions moving at the given rates.
+    ///
+    /// A rate of zero is treated as one.
+    pub fn new(rates: [u8; 2]) -> Chase {
+        Chase {
+            positions: [0, NUM_LEDS / 2],
+            rates: [rates[0].max(1), rates[1].max(1)],
+            ticks: [0, 0],
+        }
+    }
+
+    /// Returns the positions (LED indices) of the dots.
+    pub fn positions(&self) -> [usize; 2] {
+        self.positions
+    }
+
+    /// Returns the number of ticks between steps of each dot.
+    pub fn rates(&self) -> [u8; 2] {
+        self.rates
+    }
+
+    /// Sets the number of ticks between steps of each dot.
+    ///
+    /// A rate of zero is treated as one.
+    pub fn set_rates(&mut self, rates: [u8; 2]) {
+        self.rates = [rates[0].max(1), rates[1].max(1)];
+        self.ticks = [0, 0];
+    }
+
+    /// Returns which LEDs are lit by the dots.
+    ///
+    /// If the dots overlap, the LED at their position stays on.
+    pub fn pattern(&self) -> [bool; NUM_LEDS] {
+        let mut pattern = [false; NUM_LEDS];
+        for position in self.positions.iter() {
+            pattern[*position] = true;
+        }
+
+        pattern
+    }
+
+    /// Advances the chase one tick and returns which LEDs are lit by the dots.
+    ///
+    /// Each dot whose number of ticks has been reached moves one position in the given
+    /// direction.
+    pub fn advance(&mut self, direction: Direction) -> [bool; NUM_LEDS] {
+        for i in 0..self.positions.len() {
+            self.ticks[i] += 1;
+            if self.ticks[i] >= self.rates[i] {
+                self.ticks[i] = 0;
+                self.positions[i] = match direction {
+                    Direction::Clockwise => (self.positions[i] + 1) % NUM_LEDS,
+                    Direction::CounterClockwise => (self.positions[i] + NUM_LEDS - 1) % NUM_LEDS,
+                };
+            }
+        }
+
+        self.pattern()
+    }
+}
+
+impl Default for Chase {
+    fn default() -> Chase {
+        Chase::new(DEFAULT_RATES)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Chase, Direction, DEFAULT_RATES};
+
+    #[test]
+    fn chase_init() {
+        let chase = Chase::default();
+
+        assert_eq!(chase.positions(), [0, 2]);
+        assert_eq!(chase.rates(), DEFAULT_RATES);
+        assert_eq!(chase.pattern(), [true, false, true, false]);
+
+        let chase = Chase::new([0, 5]);
+        assert_eq!(chase.rates(), [1, 5]);
+    }
+
+    #[test]
+    fn chase_advance() {
+        let mut chase = Chase::new([1, 2]);
+
+        assert_eq!(
+            chase.advance(Direction::Clockwise),
+            [false, true, true, false]
+        );
+        assert_eq!(chase.positions(), [1, 2]);
+        assert_eq!(
+            chase.advance(Direction::Clockwise),
+            [false, false, true, true]
+        );
+        assert_eq!(chase.positions(), [2, 3]);
+        assert_eq!(
+            chase.advance(Direction::Clockwise),
+            [false, false, false, true]
+        );
+        assert_eq!(chase.positions(), [3, 3]);
+        assert_eq!(
+            chase.advance(Direction::Clockwise),
+            [true, false, false, false]
+        );
+        assert_eq!(chase.positions(), [0, 0]);
+        assert_eq!(
+            chase.advance(Direction::Clockwise),
+            [true, true, false, false]
+        );
+        assert_eq!(chase.positions(), [1, 0]);
+    }
+
+    #[test]
+    fn chase_advance_counter_clockwise() {
+        let mut chase = Chase::new([2, 1]);
+
+        assert_eq!(
+            chase.advance(Direction::CounterClockwise),
+            [true, true, false, false]
+        );
+        assert_eq!(chase.positions(), [0, 1]);
+        assert_eq!(
+            chase.advance(Direction::CounterClockwise),
+            [true, false, false, true]
+        );
+        assert_eq!(chase.positions(), [3, 0]);
+        assert_eq!(
+            chase.advance(Direction::CounterClockwise),
+            [false, false, false, true]
+        );
+        assert_eq!(chase.positions(), [3, 3]);
+    }
+
+    #[test]
+    fn chase_overlap() {
+        let mut chase = Chase::new([4, 1]);
+
+        // The second dot catches up with the first one after two ticks and they overlap;
+        // the LED stays on until the second dot moves on.
+        chase.advance(Direction::Clockwise);
+        chase.advance(Direction::Clockwise);
+        assert_eq!(chase.positions(), [0, 0]);
+        assert_eq!(chase.pattern(), [true, false, false, false]);
+        assert_eq!(
+            chase.advance(Direction::Clockwise),
+            [true, true, false, false]
+        );
+        assert_eq!(
+            chase.advance(Direction::Clockwise),
+            [false, true, true, false]
+        );
+        assert_eq!(chase.positions(), [1, 2]);
+    }
+
+    #[test]
+    fn chase_set_rates() {
+        let mut chase = Chase::new([3, 3]);
+
+        chase.advance(Direction::Clockwise);
+        chase.set_rates([1, 0]);
+        assert_eq!(chase.rates(), [1, 1]);
+        assert_eq!(
+            chase.advance(Direction::Clockwise),
+            [false, true, false, true]
+        );
+    }
+}
@@ -0,0 +1,69 @@
+//! Module for reporting the clock configuration of the demo application.
+
+use core::fmt::{self, Write};
+
+/// Writes the frequency (in hertz) in the largest unit that represents it exactly, e.g.
+/// `16 MHz`, `32 kHz` or `100 Hz`.
+pub fn write_frequency<W: Write>(writer: &mut W, hz: u32) -> fmt::Result {
+    match hz {
+        0 => write!(writer, "0 Hz"),
+        _ if hz % 1_000_000 == 0 => write!(writer, "{} MHz", hz / 1_000_000),
+        _ if hz % 1_000 == 0 => write!(writer, "{} kHz", hz / 1_000),
+        _ => write!(writer, "{} Hz", hz),
+    }
+}
+
+/// Writes the named clocks and their frequencies (in hertz) on a single line, separated by
+/// commas (without line terminator).
+pub fn write_clocks<W: Write>(writer: &mut W, clocks: &[(&str, u32)]) -> fmt::Result {
+    for (index, (name, hz)) in clocks.iter().enumerate() {
+        if index > 0 {
+            writer.write_str(", ")?;
+        }
+        write!(writer, "{} ", name)?;
+        write_frequency(writer, *hz)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_clocks, write_frequency};
+
+    #[test]
+    fn clocks_frequency() {
+        let cases = [
+            (16_000_000, "16 MHz"),
+            (168_000_000, "168 MHz"),
+            (8_400_000, "8400 kHz"),
+            (32_000, "32 kHz"),
+            (32_768, "32768 Hz"),
+            (0, "0 Hz"),
+        ];
+        for (hz, expected) in cases.iter() {
+            let mut text = String::new();
+            write_frequency(&mut text, *hz).unwrap();
+            assert_eq!(text, *expected);
+        }
+    }
+
+    #[test]
+    fn clocks_format() {
+        let mut line = String::new();
+        write_clocks(
+            &mut line,
+            &[
+                ("sysclk", 16_000_000),
+                ("hclk", 16_000_000),
+                ("pclk1", 8_000_000),
+            ],
+        )
+        .unwrap();
+        assert_eq!(line, "sysclk 16 MHz, hclk 16 MHz, pclk1 8 MHz");
+
+        let mut line = String::new();
+        write_clocks(&mut line, &[]).unwrap();
+        assert_eq!(line, "");
+    }
+}
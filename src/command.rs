@@ -0,0 +1,1743 @@
+//! Module for parsing commands received via the serial interface.
+
+use core::str::{self, FromStr};
+
+use heapless::{consts::U4, Vec};
+
+use crate::accel::AxisFlip;
+use crate::button::ButtonAction;
+use crate::curve::BrightnessCurve;
+use crate::led_ring::{CycleStyle, Direction, Mode, MAX_COMET_TRAIL};
+use crate::sequence::Sequence;
+use crate::serial::Framing;
+use crate::settings::SettingPairs;
+
+/// The maximum number of commands on a line.
+pub const MAX_LINE_COMMANDS: usize = 4;
+
+/// The commands given on a line.
+pub type LineCommands = Vec<Command, U4>;
+
+/// The maximum length of the command prefix.
+pub const MAX_PREFIX_LEN: usize = 8;
+
+/// The prefix that a line has to start with for its commands to be run, to tell them apart
+/// from other traffic when the serial interface is shared (e.g. `led:` for `led:on`).
+///
+/// The empty prefix accepts all lines.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CommandPrefix {
+    /// The bytes of the prefix (only the first `len` are used).
+    bytes: [u8; MAX_PREFIX_LEN],
+    /// The length of the prefix.
+    len: usize,
+}
+
+impl CommandPrefix {
+    /// The empty prefix.
+    pub const NONE: CommandPrefix = CommandPrefix {
+        bytes: [0; MAX_PREFIX_LEN],
+        len: 0,
+    };
+
+    /// Returns the prefix with the given bytes, or `None` if it is longer than
+    /// [`MAX_PREFIX_LEN`] or contains anything else than printable ASCII characters (other than
+    /// the command separator `;`).
+    pub fn from_bytes(bytes: &[u8]) -> Option<CommandPrefix> {
+        let valid = |byte: &u8| byte.is_ascii_graphic() && *byte != b';';
+        if bytes.len() > MAX_PREFIX_LEN || !bytes.iter().all(valid) {
+            return None;
+        }
+
+        let mut prefix = CommandPrefix::NONE;
+        prefix.bytes[..bytes.len()].copy_from_slice(bytes);
+        prefix.len = bytes.len();
+        Some(prefix)
+    }
+
+    /// Returns the prefix as text.
+    pub fn as_str(&self) -> &str {
+        str::from_utf8(&self.bytes[..self.len]).unwrap_or("")
+    }
+
+    /// Returns whether the prefix is empty, i.e. all lines are accepted.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the rest of the line after the prefix, or `None` if the line does not start with
+    /// the prefix (and is meant for someone else).
+    pub fn strip<'a>(&self, line: &'a [u8]) -> Option<&'a [u8]> {
+        line.strip_prefix(&self.bytes[..self.len])
+    }
+}
+
+/// A command given via the serial interface.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Command {
+    /// Switch to accelerometer mode.
+    Accel,
+    /// Report whether accelerometer mode shows the tilt by brightness, or enable/disable it.
+    AccelAnalog(Option<bool>),
+    /// Report the direction of the tilt as a single character.
+    Arrow,
+    /// Blink all LEDs the given number of times and then return to the current mode.
+    Ack(u8),
+    /// Report the period (in milliseconds) between accelerometer samples, or set it.
+    AccelPeriod(Option<u16>),
+    /// Report whether the accelerometer is polled, or enable/disable polling.
+    AccelPoll(Option<bool>),
+    /// Report whether the accelerometer range is selected automatically (and the current
+    /// range), or enable/disable it.
+    AutoRange(Option<bool>),
+    /// Switch to breathe mode.
+    Breathe,
+    /// Report the minimum brightness of the LEDs that are on, or set it.
+    BrightnessFloor(Option<u8>),
+    /// Report the action performed when the button is pressed, or set it.
+    ButtonMode(Option<ButtonAction>),
+    /// Report whether holding the button reverses the cycle direction, or enable/disable it.
+    ButtonHold(Option<bool>),
+    /// Report whether the user button is enabled, or enable/disable it.
+    Button(Option<bool>),
+    /// Report the state of the buttons.
+    Buttons,
+    /// Turn all LEDs off, without changing the mode.
+    Clear,
+    /// Report the clock configuration (frequencies of the system and bus clocks).
+    Clocks,
+    /// Report the length of the fading trail behind the head in cycle mode, or set it.
+    Comet(Option<u8>),
+    /// Switch to counter mode.
+    Count,
+    /// Report the brightness curve, or select it.
+    Curve(Option<BrightnessCurve>),
+    /// Calibrate the accelerometer after a countdown, taking the average reading as offset.
+    Calibrate,
+    /// Reset the offset of the accelerometer (as if not calibrated).
+    CalibrateReset,
+    /// Switch to chase mode, optionally changing the rates (in ticks per step) of the dots.
+    Chase(Option<[u8; 2]>),
+    /// Switch to cycle mode.
+    Cycle,
+    /// Switch to cycle mode, continuing from the LED with the given index.
+    CycleFrom(usize),
+    /// Report whether the leading LED in cycle mode is emphasized, or enable/disable it.
+    CycleLead(Option<bool>),
+    /// Report the cycle style, or set it.
+    CycleStyle(Option<CycleStyle>),
+    /// Run the self-diagnostic and report a summary of the checks.
+    Diag,
+    /// Report the cycle direction of the current mode, or set it.
+    Direction(Option<Direction>),
+    /// Report whether debug echo is enabled, or enable/disable it.
+    DebugEcho(Option<bool>),
+    /// Report whether accelerometer mode shows the downhill direction, or enable/disable it.
+    Downhill(Option<bool>),
+    /// Restore the default settings (only when confirmed).
+    Factory,
+    /// Report the features the application is built with.
+    Features,
+    /// Report whether the accelerometer FIFO is used, or enable/disable it.
+    Fifo(Option<bool>),
+    /// Report whether software flow control (XON/XOFF) is used, or enable/disable it.
+    FlowControl(Option<bool>),
+    /// Report the framing of the received commands, or select it.
+    Framing(Option<Framing>),
+    /// Reverse the cycle direction.
+    Flip,
+    /// Report which accelerometer axes are flipped, or set them.
+    FlipAccel(Option<AxisFlip>),
+    /// Report all settings as `key=value` pairs.
+    GetAll,
+    /// Report the heartbeat timeout (in milliseconds) after which host-controlled LEDs fall back
+    /// to a safe display, or set it.
+    Heartbeat(Option<u16>),
+    /// Report whether the interface is interactive (echo and prompt), or enable/disable it.
+    Interactive(Option<bool>),
+    /// Report whether the LED states are checked, or enable/disable checking them.
+    LedCheck(Option<bool>),
+    /// Report the sequence of commands (macro), or set it.
+    Macro(Option<Sequence>),
+    /// Run the sequence of commands once or repeatedly.
+    MacroRun(bool),
+    /// Stop running the sequence of commands.
+    MacroStop,
+    /// Draw the layout of the LED ring with the LEDs that are on.
+    Map,
+    /// Start party mode, rotating through several effects.
+    Party,
+    /// Stop party mode, restoring the mode from before.
+    PartyStop,
+    /// Report whether accelerometer mode shows the tilt as a meter, or enable/disable it.
+    Meter(Option<bool>),
+    /// Report the current mode, or switch to the given mode.
+    Mode(Option<Mode>),
+    /// Switch to the mode after the current one (like the user button does).
+    NextMode,
+    /// Report the acceleration (in mg) that wakes the board up while sleeping, or set it.
+    MotionWake(Option<u16>),
+    /// Report the prefix that lines have to start with, or set it (empty to accept all lines).
+    Prefix(Option<CommandPrefix>),
+    /// Report whether night mode (half brightness) is enabled, or enable/disable it.
+    NightMode(Option<bool>),
+    /// Turn all LEDs off (and disable accelerometer/cycle mode).
+    Off,
+    /// Turn all LEDs on (and disable accelerometer/cycle mode).
+    On,
+    /// Reply with `pong` and the given sequence number.
+    Ping(u32),
+    /// Force the LED with the given index on or off, or let it follow the mode again (`None`).
+    Pin { index: u8, state: Option<bool> },
+    /// Report whether unsolicited output is suppressed, or enable/disable it.
+    Quiet(Option<bool>),
+    /// Report all bytes of the accelerometer transfer that reads the X and Y acceleration.
+    RawAccel,
+    /// Read the accelerometer register with the given address.
+    RegRead(u8),
+    /// Write the value to the accelerometer register with the given address.
+    RegWrite(u8, u8),
+    /// Report the minimum interval (in milliseconds) between asynchronous messages of the
+    /// same class, or set it.
+    RateLimit(Option<u16>),
+    /// Report the maximum length (in bytes) of a response to a command, or set it.
+    ResponseLimit(Option<u16>),
+    /// Reset the system (only when confirmed).
+    Reboot,
+    /// Report the cause of the last reset.
+    ResetCause,
+    /// Switch to ramp mode, optionally changing the step size and dwell (in cycles).
+    Ramp {
+        step: Option<u8>,
+        dwell: Option<u32>,
+    },
+    /// Go to sleep until the next command or button press.
+    Sleep,
+    /// Apply several settings at once (all or none of them).
+    SetAll(SettingPairs),
+    /// Report the seed of the random LEDs of sparkle mode, or reseed them.
+    Seed(Option<u32>),
+    /// Switch to sparkle mode.
+    Sparkle,
+    /// Advance the ring one full revolution and then return to the current mode.
+    Spin,
+    /// Report the stack usage (high-water mark).
+    Stack,
+    /// Freeze the LEDs in the current position.
+    Stop,
+    /// Report whether the X and Y axes of the accelerometer are swapped, or enable/disable it.
+    SwapAxes(Option<bool>),
+    /// Report whether the tilt controls the cycle speed, or enable/disable it.
+    TiltSpeed(Option<bool>),
+    /// Report the cycle counter (monotonic timer) and the cycles elapsed since its reset.
+    Timer,
+    /// Reset the cycles reported as elapsed by the timer.
+    TimerReset,
+    /// Report whether unsolicited output is timestamped, or enable/disable it.
+    Timestamps(Option<bool>),
+    /// Report whether switching modes is animated, or enable/disable it.
+    Transitions(Option<bool>),
+    /// Report the version and build information of the application.
+    Version,
+}
+
+/// The error that occurs when parsing a command fails.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ParseError {
+    /// There is no command (the line is empty or only contains whitespace).
+    Empty,
+    /// The command is not known.
+    Unknown,
+    /// An argument of the command is invalid or missing.
+    InvalidArgument,
+    /// The command has more arguments than expected.
+    TooManyArguments,
+    /// The line has more commands than [`MAX_LINE_COMMANDS`].
+    TooManyCommands,
+}
+
+impl Command {
+    /// Parses a command from a line (without line terminator).
+    ///
+    /// The command and its arguments are separated by one or more whitespace characters.
+    pub fn parse(line: &[u8]) -> Result<Command, ParseError> {
+        let mut words = line
+            .split(|byte| byte.is_ascii_whitespace())
+            .filter(|word| !word.is_empty());
+        let name = words.next().ok_or(ParseError::Empty)?;
+
+        let command = match name {
+            b"accel" => Command::Accel,
+            b"ack" => match parse_optional_nonzero(words.next())? {
+                Some(blinks) => Command::Ack(blinks),
+                None => return Err(ParseError::InvalidArgument),
+            },
+            b"accelanalog" => Command::AccelAnalog(parse_optional_on_off(words.next())?),
+            b"accelperiod" => Command::AccelPeriod(parse_optional_nonzero(words.next())?),
+            b"accelpoll" => Command::AccelPoll(parse_optional_on_off(words.next())?),
+            b"arrow" => Command::Arrow,
+            b"autorange" => Command::AutoRange(parse_optional_on_off(words.next())?),
+            b"breathe" => Command::Breathe,
+            b"brightnessfloor" => match words.next() {
+                Some(word) => Command::BrightnessFloor(Some(parse_arg(word)?)),
+                None => Command::BrightnessFloor(None),
+            },
+            b"buttonhold" => Command::ButtonHold(parse_optional_on_off(words.next())?),
+            b"buttonmode" => match words.next() {
+                Some(name) => {
+                    let action =
+                        ButtonAction::from_name(name).ok_or(ParseError::InvalidArgument)?;
+                    Command::ButtonMode(Some(action))
+                }
+                None => Command::ButtonMode(None),
+            },
+            b"button" => Command::Button(parse_optional_on_off(words.next())?),
+            b"buttons" => Command::Buttons,
+            b"calibrate" => match words.next() {
+                Some(b"reset") => Command::CalibrateReset,
+                Some(_) => return Err(ParseError::InvalidArgument),
+                None => Command::Calibrate,
+            },
+            b"chase" => {
+                let rate1 = parse_optional_nonzero(words.next())?;
+                let rate2 = parse_optional_nonzero(words.next())?;
+                match (rate1, rate2) {
+                    (Some(rate1), Some(rate2)) => Command::Chase(Some([rate1, rate2])),
+                    (None, None) => Command::Chase(None),
+                    _ => return Err(ParseError::InvalidArgument),
+                }
+            }
+            b"clear" => Command::Clear,
+            b"clocks" => Command::Clocks,
+            b"comet" => match words.next() {
+                Some(word) => {
+                    let length = parse_arg(word)?;
+                    if length > MAX_COMET_TRAIL {
+                        return Err(ParseError::InvalidArgument);
+                    }
+                    Command::Comet(Some(length))
+                }
+                None => Command::Comet(None),
+            },
+            b"count" => Command::Count,
+            b"curve" => match words.next() {
+                Some(name) => {
+                    let curve =
+                        BrightnessCurve::from_name(name).ok_or(ParseError::InvalidArgument)?;
+                    Command::Curve(Some(curve))
+                }
+                None => Command::Curve(None),
+            },
+            b"cycle" => match words.next() {
+                Some(b"from") => {
+                    let index = words.next().ok_or(ParseError::InvalidArgument)?;
+                    let index = parse_arg(index)?;
+                    if index >= 4 {
+                        return Err(ParseError::InvalidArgument);
+                    }
+                    Command::CycleFrom(index)
+                }
+                Some(_) => return Err(ParseError::TooManyArguments),
+                None => Command::Cycle,
+            },
+            b"cyclelead" => Command::CycleLead(parse_optional_on_off(words.next())?),
+            b"cyclestyle" => match words.next() {
+                Some(name) => {
+                    let style = CycleStyle::from_name(name).ok_or(ParseError::InvalidArgument)?;
+                    Command::CycleStyle(Some(style))
+                }
+                None => Command::CycleStyle(None),
+            },
+            b"diag" => Command::Diag,
+            b"direction" => match words.next() {
+                Some(name) => {
+                    let direction =
+                        Direction::from_name(name).ok_or(ParseError::InvalidArgument)?;
+                    Command::Direction(Some(direction))
+                }
+                None => Command::Direction(None),
+            },
+            b"debugecho" => Command::DebugEcho(parse_optional_on_off(words.next())?),
+            b"ledcheck" => Command::LedCheck(parse_optional_on_off(words.next())?),
+            b"downhill" => Command::Downhill(parse_optional_on_off(words.next())?),
+            b"factory" => match words.next() {
+                Some(b"confirm") => Command::Factory,
+                _ => return Err(ParseError::InvalidArgument),
+            },
+            b"features" => Command::Features,
+            b"fifo" => Command::Fifo(parse_optional_on_off(words.next())?),
+            b"flowcontrol" => Command::FlowControl(parse_optional_on_off(words.next())?),
+            b"framing" => match words.next() {
+                Some(name) => {
+                    let framing = Framing::from_name(name).ok_or(ParseError::InvalidArgument)?;
+                    Command::Framing(Some(framing))
+                }
+                None => Command::Framing(None),
+            },
+            b"flip" => Command::Flip,
+            b"flipaccel" => match words.next() {
+                Some(name) => {
+                    let flip = AxisFlip::from_name(name).ok_or(ParseError::InvalidArgument)?;
+                    Command::FlipAccel(Some(flip))
+                }
+                None => Command::FlipAccel(None),
+            },
+            b"getall" => Command::GetAll,
+            b"heartbeat" => match words.next() {
+                Some(word) => Command::Heartbeat(Some(parse_arg(word)?)),
+                None => Command::Heartbeat(None),
+            },
+            b"interactive" => Command::Interactive(parse_optional_on_off(words.next())?),
+            b"map" => Command::Map,
+            b"party" => match words.next() {
+                Some(b"stop") => Command::PartyStop,
+                Some(_) => return Err(ParseError::InvalidArgument),
+                None => Command::Party,
+            },
+            b"macro" => match words.next() {
+                Some(b"set") => {
+                    // The steps are the rest of the line, which is not split into words.
+                    let first = words.next().ok_or(ParseError::InvalidArgument)?;
+                    let offset = first.as_ptr() as usize - line.as_ptr() as usize;
+                    return Sequence::parse(&line[offset..]).map(|seq| Command::Macro(Some(seq)));
+                }
+                Some(b"run") => Command::MacroRun(false),
+                Some(b"loop") => Command::MacroRun(true),
+                Some(b"stop") => Command::MacroStop,
+                Some(_) => return Err(ParseError::InvalidArgument),
+                None => Command::Macro(None),
+            },
+            b"meter" => Command::Meter(parse_optional_on_off(words.next())?),
+            b"mode" => match words.next() {
+                Some(b"next") => Command::NextMode,
+                Some(name) => {
+                    let mode = Mode::from_name(name).map_err(|_| ParseError::InvalidArgument)?;
+                    Command::Mode(Some(mode))
+                }
+                None => Command::Mode(None),
+            },
+            b"motionwake" => match words.next() {
+                Some(word) => Command::MotionWake(Some(parse_arg(word)?)),
+                None => Command::MotionWake(None),
+            },
+            b"prefix" => match words.next() {
+                Some(b"off") => Command::Prefix(Some(CommandPrefix::NONE)),
+                Some(word) => {
+                    let prefix =
+                        CommandPrefix::from_bytes(word).ok_or(ParseError::InvalidArgument)?;
+                    Command::Prefix(Some(prefix))
+                }
+                None => Command::Prefix(None),
+            },
+            b"nightmode" => Command::NightMode(parse_optional_on_off(words.next())?),
+            b"off" => Command::Off,
+            b"on" => Command::On,
+            b"pin" => {
+                let index = words.next().ok_or(ParseError::InvalidArgument)?;
+                let index = parse_arg(index)?;
+                if index >= 4 || words.next() != Some(b"force") {
+                    return Err(ParseError::InvalidArgument);
+                }
+                let state = match words.next() {
+                    Some(b"on") => Some(true),
+                    Some(b"off") => Some(false),
+                    Some(b"auto") => None,
+                    _ => return Err(ParseError::InvalidArgument),
+                };
+                Command::Pin { index, state }
+            }
+            b"ping" => match words.next() {
+                Some(word) => Command::Ping(parse_arg(word)?),
+                None => Command::Ping(0),
+            },
+            b"ramp" => Command::Ramp {
+                step: parse_optional_nonzero(words.next())?,
+                dwell: parse_optional_nonzero(words.next())?,
+            },
+            b"quiet" => Command::Quiet(parse_optional_on_off(words.next())?),
+            b"ratelimit" => match words.next() {
+                Some(word) => Command::RateLimit(Some(parse_arg(word)?)),
+                None => Command::RateLimit(None),
+            },
+            b"responselimit" => match words.next() {
+                Some(word) => Command::ResponseLimit(Some(parse_arg(word)?)),
+                None => Command::ResponseLimit(None),
+            },
+            b"rawaccel" => Command::RawAccel,
+            b"reboot" => match words.next() {
+                Some(b"confirm") => Command::Reboot,
+                _ => return Err(ParseError::InvalidArgument),
+            },
+            b"resetcause" => Command::ResetCause,
+            b"reg" => match words.next() {
+                Some(b"read") => Command::RegRead(parse_byte(words.next())?),
+                Some(b"write") => {
+                    Command::RegWrite(parse_byte(words.next())?, parse_byte(words.next())?)
+                }
+                _ => return Err(ParseError::InvalidArgument),
+            },
+            b"setall" => {
+                // The pairs are the rest of the line, which is not split into words.
+                let first = words.next().ok_or(ParseError::InvalidArgument)?;
+                let offset = first.as_ptr() as usize - line.as_ptr() as usize;
+                return SettingPairs::parse(&line[offset..]).map(Command::SetAll);
+            }
+            b"seed" => Command::Seed(parse_optional_nonzero(words.next())?),
+            b"sleep" => Command::Sleep,
+            b"sparkle" => Command::Sparkle,
+            b"spin" => Command::Spin,
+            b"stack" => Command::Stack,
+            b"stop" => Command::Stop,
+            b"swapaxes" => Command::SwapAxes(parse_optional_on_off(words.next())?),
+            b"tiltspeed" => Command::TiltSpeed(parse_optional_on_off(words.next())?),
+            b"timer" => match words.next() {
+                Some(b"reset") => Command::TimerReset,
+                Some(_) => return Err(ParseError::InvalidArgument),
+                None => Command::Timer,
+            },
+            b"timestamps" => Command::Timestamps(parse_optional_on_off(words.next())?),
+            b"transitions" => Command::Transitions(parse_optional_on_off(words.next())?),
+            b"version" => Command::Version,
+            _ => return Err(ParseError::Unknown),
+        };
+
+        if words.next().is_some() {
+            return Err(ParseError::TooManyArguments);
+        }
+
+        Ok(command)
+    }
+
+    /// Returns whether the command needs the accelerometer, i.e. cannot be run if it is
+    /// unavailable.
+    ///
+    /// Reporting settings and disabling features that use the accelerometer does not need it.
+    pub fn needs_accel(&self) -> bool {
+        match self {
+            Command::SetAll(pairs) => return pairs.commands().any(|command| command.needs_accel()),
+            Command::MotionWake(Some(threshold_mg)) => return *threshold_mg > 0,
+            _ => {}
+        }
+
+        matches!(
+            self,
+            Command::Accel
+                | Command::Arrow
+                | Command::AutoRange(Some(_))
+                | Command::AccelPoll(Some(true))
+                | Command::Calibrate
+                | Command::Fifo(Some(_))
+                | Command::Mode(Some(Mode::Accelerometer))
+                | Command::RawAccel
+                | Command::RegRead(_)
+                | Command::RegWrite(_, _)
+                | Command::TiltSpeed(Some(true))
+        )
+    }
+
+    /// Parses the commands on a line (without line terminator), which are separated by `;`.
+    ///
+    /// Empty commands (e.g. due to a trailing separator) are skipped.  A `macro set` command
+    /// takes the rest of the line as its steps, so it is always the last command.  If any of the
+    /// commands is invalid, the line is rejected as a whole, so that none of them is run.
+    pub fn parse_line(line: &[u8]) -> Result<LineCommands, ParseError> {
+        let mut commands = LineCommands::new();
+        let mut rest = Some(line);
+
+        while let Some(text) = rest {
+            let (segment, next) = match text.iter().position(|&byte| byte == b';') {
+                Some(index) if !is_macro_set(text) => (&text[..index], Some(&text[index + 1..])),
+                _ => (text, None),
+            };
+            rest = next;
+
+            match Command::parse(segment) {
+                Ok(command) => commands
+                    .push(command)
+                    .map_err(|_| ParseError::TooManyCommands)?,
+                Err(ParseError::Empty) => {}
+                Err(error) => return Err(error),
+            }
+        }
+
+        if commands.is_empty() {
+            return Err(ParseError::Empty);
+        }
+        Ok(commands)
+    }
+}
+
+/// Returns whether the text starts with a `macro set` command.
+fn is_macro_set(text: &[u8]) -> bool {
+    let mut words = text
+        .split(|byte| byte.is_ascii_whitespace())
+        .filter(|word| !word.is_empty());
+
+    words.next() == Some(b"macro") && words.next() == Some(b"set")
+}
+
+/// Returns the text without leading and trailing ASCII whitespace.
+pub fn trim(text: &[u8]) -> &[u8] {
+    let is_text = |byte: &u8| !byte.is_ascii_whitespace();
+    let start = text.iter().position(is_text).unwrap_or(text.len());
+    let end = text
+        .iter()
+        .rposition(is_text)
+        .map_or(start, |index| index + 1);
+
+    &text[start..end]
+}
+
+/// Parses an argument into a value.
+fn parse_arg<T: FromStr>(word: &[u8]) -> Result<T, ParseError> {
+    str::from_utf8(word)
+        .ok()
+        .and_then(|word| word.parse().ok())
+        .ok_or(ParseError::InvalidArgument)
+}
+
+/// Parses a required argument into a byte.
+///
+/// The byte can be given in decimal notation or in hexadecimal notation (prefixed by `0x`).
+fn parse_byte(word: Option<&[u8]>) -> Result<u8, ParseError> {
+    let word = word.ok_or(ParseError::InvalidArgument)?;
+    match word {
+        [b'0', b'x', digits @ ..] => str::from_utf8(digits)
+            .ok()
+            .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+            .ok_or(ParseError::InvalidArgument),
+        _ => parse_arg(word),
+    }
+}
+
+/// Parses an optional `on`/`off` argument into a boolean.
+fn parse_optional_on_off(word: Option<&[u8]>) -> Result<Option<bool>, ParseError> {
+    match word {
+        Some(b"on") => Ok(Some(true)),
+        Some(b"off") => Ok(Some(false)),
+        Some(_) => Err(ParseError::InvalidArgument),
+        None => Ok(None),
+    }
+}
+
+/// Parses an optional argument into a non-zero value.
+fn parse_optional_nonzero<T>(word: Option<&[u8]>) -> Result<Option<T>, ParseError>
+where
+    T: FromStr + Default + PartialEq,
+{
+    match word {
+        Some(word) => {
+            let value = parse_arg(word)?;
+            if value == T::default() {
+                return Err(ParseError::InvalidArgument);
+            }
+            Ok(Some(value))
+        }
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::trim;
+    use super::{
+        AxisFlip, BrightnessCurve, ButtonAction, Command, CommandPrefix, CycleStyle, Direction,
+        Framing, Mode, ParseError, Sequence, SettingPairs, MAX_LINE_COMMANDS, MAX_PREFIX_LEN,
+    };
+
+    #[test]
+    fn parse_simple() {
+        assert_eq!(Command::parse(b"accel"), Ok(Command::Accel));
+        assert_eq!(Command::parse(b"arrow"), Ok(Command::Arrow));
+        assert_eq!(Command::parse(b"breathe"), Ok(Command::Breathe));
+        assert_eq!(Command::parse(b"buttons"), Ok(Command::Buttons));
+        assert_eq!(Command::parse(b"button"), Ok(Command::Button(None)));
+        assert_eq!(
+            Command::parse(b"button off"),
+            Ok(Command::Button(Some(false)))
+        );
+        assert_eq!(
+            Command::parse(b"button on"),
+            Ok(Command::Button(Some(true)))
+        );
+        assert_eq!(
+            Command::parse(b"button maybe"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(Command::parse(b"stack"), Ok(Command::Stack));
+        assert_eq!(Command::parse(b"getall"), Ok(Command::GetAll));
+        assert_eq!(Command::parse(b"version"), Ok(Command::Version));
+        assert_eq!(Command::parse(b"clocks"), Ok(Command::Clocks));
+        assert_eq!(Command::parse(b"count"), Ok(Command::Count));
+        assert_eq!(Command::parse(b"clear"), Ok(Command::Clear));
+        assert_eq!(Command::parse(b"cycle"), Ok(Command::Cycle));
+        assert_eq!(Command::parse(b"diag"), Ok(Command::Diag));
+        assert_eq!(Command::parse(b"rawaccel"), Ok(Command::RawAccel));
+        assert_eq!(Command::parse(b"resetcause"), Ok(Command::ResetCause));
+        assert_eq!(Command::parse(b"features"), Ok(Command::Features));
+        assert_eq!(Command::parse(b"flip"), Ok(Command::Flip));
+        assert_eq!(Command::parse(b"map"), Ok(Command::Map));
+        assert_eq!(Command::parse(b"off"), Ok(Command::Off));
+        assert_eq!(Command::parse(b"on"), Ok(Command::On));
+        assert_eq!(Command::parse(b"sleep"), Ok(Command::Sleep));
+        assert_eq!(Command::parse(b"sparkle"), Ok(Command::Sparkle));
+        assert_eq!(Command::parse(b"spin"), Ok(Command::Spin));
+        assert_eq!(Command::parse(b"stop"), Ok(Command::Stop));
+        assert_eq!(Command::parse(b"  stop "), Ok(Command::Stop));
+        assert_eq!(Command::parse(b"\tstop\t"), Ok(Command::Stop));
+    }
+
+    #[test]
+    fn parse_empty() {
+        assert_eq!(Command::parse(b""), Err(ParseError::Empty));
+        assert_eq!(Command::parse(b" "), Err(ParseError::Empty));
+        assert_eq!(Command::parse(b"  \t  "), Err(ParseError::Empty));
+    }
+
+    #[test]
+    fn parse_unknown() {
+        assert_eq!(Command::parse(b"foo"), Err(ParseError::Unknown));
+        assert_eq!(Command::parse(b"Cycle"), Err(ParseError::Unknown));
+        assert_eq!(
+            Command::parse(b"stop now"),
+            Err(ParseError::TooManyArguments)
+        );
+    }
+
+    #[test]
+    fn parse_accel_period() {
+        assert_eq!(
+            Command::parse(b"accelperiod"),
+            Ok(Command::AccelPeriod(None))
+        );
+        assert_eq!(
+            Command::parse(b"accelperiod 50"),
+            Ok(Command::AccelPeriod(Some(50)))
+        );
+        assert_eq!(
+            Command::parse(b"accelperiod 0"),
+            Err(ParseError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn parse_accel_poll() {
+        assert_eq!(Command::parse(b"accelpoll"), Ok(Command::AccelPoll(None)));
+        assert_eq!(
+            Command::parse(b"accelpoll on"),
+            Ok(Command::AccelPoll(Some(true)))
+        );
+        assert_eq!(
+            Command::parse(b"accelpoll off"),
+            Ok(Command::AccelPoll(Some(false)))
+        );
+        assert_eq!(
+            Command::parse(b"accelpoll 1"),
+            Err(ParseError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn parse_chase() {
+        assert_eq!(Command::parse(b"chase"), Ok(Command::Chase(None)));
+        assert_eq!(
+            Command::parse(b"chase 2 3"),
+            Ok(Command::Chase(Some([2, 3])))
+        );
+        assert_eq!(Command::parse(b"chase 2"), Err(ParseError::InvalidArgument));
+        assert_eq!(
+            Command::parse(b"chase 0 3"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse(b"chase 2 3 4"),
+            Err(ParseError::TooManyArguments)
+        );
+    }
+
+    #[test]
+    fn parse_debug_echo() {
+        assert_eq!(Command::parse(b"debugecho"), Ok(Command::DebugEcho(None)));
+        assert_eq!(
+            Command::parse(b"debugecho on"),
+            Ok(Command::DebugEcho(Some(true)))
+        );
+        assert_eq!(
+            Command::parse(b"debugecho off"),
+            Ok(Command::DebugEcho(Some(false)))
+        );
+        assert_eq!(
+            Command::parse(b"debugecho yes"),
+            Err(ParseError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn parse_seed() {
+        assert_eq!(Command::parse(b"seed"), Ok(Command::Seed(None)));
+        assert_eq!(Command::parse(b"seed 42"), Ok(Command::Seed(Some(42))));
+        assert_eq!(
+            Command::parse(b"seed 4294967295"),
+            Ok(Command::Seed(Some(u32::MAX)))
+        );
+        assert_eq!(Command::parse(b"seed 0"), Err(ParseError::InvalidArgument));
+        assert_eq!(
+            Command::parse(b"seed 4294967296"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(Command::parse(b"seed -1"), Err(ParseError::InvalidArgument));
+        assert_eq!(Command::parse(b"seed x"), Err(ParseError::InvalidArgument));
+        assert_eq!(
+            Command::parse(b"seed 1 2"),
+            Err(ParseError::TooManyArguments)
+        );
+    }
+
+    #[test]
+    fn parse_led_check() {
+        assert_eq!(Command::parse(b"ledcheck"), Ok(Command::LedCheck(None)));
+        assert_eq!(
+            Command::parse(b"ledcheck on"),
+            Ok(Command::LedCheck(Some(true)))
+        );
+        assert_eq!(
+            Command::parse(b"ledcheck off"),
+            Ok(Command::LedCheck(Some(false)))
+        );
+        assert_eq!(
+            Command::parse(b"ledcheck 1"),
+            Err(ParseError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn parse_accel_analog() {
+        assert_eq!(
+            Command::parse(b"accelanalog"),
+            Ok(Command::AccelAnalog(None))
+        );
+        assert_eq!(
+            Command::parse(b"accelanalog on"),
+            Ok(Command::AccelAnalog(Some(true)))
+        );
+        assert_eq!(
+            Command::parse(b"accelanalog off extra"),
+            Err(ParseError::TooManyArguments)
+        );
+    }
+
+    #[test]
+    fn parse_ack() {
+        assert_eq!(Command::parse(b"ack 3"), Ok(Command::Ack(3)));
+        assert_eq!(Command::parse(b"ack 255"), Ok(Command::Ack(255)));
+        assert_eq!(Command::parse(b"ack"), Err(ParseError::InvalidArgument));
+        assert_eq!(Command::parse(b"ack 0"), Err(ParseError::InvalidArgument));
+        assert_eq!(Command::parse(b"ack 256"), Err(ParseError::InvalidArgument));
+        assert_eq!(
+            Command::parse(b"ack 1 2"),
+            Err(ParseError::TooManyArguments)
+        );
+    }
+
+    #[test]
+    fn parse_button_hold() {
+        assert_eq!(Command::parse(b"buttonhold"), Ok(Command::ButtonHold(None)));
+        assert_eq!(
+            Command::parse(b"buttonhold on"),
+            Ok(Command::ButtonHold(Some(true)))
+        );
+        assert_eq!(
+            Command::parse(b"buttonhold 1"),
+            Err(ParseError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn parse_button_mode() {
+        assert_eq!(Command::parse(b"buttonmode"), Ok(Command::ButtonMode(None)));
+        assert_eq!(
+            Command::parse(b"buttonmode direction"),
+            Ok(Command::ButtonMode(Some(ButtonAction::Direction)))
+        );
+        assert_eq!(
+            Command::parse(b"buttonmode mode"),
+            Ok(Command::ButtonMode(Some(ButtonAction::Mode)))
+        );
+        assert_eq!(
+            Command::parse(b"buttonmode flip"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse(b"buttonmode mode direction"),
+            Err(ParseError::TooManyArguments)
+        );
+    }
+
+    #[test]
+    fn parse_comet() {
+        assert_eq!(Command::parse(b"comet"), Ok(Command::Comet(None)));
+        assert_eq!(Command::parse(b"comet 0"), Ok(Command::Comet(Some(0))));
+        assert_eq!(Command::parse(b"comet 3"), Ok(Command::Comet(Some(3))));
+        assert_eq!(Command::parse(b"comet 4"), Err(ParseError::InvalidArgument));
+        assert_eq!(
+            Command::parse(b"comet tail"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse(b"comet 1 2"),
+            Err(ParseError::TooManyArguments)
+        );
+    }
+
+    #[test]
+    fn parse_framing() {
+        assert_eq!(Command::parse(b"framing"), Ok(Command::Framing(None)));
+        assert_eq!(
+            Command::parse(b"framing line"),
+            Ok(Command::Framing(Some(Framing::Line)))
+        );
+        assert_eq!(
+            Command::parse(b"framing packet"),
+            Ok(Command::Framing(Some(Framing::Packet)))
+        );
+        assert_eq!(
+            Command::parse(b"framing slip"),
+            Err(ParseError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn parse_curve() {
+        assert_eq!(Command::parse(b"curve"), Ok(Command::Curve(None)));
+        assert_eq!(
+            Command::parse(b"curve linear"),
+            Ok(Command::Curve(Some(BrightnessCurve::Linear)))
+        );
+        assert_eq!(
+            Command::parse(b"curve gamma"),
+            Ok(Command::Curve(Some(BrightnessCurve::Gamma)))
+        );
+        assert_eq!(
+            Command::parse(b"curve cubic"),
+            Err(ParseError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn parse_cycle_style() {
+        assert_eq!(Command::parse(b"cyclestyle"), Ok(Command::CycleStyle(None)));
+        assert_eq!(
+            Command::parse(b"cyclestyle single"),
+            Ok(Command::CycleStyle(Some(CycleStyle::Single)))
+        );
+        assert_eq!(
+            Command::parse(b"cyclestyle pair"),
+            Ok(Command::CycleStyle(Some(CycleStyle::Pair)))
+        );
+        assert_eq!(
+            Command::parse(b"cyclestyle snake"),
+            Err(ParseError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn parse_direction() {
+        assert_eq!(Command::parse(b"direction"), Ok(Command::Direction(None)));
+        assert_eq!(
+            Command::parse(b"direction cw"),
+            Ok(Command::Direction(Some(Direction::Clockwise)))
+        );
+        assert_eq!(
+            Command::parse(b"direction ccw"),
+            Ok(Command::Direction(Some(Direction::CounterClockwise)))
+        );
+        assert_eq!(
+            Command::parse(b"direction up"),
+            Err(ParseError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn parse_downhill() {
+        assert_eq!(Command::parse(b"downhill"), Ok(Command::Downhill(None)));
+        assert_eq!(
+            Command::parse(b"downhill on"),
+            Ok(Command::Downhill(Some(true)))
+        );
+        assert_eq!(
+            Command::parse(b"downhill sideways"),
+            Err(ParseError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn parse_flow_control() {
+        assert_eq!(
+            Command::parse(b"flowcontrol"),
+            Ok(Command::FlowControl(None))
+        );
+        assert_eq!(
+            Command::parse(b"flowcontrol on"),
+            Ok(Command::FlowControl(Some(true)))
+        );
+        assert_eq!(
+            Command::parse(b"flowcontrol off"),
+            Ok(Command::FlowControl(Some(false)))
+        );
+        assert_eq!(
+            Command::parse(b"flowcontrol xon"),
+            Err(ParseError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn parse_fifo() {
+        assert_eq!(Command::parse(b"fifo"), Ok(Command::Fifo(None)));
+        assert_eq!(Command::parse(b"fifo on"), Ok(Command::Fifo(Some(true))));
+        assert_eq!(Command::parse(b"fifo off"), Ok(Command::Fifo(Some(false))));
+        assert_eq!(Command::parse(b"fifo 1"), Err(ParseError::InvalidArgument));
+    }
+
+    #[test]
+    fn parse_cycle_lead() {
+        assert_eq!(Command::parse(b"cyclelead"), Ok(Command::CycleLead(None)));
+        assert_eq!(
+            Command::parse(b"cyclelead on"),
+            Ok(Command::CycleLead(Some(true)))
+        );
+        assert_eq!(
+            Command::parse(b"cyclelead off"),
+            Ok(Command::CycleLead(Some(false)))
+        );
+        assert_eq!(
+            Command::parse(b"cyclelead pair"),
+            Err(ParseError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn parse_auto_range() {
+        assert_eq!(Command::parse(b"autorange"), Ok(Command::AutoRange(None)));
+        assert_eq!(
+            Command::parse(b"autorange on"),
+            Ok(Command::AutoRange(Some(true)))
+        );
+        assert_eq!(
+            Command::parse(b"autorange off"),
+            Ok(Command::AutoRange(Some(false)))
+        );
+        assert_eq!(
+            Command::parse(b"autorange 4"),
+            Err(ParseError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn parse_flip_accel() {
+        assert_eq!(Command::parse(b"flipaccel"), Ok(Command::FlipAccel(None)));
+        assert_eq!(
+            Command::parse(b"flipaccel xy"),
+            Ok(Command::FlipAccel(Some(AxisFlip { x: true, y: true })))
+        );
+        assert_eq!(
+            Command::parse(b"flipaccel none"),
+            Ok(Command::FlipAccel(Some(AxisFlip::NONE)))
+        );
+        assert_eq!(
+            Command::parse(b"flipaccel z"),
+            Err(ParseError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn parse_interactive() {
+        assert_eq!(
+            Command::parse(b"interactive"),
+            Ok(Command::Interactive(None))
+        );
+        assert_eq!(
+            Command::parse(b"interactive off"),
+            Ok(Command::Interactive(Some(false)))
+        );
+        assert_eq!(
+            Command::parse(b"interactive yes"),
+            Err(ParseError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn parse_meter() {
+        assert_eq!(Command::parse(b"meter"), Ok(Command::Meter(None)));
+        assert_eq!(Command::parse(b"meter on"), Ok(Command::Meter(Some(true))));
+        assert_eq!(
+            Command::parse(b"meter off"),
+            Ok(Command::Meter(Some(false)))
+        );
+        assert_eq!(Command::parse(b"meter 3"), Err(ParseError::InvalidArgument));
+    }
+
+    #[test]
+    fn parse_quiet() {
+        assert_eq!(Command::parse(b"quiet"), Ok(Command::Quiet(None)));
+        assert_eq!(Command::parse(b"quiet on"), Ok(Command::Quiet(Some(true))));
+        assert_eq!(
+            Command::parse(b"quiet off"),
+            Ok(Command::Quiet(Some(false)))
+        );
+        assert_eq!(
+            Command::parse(b"quiet please"),
+            Err(ParseError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn parse_brightness_floor() {
+        assert_eq!(
+            Command::parse(b"brightnessfloor"),
+            Ok(Command::BrightnessFloor(None))
+        );
+        assert_eq!(
+            Command::parse(b"brightnessfloor 32"),
+            Ok(Command::BrightnessFloor(Some(32)))
+        );
+        assert_eq!(
+            Command::parse(b"brightnessfloor 0"),
+            Ok(Command::BrightnessFloor(Some(0)))
+        );
+        assert_eq!(
+            Command::parse(b"brightnessfloor 256"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse(b"brightnessfloor 1 2"),
+            Err(ParseError::TooManyArguments)
+        );
+    }
+
+    #[test]
+    fn parse_heartbeat() {
+        assert_eq!(Command::parse(b"heartbeat"), Ok(Command::Heartbeat(None)));
+        assert_eq!(
+            Command::parse(b"heartbeat 5000"),
+            Ok(Command::Heartbeat(Some(5000)))
+        );
+        assert_eq!(
+            Command::parse(b"heartbeat 0"),
+            Ok(Command::Heartbeat(Some(0)))
+        );
+        assert_eq!(
+            Command::parse(b"heartbeat 70000"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse(b"heartbeat 1 2"),
+            Err(ParseError::TooManyArguments)
+        );
+    }
+
+    #[test]
+    fn parse_motion_wake() {
+        assert_eq!(Command::parse(b"motionwake"), Ok(Command::MotionWake(None)));
+        assert_eq!(
+            Command::parse(b"motionwake 200"),
+            Ok(Command::MotionWake(Some(200)))
+        );
+        assert_eq!(
+            Command::parse(b"motionwake 0"),
+            Ok(Command::MotionWake(Some(0)))
+        );
+        assert_eq!(
+            Command::parse(b"motionwake off"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse(b"motionwake 1 2"),
+            Err(ParseError::TooManyArguments)
+        );
+    }
+
+    #[test]
+    fn parse_rate_limit() {
+        assert_eq!(Command::parse(b"ratelimit"), Ok(Command::RateLimit(None)));
+        assert_eq!(
+            Command::parse(b"ratelimit 250"),
+            Ok(Command::RateLimit(Some(250)))
+        );
+        assert_eq!(
+            Command::parse(b"ratelimit 0"),
+            Ok(Command::RateLimit(Some(0)))
+        );
+        assert_eq!(
+            Command::parse(b"ratelimit 70000"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse(b"ratelimit 1 2"),
+            Err(ParseError::TooManyArguments)
+        );
+    }
+
+    #[test]
+    fn parse_response_limit() {
+        assert_eq!(
+            Command::parse(b"responselimit"),
+            Ok(Command::ResponseLimit(None))
+        );
+        assert_eq!(
+            Command::parse(b"responselimit 128"),
+            Ok(Command::ResponseLimit(Some(128)))
+        );
+        assert_eq!(
+            Command::parse(b"responselimit -1"),
+            Err(ParseError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn parse_swap_axes() {
+        assert_eq!(Command::parse(b"swapaxes"), Ok(Command::SwapAxes(None)));
+        assert_eq!(
+            Command::parse(b"swapaxes off"),
+            Ok(Command::SwapAxes(Some(false)))
+        );
+        assert_eq!(
+            Command::parse(b"swapaxes xy"),
+            Err(ParseError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn parse_tilt_speed() {
+        assert_eq!(Command::parse(b"tiltspeed"), Ok(Command::TiltSpeed(None)));
+        assert_eq!(
+            Command::parse(b"tiltspeed on"),
+            Ok(Command::TiltSpeed(Some(true)))
+        );
+        assert_eq!(
+            Command::parse(b"tiltspeed fast"),
+            Err(ParseError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn parse_timestamps() {
+        assert_eq!(Command::parse(b"timestamps"), Ok(Command::Timestamps(None)));
+        assert_eq!(
+            Command::parse(b"timestamps on"),
+            Ok(Command::Timestamps(Some(true)))
+        );
+        assert_eq!(
+            Command::parse(b"timestamps ms"),
+            Err(ParseError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn parse_transitions() {
+        assert_eq!(
+            Command::parse(b"transitions"),
+            Ok(Command::Transitions(None))
+        );
+        assert_eq!(
+            Command::parse(b"transitions off"),
+            Ok(Command::Transitions(Some(false)))
+        );
+        assert_eq!(
+            Command::parse(b"transitions 0"),
+            Err(ParseError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn parse_night_mode() {
+        assert_eq!(Command::parse(b"nightmode"), Ok(Command::NightMode(None)));
+        assert_eq!(
+            Command::parse(b"nightmode on"),
+            Ok(Command::NightMode(Some(true)))
+        );
+        assert_eq!(
+            Command::parse(b"nightmode dark"),
+            Err(ParseError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn parse_mode() {
+        assert_eq!(Command::parse(b"mode"), Ok(Command::Mode(None)));
+        for mode in Mode::ALL.iter() {
+            let mut line = b"mode ".to_vec();
+            line.extend_from_slice(mode.as_name().as_bytes());
+            assert_eq!(Command::parse(&line), Ok(Command::Mode(Some(*mode))));
+        }
+        assert_eq!(Command::parse(b"mode next"), Ok(Command::NextMode));
+        assert_eq!(
+            Command::parse(b"mode foo"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse(b"mode cycle accel"),
+            Err(ParseError::TooManyArguments)
+        );
+    }
+
+    #[test]
+    fn parse_calibrate() {
+        assert_eq!(Command::parse(b"calibrate"), Ok(Command::Calibrate));
+        assert_eq!(
+            Command::parse(b"calibrate reset"),
+            Ok(Command::CalibrateReset)
+        );
+        assert_eq!(
+            Command::parse(b"calibrate now"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse(b"calibrate reset now"),
+            Err(ParseError::TooManyArguments)
+        );
+    }
+
+    #[test]
+    fn parse_party() {
+        assert_eq!(Command::parse(b"party"), Ok(Command::Party));
+        assert_eq!(Command::parse(b"party stop"), Ok(Command::PartyStop));
+        assert_eq!(Command::parse(b"timer"), Ok(Command::Timer));
+        assert_eq!(Command::parse(b"timer reset"), Ok(Command::TimerReset));
+        assert_eq!(
+            Command::parse(b"timer stop"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse(b"party on"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse(b"party stop now"),
+            Err(ParseError::TooManyArguments)
+        );
+    }
+
+    #[test]
+    fn parse_cycle_from() {
+        assert_eq!(Command::parse(b"cycle"), Ok(Command::Cycle));
+        assert_eq!(Command::parse(b"cycle from 0"), Ok(Command::CycleFrom(0)));
+        assert_eq!(Command::parse(b"cycle from 3"), Ok(Command::CycleFrom(3)));
+        assert_eq!(
+            Command::parse(b"cycle from 4"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse(b"cycle from"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse(b"cycle from -1"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse(b"cycle from 1 2"),
+            Err(ParseError::TooManyArguments)
+        );
+        assert_eq!(
+            Command::parse(b"cycle 1"),
+            Err(ParseError::TooManyArguments)
+        );
+    }
+
+    #[test]
+    fn parse_pin() {
+        assert_eq!(
+            Command::parse(b"pin 0 force on"),
+            Ok(Command::Pin {
+                index: 0,
+                state: Some(true)
+            })
+        );
+        assert_eq!(
+            Command::parse(b"pin 3 force off"),
+            Ok(Command::Pin {
+                index: 3,
+                state: Some(false)
+            })
+        );
+        assert_eq!(
+            Command::parse(b"pin 1 force auto"),
+            Ok(Command::Pin {
+                index: 1,
+                state: None
+            })
+        );
+        assert_eq!(Command::parse(b"pin"), Err(ParseError::InvalidArgument));
+        assert_eq!(
+            Command::parse(b"pin 4 force on"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse(b"pin 0 on"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse(b"pin 0 force"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse(b"pin 0 force maybe"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse(b"pin 0 force on now"),
+            Err(ParseError::TooManyArguments)
+        );
+    }
+
+    #[test]
+    fn parse_ping() {
+        assert_eq!(Command::parse(b"ping"), Ok(Command::Ping(0)));
+        assert_eq!(Command::parse(b"ping 0"), Ok(Command::Ping(0)));
+        assert_eq!(Command::parse(b"ping 42"), Ok(Command::Ping(42)));
+        assert_eq!(
+            Command::parse(b"ping 4294967295"),
+            Ok(Command::Ping(u32::MAX))
+        );
+        assert_eq!(
+            Command::parse(b"ping 4294967296"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(Command::parse(b"ping -1"), Err(ParseError::InvalidArgument));
+        assert_eq!(Command::parse(b"ping x"), Err(ParseError::InvalidArgument));
+        assert_eq!(
+            Command::parse(b"ping 1 2"),
+            Err(ParseError::TooManyArguments)
+        );
+    }
+
+    #[test]
+    fn parse_reboot() {
+        assert_eq!(Command::parse(b"reboot confirm"), Ok(Command::Reboot));
+        assert_eq!(Command::parse(b"reboot"), Err(ParseError::InvalidArgument));
+        assert_eq!(
+            Command::parse(b"reboot now"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse(b"reboot confirm now"),
+            Err(ParseError::TooManyArguments)
+        );
+    }
+
+    #[test]
+    fn parse_line() {
+        let commands = Command::parse_line(b"cycle;flip;accelperiod 100").unwrap();
+        assert_eq!(
+            &commands[..],
+            &[
+                Command::Cycle,
+                Command::Flip,
+                Command::AccelPeriod(Some(100))
+            ]
+        );
+        let commands = Command::parse_line(b"on").unwrap();
+        assert_eq!(&commands[..], &[Command::On]);
+
+        // Whitespace around the commands, empty commands and trailing separators are ignored.
+        let commands = Command::parse_line(b" cycle ; ;flip;  ").unwrap();
+        assert_eq!(&commands[..], &[Command::Cycle, Command::Flip]);
+        assert_eq!(Command::parse_line(b""), Err(ParseError::Empty));
+        assert_eq!(Command::parse_line(b" ; ;"), Err(ParseError::Empty));
+
+        // The steps of a macro are the rest of the line.
+        let commands = Command::parse_line(b"off; macro set cycle; wait 100; flip").unwrap();
+        assert_eq!(commands.len(), 2);
+        assert_eq!(commands[0], Command::Off);
+        assert_eq!(
+            commands[1],
+            Command::Macro(Some(Sequence::parse(b"cycle; wait 100; flip").unwrap()))
+        );
+        let commands = Command::parse_line(b"macro run; off").unwrap();
+        assert_eq!(&commands[..], &[Command::MacroRun(false), Command::Off]);
+    }
+
+    #[test]
+    fn needs_accel() {
+        let needing = [
+            "accel",
+            "arrow",
+            "autorange on",
+            "autorange off",
+            "accelpoll on",
+            "calibrate",
+            "fifo off",
+            "mode accel",
+            "motionwake 200",
+            "rawaccel",
+            "setall quiet=on fifo=off",
+            "reg read 0x0f",
+            "reg write 0x20 0x47",
+            "tiltspeed on",
+        ];
+        for line in needing.iter() {
+            let command = Command::parse(line.as_bytes()).unwrap();
+            assert!(command.needs_accel(), "{}", line);
+        }
+
+        let not_needing = [
+            "autorange",
+            "accelpoll off",
+            "calibrate reset",
+            "cycle",
+            "fifo",
+            "flipaccel x",
+            "mode",
+            "mode cycle",
+            "motionwake 0",
+            "setall quiet=on motionwake=0",
+            "setall quiet=on tiltspeed=off",
+            "tiltspeed off",
+        ];
+        for line in not_needing.iter() {
+            let command = Command::parse(line.as_bytes()).unwrap();
+            assert!(!command.needs_accel(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn parse_line_invalid() {
+        // The line is rejected if any of the commands is invalid.
+        assert_eq!(
+            Command::parse_line(b"cycle;fly;flip"),
+            Err(ParseError::Unknown)
+        );
+        assert_eq!(
+            Command::parse_line(b"cycle;accelperiod 0"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse_line(b"flip on;cycle"),
+            Err(ParseError::TooManyArguments)
+        );
+        assert_eq!(
+            Command::parse_line(b"cycle flip"),
+            Err(ParseError::TooManyArguments)
+        );
+
+        let mut line = b"on;".repeat(MAX_LINE_COMMANDS);
+        assert_eq!(Command::parse_line(&line).unwrap().len(), MAX_LINE_COMMANDS);
+        line.extend_from_slice(b"off");
+        assert_eq!(Command::parse_line(&line), Err(ParseError::TooManyCommands));
+    }
+
+    #[test]
+    fn parse_prefix() {
+        let prefix = CommandPrefix::from_bytes(b"led:").unwrap();
+        assert_eq!(prefix.as_str(), "led:");
+        assert!(!prefix.is_empty());
+        assert_eq!(Command::parse(b"prefix"), Ok(Command::Prefix(None)));
+        assert_eq!(
+            Command::parse(b"prefix led:"),
+            Ok(Command::Prefix(Some(prefix)))
+        );
+        assert_eq!(
+            Command::parse(b"prefix off"),
+            Ok(Command::Prefix(Some(CommandPrefix::NONE)))
+        );
+        assert_eq!(
+            Command::parse(b"prefix led:;"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse(b"prefix led:\xff"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse(b"prefix led: extra"),
+            Err(ParseError::TooManyArguments)
+        );
+
+        let longest = [b'x'; MAX_PREFIX_LEN];
+        assert!(CommandPrefix::from_bytes(&longest).is_some());
+        assert_eq!(CommandPrefix::from_bytes(b"012345678"), None);
+        assert_eq!(CommandPrefix::from_bytes(b""), Some(CommandPrefix::NONE));
+        assert!(CommandPrefix::NONE.is_empty());
+        assert_eq!(CommandPrefix::NONE.as_str(), "");
+    }
+
+    #[test]
+    fn dispatch_prefix() {
+        // Without a prefix, all lines are dispatched as they are.
+        let none = CommandPrefix::NONE;
+        assert_eq!(none.strip(b"on;flip"), Some(&b"on;flip"[..]));
+        assert_eq!(none.strip(b""), Some(&b""[..]));
+
+        // With a prefix, only the lines that start with it are dispatched (without it).
+        let prefix = CommandPrefix::from_bytes(b"led:").unwrap();
+        let commands = prefix.strip(b"led:on;flip").map(Command::parse_line);
+        assert_eq!(
+            commands.unwrap().unwrap()[..],
+            [Command::On, Command::Flip][..]
+        );
+        assert_eq!(
+            prefix.strip(b"led: cycle").map(Command::parse),
+            Some(Ok(Command::Cycle))
+        );
+        assert_eq!(prefix.strip(b"on"), None);
+        assert_eq!(prefix.strip(b"led"), None);
+        assert_eq!(prefix.strip(b"LED:on"), None);
+        assert_eq!(prefix.strip(b" led:on"), None);
+        // Only the line is prefixed, not each command on it.
+        assert_eq!(
+            prefix.strip(b"led:on;led:flip").map(Command::parse_line),
+            Some(Err(ParseError::Unknown))
+        );
+    }
+
+    #[test]
+    fn parse_setall() {
+        let pairs = SettingPairs::parse(b"quiet=on heartbeat=5000").unwrap();
+        assert_eq!(
+            Command::parse(b"setall  quiet=on heartbeat=5000 "),
+            Ok(Command::SetAll(pairs))
+        );
+        assert_eq!(Command::parse(b"setall"), Err(ParseError::InvalidArgument));
+        assert_eq!(
+            Command::parse(b"setall quiet=on heartbeat=-1"),
+            Err(ParseError::InvalidArgument)
+        );
+        // The pairs are not separate commands on the line.
+        let commands = Command::parse_line(b"setall quiet=on interactive=off;cycle").unwrap();
+        assert_eq!(commands.len(), 2);
+        assert_eq!(commands[1], Command::Cycle);
+    }
+
+    #[test]
+    fn parse_factory() {
+        assert_eq!(Command::parse(b"factory confirm"), Ok(Command::Factory));
+        assert_eq!(Command::parse(b"factory"), Err(ParseError::InvalidArgument));
+        assert_eq!(
+            Command::parse(b"factory reset"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse(b"factory confirm now"),
+            Err(ParseError::TooManyArguments)
+        );
+    }
+
+    #[test]
+    fn parse_reg() {
+        assert_eq!(Command::parse(b"reg read 0x0f"), Ok(Command::RegRead(0x0F)));
+        assert_eq!(Command::parse(b"reg read 15"), Ok(Command::RegRead(0x0F)));
+        assert_eq!(
+            Command::parse(b"reg write 0x20 0x47"),
+            Ok(Command::RegWrite(0x20, 0x47))
+        );
+        assert_eq!(
+            Command::parse(b"reg write 32 71"),
+            Ok(Command::RegWrite(0x20, 0x47))
+        );
+        assert_eq!(Command::parse(b"reg"), Err(ParseError::InvalidArgument));
+        assert_eq!(
+            Command::parse(b"reg read"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse(b"reg read 0x100"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse(b"reg read 0xzz"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse(b"reg write 0x20"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse(b"reg erase 0x20"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse(b"reg read 0x20 0x47"),
+            Err(ParseError::TooManyArguments)
+        );
+    }
+
+    #[test]
+    fn parse_ramp() {
+        assert_eq!(
+            Command::parse(b"ramp"),
+            Ok(Command::Ramp {
+                step: None,
+                dwell: None
+            })
+        );
+        assert_eq!(
+            Command::parse(b"ramp 10"),
+            Ok(Command::Ramp {
+                step: Some(10),
+                dwell: None
+            })
+        );
+        assert_eq!(
+            Command::parse(b"ramp 10 \t400000"),
+            Ok(Command::Ramp {
+                step: Some(10),
+                dwell: Some(400_000)
+            })
+        );
+        assert_eq!(Command::parse(b"ramp 0"), Err(ParseError::InvalidArgument));
+        assert_eq!(
+            Command::parse(b"ramp 256"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(Command::parse(b"ramp x"), Err(ParseError::InvalidArgument));
+        assert_eq!(
+            Command::parse(b"ramp 1 0"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse(b"ramp 1 2 3"),
+            Err(ParseError::TooManyArguments)
+        );
+    }
+
+    #[test]
+    fn trim_text() {
+        assert_eq!(trim(b"  on;off \t"), b"on;off");
+        assert_eq!(trim(b"on"), b"on");
+        assert_eq!(trim(b" \t "), b"");
+        assert_eq!(trim(b""), b"");
+    }
+
+    #[test]
+    fn parse_macro() {
+        let sequence = Sequence::parse(b"cycle; wait 2000; off").unwrap();
+        assert_eq!(
+            Command::parse(b"macro set cycle; wait 2000; off"),
+            Ok(Command::Macro(Some(sequence)))
+        );
+        assert_eq!(
+            Command::parse(b" macro  set\tcycle; wait 2000; off "),
+            Ok(Command::Macro(Some(sequence)))
+        );
+        assert_eq!(Command::parse(b"macro"), Ok(Command::Macro(None)));
+        assert_eq!(Command::parse(b"macro run"), Ok(Command::MacroRun(false)));
+        assert_eq!(Command::parse(b"macro loop"), Ok(Command::MacroRun(true)));
+        assert_eq!(Command::parse(b"macro stop"), Ok(Command::MacroStop));
+        assert_eq!(
+            Command::parse(b"macro set"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse(b"macro set on; macro run"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse(b"macro play"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse(b"macro run now"),
+            Err(ParseError::TooManyArguments)
+        );
+    }
+}
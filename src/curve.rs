@@ -0,0 +1,107 @@
+//! Module for the brightness curve that maps the brightness to the PWM brightness.
+//!
+//! The perceived brightness of an LED is not linear in its duty: the steps at low brightness
+//! look much larger than those at high brightness.  The gamma curve compensates for this, so
+//! that fades (e.g. of ramp and breathe mode) look even.
+
+/// The gamma-corrected brightness for each brightness.
+///
+/// The values follow a gamma of 2.2 (`255 * (i / 255)^2.2`, rounded), but are at least one for
+/// a non-zero brightness, so that the curve does not turn off LEDs that are on (and the
+/// brightness floor still applies to them).
+const GAMMA: [u8; 256] = [
+    0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2,
+    3, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 6, 6, 6, 6, 7, 7, 7, 8, 8, 8, 9, 9, 9, 10, 10, 11, 11,
+    11, 12, 12, 13, 13, 13, 14, 14, 15, 15, 16, 16, 17, 17, 18, 18, 19, 19, 20, 20, 21, 22, 22, 23,
+    23, 24, 25, 25, 26, 26, 27, 28, 28, 29, 30, 30, 31, 32, 33, 33, 34, 35, 35, 36, 37, 38, 39, 39,
+    40, 41, 42, 43, 43, 44, 45, 46, 47, 48, 49, 49, 50, 51, 52, 53, 54, 55, 56, 57, 58, 59, 60, 61,
+    62, 63, 64, 65, 66, 67, 68, 69, 70, 71, 73, 74, 75, 76, 77, 78, 79, 81, 82, 83, 84, 85, 87, 88,
+    89, 90, 91, 93, 94, 95, 97, 98, 99, 100, 102, 103, 105, 106, 107, 109, 110, 111, 113, 114, 116,
+    117, 119, 120, 121, 123, 124, 126, 127, 129, 130, 132, 133, 135, 137, 138, 140, 141, 143, 145,
+    146, 148, 149, 151, 153, 154, 156, 158, 159, 161, 163, 165, 166, 168, 170, 172, 173, 175, 177,
+    179, 181, 182, 184, 186, 188, 190, 192, 194, 196, 197, 199, 201, 203, 205, 207, 209, 211, 213,
+    215, 217, 219, 221, 223, 225, 227, 229, 231, 234, 236, 238, 240, 242, 244, 246, 248, 251, 253,
+    255,
+];
+
+/// The curve that is applied to the brightness.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BrightnessCurve {
+    /// The brightness is used as is.
+    Linear,
+    /// The brightness is gamma-corrected (see [`GAMMA`]).
+    Gamma,
+}
+
+impl BrightnessCurve {
+    /// Returns the brightness curve with the given name.
+    pub fn from_name(name: &[u8]) -> Option<BrightnessCurve> {
+        match name {
+            b"linear" => Some(BrightnessCurve::Linear),
+            b"gamma" => Some(BrightnessCurve::Gamma),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the brightness curve (as used by the serial interface).
+    pub fn as_name(&self) -> &'static str {
+        match self {
+            BrightnessCurve::Linear => "linear",
+            BrightnessCurve::Gamma => "gamma",
+        }
+    }
+
+    /// Returns the brightness after applying the curve.
+    pub fn apply(&self, brightness: u8) -> u8 {
+        match self {
+            BrightnessCurve::Linear => brightness,
+            BrightnessCurve::Gamma => GAMMA[brightness as usize],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BrightnessCurve;
+
+    #[test]
+    fn curve_names() {
+        for curve in [BrightnessCurve::Linear, BrightnessCurve::Gamma].iter() {
+            assert_eq!(
+                BrightnessCurve::from_name(curve.as_name().as_bytes()),
+                Some(*curve)
+            );
+        }
+        assert_eq!(BrightnessCurve::from_name(b"cubic"), None);
+    }
+
+    #[test]
+    fn curve_linear() {
+        for brightness in 0..=u8::MAX {
+            assert_eq!(BrightnessCurve::Linear.apply(brightness), brightness);
+        }
+    }
+
+    #[test]
+    fn curve_gamma() {
+        let gamma = BrightnessCurve::Gamma;
+
+        // The ends are kept.
+        assert_eq!(gamma.apply(0), 0);
+        assert_eq!(gamma.apply(255), 255);
+        // In between, the brightness is lowered the most at the low end, but not to zero.
+        assert_eq!(gamma.apply(1), 1);
+        assert_eq!(gamma.apply(24), 1);
+        assert_eq!(gamma.apply(25), 2);
+        assert_eq!(gamma.apply(64), 12);
+        assert_eq!(gamma.apply(128), 56);
+        assert_eq!(gamma.apply(192), 137);
+        for brightness in 2..u8::MAX {
+            assert!(gamma.apply(brightness) < brightness);
+        }
+        for brightness in 1..u8::MAX {
+            assert!(gamma.apply(brightness) > 0);
+            assert!(gamma.apply(brightness) <= gamma.apply(brightness + 1));
+        }
+    }
+}
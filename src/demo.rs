@@ -0,0 +1,119 @@
+//! Module for the demo that plays automatically when the board is not controlled via the serial
+//! interface, e.g. for kiosk use.
+//!
+//! If nothing is received via the serial interface for a while after startup, the board starts
+//! party mode on its own.  The demo stops as soon as a command arrives, after which it does not
+//! start again.
+//!
+//! Like the heartbeat, the decision is generic over the instant type of the clock; the instants
+//! are only compared for equality, so wrapping around does not matter.
+
+/// The state of the demo.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DemoState {
+    /// Waiting for the delay after startup to pass.
+    Waiting,
+    /// The demo is playing.
+    Playing,
+    /// The demo is not (or no longer) played.
+    Done,
+}
+
+/// The demo that starts automatically unless the serial interface is used after startup.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Demo<I> {
+    /// The instant of startup.
+    started: I,
+    /// The state of the demo.
+    state: DemoState,
+}
+
+impl<I> Demo<I>
+where
+    I: Copy + Eq,
+{
+    /// Sets up the demo for the board started at the given instant.
+    pub fn new(started: I) -> Demo<I> {
+        Demo {
+            started,
+            state: DemoState::Waiting,
+        }
+    }
+
+    /// Returns the state of the demo.
+    pub fn state(&self) -> DemoState {
+        self.state
+    }
+
+    /// Decides whether to start the demo once the delay after startup has passed, given the
+    /// instant of the last activity on the serial interface.
+    ///
+    /// The demo only starts if nothing has been received since startup (not even a partial
+    /// command) and it has not been interrupted yet.
+    pub fn check(&mut self, last_activity: I) -> bool {
+        if self.state != DemoState::Waiting {
+            return false;
+        }
+
+        if last_activity == self.started {
+            self.state = DemoState::Playing;
+            true
+        } else {
+            self.state = DemoState::Done;
+            false
+        }
+    }
+
+    /// Interrupts the demo because a command arrived, returning whether it was playing (and
+    /// has to be stopped).
+    pub fn interrupt(&mut self) -> bool {
+        let playing = self.state == DemoState::Playing;
+        self.state = DemoState::Done;
+        playing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Demo, DemoState};
+
+    #[test]
+    fn demo_start_without_activity() {
+        let mut demo = Demo::new(100u32);
+        assert_eq!(demo.state(), DemoState::Waiting);
+
+        assert!(demo.check(100));
+        assert_eq!(demo.state(), DemoState::Playing);
+        // The demo is only started once.
+        assert!(!demo.check(100));
+        assert_eq!(demo.state(), DemoState::Playing);
+    }
+
+    #[test]
+    fn demo_skipped_after_activity() {
+        let mut demo = Demo::new(100u32);
+        assert!(!demo.check(250));
+        assert_eq!(demo.state(), DemoState::Done);
+
+        // Activity that appears to be before startup (after wrapping around) counts as well.
+        let mut demo = Demo::new(100u32);
+        assert!(!demo.check(50));
+        assert_eq!(demo.state(), DemoState::Done);
+    }
+
+    #[test]
+    fn demo_interrupt() {
+        // A command stops the playing demo.
+        let mut demo = Demo::new(100u32);
+        assert!(demo.check(100));
+        assert!(demo.interrupt());
+        assert_eq!(demo.state(), DemoState::Done);
+        assert!(!demo.interrupt());
+
+        // A command before the delay has passed prevents the demo from starting.
+        let mut demo = Demo::new(100u32);
+        assert!(!demo.interrupt());
+        assert!(!demo.check(100));
+        assert_eq!(demo.state(), DemoState::Done);
+    }
+}
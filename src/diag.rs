@@ -0,0 +1,196 @@
+//! Module for the combined self-diagnostic.
+//!
+//! The diagnostic runs a number of checks, each resulting in a typed [`CheckResult`], and
+//! summarizes them as a table.  The checks that need hardware are given the results of the
+//! hardware access, so that they can be tested without it.
+
+use core::fmt::{self, Write};
+
+use crate::led_ring::{LedOutputState, LedRing, StateSink, SWEEP_STEPS};
+
+/// The result of a single check of the diagnostic.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CheckResult {
+    /// The check passed.
+    Pass,
+    /// The check failed.
+    Fail,
+    /// The check could not be performed (e.g. because it needs external wiring).
+    Skip,
+}
+
+impl CheckResult {
+    /// Returns the name of the result as shown in the summary.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CheckResult::Pass => "pass",
+            CheckResult::Fail => "FAIL",
+            CheckResult::Skip => "skip",
+        }
+    }
+}
+
+/// A check of the diagnostic with its result.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Check {
+    /// The name of the check.
+    pub name: &'static str,
+    /// The result of the check.
+    pub result: CheckResult,
+}
+
+/// Performs the self-test sweep of the LED ring and checks that the outputs of the LEDs
+/// follow their logical states at every step; the LEDs are restored afterwards.
+///
+/// An LED that is forced on or off (see [`LedRing::set_override`]) passes as long as its output
+/// follows the forced state.
+pub fn check_led_sweep<LED, SINK>(led_ring: &mut LedRing<LED, SINK>) -> CheckResult
+where
+    LED: LedOutputState,
+    SINK: StateSink,
+{
+    let states = led_ring.states();
+    let mut result = CheckResult::Pass;
+
+    for step in 0..SWEEP_STEPS {
+        led_ring.sweep(step);
+        if led_ring.output_states() != led_ring.states() {
+            result = CheckResult::Fail;
+        }
+    }
+    led_ring.specific_on(states);
+
+    result
+}
+
+/// Checks whether the accelerometer is present, given the result of identifying it.
+pub fn check_accel_presence<E>(present: Result<bool, E>) -> CheckResult {
+    match present {
+        Ok(true) => CheckResult::Pass,
+        Ok(false) | Err(_) => CheckResult::Fail,
+    }
+}
+
+/// Checks whether the accelerometer can be read, given the result of reading it.
+pub fn check_accel_read<E>(reading: Result<(i8, i8), E>) -> CheckResult {
+    match reading {
+        Ok(_) => CheckResult::Pass,
+        Err(_) => CheckResult::Fail,
+    }
+}
+
+/// Checks the serial loopback.
+///
+/// This needs TX and RX to be wired together, which cannot be done while the serial interface
+/// is in use, so the check is always skipped.
+pub fn check_serial_loopback() -> CheckResult {
+    CheckResult::Skip
+}
+
+/// Checks that no write to the serial interface has timed out, given the number of timeouts
+/// so far (see [`crate::serial::TimeoutTx`]).
+pub fn check_serial_timeouts(timeouts: u32) -> CheckResult {
+    match timeouts {
+        0 => CheckResult::Pass,
+        _ => CheckResult::Fail,
+    }
+}
+
+/// Writes the summary of the checks: a table with a line per check followed by a line with
+/// the totals.  Each line is terminated by `\r\n`.
+pub fn write_summary<W: Write>(writer: &mut W, checks: &[Check]) -> fmt::Result {
+    for check in checks {
+        writeln!(writer, "diag {:<9} {}\r", check.name, check.result.as_str())?;
+    }
+
+    let count = |result| checks.iter().filter(|check| check.result == result).count();
+    writeln!(
+        writer,
+        "diag: {} passed, {} failed, {} skipped\r",
+        count(CheckResult::Pass),
+        count(CheckResult::Fail),
+        count(CheckResult::Skip)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        check_accel_presence, check_accel_read, check_led_sweep, check_serial_loopback,
+        check_serial_timeouts, write_summary, Check, CheckResult,
+    };
+    use crate::led_ring::LedRing;
+    use crate::mock::MockOutputPin;
+
+    #[test]
+    fn diag_led_sweep() {
+        let mut led_ring = LedRing::from(MockOutputPin::get_4());
+        led_ring.specific_on([true, false, false, true]);
+
+        assert_eq!(check_led_sweep(&mut led_ring), CheckResult::Pass);
+        // The LEDs are restored.
+        assert_eq!(led_ring.states(), [true, false, false, true]);
+
+        // A forced LED follows the forced state.
+        led_ring.set_override(1, Some(true));
+        assert_eq!(check_led_sweep(&mut led_ring), CheckResult::Pass);
+        assert_eq!(led_ring.states(), [true, true, false, true]);
+    }
+
+    #[test]
+    fn diag_led_sweep_stuck() {
+        let mut leds = MockOutputPin::get_4();
+        leds[2].stuck = Some(false);
+        let mut led_ring = LedRing::from(leds);
+        assert_eq!(check_led_sweep(&mut led_ring), CheckResult::Fail);
+
+        let mut leds = MockOutputPin::get_4();
+        leds[0].stuck = Some(true);
+        let mut led_ring = LedRing::from(leds);
+        assert_eq!(check_led_sweep(&mut led_ring), CheckResult::Fail);
+    }
+
+    #[test]
+    fn diag_accel() {
+        assert_eq!(check_accel_presence::<()>(Ok(true)), CheckResult::Pass);
+        assert_eq!(check_accel_presence::<()>(Ok(false)), CheckResult::Fail);
+        assert_eq!(check_accel_presence(Err(())), CheckResult::Fail);
+
+        assert_eq!(check_accel_read::<()>(Ok((0, -5))), CheckResult::Pass);
+        assert_eq!(check_accel_read(Err(())), CheckResult::Fail);
+    }
+
+    #[test]
+    fn diag_serial_timeouts() {
+        assert_eq!(check_serial_timeouts(0), CheckResult::Pass);
+        assert_eq!(check_serial_timeouts(1), CheckResult::Fail);
+        assert_eq!(check_serial_timeouts(u32::MAX), CheckResult::Fail);
+    }
+
+    #[test]
+    fn diag_summary() {
+        let checks = [
+            Check {
+                name: "leds",
+                result: CheckResult::Pass,
+            },
+            Check {
+                name: "accel",
+                result: CheckResult::Fail,
+            },
+            Check {
+                name: "loopback",
+                result: check_serial_loopback(),
+            },
+        ];
+        let mut text = String::new();
+        write_summary(&mut text, &checks).unwrap();
+        assert_eq!(
+            text,
+            "diag leds      pass\r\n\
+             diag accel     FAIL\r\n\
+             diag loopback  skip\r\n\
+             diag: 1 passed, 1 failed, 1 skipped\r\n"
+        );
+    }
+}
@@ -0,0 +1,70 @@
+//! Module for reporting the features the demo application is built with.
+
+use core::fmt::{self, Write};
+
+/// The features of the crate and whether each is enabled in this build.
+///
+/// This has to list all features in `Cargo.toml` (which is checked by a test), except for
+/// `default`, which only selects some of the others.
+pub const FEATURES: [(&str, bool); 3] = [
+    ("accel", cfg!(feature = "accel")),
+    ("panic-sos", cfg!(feature = "panic-sos")),
+    ("pwm-timer", cfg!(feature = "pwm-timer")),
+];
+
+/// Writes the names of the enabled features on a single line, separated by spaces, or `none`
+/// if no feature is enabled (without line terminator).
+pub fn write_features<W: Write>(writer: &mut W, features: &[(&str, bool)]) -> fmt::Result {
+    let mut enabled = features
+        .iter()
+        .filter(|(_name, enabled)| *enabled)
+        .map(|(name, _enabled)| name);
+
+    match enabled.next() {
+        Some(first) => {
+            writer.write_str(first)?;
+            enabled.try_for_each(|name| write!(writer, " {}", name))
+        }
+        None => writer.write_str("none"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_features, FEATURES};
+
+    #[test]
+    fn features_in_sync() {
+        let manifest = include_str!("../Cargo.toml");
+        let section = manifest.split("[features]").nth(1).unwrap();
+        let names: Vec<&str> = section
+            .lines()
+            .take_while(|line| !line.starts_with('['))
+            .filter_map(|line| line.split_once('='))
+            .map(|(name, _value)| name.trim())
+            .filter(|name| *name != "default")
+            .collect();
+
+        let listed: Vec<&str> = FEATURES.iter().map(|(name, _enabled)| *name).collect();
+        assert_eq!(listed, names);
+    }
+
+    #[test]
+    fn features_format() {
+        let mut line = String::new();
+        write_features(&mut line, &[("a", false), ("b", false)]).unwrap();
+        assert_eq!(line, "none");
+
+        let mut line = String::new();
+        write_features(&mut line, &[]).unwrap();
+        assert_eq!(line, "none");
+
+        let mut line = String::new();
+        write_features(&mut line, &[("a", false), ("b", true)]).unwrap();
+        assert_eq!(line, "b");
+
+        let mut line = String::new();
+        write_features(&mut line, &[("a", true), ("b", false), ("c", true)]).unwrap();
+        assert_eq!(line, "a c");
+    }
+}
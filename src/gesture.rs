@@ -0,0 +1,95 @@
+//! Module for mapping gestures (like shaking the board) to commands.
+//!
+//! A gesture results in the same command as would be given via the serial interface, so that
+//! physical gestures and serial commands are handled in the same way.
+
+use crate::command::Command;
+use crate::led_ring::Mode;
+
+/// A gesture performed on the board.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Gesture {
+    /// The board is shaken (in accelerometer mode).
+    Shake,
+}
+
+/// The action performed for a gesture.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Action {
+    /// Give the command.
+    Command(Command),
+    /// Switch to the second mode if in the first mode, otherwise switch to the first mode.
+    ToggleMode(Mode, Mode),
+}
+
+impl Action {
+    /// Returns the command to give for the action, given the current mode.
+    pub fn command(&self, mode: Mode) -> Command {
+        match *self {
+            Action::Command(command) => command,
+            Action::ToggleMode(first, second) if mode == first => Command::Mode(Some(second)),
+            Action::ToggleMode(first, _second) => Command::Mode(Some(first)),
+        }
+    }
+}
+
+/// The table that maps gestures to actions.
+///
+/// Shaking flashes all LEDs once.
+pub const GESTURES: [(Gesture, Action); 1] = [(Gesture::Shake, Action::Command(Command::Ack(1)))];
+
+/// Returns the command for the gesture according to the table, given the current mode.
+///
+/// The first entry for the gesture is used; a gesture without an entry gives no command.
+pub fn command_for(table: &[(Gesture, Action)], gesture: Gesture, mode: Mode) -> Option<Command> {
+    table
+        .iter()
+        .find(|(entry, _action)| *entry == gesture)
+        .map(|(_entry, action)| action.command(mode))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{command_for, Action, Command, Gesture, Mode, GESTURES};
+
+    #[test]
+    fn default_gestures() {
+        for mode in Mode::ALL.iter() {
+            assert_eq!(
+                command_for(&GESTURES, Gesture::Shake, *mode),
+                Some(Command::Ack(1))
+            );
+        }
+    }
+
+    #[test]
+    fn custom_gestures() {
+        let table = [
+            (Gesture::Shake, Action::Command(Command::Sleep)),
+            (Gesture::Shake, Action::Command(Command::Off)),
+        ];
+
+        // The first entry for a gesture is used.
+        assert_eq!(
+            command_for(&table, Gesture::Shake, Mode::Cycle),
+            Some(Command::Sleep)
+        );
+        assert_eq!(command_for(&[], Gesture::Shake, Mode::Cycle), None);
+
+        // Toggling switches to the second mode only from the first one.
+        let table = [(
+            Gesture::Shake,
+            Action::ToggleMode(Mode::Cycle, Mode::Accelerometer),
+        )];
+        let toggle = |mode| command_for(&table, Gesture::Shake, mode);
+        assert_eq!(
+            toggle(Mode::Cycle),
+            Some(Command::Mode(Some(Mode::Accelerometer)))
+        );
+        assert_eq!(
+            toggle(Mode::Accelerometer),
+            Some(Command::Mode(Some(Mode::Cycle)))
+        );
+        assert_eq!(toggle(Mode::Off), Some(Command::Mode(Some(Mode::Cycle))));
+    }
+}
@@ -0,0 +1,68 @@
+//! Module for falling back to a safe display when the host stops sending.
+//!
+//! When the host controls the LEDs directly (e.g. using `on`, `off` or `pin`), the LED ring is
+//! not animated, so a host that stopped would leave the board looking frozen.  If the host has
+//! not sent anything for the heartbeat timeout, the board falls back to [`FALLBACK_MODE`].
+//!
+//! Like the rate limiter, the decision is generic over the instant type of the clock, so that
+//! it can be used with the CYCCNT-based instants of the scheduler, which wrap around.
+
+use core::ops::Add;
+
+use crate::led_ring::Mode;
+
+/// The mode that is switched to when the heartbeat times out.
+pub const FALLBACK_MODE: Mode = Mode::Cycle;
+
+/// Returns whether the LED ring is controlled by the host, i.e. not animated by a mode (nor
+/// sleeping or temporarily animated by e.g. an acknowledgment).
+pub fn is_host_controlled(mode: Mode, sleeping: bool, overlay: bool) -> bool {
+    mode == Mode::Off && !sleeping && !overlay
+}
+
+/// Returns whether the heartbeat timed out at the current instant, given the instant of the
+/// last activity of the host and the timeout (if enabled).
+///
+/// Because the CYCCNT-based instants wrap around, the heartbeat also times out if the last
+/// activity was so long ago that it appears to be in the future.
+pub fn is_expired<I, D>(timeout: Option<D>, last_activity: I, now: I) -> bool
+where
+    I: Add<D, Output = I> + Copy + Ord,
+{
+    match timeout {
+        Some(timeout) => now >= last_activity + timeout || now < last_activity,
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_expired, is_host_controlled, Mode, FALLBACK_MODE};
+
+    #[test]
+    fn heartbeat_host_controlled() {
+        assert!(is_host_controlled(Mode::Off, false, false));
+        // Sleeping or a temporary animation is not a sign of a frozen board.
+        assert!(!is_host_controlled(Mode::Off, true, false));
+        assert!(!is_host_controlled(Mode::Off, false, true));
+        for mode in Mode::ALL.iter().filter(|mode| **mode != Mode::Off) {
+            assert!(!is_host_controlled(*mode, false, false));
+        }
+        assert!(!is_host_controlled(FALLBACK_MODE, false, false));
+    }
+
+    #[test]
+    fn heartbeat_expired() {
+        // Disabled, the heartbeat never times out.
+        assert!(!is_expired::<u32, u32>(None, 100, 100));
+        assert!(!is_expired::<u32, u32>(None, 100, 1_000_000));
+
+        assert!(!is_expired(Some(500u32), 100u32, 100));
+        assert!(!is_expired(Some(500u32), 100u32, 599));
+        assert!(is_expired(Some(500u32), 100u32, 600));
+        assert!(is_expired(Some(500u32), 100u32, 10_000));
+
+        // Activity that appears to be in the future happened long ago.
+        assert!(is_expired(Some(500u32), 100u32, 50));
+    }
+}
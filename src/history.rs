@@ -0,0 +1,208 @@
+//! Module for the history of commands given via the serial interface.
+
+use heapless::{
+    consts::{U4, U64},
+    Vec,
+};
+
+/// A command line as stored in the history.
+pub type Line = Vec<u8, U64>;
+
+/// The history of the last few command lines.
+///
+/// When the history is full, the oldest line is overwritten.  The history keeps a cursor to
+/// recall the lines one by one, from the most recent line to the oldest line and back.
+#[derive(Debug)]
+pub struct History {
+    /// The stored lines.
+    lines: Vec<Line, U4>,
+    /// The index of the most recently stored line.
+    newest: usize,
+    /// The age of the currently recalled line (0 being the most recent), if any.
+    cursor: Option<usize>,
+}
+
+impl History {
+    /// Sets up an empty history.
+    pub fn new() -> History {
+        History {
+            lines: Vec::new(),
+            newest: 0,
+            cursor: None,
+        }
+    }
+
+    /// Returns the number of stored lines.
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// Returns whether the history is empty.
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    /// Returns the stored line of the given age (0 being the most recent), if any.
+    pub fn get(&self, age: usize) -> Option<&[u8]> {
+        let len = self.lines.len();
+        if age >= len {
+            return None;
+        }
+
+        Some(&self.lines[(self.newest + len - age) % len])
+    }
+
+    /// Stores the line in the history and resets the recall cursor.
+    ///
+    /// Empty lines and repetitions of the most recent line are not stored.
+    pub fn push(&mut self, line: &[u8]) {
+        self.cursor = None;
+        if line.iter().all(u8::is_ascii_whitespace) || self.get(0) == Some(line) {
+            return;
+        }
+
+        let mut stored = Line::new();
+        // Lines longer than the command buffer cannot be received, so truncating is fine.
+        let len = line.len().min(stored.capacity());
+        stored.extend_from_slice(&line[..len]).unwrap();
+
+        if self.lines.len() < self.lines.capacity() {
+            self.lines.push(stored).unwrap();
+            self.newest = self.lines.len() - 1;
+        } else {
+            self.newest = (self.newest + 1) % self.lines.len();
+            self.lines[self.newest] = stored;
+        }
+    }
+
+    /// Returns the most recent line to repeat it if the given line is empty (or only contains
+    /// whitespace), or `None` otherwise or if the history is empty.
+    pub fn repeat(&self, line: &[u8]) -> Option<&[u8]> {
+        if line.iter().all(u8::is_ascii_whitespace) {
+            self.get(0)
+        } else {
+            None
+        }
+    }
+
+    /// Recalls the previous (older) line.
+    ///
+    /// Returns `None` if the history is empty; once the oldest line is reached, it keeps being
+    /// recalled.
+    pub fn older(&mut self) -> Option<&[u8]> {
+        if self.lines.is_empty() {
+            return None;
+        }
+
+        let age = match self.cursor {
+            Some(age) => (age + 1).min(self.lines.len() - 1),
+            None => 0,
+        };
+        self.cursor = Some(age);
+        self.get(age)
+    }
+
+    /// Recalls the next (more recent) line.
+    ///
+    /// Returns an empty line when moving past the most recent line, and `None` if no line is
+    /// being recalled.
+    pub fn newer(&mut self) -> Option<&[u8]> {
+        match self.cursor {
+            Some(0) => {
+                self.cursor = None;
+                Some(&[])
+            }
+            Some(age) => {
+                self.cursor = Some(age - 1);
+                self.get(age - 1)
+            }
+            None => None,
+        }
+    }
+}
+
+impl Default for History {
+    fn default() -> History {
+        History::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::History;
+
+    #[test]
+    fn history_push() {
+        let mut history = History::new();
+        assert!(history.is_empty());
+        assert_eq!(history.get(0), None);
+
+        history.push(b"on");
+        history.push(b"");
+        history.push(b"  ");
+        history.push(b"off");
+        history.push(b"off");
+        assert_eq!(history.len(), 2);
+        assert_eq!(history.get(0), Some(&b"off"[..]));
+        assert_eq!(history.get(1), Some(&b"on"[..]));
+        assert_eq!(history.get(2), None);
+    }
+
+    #[test]
+    fn history_overwrite_oldest() {
+        let mut history = History::new();
+
+        for line in [&b"1"[..], b"2", b"3", b"4", b"5", b"6"].iter() {
+            history.push(line);
+        }
+        assert_eq!(history.len(), 4);
+        assert_eq!(history.get(0), Some(&b"6"[..]));
+        assert_eq!(history.get(1), Some(&b"5"[..]));
+        assert_eq!(history.get(2), Some(&b"4"[..]));
+        assert_eq!(history.get(3), Some(&b"3"[..]));
+        assert_eq!(history.get(4), None);
+    }
+
+    #[test]
+    fn history_repeat() {
+        let mut history = History::new();
+        // Without a previous line, an empty line does nothing.
+        assert_eq!(history.repeat(b""), None);
+
+        history.push(b"flip");
+        history.push(b"");
+        assert_eq!(history.repeat(b""), Some(&b"flip"[..]));
+        assert_eq!(history.repeat(b" \t"), Some(&b"flip"[..]));
+        assert_eq!(history.repeat(b"on"), None);
+
+        history.push(b"on");
+        assert_eq!(history.repeat(b""), Some(&b"on"[..]));
+    }
+
+    #[test]
+    fn history_recall() {
+        let mut history = History::new();
+        assert_eq!(history.older(), None);
+        assert_eq!(history.newer(), None);
+
+        history.push(b"on");
+        history.push(b"cycle");
+        history.push(b"flip");
+
+        assert_eq!(history.older(), Some(&b"flip"[..]));
+        assert_eq!(history.older(), Some(&b"cycle"[..]));
+        assert_eq!(history.older(), Some(&b"on"[..]));
+        // The oldest line keeps being recalled.
+        assert_eq!(history.older(), Some(&b"on"[..]));
+        assert_eq!(history.newer(), Some(&b"cycle"[..]));
+        assert_eq!(history.newer(), Some(&b"flip"[..]));
+        // Moving past the most recent line gives an empty line.
+        assert_eq!(history.newer(), Some(&b""[..]));
+        assert_eq!(history.newer(), None);
+        assert_eq!(history.older(), Some(&b"flip"[..]));
+
+        // Storing a line resets the cursor.
+        history.push(b"accel");
+        assert_eq!(history.older(), Some(&b"accel"[..]));
+    }
+}
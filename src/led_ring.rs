@@ -2,6 +2,44 @@
 
 use core::convert::Infallible;
 use hal::prelude::_embedded_hal_digital_v2_OutputPin as OutputPin;
+use hal::prelude::_embedded_hal_digital_v2_StatefulOutputPin as StatefulOutputPin;
+
+use crate::breathe::Breathe;
+use crate::chase::Chase;
+use crate::curve::BrightnessCurve;
+use crate::ramp::Ramp;
+use crate::sparkle::Sparkle;
+
+/// The number of refresh steps in one period of the software PWM.
+pub const PWM_STEPS: u8 = 16;
+
+/// The frequency (in hertz) of the software PWM period.
+///
+/// The refresh runs in its own task at this rate, independent of the period of the animation
+/// steps, so that the brightness is stable however slowly the ring advances.
+pub const PWM_FREQUENCY_HZ: u32 = 100;
+
+/// Returns the number of cycles between software PWM refreshes at the given clock frequency.
+pub const fn pwm_refresh_cycles(clock_hz: u32) -> u32 {
+    clock_hz / PWM_FREQUENCY_HZ / PWM_STEPS as u32
+}
+
+/// The default minimum brightness of LEDs that are on, which is the lowest brightness that is
+/// lit during at least one step of the software PWM period.
+pub const DEFAULT_BRIGHTNESS_FLOOR: u8 = 16;
+
+/// The PWM duty of the trailing LED in cycle mode when the leading LED is emphasized, so that
+/// the direction is visible.
+pub const TRAIL_DUTY: u8 = PWM_STEPS / 4;
+
+/// The maximum length of the trail behind the head in cycle mode (all other LEDs of the ring).
+pub const MAX_COMET_TRAIL: u8 = 3;
+
+/// The number of steps of the self-test sweep (one per LED plus one to turn them off).
+pub const SWEEP_STEPS: usize = 5;
+
+/// The number of values counter mode shows before wrapping around (four bits).
+pub const COUNTER_VALUES: u8 = 16;
 
 /// The cycle direction of the LED ring.
 ///
@@ -16,8 +54,25 @@ pub enum Direction {
 }
 
 impl Direction {
+    /// Returns the direction with the given name.
+    pub fn from_name(name: &[u8]) -> Option<Direction> {
+        match name {
+            b"cw" => Some(Direction::Clockwise),
+            b"ccw" => Some(Direction::CounterClockwise),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the direction (as used by the serial interface).
+    pub fn as_name(&self) -> &'static str {
+        match self {
+            Direction::Clockwise => "cw",
+            Direction::CounterClockwise => "ccw",
+        }
+    }
+
     /// Returns the flipped/reversed direction.
-    fn flip(&self) -> Direction {
+    pub fn flip(&self) -> Direction {
         match self {
             Direction::Clockwise => Direction::CounterClockwise,
             Direction::CounterClockwise => Direction::Clockwise,
@@ -25,64 +80,617 @@ impl Direction {
     }
 }
 
+/// An output that drives an LED of the ring.
+///
+/// GPIO output pins implement this trait (by a blanket implementation): they can only be set
+/// high or low, so the software PWM (see [`LedRing::refresh`]) switches them during its period.
+/// An output with hardware PWM, like a channel of a timer (see [`crate::board::LedChannel`]),
+/// sets [`LedOutput::HARDWARE_PWM`] and is given the duty directly instead.
+pub trait LedOutput {
+    /// Whether the output dims the LED by itself, given its duty via [`LedOutput::set_duty`].
+    const HARDWARE_PWM: bool = false;
+
+    /// Sets the output high (or low) during the whole PWM period.
+    fn set_level(&mut self, high: bool);
+
+    /// Sets the output high during the given number of steps of each PWM period (up to
+    /// [`PWM_STEPS`]), and low for the rest of the period.
+    ///
+    /// Without hardware PWM, this can only set the output high or low (for a non-zero duty).
+    fn set_duty(&mut self, high_steps: u8) {
+        self.set_level(high_steps > 0);
+    }
+}
+
+impl<PIN> LedOutput for PIN
+where
+    PIN: OutputPin<Error = Infallible>,
+{
+    fn set_level(&mut self, high: bool) {
+        if high {
+            self.set_high().unwrap();
+        } else {
+            self.set_low().unwrap();
+        }
+    }
+}
+
+/// An LED output that can be read back.
+pub trait LedOutputState: LedOutput {
+    /// Returns whether the output is set high (during any part of the PWM period).
+    fn is_high(&self) -> bool;
+}
+
+impl<PIN> LedOutputState for PIN
+where
+    PIN: StatefulOutputPin + OutputPin<Error = Infallible>,
+{
+    fn is_high(&self) -> bool {
+        self.is_set_high().unwrap()
+    }
+}
+
+/// The polarity of the LED outputs.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Polarity {
+    /// An LED is lit when its output is high (as on this board).
+    ActiveHigh,
+    /// An LED is lit when its output is low.
+    ActiveLow,
+}
+
+impl Polarity {
+    /// Drives the LED output such that the LED is lit or not.
+    fn drive<LED: LedOutput>(&self, led: &mut LED, lit: bool) {
+        led.set_level(lit == (*self == Polarity::ActiveHigh));
+    }
+
+    /// Drives the LED output (with hardware PWM) such that the LED is lit during the given
+    /// number of steps of the PWM period.
+    fn drive_duty<LED: LedOutput>(&self, led: &mut LED, lit_steps: u8) {
+        match self {
+            Polarity::ActiveHigh => led.set_duty(lit_steps),
+            Polarity::ActiveLow => led.set_duty(PWM_STEPS - lit_steps),
+        }
+    }
+}
+
+/// The style of cycling.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CycleStyle {
+    /// A single LED is on at any time.
+    Single,
+    /// Two (neighbouring) LEDs are on at any time.
+    Pair,
+}
+
+impl CycleStyle {
+    /// Returns the cycle style with the given name.
+    pub fn from_name(name: &[u8]) -> Option<CycleStyle> {
+        match name {
+            b"single" => Some(CycleStyle::Single),
+            b"pair" => Some(CycleStyle::Pair),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the cycle style (as used by the serial interface).
+    pub fn as_name(&self) -> &'static str {
+        match self {
+            CycleStyle::Single => "single",
+            CycleStyle::Pair => "pair",
+        }
+    }
+}
+
 /// The mode the LED ring is in.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Mode {
     /// All LEDs are off.
     Off,
-    /// The LEDs are cycling (two on at any time) following some direction.
+    /// One or more LEDs are cycling following some direction, according to the cycle style (see
+    /// [`CycleStyle`]).
     Cycle,
     /// The LEDs follow the accelerometer (shows which side of the board is pointing down).
     Accelerometer,
+    /// All LEDs are on and continuously ramp from zero to full brightness and back.
+    Ramp,
+    /// Two dots chase each other around the ring at different speeds.
+    Chase,
+    /// All LEDs are on and smoothly fade in and out, like breathing.
+    Breathe,
+    /// Random LEDs light up.
+    Sparkle,
+    /// The LEDs show a binary count that increments every step.
+    Counter,
+}
+
+/// The error that occurs when looking up a mode by an unknown name.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct UnknownModeError;
+
+/// The error that occurs when setting an index beyond the LEDs of the ring.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct InvalidIndexError;
+
+impl Mode {
+    /// All modes (in the order they are listed to the user).
+    pub const ALL: [Mode; 8] = [
+        Mode::Off,
+        Mode::Cycle,
+        Mode::Accelerometer,
+        Mode::Ramp,
+        Mode::Chase,
+        Mode::Breathe,
+        Mode::Sparkle,
+        Mode::Counter,
+    ];
+
+    /// Returns the mode with the given name.
+    pub fn from_name(name: &[u8]) -> Result<Mode, UnknownModeError> {
+        Mode::ALL
+            .iter()
+            .find(|mode| mode.as_name().as_bytes() == name)
+            .copied()
+            .ok_or(UnknownModeError)
+    }
+
+    /// Returns the index of the mode in the list of all modes.
+    pub fn index(&self) -> usize {
+        match self {
+            Mode::Off => 0,
+            Mode::Cycle => 1,
+            Mode::Accelerometer => 2,
+            Mode::Ramp => 3,
+            Mode::Chase => 4,
+            Mode::Breathe => 5,
+            Mode::Sparkle => 6,
+            Mode::Counter => 7,
+        }
+    }
+
+    /// Returns the name of the mode (as used by the serial interface).
+    pub fn as_name(&self) -> &'static str {
+        match self {
+            Mode::Off => "off",
+            Mode::Cycle => "cycle",
+            Mode::Accelerometer => "accel",
+            Mode::Ramp => "ramp",
+            Mode::Chase => "chase",
+            Mode::Breathe => "breathe",
+            Mode::Sparkle => "sparkle",
+            Mode::Counter => "count",
+        }
+    }
+
+    /// Returns the LED states the mode starts with when it is entered: all LEDs on for the modes
+    /// that only change the brightness (ramp and breathe mode), otherwise all LEDs off.
+    pub fn initial_states(&self) -> [bool; 4] {
+        let on = *self == Mode::Ramp || *self == Mode::Breathe;
+        [on; 4]
+    }
+}
+
+/// An output that mirrors the logical state of the LED ring, e.g. an LED strip.
+pub trait StateSink {
+    /// Updates the output with the logical (on/off) states of the LEDs.
+    ///
+    /// This is called each time the states change.
+    fn update(&mut self, states: [bool; 4]);
+}
+
+/// The sink that ignores all state changes (i.e. no additional output).
+impl StateSink for () {
+    fn update(&mut self, _states: [bool; 4]) {}
+}
+
+/// The sink that keeps the latest state change until it is taken.
+///
+/// This defers a slow output (e.g. an LED strip) until it can be done outside of the critical
+/// section that changes the LED ring.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct PendingStates(Option<[bool; 4]>);
+
+impl PendingStates {
+    /// Takes the latest logical LED states, if they changed since they were last taken.
+    pub fn take(&mut self) -> Option<[bool; 4]> {
+        self.0.take()
+    }
+}
+
+impl StateSink for PendingStates {
+    fn update(&mut self, states: [bool; 4]) {
+        self.0 = Some(states);
+    }
+}
+
+/// Returns the number of software PWM steps during which an LED is lit for the given
+/// brightness.
+fn duty(brightness: u8) -> u8 {
+    (brightness as u16 * PWM_STEPS as u16 / u8::MAX as u16) as u8
+}
+
+/// Returns the brightness raised to the floor, except for zero brightness, which stays off.
+pub fn floored_brightness(brightness: u8, floor: u8) -> u8 {
+    if brightness == 0 {
+        0
+    } else {
+        brightness.max(floor)
+    }
+}
+
+/// Returns the LED states that show the given value in binary (below [`COUNTER_VALUES`]).
+///
+/// Bit 0 (the least significant bit) is shown by the first LED, bit 3 by the last.
+pub fn counter_pattern(value: u8) -> [bool; 4] {
+    let mut pattern = [false; 4];
+    for (bit, on) in pattern.iter_mut().enumerate() {
+        *on = value & (1 << bit) != 0;
+    }
+
+    pattern
+}
+
+/// Returns the PWM duty of each LED in cycle mode with a comet trail.
+///
+/// The head (the LED with the given index) gets the full duty and the given number of LEDs
+/// behind it (opposite to the direction the head moves in) get a duty that fades with their
+/// distance to the head, in equal steps down to zero just past the end of the trail.  The other
+/// LEDs get no duty.
+pub fn comet_duties(head: usize, trail: u8, direction: Direction) -> [u8; 4] {
+    let mut duties = [0; 4];
+    for (index, duty) in duties.iter_mut().enumerate() {
+        let distance = match direction {
+            Direction::Clockwise => (head + 4 - index) % 4,
+            Direction::CounterClockwise => (index + 4 - head) % 4,
+        } as u8;
+        if distance <= trail {
+            *duty = PWM_STEPS * (trail + 1 - distance) / (trail + 1);
+        }
+    }
+
+    duties
+}
+
+/// Returns the PWM duty of each LED in cycle mode when the leading LED is emphasized.
+///
+/// The leading LED (with the given index) gets the full duty, the other LEDs that are on get
+/// [`TRAIL_DUTY`] and the LEDs that are off get no duty.
+pub fn lead_duties(states: [bool; 4], leading: usize) -> [u8; 4] {
+    let mut duties = [0; 4];
+    for (index, duty) in duties.iter_mut().enumerate() {
+        *duty = match (states[index], index == leading) {
+            (false, _) => 0,
+            (true, true) => PWM_STEPS,
+            (true, false) => TRAIL_DUTY,
+        };
+    }
+
+    duties
 }
 
 /// The LED ring.
 ///
 /// The ring on this board is comprised of four LEDs (output pins).  This struct provides methods
-/// for animating them.
-pub struct LedRing<LED> {
-    /// The current cycle direction.
-    direction: Direction,
+/// for animating them.  The logical state of the LEDs can additionally be mirrored onto a sink.
+pub struct LedRing<LED, SINK = ()> {
+    /// The cycle direction of each mode (indexed by mode).
+    directions: [Direction; Mode::ALL.len()],
     /// The current mode.
     mode: Mode,
+    /// Whether the task of each mode is pending, i.e. spawned or scheduled (indexed by mode).
+    pending_tasks: [bool; Mode::ALL.len()],
     /// The index of the current LED being lit.
     index: usize,
+    /// The style of cycling.
+    cycle_style: CycleStyle,
+    /// Whether the leading LED in cycle mode is brighter than the trailing one.
+    cycle_lead: bool,
+    /// The length of the fading trail behind the head in cycle mode (zero if disabled).
+    comet_trail: u8,
+    /// The logical (on/off) state of the LEDs.
+    states: [bool; 4],
+    /// The state each LED is forced to, regardless of the mode (if overridden).
+    overrides: [Option<bool>; 4],
+    /// The brightness of the LEDs that are on.
+    brightness: u8,
+    /// The minimum brightness of the LEDs that are on (unless the brightness is zero).
+    brightness_floor: u8,
+    /// The curve that is applied to the brightness.
+    curve: BrightnessCurve,
+    /// The polarity of the LED outputs.
+    polarity: Polarity,
+    /// The brightness before night mode was enabled (if enabled).
+    saved_brightness: Option<u8>,
+    /// The PWM duty of each LED relative to the brightness (up to [`PWM_STEPS`]).
+    duties: [u8; 4],
+    /// The brightness ramp used in ramp mode.
+    ramp: Ramp,
+    /// The dots used in chase mode.
+    chase: Chase,
+    /// The breath used in breathe mode.
+    breathe: Breathe,
+    /// The sparkling LEDs used in sparkle mode.
+    sparkle: Sparkle,
+    /// The value shown next in counter mode (0-15).
+    counter: u8,
+    /// The current step in the software PWM period.
+    pwm_step: u8,
+    /// The mode and logical LED states to resume when waking up (if sleeping).
+    sleep_state: Option<(Mode, [bool; 4])>,
+    /// The logical LED states left by the last step of the current mode (if any).
+    expected_states: Option<[bool; 4]>,
     /// The LED outputs being used to comprise the LED ring.
     leds: [LED; 4],
+    /// The sink that is notified of state changes.
+    sink: SINK,
 }
 
 impl<LED> LedRing<LED>
 where
-    LED: OutputPin<Error = Infallible>,
+    LED: LedOutput,
 {
     /// Sets up the LED ring using using four LED GPIO outputs.
     pub fn from(leds: [LED; 4]) -> LedRing<LED> {
         LedRing {
-            direction: Direction::Clockwise,
+            directions: [Direction::Clockwise; Mode::ALL.len()],
             mode: Mode::Cycle,
+            pending_tasks: [false; Mode::ALL.len()],
             index: 0,
+            cycle_style: CycleStyle::Pair,
+            cycle_lead: false,
+            comet_trail: 0,
+            states: [false; 4],
+            overrides: [None; 4],
+            brightness: u8::MAX,
+            brightness_floor: DEFAULT_BRIGHTNESS_FLOOR,
+            curve: BrightnessCurve::Linear,
+            polarity: Polarity::ActiveHigh,
+            saved_brightness: None,
+            duties: [PWM_STEPS; 4],
+            ramp: Ramp::default(),
+            chase: Chase::default(),
+            breathe: Breathe::default(),
+            sparkle: Sparkle::default(),
+            counter: 0,
+            pwm_step: 0,
+            sleep_state: None,
+            expected_states: None,
+            leds,
+            sink: (),
+        }
+    }
+
+    /// Returns a builder for setting up the LED ring using four LED GPIO outputs with a
+    /// validated configuration.
+    pub fn builder(leds: [LED; 4]) -> LedRingBuilder<LED> {
+        LedRingBuilder {
             leds,
+            brightness: u8::MAX,
+            polarity: Polarity::ActiveHigh,
+            direction: Direction::Clockwise,
+        }
+    }
+}
+
+/// The error that occurs when building an LED ring with an invalid configuration.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BuildError {
+    /// The brightness is zero, so the LEDs would never be lit.
+    ZeroBrightness,
+}
+
+impl BuildError {
+    /// Returns a short description of the error.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BuildError::ZeroBrightness => "zero brightness",
+        }
+    }
+}
+
+/// The builder of an LED ring.
+///
+/// The configuration is only validated when building the LED ring, see
+/// [`LedRingBuilder::build`].  Without further configuration, the LED ring is the same as the
+/// one set up by [`LedRing::from`].
+pub struct LedRingBuilder<LED> {
+    /// The LED outputs being used to comprise the LED ring.
+    leds: [LED; 4],
+    /// The brightness of the LEDs that are on.
+    brightness: u8,
+    /// The polarity of the LED outputs.
+    polarity: Polarity,
+    /// The initial cycle direction of all modes.
+    direction: Direction,
+}
+
+impl<LED> LedRingBuilder<LED>
+where
+    LED: LedOutput,
+{
+    /// Sets the brightness of the LEDs that are on (full brightness by default).
+    pub fn brightness(mut self, brightness: u8) -> Self {
+        self.brightness = brightness;
+        self
+    }
+
+    /// Sets the polarity of the LED outputs (active high by default).
+    pub fn polarity(mut self, polarity: Polarity) -> Self {
+        self.polarity = polarity;
+        self
+    }
+
+    /// Sets the initial cycle direction of all modes (clockwise by default).
+    pub fn direction(mut self, direction: Direction) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    /// Builds the LED ring, with all LEDs off, or returns the error if the configuration is
+    /// invalid.
+    pub fn build(self) -> Result<LedRing<LED>, BuildError> {
+        if self.brightness == 0 {
+            return Err(BuildError::ZeroBrightness);
+        }
+
+        let mut led_ring = LedRing::from(self.leds);
+        led_ring.directions = [self.direction; Mode::ALL.len()];
+        led_ring.brightness = self.brightness;
+        led_ring.polarity = self.polarity;
+        for index in 0..led_ring.leds.len() {
+            led_ring.set_led(index, false);
+        }
+
+        Ok(led_ring)
+    }
+}
+
+impl<LED, SINK> LedRing<LED, SINK>
+where
+    LED: LedOutput,
+    SINK: StateSink,
+{
+    /// Attaches the sink that is notified of state changes of the LED ring.
+    ///
+    /// The sink is updated with the current state right away.
+    pub fn with_sink<S: StateSink>(self, mut sink: S) -> LedRing<LED, S> {
+        sink.update(self.states);
+
+        LedRing {
+            directions: self.directions,
+            mode: self.mode,
+            pending_tasks: self.pending_tasks,
+            index: self.index,
+            cycle_style: self.cycle_style,
+            cycle_lead: self.cycle_lead,
+            comet_trail: self.comet_trail,
+            states: self.states,
+            overrides: self.overrides,
+            brightness: self.brightness,
+            brightness_floor: self.brightness_floor,
+            curve: self.curve,
+            polarity: self.polarity,
+            saved_brightness: self.saved_brightness,
+            duties: self.duties,
+            ramp: self.ramp,
+            chase: self.chase,
+            breathe: self.breathe,
+            sparkle: self.sparkle,
+            counter: self.counter,
+            pwm_step: self.pwm_step,
+            sleep_state: self.sleep_state,
+            expected_states: self.expected_states,
+            leds: self.leds,
+            sink,
         }
     }
 
+    /// Returns the sink that is notified of state changes.
+    pub fn sink(&self) -> &SINK {
+        &self.sink
+    }
+
+    /// Returns the sink that is notified of state changes (mutably).
+    pub fn sink_mut(&mut self) -> &mut SINK {
+        &mut self.sink
+    }
+
     /// Returns the current cycle mode.
     pub fn mode(&self) -> Mode {
         self.mode
     }
 
+    /// Sets the mode.
+    ///
+    /// The expected LED states are forgotten, since the new mode has not taken a step yet.
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+        self.expected_states = None;
+    }
+
     /// Enables cycle mode.
     pub fn enable_cycle(&mut self) {
-        self.mode = Mode::Cycle;
+        self.set_mode(Mode::Cycle);
     }
 
     /// Enables accelerometer mode.
     pub fn enable_accel(&mut self) {
-        self.mode = Mode::Accelerometer;
+        self.set_mode(Mode::Accelerometer);
+    }
+
+    /// Enables ramp mode.
+    pub fn enable_ramp(&mut self) {
+        self.set_mode(Mode::Ramp);
+    }
+
+    /// Enables chase mode.
+    pub fn enable_chase(&mut self) {
+        self.set_mode(Mode::Chase);
     }
 
-    /// Disables either cycle or accelerometer mode.
+    /// Enables breathe mode.
+    pub fn enable_breathe(&mut self) {
+        self.set_mode(Mode::Breathe);
+    }
+
+    /// Enables sparkle mode.
+    pub fn enable_sparkle(&mut self) {
+        self.set_mode(Mode::Sparkle);
+    }
+
+    /// Enables counter mode.
+    pub fn enable_counter(&mut self) {
+        self.set_mode(Mode::Counter);
+    }
+
+    /// Disables the current mode.
     pub fn disable(&mut self) {
-        self.mode = Mode::Off;
+        self.set_mode(Mode::Off);
+    }
+
+    /// Claims the task of the given mode when the mode is entered.
+    ///
+    /// Returns whether the task needs to be spawned.  It does not if it is still pending, e.g.
+    /// when the mode is left and entered again before the task noticed, in which case that task
+    /// simply continues; this way there is never more than one task animating a mode.  Off mode
+    /// has no task.
+    pub fn claim_task(&mut self, mode: Mode) -> bool {
+        if mode == Mode::Off || self.pending_tasks[mode.index()] {
+            return false;
+        }
+
+        self.pending_tasks[mode.index()] = true;
+        true
+    }
+
+    /// Releases the task of the given mode when it stops because the mode was left.
+    pub fn release_task(&mut self, mode: Mode) {
+        self.pending_tasks[mode.index()] = false;
+    }
+
+    /// Returns whether the task of the given mode is pending.
+    pub fn is_task_pending(&self, mode: Mode) -> bool {
+        self.pending_tasks[mode.index()]
+    }
+
+    /// Records the current LED states as left by a step of the current mode, so that they can
+    /// be checked at the next step.
+    pub fn expect_states(&mut self) {
+        self.expected_states = Some(self.states);
+    }
+
+    /// Returns whether the LED states are still as left by the last step of the current mode.
+    ///
+    /// The animation of a mode is the only thing that changes the LED states while the mode
+    /// stays the same: everything else that changes them (turning LEDs on or off, blinking,
+    /// spinning, sleeping) changes the mode as well.  States that changed in between two steps
+    /// thus indicate an inconsistency.  Before the first step of a mode, this always holds.
+    pub fn states_as_expected(&self) -> bool {
+        match self.expected_states {
+            Some(expected) => expected == self.states,
+            None => true,
+        }
     }
 
     /// Returns whether the LED ring is in cycle mode.
@@ -95,132 +703,699 @@ where
         self.mode == Mode::Accelerometer
     }
 
-    /// Returns the current cycle direction.
-    pub fn direction(&self) -> Direction {
-        self.direction
+    /// Returns whether the LED ring is in ramp mode.
+    pub fn is_mode_ramp(&self) -> bool {
+        self.mode == Mode::Ramp
     }
 
-    /// Reverses the cycle direction.
-    ///
-    /// This will have no immediately visible effect if the LED ring is not in cycle mode
-    /// but it will be used when the cycle mode is enabled again.
-    pub fn reverse(&mut self) {
-        self.direction = self.direction.flip();
+    /// Returns whether the LED ring is in chase mode.
+    pub fn is_mode_chase(&self) -> bool {
+        self.mode == Mode::Chase
     }
 
-    /// Advances the cycling one step.
-    ///
-    /// This will have have directly visible effect regardless of the mode the
-    /// LED ring is in and override what is shown at that moment.
-    pub fn advance(&mut self) {
-        let num_leds = self.leds.len();
-
-        self.leds[self.index].set_high().unwrap();
-        self.leds[(self.index + 2) % num_leds].set_low().unwrap();
+    /// Returns whether the LED ring is in breathe mode.
+    pub fn is_mode_breathe(&self) -> bool {
+        self.mode == Mode::Breathe
+    }
 
-        self.index = match self.direction {
-            Direction::Clockwise => (self.index + 1) % num_leds,
-            Direction::CounterClockwise => (self.index + 3) % num_leds,
-        };
+    /// Returns whether the LED ring is in sparkle mode.
+    pub fn is_mode_sparkle(&self) -> bool {
+        self.mode == Mode::Sparkle
     }
 
-    /// Turns all LEDs on.
-    ///
-    /// This is done immediately, regardless of the current mode.
-    pub fn all_on(&mut self) {
-        for led in self.leds.iter_mut() {
-            led.set_high().unwrap();
-        }
+    /// Returns whether the LED ring is in counter mode.
+    pub fn is_mode_counter(&self) -> bool {
+        self.mode == Mode::Counter
     }
 
-    /// Turns all LEDs off.
+    /// Puts the LED ring to sleep.
     ///
-    /// This is done immediately, regardless of the current mode.
-    pub fn all_off(&mut self) {
-        for led in self.leds.iter_mut() {
-            led.set_low().unwrap();
+    /// This disables the current mode and turns all LEDs off, but both are remembered so they
+    /// can be resumed when waking up.
+    pub fn sleep(&mut self) {
+        if self.sleep_state.is_none() {
+            self.sleep_state = Some((self.mode, self.states));
+            self.set_mode(Mode::Off);
+            self.all_off();
         }
     }
 
-    /// Turns on specific LEDs based on the "direction" array.
+    /// Wakes the LED ring up, resuming the mode and LEDs from before going to sleep.
     ///
-    /// When looking with the mini-USB port of the board held down (south), the directions of
-    /// the array can be interpreted as: `[east, south, west, north]`.
-    pub fn specific_on(&mut self, directions: [bool; 4]) {
-        for (led, on_off) in self.leds.iter_mut().zip(directions.iter()) {
-            if *on_off {
-                led.set_high().unwrap();
-            } else {
-                led.set_low().unwrap();
+    /// Returns whether the LED ring was sleeping.
+    pub fn wake(&mut self) -> bool {
+        match self.sleep_state.take() {
+            Some((mode, states)) => {
+                self.set_mode(mode);
+                self.specific_on(states);
+                true
             }
+            None => false,
         }
     }
 
-    /// Provides access to the LEDs (for testing purposes only).
-    #[cfg(test)]
-    pub fn leds_mut(&self) -> &[LED; 4] {
-        &self.leds
+    /// Returns whether the LED ring is sleeping.
+    pub fn is_sleeping(&self) -> bool {
+        self.sleep_state.is_some()
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::{Direction, Infallible, LedRing, Mode, OutputPin};
-
-    #[derive(Debug, Eq, PartialEq)]
-    struct MockOutputPin {
-        state: bool,
+    /// Returns the cycle direction of the current mode.
+    ///
+    /// Each mode remembers its own direction, so that switching modes and back does not affect
+    /// it.
+    pub fn direction(&self) -> Direction {
+        self.directions[self.mode.index()]
     }
 
-    impl MockOutputPin {
-        fn get_4() -> [Self; 4] {
-            [
-                Self { state: false },
-                Self { state: false },
-                Self { state: false },
-                Self { state: false },
-            ]
-        }
+    /// Sets the cycle direction of the current mode.
+    pub fn set_direction(&mut self, direction: Direction) {
+        self.set_mode_direction(self.mode, direction);
     }
 
-    impl OutputPin for MockOutputPin {
-        type Error = Infallible;
+    /// Sets the cycle direction of the given mode, e.g. before switching to it.
+    pub fn set_mode_direction(&mut self, mode: Mode, direction: Direction) {
+        self.directions[mode.index()] = direction;
+    }
 
-        fn set_high(&mut self) -> Result<(), Self::Error> {
-            self.state = true;
-            Ok(())
-        }
+    /// Reverses the cycle direction of the current mode.
+    ///
+    /// This will have no visible effect if the current mode does not follow a direction.
+    pub fn reverse(&mut self) {
+        self.set_direction(self.direction().flip());
+    }
 
-        fn set_low(&mut self) -> Result<(), Self::Error> {
-            self.state = false;
-            Ok(())
-        }
+    /// Returns the style of cycling.
+    pub fn cycle_style(&self) -> CycleStyle {
+        self.cycle_style
     }
 
-    macro_rules! assert_pins {
-        ($pins:expr, [$pin0:expr, $pin1:expr, $pin2:expr, $pin3:expr]) => {{
-            assert_eq!($pins[0].state, $pin0, "(mock pin 0)");
-            assert_eq!($pins[1].state, $pin1, "(mock pin 1)");
-            assert_eq!($pins[2].state, $pin2, "(mock pin 2)");
-            assert_eq!($pins[3].state, $pin3, "(mock pin 3)");
-        }};
+    /// Sets the style of cycling.
+    ///
+    /// This will be visible from the next cycling step on.
+    pub fn set_cycle_style(&mut self, cycle_style: CycleStyle) {
+        self.cycle_style = cycle_style;
     }
 
-    #[test]
-    fn direction_flip() {
-        let cw_dir = Direction::Clockwise;
-        assert_eq!(cw_dir.flip(), Direction::CounterClockwise);
-        let ccw_dir = Direction::CounterClockwise;
-        assert_eq!(ccw_dir.flip(), Direction::Clockwise);
+    /// Returns whether the leading LED in cycle mode is brighter than the trailing one.
+    pub fn cycle_lead(&self) -> bool {
+        self.cycle_lead
     }
 
-    #[test]
-    fn led_ring_init() {
-        let mock_leds = MockOutputPin::get_4();
-        let led_ring = LedRing::<MockOutputPin>::from(mock_leds);
+    /// Sets whether the leading LED in cycle mode is brighter than the trailing one.
+    ///
+    /// This will be visible from the next cycling step on.
+    pub fn set_cycle_lead(&mut self, enable: bool) {
+        self.cycle_lead = enable;
+    }
 
-        assert_eq!(led_ring.direction(), Direction::Clockwise);
-        assert_eq!(led_ring.mode(), Mode::Cycle);
+    /// Returns the length of the fading trail behind the head in cycle mode.
+    pub fn comet_trail(&self) -> u8 {
+        self.comet_trail
+    }
+
+    /// Sets the length of the fading trail behind the head in cycle mode (up to
+    /// [`MAX_COMET_TRAIL`]), where zero disables it.
+    ///
+    /// This will be visible from the next cycling step on.
+    pub fn set_comet_trail(&mut self, length: u8) {
+        self.comet_trail = length.min(MAX_COMET_TRAIL);
+    }
+
+    /// Sets the index of the LED that is lit at the next cycling step, e.g. to align the phase
+    /// of several boards.
+    ///
+    /// The cycling continues from there in the direction of cycle mode.
+    pub fn set_index(&mut self, index: usize) -> Result<(), InvalidIndexError> {
+        if index >= self.leds.len() {
+            return Err(InvalidIndexError);
+        }
+        self.index = index;
+
+        Ok(())
+    }
+
+    /// Advances the cycling one step (following the direction of cycle mode).
+    ///
+    /// Depending on the cycle style, the current LED is lit on its own or together with the
+    /// previously lit LED.  If the leading LED is emphasized, the previously lit LED is
+    /// dimmed (see [`lead_duties`]).  With a comet trail, the current LED is lit followed by a
+    /// fading trail instead (see [`comet_duties`]).
+    ///
+    /// This will have have directly visible effect regardless of the mode the
+    /// LED ring is in and override what is shown at that moment.
+    pub fn advance(&mut self) {
+        let num_leds = self.leds.len();
+        let prev_states = self.states;
+        let direction = self.directions[Mode::Cycle.index()];
+
+        if self.comet_trail > 0 {
+            let duties = comet_duties(self.index, self.comet_trail, direction);
+            for (index, duty) in duties.iter().enumerate() {
+                self.set_led(index, *duty > 0);
+                // Overridden LEDs keep the full duty.
+                if self.overrides[index].is_none() {
+                    self.duties[index] = *duty;
+                }
+            }
+            self.notify(prev_states);
+        } else {
+            match self.cycle_style {
+                CycleStyle::Single => {
+                    for index in 0..num_leds {
+                        self.set_led(index, index == self.index);
+                    }
+                }
+                CycleStyle::Pair => {
+                    self.set_led(self.index, true);
+                    self.set_led((self.index + 2) % num_leds, false);
+                }
+            }
+            // The trailing LED is not set in pair style, so reset its duty as well.
+            self.duties = if self.cycle_lead {
+                lead_duties(self.states, self.index)
+            } else {
+                [PWM_STEPS; 4]
+            };
+            self.notify(prev_states);
+        }
+
+        self.index = match direction {
+            Direction::Clockwise => (self.index + 1) % num_leds,
+            Direction::CounterClockwise => (self.index + 3) % num_leds,
+        };
+    }
+
+    /// Advances the chasing dots one tick (following the direction of chase mode).
+    ///
+    /// This will have have directly visible effect regardless of the mode the
+    /// LED ring is in and override what is shown at that moment.
+    pub fn advance_chase(&mut self) {
+        let pattern = self.chase.advance(self.directions[Mode::Chase.index()]);
+        self.specific_on(pattern);
+    }
+
+    /// Advances the sparkling LEDs one step.
+    ///
+    /// This will have have directly visible effect regardless of the mode the
+    /// LED ring is in and override what is shown at that moment.
+    pub fn advance_sparkle(&mut self) {
+        let pattern = self.sparkle.advance();
+        self.specific_on(pattern);
+    }
+
+    /// Shows the next value of the binary count, wrapping around after 15.
+    ///
+    /// This will have have directly visible effect regardless of the mode the
+    /// LED ring is in and override what is shown at that moment.
+    pub fn advance_counter(&mut self) {
+        self.specific_on(counter_pattern(self.counter));
+        self.counter = (self.counter + 1) % COUNTER_VALUES;
+    }
+
+    /// Shows the given step of the self-test sweep.
+    ///
+    /// The sweep lights each LED in order and then turns all of them off.  Returns whether the
+    /// sweep is finished, i.e. the LEDs are off and the ring is ready for the mode to begin.
+    pub fn sweep(&mut self, step: usize) -> bool {
+        if step < self.leds.len() {
+            let mut pattern = [false; 4];
+            pattern[step] = true;
+            self.specific_on(pattern);
+            false
+        } else {
+            self.all_off();
+            true
+        }
+    }
+
+    /// Returns which LEDs are logically on (regardless of the brightness).
+    pub fn states(&self) -> [bool; 4] {
+        self.states
+    }
+
+    /// Turns all LEDs on.
+    ///
+    /// This is done immediately, regardless of the current mode.
+    pub fn all_on(&mut self) {
+        self.specific_on([true; 4]);
+    }
+
+    /// Turns all LEDs off.
+    ///
+    /// This is done immediately, regardless of the current mode.
+    pub fn all_off(&mut self) {
+        self.specific_on([false; 4]);
+    }
+
+    /// Turns all LEDs off, without changing the mode.
+    ///
+    /// Unlike disabling the mode, this only blanks the LEDs momentarily: the animation of the
+    /// mode (if any) continues from the cleared states at its next step, and in a mode without
+    /// animation the LEDs stay off until they are set again.
+    pub fn clear(&mut self) {
+        self.all_off();
+        // Clearing is not an anomaly of the animation.
+        if self.expected_states.is_some() {
+            self.expected_states = Some(self.states);
+        }
+    }
+
+    /// Turns on specific LEDs based on the "direction" array.
+    ///
+    /// When looking with the mini-USB port of the board held down (south), the directions of
+    /// the array can be interpreted as: `[east, south, west, north]`.
+    pub fn specific_on(&mut self, directions: [bool; 4]) {
+        self.set_pattern(&directions);
+    }
+
+    /// Turns on specific LEDs based on a pattern of any length.
+    ///
+    /// The LEDs are set in the same order as for [`LedRing::specific_on`].  Values beyond the
+    /// number of LEDs are ignored, and LEDs beyond the length of the pattern are turned off.
+    ///
+    /// LEDs that already have the state (at full duty) are not written again, so that setting
+    /// the same pattern every period (like accelerometer mode does) leaves the software PWM
+    /// undisturbed instead of making the LEDs flicker.
+    pub fn set_pattern(&mut self, pattern: &[bool]) {
+        let prev_states = self.states;
+
+        for index in 0..self.states.len() {
+            let on_off = pattern.get(index).copied().unwrap_or(false);
+            if !self.is_led_unchanged(index, on_off) {
+                self.set_led(index, on_off);
+            }
+        }
+        self.notify(prev_states);
+    }
+
+    /// Returns the state each LED is forced to, or `None` if it is not overridden.
+    pub fn overrides(&self) -> [Option<bool>; 4] {
+        self.overrides
+    }
+
+    /// Forces the LED with the given index on or off, or lets it follow the mode again (`None`).
+    ///
+    /// An overridden LED ignores all methods that turn LEDs on or off (e.g. to mask a dead LED),
+    /// while the others keep animating.  The override takes effect immediately; once it is
+    /// removed, the LED keeps its state until it is turned on or off next.
+    pub fn set_override(&mut self, index: usize, state: Option<bool>) {
+        let prev_states = self.states;
+
+        self.overrides[index] = state;
+        if let Some(on) = state {
+            self.set_led(index, on);
+        }
+        // The override is not an anomaly of the animation.
+        if self.expected_states.is_some() {
+            self.expected_states = Some(self.states);
+        }
+        self.notify(prev_states);
+    }
+
+    /// Turns on specific LEDs with a specific PWM duty each (up to [`PWM_STEPS`]).
+    ///
+    /// The LEDs with a zero duty are off.  The duties are relative to the brightness and are
+    /// reset to the full duty by the other methods that turn LEDs on or off.
+    pub fn specific_duties(&mut self, duties: [u8; 4]) {
+        let prev_states = self.states;
+
+        for (index, duty) in duties.iter().enumerate() {
+            self.set_led(index, *duty > 0);
+            self.duties[index] = (*duty).min(PWM_STEPS);
+        }
+        self.notify(prev_states);
+    }
+
+    /// Returns the PWM duty of each LED relative to the brightness.
+    pub fn duties(&self) -> [u8; 4] {
+        self.duties
+    }
+
+    /// Returns the brightness of the LEDs that are on.
+    pub fn brightness(&self) -> u8 {
+        self.brightness
+    }
+
+    /// Returns the minimum brightness of the LEDs that are on.
+    pub fn brightness_floor(&self) -> u8 {
+        self.brightness_floor
+    }
+
+    /// Sets the minimum brightness of the LEDs that are on, so that they still visibly glow at
+    /// a low brightness; a brightness of zero still means off, and a floor of zero disables it.
+    pub fn set_brightness_floor(&mut self, floor: u8) {
+        self.brightness_floor = floor;
+    }
+
+    /// Returns the curve that is applied to the brightness.
+    pub fn curve(&self) -> BrightnessCurve {
+        self.curve
+    }
+
+    /// Sets the curve that is applied to the brightness (before raising it to the floor).
+    ///
+    /// This takes effect at the next refresh of the software PWM.
+    pub fn set_curve(&mut self, curve: BrightnessCurve) {
+        self.curve = curve;
+    }
+
+    /// Returns the polarity of the LED outputs.
+    pub fn polarity(&self) -> Polarity {
+        self.polarity
+    }
+
+    /// Sets the brightness of the LEDs that are on.
+    ///
+    /// This takes effect at the next refresh of the software PWM.
+    pub fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = brightness;
+    }
+
+    /// Returns whether night mode is enabled.
+    pub fn is_night_mode(&self) -> bool {
+        self.saved_brightness.is_some()
+    }
+
+    /// Enables or disables night mode.
+    ///
+    /// Enabling night mode halves the brightness, which also dims the blinking of
+    /// acknowledgments.  Disabling it restores the brightness from before it was enabled.
+    pub fn set_night_mode(&mut self, enable: bool) {
+        match (enable, self.saved_brightness) {
+            (true, None) => {
+                self.saved_brightness = Some(self.brightness);
+                self.brightness /= 2;
+            }
+            (false, Some(brightness)) => {
+                self.saved_brightness = None;
+                self.brightness = brightness;
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns the brightness ramp used in ramp mode.
+    pub fn ramp(&self) -> &Ramp {
+        &self.ramp
+    }
+
+    /// Returns the brightness ramp used in ramp mode for modification.
+    pub fn ramp_mut(&mut self) -> &mut Ramp {
+        &mut self.ramp
+    }
+
+    /// Returns the dots used in chase mode.
+    pub fn chase(&self) -> &Chase {
+        &self.chase
+    }
+
+    /// Returns the dots used in chase mode for modification.
+    pub fn chase_mut(&mut self) -> &mut Chase {
+        &mut self.chase
+    }
+
+    /// Returns the breath used in breathe mode.
+    pub fn breathe(&self) -> &Breathe {
+        &self.breathe
+    }
+
+    /// Returns the breath used in breathe mode for modification.
+    pub fn breathe_mut(&mut self) -> &mut Breathe {
+        &mut self.breathe
+    }
+
+    /// Returns the sparkling LEDs used in sparkle mode.
+    pub fn sparkle(&self) -> &Sparkle {
+        &self.sparkle
+    }
+
+    /// Returns the sparkling LEDs used in sparkle mode for modification.
+    pub fn sparkle_mut(&mut self) -> &mut Sparkle {
+        &mut self.sparkle
+    }
+
+    /// Returns the value shown next in counter mode.
+    pub fn counter(&self) -> u8 {
+        self.counter
+    }
+
+    /// Refreshes the LEDs one step of the software PWM period.
+    ///
+    /// This needs to be called [`PWM_STEPS`] times per PWM period.  The LEDs that are on are
+    /// only lit during the part of the period that corresponds to the brightness (or the ramp
+    /// or breath brightness level in ramp or breathe mode) after applying the brightness curve,
+    /// raised to the brightness floor and scaled by their own duty.
+    ///
+    /// Outputs with hardware PWM are given that part as their duty instead of being switched at
+    /// each step.
+    pub fn refresh(&mut self) {
+        let brightness = match self.mode {
+            Mode::Ramp => self.ramp.level(),
+            Mode::Breathe => self.breathe.level(),
+            _ => self.brightness,
+        };
+        let brightness = floored_brightness(self.curve.apply(brightness), self.brightness_floor);
+        let base_duty = duty(brightness) as u16;
+
+        for (index, led) in self.leds.iter_mut().enumerate() {
+            let duty = base_duty * self.duties[index] as u16 / PWM_STEPS as u16;
+            if LED::HARDWARE_PWM {
+                let lit_steps = if self.states[index] { duty as u8 } else { 0 };
+                self.polarity.drive_duty(led, lit_steps);
+            } else {
+                let lit = (self.pwm_step as u16) < duty;
+                self.polarity.drive(led, self.states[index] && lit);
+            }
+        }
+
+        self.pwm_step = (self.pwm_step + 1) % PWM_STEPS;
+    }
+
+    /// Sets the logical state of the LED with the given index (at full duty) and updates its
+    /// output, unless the LED is overridden.
+    fn set_led(&mut self, index: usize, on: bool) {
+        let on = self.overrides[index].unwrap_or(on);
+        self.states[index] = on;
+        self.duties[index] = PWM_STEPS;
+        self.polarity.drive(&mut self.leds[index], on);
+    }
+
+    /// Returns whether setting the LED with the given index would leave its logical state and
+    /// duty as they are.
+    fn is_led_unchanged(&self, index: usize, on: bool) -> bool {
+        let on = self.overrides[index].unwrap_or(on);
+
+        self.states[index] == on && self.duties[index] == PWM_STEPS
+    }
+
+    /// Notifies the sink if the states changed compared to the given previous states.
+    fn notify(&mut self, prev_states: [bool; 4]) {
+        if self.states != prev_states {
+            self.sink.update(self.states);
+        }
+    }
+
+    /// Provides access to the LEDs (for testing purposes only).
+    #[cfg(test)]
+    pub fn leds_mut(&self) -> &[LED; 4] {
+        &self.leds
+    }
+}
+
+impl<LED, SINK> LedRing<LED, SINK>
+where
+    LED: LedOutputState,
+    SINK: StateSink,
+{
+    /// Returns which LEDs are lit according to their outputs (taking the polarity into account).
+    ///
+    /// Unlike [`LedRing::states`], this reads back the outputs, so it can be used to check that
+    /// they follow the logical states.  During the software PWM period, LEDs that are on may
+    /// not be lit all the time.
+    pub fn output_states(&self) -> [bool; 4] {
+        let mut states = [false; 4];
+        for (state, led) in states.iter_mut().zip(self.leds.iter()) {
+            *state = led.is_high() == (self.polarity == Polarity::ActiveHigh);
+        }
+
+        states
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        comet_duties, counter_pattern, duty, floored_brightness, lead_duties, pwm_refresh_cycles,
+        BrightnessCurve, BuildError, CycleStyle, Direction, InvalidIndexError, LedOutput,
+        LedOutputState, LedRing, Mode, PendingStates, Polarity, StateSink, UnknownModeError,
+        COUNTER_VALUES, DEFAULT_BRIGHTNESS_FLOOR, MAX_COMET_TRAIL, PWM_FREQUENCY_HZ, PWM_STEPS,
+        SWEEP_STEPS, TRAIL_DUTY,
+    };
+    use crate::mock::MockOutputPin;
+
+    #[derive(Debug, Default)]
+    struct MockPwmChannel {
+        duty: u8,
+    }
+
+    impl LedOutput for MockPwmChannel {
+        const HARDWARE_PWM: bool = true;
+
+        fn set_level(&mut self, high: bool) {
+            self.duty = if high { PWM_STEPS } else { 0 };
+        }
+
+        fn set_duty(&mut self, high_steps: u8) {
+            self.duty = high_steps;
+        }
+    }
+
+    impl LedOutputState for MockPwmChannel {
+        fn is_high(&self) -> bool {
+            self.duty > 0
+        }
+    }
+
+    #[derive(Debug, Default)]
+    struct MockSink {
+        updates: Vec<[bool; 4]>,
+    }
+
+    impl StateSink for MockSink {
+        fn update(&mut self, states: [bool; 4]) {
+            self.updates.push(states);
+        }
+    }
+
+    macro_rules! assert_pins {
+        ($pins:expr, [$pin0:expr, $pin1:expr, $pin2:expr, $pin3:expr]) => {{
+            assert_eq!($pins[0].state, $pin0, "(mock pin 0)");
+            assert_eq!($pins[1].state, $pin1, "(mock pin 1)");
+            assert_eq!($pins[2].state, $pin2, "(mock pin 2)");
+            assert_eq!($pins[3].state, $pin3, "(mock pin 3)");
+        }};
+    }
+
+    #[test]
+    fn direction_flip() {
+        let cw_dir = Direction::Clockwise;
+        assert_eq!(cw_dir.flip(), Direction::CounterClockwise);
+        let ccw_dir = Direction::CounterClockwise;
+        assert_eq!(ccw_dir.flip(), Direction::Clockwise);
+    }
+
+    #[test]
+    fn mode_names() {
+        for mode in Mode::ALL.iter() {
+            assert_eq!(Mode::from_name(mode.as_name().as_bytes()), Ok(*mode));
+        }
+        assert_eq!(Mode::Accelerometer.as_name(), "accel");
+        assert_eq!(Mode::from_name(b"cycle"), Ok(Mode::Cycle));
+        assert_eq!(Mode::from_name(b"foo"), Err(UnknownModeError));
+        assert_eq!(Mode::from_name(b"Cycle"), Err(UnknownModeError));
+        assert_eq!(Mode::from_name(b""), Err(UnknownModeError));
+        for (index, mode) in Mode::ALL.iter().enumerate() {
+            assert_eq!(mode.index(), index);
+        }
+    }
+
+    #[test]
+    fn led_ring_init() {
+        let mock_leds = MockOutputPin::get_4();
+        let led_ring = LedRing::<MockOutputPin>::from(mock_leds);
+
+        assert_eq!(led_ring.direction(), Direction::Clockwise);
+        assert_eq!(led_ring.mode(), Mode::Cycle);
+        assert_eq!(led_ring.brightness(), 255);
+    }
+
+    #[test]
+    fn led_ring_builder() {
+        let mock_leds = MockOutputPin::get_4();
+        let led_ring = LedRing::builder(mock_leds).build().unwrap();
+
+        // Without configuration it is the same as set up from the LEDs.
+        assert_eq!(led_ring.direction(), Direction::Clockwise);
+        assert_eq!(led_ring.mode(), Mode::Cycle);
+        assert_eq!(led_ring.brightness(), 255);
+        assert_eq!(led_ring.polarity(), Polarity::ActiveHigh);
+        assert_pins!(led_ring.leds_mut(), [false, false, false, false]);
+
+        let mock_leds = MockOutputPin::get_4();
+        let mut led_ring = LedRing::builder(mock_leds)
+            .brightness(1)
+            .polarity(Polarity::ActiveLow)
+            .direction(Direction::CounterClockwise)
+            .build()
+            .unwrap();
+        assert_eq!(led_ring.direction(), Direction::CounterClockwise);
+        assert_eq!(led_ring.brightness(), 1);
+        assert_eq!(led_ring.polarity(), Polarity::ActiveLow);
+        // The outputs of LEDs that are off are high.
+        assert_pins!(led_ring.leds_mut(), [true, true, true, true]);
+
+        // The direction applies to all modes.
+        led_ring.set_mode(Mode::Chase);
+        assert_eq!(led_ring.direction(), Direction::CounterClockwise);
+
+        led_ring.specific_on([true, false, true, false]);
+        assert_pins!(led_ring.leds_mut(), [false, true, false, true]);
+    }
+
+    #[test]
+    fn led_ring_builder_invalid() {
+        let mock_leds = MockOutputPin::get_4();
+        let result = LedRing::builder(mock_leds).brightness(0).build();
+        assert_eq!(result.err(), Some(BuildError::ZeroBrightness));
+        assert_eq!(BuildError::ZeroBrightness.as_str(), "zero brightness");
+
+        // The last brightness set is validated.
+        let mock_leds = MockOutputPin::get_4();
+        let result = LedRing::builder(mock_leds)
+            .brightness(0)
+            .polarity(Polarity::ActiveLow)
+            .brightness(10)
+            .build();
+        assert_eq!(result.map(|led_ring| led_ring.brightness()).ok(), Some(10));
+    }
+
+    #[test]
+    fn led_ring_output_states() {
+        let mock_leds = MockOutputPin::get_4();
+        let mut led_ring = LedRing::<MockOutputPin>::from(mock_leds);
+        led_ring.specific_on([true, false, true, false]);
+        assert_eq!(led_ring.output_states(), [true, false, true, false]);
+
+        let mock_leds = MockOutputPin::get_4();
+        let mut led_ring = LedRing::builder(mock_leds)
+            .polarity(Polarity::ActiveLow)
+            .build()
+            .unwrap();
+        assert_eq!(led_ring.output_states(), [false, false, false, false]);
+        led_ring.specific_on([false, true, true, false]);
+        assert_eq!(led_ring.output_states(), [false, true, true, false]);
+    }
+
+    #[test]
+    fn led_ring_refresh_active_low() {
+        let mock_leds = MockOutputPin::get_4();
+        let mut led_ring = LedRing::builder(mock_leds)
+            .brightness(128)
+            .polarity(Polarity::ActiveLow)
+            .build()
+            .unwrap();
+
+        led_ring.specific_on([true, true, false, false]);
+        // At half brightness the LEDs that are on are only lit (low) during half of the period.
+        let mut lit_steps = 0;
+        for _ in 0..PWM_STEPS {
+            led_ring.refresh();
+            if !led_ring.leds_mut()[0].state {
+                lit_steps += 1;
+            }
+            assert!(led_ring.leds_mut()[2].state);
+        }
+        assert_eq!(lit_steps, PWM_STEPS / 2);
     }
 
     #[test]
@@ -228,53 +1403,524 @@ mod tests {
         let mock_leds = MockOutputPin::get_4();
         let mut led_ring = LedRing::<MockOutputPin>::from(mock_leds);
 
-        led_ring.enable_accel();
-        assert_eq!(led_ring.mode(), Mode::Accelerometer);
-        assert!(led_ring.is_mode_accel());
-        assert!(!led_ring.is_mode_cycle());
+        led_ring.enable_accel();
+        assert_eq!(led_ring.mode(), Mode::Accelerometer);
+        assert!(led_ring.is_mode_accel());
+        assert!(!led_ring.is_mode_cycle());
+
+        led_ring.disable();
+        assert_eq!(led_ring.mode(), Mode::Off);
+        assert!(!led_ring.is_mode_accel());
+        assert!(!led_ring.is_mode_cycle());
+
+        led_ring.enable_cycle();
+        assert_eq!(led_ring.mode(), Mode::Cycle);
+        assert!(!led_ring.is_mode_accel());
+        assert!(led_ring.is_mode_cycle());
+
+        led_ring.enable_ramp();
+        assert_eq!(led_ring.mode(), Mode::Ramp);
+        assert!(led_ring.is_mode_ramp());
+        assert!(!led_ring.is_mode_cycle());
+
+        led_ring.enable_chase();
+        assert_eq!(led_ring.mode(), Mode::Chase);
+        assert!(led_ring.is_mode_chase());
+        assert!(!led_ring.is_mode_ramp());
+
+        led_ring.enable_breathe();
+        assert_eq!(led_ring.mode(), Mode::Breathe);
+        assert!(led_ring.is_mode_breathe());
+        assert!(!led_ring.is_mode_chase());
+
+        led_ring.enable_sparkle();
+        assert_eq!(led_ring.mode(), Mode::Sparkle);
+        assert!(led_ring.is_mode_sparkle());
+        assert!(!led_ring.is_mode_breathe());
+
+        led_ring.enable_counter();
+        assert_eq!(led_ring.mode(), Mode::Counter);
+        assert!(led_ring.is_mode_counter());
+        assert!(!led_ring.is_mode_sparkle());
+
+        led_ring.set_mode(Mode::Accelerometer);
+        assert_eq!(led_ring.mode(), Mode::Accelerometer);
+        assert!(led_ring.is_mode_accel());
+    }
+
+    #[test]
+    fn led_ring_claim_task() {
+        let mock_leds = MockOutputPin::get_4();
+        let mut led_ring = LedRing::<MockOutputPin>::from(mock_leds);
+
+        assert!(!led_ring.is_task_pending(Mode::Cycle));
+        assert!(led_ring.claim_task(Mode::Cycle));
+        assert!(led_ring.is_task_pending(Mode::Cycle));
+        // A pending task is not claimed twice.
+        assert!(!led_ring.claim_task(Mode::Cycle));
+        // The tasks of different modes are independent.
+        assert!(led_ring.claim_task(Mode::Chase));
+        led_ring.release_task(Mode::Cycle);
+        assert!(!led_ring.is_task_pending(Mode::Cycle));
+        assert!(led_ring.is_task_pending(Mode::Chase));
+        assert!(led_ring.claim_task(Mode::Cycle));
+        // Off mode has no task.
+        assert!(!led_ring.claim_task(Mode::Off));
+        assert!(!led_ring.is_task_pending(Mode::Off));
+    }
+
+    #[test]
+    fn led_ring_direction() {
+        let mock_leds = MockOutputPin::get_4();
+        let mut led_ring = LedRing::<MockOutputPin>::from(mock_leds);
+
+        led_ring.reverse();
+        assert_eq!(led_ring.direction(), Direction::CounterClockwise);
+
+        led_ring.reverse();
+        assert_eq!(led_ring.direction(), Direction::Clockwise);
+
+        led_ring.set_direction(Direction::CounterClockwise);
+        assert_eq!(led_ring.direction(), Direction::CounterClockwise);
+
+        for direction in [Direction::Clockwise, Direction::CounterClockwise].iter() {
+            let name = direction.as_name().as_bytes();
+            assert_eq!(Direction::from_name(name), Some(*direction));
+        }
+        assert_eq!(Direction::from_name(b"up"), None);
+    }
+
+    #[test]
+    fn led_ring_direction_per_mode() {
+        let mock_leds = MockOutputPin::get_4();
+        let mut led_ring = LedRing::<MockOutputPin>::from(mock_leds);
+
+        // Reversing in cycle mode does not affect chase mode, and vice versa.
+        led_ring.enable_cycle();
+        led_ring.reverse();
+        assert_eq!(led_ring.direction(), Direction::CounterClockwise);
+        led_ring.enable_chase();
+        assert_eq!(led_ring.direction(), Direction::Clockwise);
+        led_ring.reverse();
+        led_ring.reverse();
+        led_ring.reverse();
+        assert_eq!(led_ring.direction(), Direction::CounterClockwise);
+        led_ring.enable_cycle();
+        led_ring.reverse();
+        assert_eq!(led_ring.direction(), Direction::Clockwise);
+        led_ring.enable_chase();
+        assert_eq!(led_ring.direction(), Direction::CounterClockwise);
+
+        // The directions persist while sleeping.
+        led_ring.sleep();
+        assert!(led_ring.wake());
+        assert_eq!(led_ring.direction(), Direction::CounterClockwise);
+
+        // Advancing follows the direction of the corresponding mode, regardless of the current
+        // mode.
+        led_ring.advance();
+        assert_pins!(led_ring.leds_mut(), [true, false, false, false]);
+        led_ring.advance();
+        assert_pins!(led_ring.leds_mut(), [true, true, false, false]);
+
+        // The direction of another mode can be set before switching to it.
+        led_ring.set_mode_direction(Mode::Cycle, Direction::Clockwise);
+        assert_eq!(led_ring.direction(), Direction::CounterClockwise);
+        led_ring.enable_cycle();
+        assert_eq!(led_ring.direction(), Direction::Clockwise);
+    }
+
+    #[test]
+    fn led_ring_sleep_wake() {
+        let mock_leds = MockOutputPin::get_4();
+        let mut led_ring = LedRing::<MockOutputPin>::from(mock_leds);
+
+        assert!(!led_ring.wake());
+
+        led_ring.enable_accel();
+        led_ring.specific_on([true, true, false, false]);
+        led_ring.sleep();
+        assert!(led_ring.is_sleeping());
+        assert_eq!(led_ring.mode(), Mode::Off);
+        assert_pins!(led_ring.leds_mut(), [false, false, false, false]);
+
+        // Going to sleep again does not overwrite what to resume.
+        led_ring.sleep();
+        assert!(led_ring.wake());
+        assert!(!led_ring.is_sleeping());
+        assert_eq!(led_ring.mode(), Mode::Accelerometer);
+        assert_pins!(led_ring.leds_mut(), [true, true, false, false]);
+
+        assert!(!led_ring.wake());
+        assert_eq!(led_ring.mode(), Mode::Accelerometer);
+    }
+
+    #[test]
+    fn led_ring_expected_states() {
+        let mock_leds = MockOutputPin::get_4();
+        let mut led_ring = LedRing::<MockOutputPin>::from(mock_leds);
+
+        // Nothing is expected before the first step.
+        led_ring.all_on();
+        assert!(led_ring.states_as_expected());
+
+        led_ring.advance();
+        led_ring.expect_states();
+        assert!(led_ring.states_as_expected());
+        led_ring.set_brightness(10);
+        led_ring.reverse();
+        assert!(led_ring.states_as_expected());
+
+        // Changing the states without changing the mode is inconsistent.
+        led_ring.all_off();
+        assert!(!led_ring.states_as_expected());
+        led_ring.expect_states();
+        assert!(led_ring.states_as_expected());
+
+        // Changing the mode (also by sleeping and waking up) forgets the expected states.
+        for change_mode in [LedRing::disable, LedRing::enable_cycle, LedRing::sleep].iter() {
+            led_ring.expect_states();
+            change_mode(&mut led_ring);
+            led_ring.all_on();
+            assert!(led_ring.states_as_expected());
+        }
+        led_ring.expect_states();
+        led_ring.wake();
+        led_ring.all_off();
+        assert!(led_ring.states_as_expected());
+    }
+
+    #[test]
+    fn led_ring_advance() {
+        let mock_leds = MockOutputPin::get_4();
+        let mut led_ring = LedRing::<MockOutputPin>::from(mock_leds);
+
+        assert_pins!(led_ring.leds_mut(), [false, false, false, false]);
+        led_ring.advance();
+        assert_pins!(led_ring.leds_mut(), [true, false, false, false]);
+        led_ring.advance();
+        assert_pins!(led_ring.leds_mut(), [true, true, false, false]);
+        led_ring.advance();
+        assert_pins!(led_ring.leds_mut(), [false, true, true, false]);
+        led_ring.advance();
+        assert_pins!(led_ring.leds_mut(), [false, false, true, true]);
+        led_ring.advance();
+        assert_pins!(led_ring.leds_mut(), [true, false, false, true]);
+        led_ring.advance();
+        assert_pins!(led_ring.leds_mut(), [true, true, false, false]);
+        led_ring.advance();
+    }
+
+    #[test]
+    fn led_ring_set_index() {
+        let mock_leds = MockOutputPin::get_4();
+        let mut led_ring = LedRing::<MockOutputPin>::from(mock_leds);
+
+        assert_eq!(led_ring.set_index(4), Err(InvalidIndexError));
+        assert_eq!(led_ring.set_index(usize::MAX), Err(InvalidIndexError));
+        assert_eq!(led_ring.set_index(2), Ok(()));
+        led_ring.advance();
+        assert_pins!(led_ring.leds_mut(), [false, false, true, false]);
+        led_ring.advance();
+        assert_pins!(led_ring.leds_mut(), [false, false, true, true]);
+        led_ring.advance();
+        assert_pins!(led_ring.leds_mut(), [true, false, false, true]);
+
+        // Setting the index in between steps continues from there (counter-clockwise).
+        led_ring.set_cycle_style(CycleStyle::Single);
+        led_ring.reverse();
+        assert_eq!(led_ring.set_index(3), Ok(()));
+        led_ring.advance();
+        assert_pins!(led_ring.leds_mut(), [false, false, false, true]);
+        led_ring.advance();
+        assert_pins!(led_ring.leds_mut(), [false, false, true, false]);
+        led_ring.advance();
+        assert_pins!(led_ring.leds_mut(), [false, true, false, false]);
+    }
+
+    #[test]
+    fn led_ring_advance_styles() {
+        let mock_leds = MockOutputPin::get_4();
+        let mut led_ring = LedRing::<MockOutputPin>::from(mock_leds);
+        assert_eq!(led_ring.cycle_style(), CycleStyle::Pair);
+
+        // A single LED goes round, also when switching from the pair style.
+        led_ring.advance();
+        led_ring.advance();
+        assert_pins!(led_ring.leds_mut(), [true, true, false, false]);
+        led_ring.set_cycle_style(CycleStyle::Single);
+        led_ring.advance();
+        assert_pins!(led_ring.leds_mut(), [false, false, true, false]);
+        led_ring.advance();
+        assert_pins!(led_ring.leds_mut(), [false, false, false, true]);
+        led_ring.advance();
+        assert_pins!(led_ring.leds_mut(), [true, false, false, false]);
+        led_ring.advance();
+        assert_pins!(led_ring.leds_mut(), [false, true, false, false]);
+        led_ring.advance();
+        assert_pins!(led_ring.leds_mut(), [false, false, true, false]);
+
+        led_ring.reverse();
+        led_ring.advance();
+        assert_pins!(led_ring.leds_mut(), [false, false, false, true]);
+        led_ring.advance();
+        assert_pins!(led_ring.leds_mut(), [false, false, true, false]);
+
+        // A full revolution in the pair style.
+        led_ring.reverse();
+        led_ring.set_cycle_style(CycleStyle::Pair);
+        led_ring.advance();
+        let mut seen = [false; 4];
+        for _ in 0..4 {
+            led_ring.advance();
+            let states = led_ring.states();
+            assert_eq!(states.iter().filter(|state| **state).count(), 2);
+            for (seen, state) in seen.iter_mut().zip(states.iter()) {
+                *seen |= *state;
+            }
+        }
+        assert_eq!(seen, [true; 4]);
+    }
+
+    #[test]
+    fn cycle_style_names() {
+        for style in [CycleStyle::Single, CycleStyle::Pair].iter() {
+            assert_eq!(
+                CycleStyle::from_name(style.as_name().as_bytes()),
+                Some(*style)
+            );
+        }
+        assert_eq!(CycleStyle::from_name(b"triple"), None);
+    }
+
+    #[test]
+    fn led_ring_overrides() {
+        let mock_leds = MockOutputPin::get_4();
+        let mut led_ring = LedRing::<MockOutputPin>::from(mock_leds);
+        led_ring.set_cycle_style(CycleStyle::Single);
+        assert_eq!(led_ring.overrides(), [None; 4]);
+
+        // Overrides take effect immediately.
+        led_ring.set_override(1, Some(false));
+        led_ring.set_override(2, Some(true));
+        assert_eq!(led_ring.overrides(), [None, Some(false), Some(true), None]);
+        assert_eq!(led_ring.states(), [false, false, true, false]);
+        assert_pins!(led_ring.leds_mut(), [false, false, true, false]);
+
+        // The overridden LEDs ignore the animation, while the others still animate.
+        led_ring.advance();
+        assert_eq!(led_ring.states(), [true, false, true, false]);
+        led_ring.advance();
+        assert_eq!(led_ring.states(), [false, false, true, false]);
+        led_ring.advance();
+        assert_eq!(led_ring.states(), [false, false, true, false]);
+        led_ring.advance();
+        assert_eq!(led_ring.states(), [false, false, true, true]);
+        assert_pins!(led_ring.leds_mut(), [false, false, true, true]);
+        led_ring.all_on();
+        assert_eq!(led_ring.states(), [true, false, true, true]);
+        led_ring.specific_duties([0; 4]);
+        assert_eq!(led_ring.states(), [false, false, true, false]);
+
+        // Without override, the LED keeps its state until it is turned on or off next.
+        led_ring.set_override(2, None);
+        assert_eq!(led_ring.states(), [false, false, true, false]);
+        led_ring.all_off();
+        assert_eq!(led_ring.states(), [false; 4]);
+        led_ring.set_override(1, None);
+        led_ring.all_on();
+        assert_eq!(led_ring.states(), [true; 4]);
+        assert_pins!(led_ring.leds_mut(), [true, true, true, true]);
+    }
 
+    #[test]
+    fn led_ring_clear() {
+        let mock_leds = MockOutputPin::get_4();
+        let mut led_ring = LedRing::<MockOutputPin>::from(mock_leds);
+
+        // Without a mode, the LEDs are turned off and stay off.
         led_ring.disable();
+        led_ring.all_on();
+        led_ring.clear();
         assert_eq!(led_ring.mode(), Mode::Off);
-        assert!(!led_ring.is_mode_accel());
-        assert!(!led_ring.is_mode_cycle());
+        assert_eq!(led_ring.states(), [false; 4]);
+        assert_pins!(led_ring.leds_mut(), [false, false, false, false]);
 
+        // In a mode, the mode is preserved and clearing is not reported as an anomaly.
+        for enable_mode in [LedRing::enable_cycle, LedRing::enable_accel].iter() {
+            enable_mode(&mut led_ring);
+            let mode = led_ring.mode();
+            led_ring.all_on();
+            led_ring.expect_states();
+            led_ring.clear();
+            assert_eq!(led_ring.mode(), mode);
+            assert_eq!(led_ring.states(), [false; 4]);
+            assert!(led_ring.states_as_expected());
+        }
+
+        // The animation continues from the cleared states.
         led_ring.enable_cycle();
+        led_ring.clear();
+        led_ring.advance();
         assert_eq!(led_ring.mode(), Mode::Cycle);
-        assert!(!led_ring.is_mode_accel());
-        assert!(led_ring.is_mode_cycle());
+        assert_eq!(led_ring.states().iter().filter(|on| **on).count(), 1);
     }
 
     #[test]
-    fn led_ring_direction() {
+    fn led_ring_override_expected_states() {
         let mock_leds = MockOutputPin::get_4();
         let mut led_ring = LedRing::<MockOutputPin>::from(mock_leds);
 
-        led_ring.reverse();
-        assert_eq!(led_ring.direction(), Direction::CounterClockwise);
+        // Overriding an LED is not reported as an anomaly of the animation.
+        led_ring.advance();
+        led_ring.expect_states();
+        led_ring.set_override(3, Some(true));
+        assert!(led_ring.states_as_expected());
+    }
 
-        led_ring.reverse();
-        assert_eq!(led_ring.direction(), Direction::Clockwise);
+    #[test]
+    fn lead_duty_mapping() {
+        assert_eq!(
+            lead_duties([true, true, false, false], 1),
+            [TRAIL_DUTY, PWM_STEPS, 0, 0]
+        );
+        assert_eq!(
+            lead_duties([true, false, false, true], 0),
+            [PWM_STEPS, 0, 0, TRAIL_DUTY]
+        );
+        assert_eq!(
+            lead_duties([false, false, true, false], 2),
+            [0, 0, PWM_STEPS, 0]
+        );
+        assert_eq!(lead_duties([false; 4], 3), [0; 4]);
     }
 
     #[test]
-    fn led_ring_advance() {
+    fn led_ring_cycle_lead() {
         let mock_leds = MockOutputPin::get_4();
         let mut led_ring = LedRing::<MockOutputPin>::from(mock_leds);
+        assert!(!led_ring.cycle_lead());
 
-        assert_pins!(led_ring.leds_mut(), [false, false, false, false]);
+        led_ring.set_cycle_lead(true);
+        assert!(led_ring.cycle_lead());
         led_ring.advance();
-        assert_pins!(led_ring.leds_mut(), [true, false, false, false]);
+        assert_eq!(led_ring.duties(), [PWM_STEPS, 0, 0, 0]);
         led_ring.advance();
-        assert_pins!(led_ring.leds_mut(), [true, true, false, false]);
+        assert_eq!(led_ring.states(), [true, true, false, false]);
+        assert_eq!(led_ring.duties(), [TRAIL_DUTY, PWM_STEPS, 0, 0]);
         led_ring.advance();
-        assert_pins!(led_ring.leds_mut(), [false, true, true, false]);
+        assert_eq!(led_ring.states(), [false, true, true, false]);
+        assert_eq!(led_ring.duties(), [0, TRAIL_DUTY, PWM_STEPS, 0]);
+
+        // The leading LED follows the direction.
+        led_ring.reverse();
         led_ring.advance();
-        assert_pins!(led_ring.leds_mut(), [false, false, true, true]);
         led_ring.advance();
-        assert_pins!(led_ring.leds_mut(), [true, false, false, true]);
+        assert_eq!(led_ring.states(), [false, false, true, true]);
+        assert_eq!(led_ring.duties(), [0, 0, PWM_STEPS, TRAIL_DUTY]);
+
+        // Without emphasis, all LEDs that are on get the full duty again.
+        led_ring.set_cycle_lead(false);
         led_ring.advance();
-        assert_pins!(led_ring.leds_mut(), [true, true, false, false]);
+        assert_eq!(led_ring.states(), [false, true, true, false]);
+        assert_eq!(led_ring.duties(), [PWM_STEPS; 4]);
+    }
+
+    #[test]
+    fn comet_fading() {
+        let cw = Direction::Clockwise;
+        let ccw = Direction::CounterClockwise;
+
+        // Without trail, only the head is lit.
+        assert_eq!(comet_duties(1, 0, cw), [0, PWM_STEPS, 0, 0]);
+        // The trail fades in equal steps behind the head.
+        assert_eq!(comet_duties(0, 1, cw), [PWM_STEPS, 0, 0, 8]);
+        assert_eq!(comet_duties(0, 2, cw), [PWM_STEPS, 0, 5, 10]);
+        assert_eq!(comet_duties(0, 3, cw), [PWM_STEPS, 4, 8, 12]);
+        assert_eq!(comet_duties(2, 3, cw), [8, 12, PWM_STEPS, 4]);
+        // Counter clock-wise, the trail is on the other side of the head.
+        assert_eq!(comet_duties(0, 1, ccw), [PWM_STEPS, 8, 0, 0]);
+        assert_eq!(comet_duties(0, 3, ccw), [PWM_STEPS, 12, 8, 4]);
+        assert_eq!(comet_duties(3, 2, ccw), [10, 5, 0, PWM_STEPS]);
+    }
+
+    #[test]
+    fn led_ring_comet() {
+        let mock_leds = MockOutputPin::get_4();
+        let mut led_ring = LedRing::<MockOutputPin>::from(mock_leds);
+        assert_eq!(led_ring.comet_trail(), 0);
+
+        led_ring.set_comet_trail(2);
+        assert_eq!(led_ring.comet_trail(), 2);
+        // The trail moves along with the head, which overrides the cycle style.
+        let steps = [
+            ([true, false, true, true], [PWM_STEPS, 0, 5, 10]),
+            ([true, true, false, true], [10, PWM_STEPS, 0, 5]),
+            ([true, true, true, false], [5, 10, PWM_STEPS, 0]),
+            ([false, true, true, true], [0, 5, 10, PWM_STEPS]),
+            ([true, false, true, true], [PWM_STEPS, 0, 5, 10]),
+        ];
+        for (states, duties) in steps.iter() {
+            led_ring.advance();
+            assert_eq!(led_ring.states(), *states);
+            assert_eq!(led_ring.duties(), *duties);
+        }
+
+        // The trail follows the direction.
+        led_ring.reverse();
+        led_ring.advance();
+        led_ring.advance();
+        assert_eq!(led_ring.duties(), [PWM_STEPS, 10, 5, 0]);
+        led_ring.advance();
+        assert_eq!(led_ring.duties(), [10, 5, 0, PWM_STEPS]);
+
+        // The trail is limited to the other LEDs of the ring.
+        led_ring.set_comet_trail(10);
+        assert_eq!(led_ring.comet_trail(), MAX_COMET_TRAIL);
+
+        // Without trail, cycling is as before (the pair style only turns off the LED opposite
+        // of the head, so the rest of the trail is gone after the next step).
+        led_ring.set_comet_trail(0);
         led_ring.advance();
+        assert_eq!(led_ring.duties(), [PWM_STEPS; 4]);
+        led_ring.advance();
+        assert_eq!(led_ring.states(), [false, true, true, false]);
+    }
+
+    #[test]
+    fn led_ring_advance_chase() {
+        let mock_leds = MockOutputPin::get_4();
+        let mut led_ring = LedRing::<MockOutputPin>::from(mock_leds);
+
+        led_ring.enable_chase();
+        led_ring.chase_mut().set_rates([1, 2]);
+        led_ring.advance_chase();
+        assert_pins!(led_ring.leds_mut(), [false, true, true, false]);
+        led_ring.advance_chase();
+        assert_pins!(led_ring.leds_mut(), [false, false, true, true]);
+
+        led_ring.reverse();
+        led_ring.advance_chase();
+        assert_pins!(led_ring.leds_mut(), [false, true, false, true]);
+        assert_eq!(led_ring.chase().positions(), [1, 3]);
+    }
+
+    #[test]
+    fn led_ring_sweep() {
+        let mock_leds = MockOutputPin::get_4();
+        let mut led_ring = LedRing::<MockOutputPin>::from(mock_leds);
+
+        assert!(!led_ring.sweep(0));
+        assert_pins!(led_ring.leds_mut(), [true, false, false, false]);
+        assert!(!led_ring.sweep(1));
+        assert_pins!(led_ring.leds_mut(), [false, true, false, false]);
+        assert!(!led_ring.sweep(2));
+        assert_pins!(led_ring.leds_mut(), [false, false, true, false]);
+        assert!(!led_ring.sweep(3));
+        assert_pins!(led_ring.leds_mut(), [false, false, false, true]);
+        assert!(led_ring.sweep(SWEEP_STEPS - 1));
+        assert_pins!(led_ring.leds_mut(), [false, false, false, false]);
+        assert_eq!(led_ring.mode(), Mode::Cycle);
     }
 
     #[test]
@@ -297,5 +1943,477 @@ mod tests {
         assert_pins!(led_ring.leds_mut(), [false, false, false, false]);
         led_ring.specific_on([true, false, true, false]);
         assert_pins!(led_ring.leds_mut(), [true, false, true, false]);
+        assert_eq!(led_ring.states(), [true, false, true, false]);
+    }
+
+    #[test]
+    fn pwm_duty() {
+        assert_eq!(duty(0), 0);
+        assert_eq!(duty(15), 0);
+        assert_eq!(duty(128), PWM_STEPS / 2);
+        assert_eq!(duty(255), PWM_STEPS);
+        // The default floor is the lowest brightness that is lit at all.
+        assert_eq!(duty(DEFAULT_BRIGHTNESS_FLOOR - 1), 0);
+        assert_eq!(duty(DEFAULT_BRIGHTNESS_FLOOR), 1);
+        // A higher brightness is never lit shorter.
+        for brightness in 1..=u8::MAX {
+            assert!(duty(brightness) >= duty(brightness - 1));
+        }
+    }
+
+    #[test]
+    fn pwm_refresh_period() {
+        // At the default 16 MHz clock, a refresh every 10000 cycles gives a 100 Hz period.
+        assert_eq!(PWM_FREQUENCY_HZ, 100);
+        assert_eq!(pwm_refresh_cycles(16_000_000), 10_000);
+        assert_eq!(
+            pwm_refresh_cycles(16_000_000) * PWM_STEPS as u32 * PWM_FREQUENCY_HZ,
+            16_000_000
+        );
+        assert_eq!(pwm_refresh_cycles(168_000_000), 105_000);
+    }
+
+    #[test]
+    fn pwm_brightness_floor() {
+        // Zero brightness stays off.
+        assert_eq!(floored_brightness(0, 16), 0);
+        assert_eq!(floored_brightness(0, 255), 0);
+        // Brightnesses between 1 and the floor are raised to the floor.
+        for brightness in 1..=16 {
+            assert_eq!(floored_brightness(brightness, 16), 16);
+        }
+        assert_eq!(floored_brightness(17, 16), 17);
+        assert_eq!(floored_brightness(255, 16), 255);
+        // A floor of zero leaves the brightness as is.
+        assert_eq!(floored_brightness(1, 0), 1);
+    }
+
+    #[test]
+    fn counter_bits() {
+        assert_eq!(counter_pattern(0), [false, false, false, false]);
+        assert_eq!(counter_pattern(1), [true, false, false, false]);
+        assert_eq!(counter_pattern(2), [false, true, false, false]);
+        assert_eq!(counter_pattern(4), [false, false, true, false]);
+        assert_eq!(counter_pattern(8), [false, false, false, true]);
+        assert_eq!(counter_pattern(15), [true, true, true, true]);
+        // Every value maps back to itself when reading the LEDs as bits.
+        for value in 0..COUNTER_VALUES {
+            let pattern = counter_pattern(value);
+            let read = pattern
+                .iter()
+                .enumerate()
+                .fold(0, |acc, (bit, &on)| acc | ((on as u8) << bit));
+            assert_eq!(read, value);
+        }
+    }
+
+    #[test]
+    fn led_ring_counter() {
+        let mock_leds = MockOutputPin::get_4();
+        let mut led_ring = LedRing::<MockOutputPin>::from(mock_leds);
+
+        led_ring.enable_counter();
+        assert_eq!(led_ring.counter(), 0);
+        for value in 0..COUNTER_VALUES {
+            led_ring.advance_counter();
+            assert_eq!(led_ring.states(), counter_pattern(value));
+        }
+        // The count wraps around after 15.
+        assert_eq!(led_ring.counter(), 0);
+        led_ring.advance_counter();
+        assert_pins!(led_ring.leds_mut(), [false, false, false, false]);
+        led_ring.advance_counter();
+        assert_pins!(led_ring.leds_mut(), [true, false, false, false]);
+    }
+
+    #[test]
+    fn led_ring_refresh() {
+        let mock_leds = MockOutputPin::get_4();
+        let mut led_ring = LedRing::<MockOutputPin>::from(mock_leds);
+
+        // At full brightness the LEDs follow their logical state during the whole period.
+        led_ring.specific_on([true, false, true, false]);
+        for _ in 0..PWM_STEPS {
+            led_ring.refresh();
+            assert_pins!(led_ring.leds_mut(), [true, false, true, false]);
+        }
+
+        // At half brightness the LEDs that are on are only lit during half of the period.
+        led_ring.set_brightness(128);
+        for step in 0..PWM_STEPS {
+            led_ring.refresh();
+            let lit = step < PWM_STEPS / 2;
+            assert_pins!(led_ring.leds_mut(), [lit, false, lit, false]);
+        }
+
+        // At zero brightness the LEDs are never lit.
+        led_ring.set_brightness(0);
+        for _ in 0..PWM_STEPS {
+            led_ring.refresh();
+            assert_pins!(led_ring.leds_mut(), [false, false, false, false]);
+        }
+    }
+
+    #[test]
+    fn led_ring_refresh_between_steps() {
+        let mock_leds = MockOutputPin::get_4();
+        let mut led_ring = LedRing::<MockOutputPin>::from(mock_leds);
+        led_ring.set_brightness(128);
+
+        // An animation step in the middle of a PWM period does not restart it, so every period
+        // is lit for the duty of the brightness however the steps and refreshes interleave.
+        led_ring.specific_on([true, false, false, false]);
+        for _ in 0..PWM_STEPS / 4 {
+            led_ring.refresh();
+        }
+        led_ring.specific_on([true, false, false, false]);
+        let mut lit_steps = 0;
+        for _ in 0..PWM_STEPS {
+            led_ring.refresh();
+            if led_ring.leds_mut()[0].state {
+                lit_steps += 1;
+            }
+        }
+        assert_eq!(lit_steps, duty(128));
+    }
+
+    #[test]
+    fn led_ring_refresh_floor() {
+        let mock_leds = MockOutputPin::get_4();
+        let mut led_ring = LedRing::<MockOutputPin>::from(mock_leds);
+        assert_eq!(led_ring.brightness_floor(), DEFAULT_BRIGHTNESS_FLOOR);
+
+        // At a brightness below the floor the LEDs that are on still glow.
+        led_ring.specific_on([true, true, false, false]);
+        led_ring.set_brightness(1);
+        for step in 0..PWM_STEPS {
+            led_ring.refresh();
+            let lit = step < duty(DEFAULT_BRIGHTNESS_FLOOR);
+            assert_pins!(led_ring.leds_mut(), [lit, lit, false, false]);
+        }
+
+        // A higher floor raises them further.
+        led_ring.set_brightness_floor(128);
+        for step in 0..PWM_STEPS {
+            led_ring.refresh();
+            let lit = step < PWM_STEPS / 2;
+            assert_pins!(led_ring.leds_mut(), [lit, lit, false, false]);
+        }
+
+        // Zero brightness still means off.
+        led_ring.set_brightness(0);
+        for _ in 0..PWM_STEPS {
+            led_ring.refresh();
+            assert_pins!(led_ring.leds_mut(), [false, false, false, false]);
+        }
+
+        // Without floor, a low brightness is not lit at all.
+        led_ring.set_brightness_floor(0);
+        led_ring.set_brightness(1);
+        for _ in 0..PWM_STEPS {
+            led_ring.refresh();
+            assert_pins!(led_ring.leds_mut(), [false, false, false, false]);
+        }
+    }
+
+    #[test]
+    fn led_ring_refresh_curve() {
+        let mock_leds = MockOutputPin::get_4();
+        let mut led_ring = LedRing::<MockOutputPin>::from(mock_leds);
+        assert_eq!(led_ring.curve(), BrightnessCurve::Linear);
+
+        // Linearly, half brightness is lit half of the period.
+        led_ring.specific_on([true, false, false, true]);
+        led_ring.set_brightness(128);
+        for step in 0..PWM_STEPS {
+            led_ring.refresh();
+            let lit = step < duty(128);
+            assert_pins!(led_ring.leds_mut(), [lit, false, false, lit]);
+        }
+
+        // Gamma-corrected, it is lit much shorter.
+        led_ring.set_curve(BrightnessCurve::Gamma);
+        assert_eq!(led_ring.curve(), BrightnessCurve::Gamma);
+        for step in 0..PWM_STEPS {
+            led_ring.refresh();
+            let lit = step < duty(BrightnessCurve::Gamma.apply(128));
+            assert_pins!(led_ring.leds_mut(), [lit, false, false, lit]);
+        }
+        assert!(duty(BrightnessCurve::Gamma.apply(128)) < duty(128));
+
+        // The floor still applies after the curve.
+        led_ring.set_brightness(1);
+        for step in 0..PWM_STEPS {
+            led_ring.refresh();
+            let lit = step < duty(DEFAULT_BRIGHTNESS_FLOOR);
+            assert_pins!(led_ring.leds_mut(), [lit, false, false, lit]);
+        }
+    }
+
+    #[test]
+    fn led_ring_refresh_duties() {
+        let mock_leds = MockOutputPin::get_4();
+        let mut led_ring = LedRing::<MockOutputPin>::from(mock_leds);
+
+        // Each LED is lit during its own part of the period; too high duties are clamped.
+        led_ring.specific_duties([PWM_STEPS, PWM_STEPS / 2, 0, 100]);
+        assert_eq!(led_ring.states(), [true, true, false, true]);
+        assert_eq!(led_ring.duties(), [PWM_STEPS, PWM_STEPS / 2, 0, PWM_STEPS]);
+        for step in 0..PWM_STEPS {
+            led_ring.refresh();
+            let lit = step < PWM_STEPS / 2;
+            assert_pins!(led_ring.leds_mut(), [true, lit, false, true]);
+        }
+
+        // The duties are relative to the brightness.
+        led_ring.set_brightness(128);
+        for step in 0..PWM_STEPS {
+            led_ring.refresh();
+            let lit = step < PWM_STEPS / 2;
+            let lit_half = step < PWM_STEPS / 4;
+            assert_pins!(led_ring.leds_mut(), [lit, lit_half, false, lit]);
+        }
+
+        // Turning LEDs on resets the duties.
+        led_ring.all_on();
+        assert_eq!(led_ring.duties(), [PWM_STEPS; 4]);
+    }
+
+    #[test]
+    fn led_ring_refresh_hardware_pwm() {
+        let channels = <[MockPwmChannel; 4]>::default();
+        let mut led_ring = LedRing::<MockPwmChannel>::from(channels);
+        let duties = |led_ring: &mut LedRing<MockPwmChannel>| {
+            let mut duties = [0; 4];
+            for (duty, channel) in duties.iter_mut().zip(led_ring.leds_mut()) {
+                *duty = channel.duty;
+            }
+            duties
+        };
+
+        // The channels are given the part of the period the LEDs are lit, at every step.
+        led_ring.specific_duties([PWM_STEPS, PWM_STEPS / 2, 0, 3]);
+        led_ring.refresh();
+        assert_eq!(duties(&mut led_ring), [PWM_STEPS, PWM_STEPS / 2, 0, 3]);
+        led_ring.refresh();
+        assert_eq!(duties(&mut led_ring), [PWM_STEPS, PWM_STEPS / 2, 0, 3]);
+        assert_eq!(led_ring.output_states(), [true, true, false, true]);
+
+        // The duties are relative to the brightness.
+        led_ring.set_brightness(128);
+        led_ring.refresh();
+        assert_eq!(duties(&mut led_ring), [PWM_STEPS / 2, PWM_STEPS / 4, 0, 1]);
+
+        // Active low channels are given the part of the period the LEDs are not lit.
+        let channels = <[MockPwmChannel; 4]>::default();
+        let mut led_ring = LedRing::builder(channels)
+            .polarity(Polarity::ActiveLow)
+            .build()
+            .unwrap();
+        led_ring.specific_on([true, false, false, true]);
+        led_ring.refresh();
+        assert_eq!(duties(&mut led_ring), [0, PWM_STEPS, PWM_STEPS, 0]);
+        assert_eq!(led_ring.output_states(), [true, false, false, true]);
+    }
+
+    #[test]
+    fn led_ring_refresh_ramp() {
+        let mock_leds = MockOutputPin::get_4();
+        let mut led_ring = LedRing::<MockOutputPin>::from(mock_leds);
+
+        led_ring.enable_ramp();
+        led_ring.all_on();
+        led_ring.ramp_mut().set_step(128);
+
+        // The ramp starts at zero brightness, regardless of the brightness setting.
+        for _ in 0..PWM_STEPS {
+            led_ring.refresh();
+            assert_pins!(led_ring.leds_mut(), [false, false, false, false]);
+        }
+
+        // The ramp level is used for the brightness in ramp mode.
+        led_ring.ramp_mut().advance();
+        for step in 0..PWM_STEPS {
+            led_ring.refresh();
+            let lit = step < PWM_STEPS / 2;
+            assert_pins!(led_ring.leds_mut(), [lit, lit, lit, lit]);
+        }
+    }
+
+    #[test]
+    fn led_ring_refresh_breathe() {
+        let mock_leds = MockOutputPin::get_4();
+        let mut led_ring = LedRing::<MockOutputPin>::from(mock_leds);
+
+        led_ring.enable_breathe();
+        led_ring.all_on();
+
+        // The breath starts at zero brightness, regardless of the brightness setting.
+        for _ in 0..PWM_STEPS {
+            led_ring.refresh();
+            assert_pins!(led_ring.leds_mut(), [false, false, false, false]);
+        }
+
+        // The breath level is used for the brightness in breathe mode.
+        while led_ring.breathe_mut().advance() != u8::MAX {}
+        for _ in 0..PWM_STEPS {
+            led_ring.refresh();
+            assert_pins!(led_ring.leds_mut(), [true, true, true, true]);
+        }
+    }
+
+    #[test]
+    fn led_ring_night_mode() {
+        let mock_leds = MockOutputPin::get_4();
+        let mut led_ring = LedRing::<MockOutputPin>::from(mock_leds);
+        led_ring.set_brightness(200);
+        assert!(!led_ring.is_night_mode());
+
+        led_ring.set_night_mode(true);
+        assert!(led_ring.is_night_mode());
+        assert_eq!(led_ring.brightness(), 100);
+
+        // Enabling it again does not halve the brightness again.
+        led_ring.set_night_mode(true);
+        assert_eq!(led_ring.brightness(), 100);
+
+        led_ring.set_night_mode(false);
+        assert!(!led_ring.is_night_mode());
+        assert_eq!(led_ring.brightness(), 200);
+
+        // Disabling it again does not change the brightness.
+        led_ring.set_brightness(50);
+        led_ring.set_night_mode(false);
+        assert_eq!(led_ring.brightness(), 50);
+
+        // The brightness is dimmed during refreshes.
+        led_ring.set_brightness(u8::MAX);
+        led_ring.set_night_mode(true);
+        led_ring.all_on();
+        for step in 0..PWM_STEPS {
+            led_ring.refresh();
+            let lit = step < duty(u8::MAX / 2);
+            assert_pins!(led_ring.leds_mut(), [lit, lit, lit, lit]);
+        }
+    }
+
+    #[test]
+    fn led_ring_set_pattern() {
+        let mock_leds = MockOutputPin::get_4();
+        let mut led_ring = LedRing::<MockOutputPin>::from(mock_leds);
+
+        led_ring.set_pattern(&[true, false, true, true]);
+        assert_eq!(led_ring.states(), [true, false, true, true]);
+        assert_pins!(led_ring.leds_mut(), [true, false, true, true]);
+
+        // A short pattern turns off the remaining LEDs.
+        led_ring.set_pattern(&[false, true]);
+        assert_eq!(led_ring.states(), [false, true, false, false]);
+        led_ring.set_pattern(&[]);
+        assert_eq!(led_ring.states(), [false; 4]);
+
+        // A long pattern is cut off.
+        led_ring.set_pattern(&[true, true, false, true, false, false]);
+        assert_eq!(led_ring.states(), [true, true, false, true]);
+        assert_pins!(led_ring.leds_mut(), [true, true, false, true]);
+    }
+
+    #[test]
+    fn led_ring_set_pattern_unchanged() {
+        let mock_leds = MockOutputPin::get_4();
+        let mut led_ring = LedRing::<MockOutputPin>::from(mock_leds);
+        let writes = |led_ring: &LedRing<MockOutputPin>| {
+            let leds = led_ring.leds_mut();
+            [
+                leds[0].writes,
+                leds[1].writes,
+                leds[2].writes,
+                leds[3].writes,
+            ]
+        };
+
+        // LEDs that are already off are not written.
+        led_ring.specific_on([true, false, true, false]);
+        assert_eq!(writes(&led_ring), [1, 0, 1, 0]);
+
+        // Setting the same pattern again writes nothing, changed LEDs are written.
+        led_ring.specific_on([true, false, true, false]);
+        assert_eq!(writes(&led_ring), [1, 0, 1, 0]);
+        led_ring.set_pattern(&[true, true, false, false]);
+        assert_eq!(writes(&led_ring), [1, 1, 2, 0]);
+        assert_pins!(led_ring.leds_mut(), [true, true, false, false]);
+
+        // An LED that is on at less than full duty is written to restore the full duty.
+        led_ring.specific_duties([PWM_STEPS, PWM_STEPS / 2, PWM_STEPS, PWM_STEPS]);
+        let before = writes(&led_ring);
+        led_ring.specific_on([true, true, false, false]);
+        assert_eq!(writes(&led_ring)[0], before[0]);
+        assert_eq!(writes(&led_ring)[1], before[1] + 1);
+        assert_eq!(led_ring.duties()[1], PWM_STEPS);
+
+        // An overridden LED is not written when its forced state does not change.
+        led_ring.set_override(3, Some(true));
+        let before = writes(&led_ring);
+        led_ring.specific_on([true, true, false, false]);
+        assert_eq!(writes(&led_ring), before);
+        assert_eq!(led_ring.states(), [true, true, false, true]);
+    }
+
+    #[test]
+    fn led_ring_sink() {
+        let mock_leds = MockOutputPin::get_4();
+        let led_ring = LedRing::<MockOutputPin>::from(mock_leds);
+        let mut led_ring = led_ring.with_sink(MockSink::default());
+        assert_eq!(led_ring.sink().updates, vec![[false; 4]]);
+
+        led_ring.advance();
+        led_ring.advance();
+        led_ring.all_on();
+        // Unchanged states do not result in a notification.
+        led_ring.all_on();
+        led_ring.specific_on([true, true, true, true]);
+        led_ring.sleep();
+        led_ring.wake();
+        assert_eq!(
+            led_ring.sink().updates,
+            vec![
+                [false; 4],
+                [true, false, false, false],
+                [true, true, false, false],
+                [true; 4],
+                [false; 4],
+                [true; 4],
+            ]
+        );
+
+        // The onboard LEDs keep working.
+        assert_pins!(led_ring.leds_mut(), [true, true, true, true]);
+
+        // Refreshing the PWM does not change the logical states.
+        led_ring.sink_mut().updates.clear();
+        led_ring.set_brightness(128);
+        for _ in 0..PWM_STEPS {
+            led_ring.refresh();
+        }
+        assert!(led_ring.sink().updates.is_empty());
+    }
+
+    #[test]
+    fn pending_states() {
+        let mock_leds = MockOutputPin::get_4();
+        let led_ring = LedRing::<MockOutputPin>::from(mock_leds);
+        let mut led_ring = led_ring.with_sink(PendingStates::default());
+        assert_eq!(led_ring.sink_mut().take(), Some([false; 4]));
+        assert_eq!(led_ring.sink_mut().take(), None);
+
+        // Only the latest change is kept.
+        led_ring.all_on();
+        led_ring.specific_on([true, false, true, false]);
+        assert_eq!(led_ring.sink_mut().take(), Some([true, false, true, false]));
+        assert_eq!(led_ring.sink_mut().take(), None);
+
+        // Showing the same states again is not a change.
+        led_ring.specific_on([true, false, true, false]);
+        assert_eq!(led_ring.sink_mut().take(), None);
     }
 }
@@ -1,2 +1,38 @@
 #![cfg_attr(not(test), no_std)]
+pub mod accel;
+pub mod ack;
+pub mod beacon;
+pub mod board;
+pub mod breathe;
+pub mod button;
+pub mod calibrate;
+pub mod chase;
+pub mod clocks;
+pub mod command;
+pub mod curve;
+pub mod demo;
+pub mod diag;
+pub mod features;
+pub mod gesture;
+pub mod heartbeat;
+pub mod history;
 pub mod led_ring;
+pub mod map;
+#[cfg(test)]
+mod mock;
+pub mod party;
+pub mod ramp;
+pub mod rate_limit;
+pub mod reset;
+pub mod sequence;
+pub mod serial;
+pub mod settings;
+pub mod sos;
+pub mod sparkle;
+pub mod spi_bus;
+pub mod spin;
+pub mod stack;
+pub mod tasks;
+pub mod timer;
+pub mod transition;
+pub mod ws2812;
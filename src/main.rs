@@ -1,271 +1,2136 @@
 //! STM32F4DISCOVERY demo application
 //!
 //! This demo application sports a serial command-interface for controlling what the LED
-//! ring does: cycle clock-wise, counter clock-wise, or follow the accelerometer.
+//! ring does: cycle clock-wise, counter clock-wise, follow the accelerometer, ramp the
+//! brightness up and down, chase two dots around, breathe, sparkle, or count in binary.
+//!
+//! The LED ring is shared by the tasks that animate it (priority 1), the serial interface and
+//! the commands it runs (priority 2), the user button and the idle loop.  The tasks at the
+//! ceiling priority, which include all commands that report its state, access it directly;
+//! all others lock it only to apply a single step or to read a few fields, and do anything slow
+//! (accelerometer transfers, serial output) outside of the lock.  This is also why the LED
+//! strip is not updated while the LED ring is locked: the state changes are kept pending and
+//! written to the strip by the PWM task afterwards, so that animating does not delay the serial
+//! interface.
+//!
+//! All changes of the mode and direction are serialized by running them as commands in a single
+//! task (at priority 2), in the order they were given.  The user button and gestures do not
+//! change the LED ring themselves (except for waking it up), but spawn the command for their
+//! action, which is relative to the state when it runs (e.g. `mode next`), not when the button
+//! was pressed.  A press at the same time as a command via the serial interface thus acts on the
+//! mode that command leaves behind, and each is applied as a whole.
+//!
+//! Without the `accel` feature, the accelerometer is not used at all: neither the SPI
+//! peripheral it is connected to nor its interrupt pin are set up, accelerometer mode and the
+//! calibration are left out, and the commands that need the accelerometer report that it is
+//! unsupported.
 
 #![deny(unsafe_code)]
 #![no_main]
 #![no_std]
 
-use core::fmt::Write;
+use core::fmt::{Display, Write};
 
+use cortex_m::asm::wfi;
+use cortex_m::peripheral::{DWT, NVIC, SCB};
 use cortex_m_semihosting::hprintln;
 use hal::{
-    block,
-    gpio::{Alternate, Edge, ExtiPin, Floating, Input, Output, PushPull, AF5},
+    gpio::{Alternate, ExtiPin, Output, PushPull, AF5},
     prelude::*,
+    rcc::Clocks,
     serial::{self, config::Config as SerialConfig, Serial},
-    spi::{Mode, Phase, Polarity, Spi},
-    stm32::{EXTI, SPI1, USART2},
+    spi::{self, NoMiso, Phase, Polarity, Spi},
+    stm32::{EXTI, SPI1, SPI2, USART2},
+};
+use heapless::{
+    consts::{U32, U64},
+    i,
+    spsc::{Consumer, Queue},
+    Vec,
 };
-use heapless::{consts::U8, Vec};
-#[cfg(not(test))]
+#[cfg(not(any(test, feature = "panic-sos")))]
 use panic_semihosting as _;
 use rtfm::app;
 use rtfm::cyccnt::{Instant, U32Ext};
-use stm32f4disc_demo::led_ring::LedRing;
+use rtfm::Mutex;
+use stm32f4disc_demo::accel;
+#[cfg(feature = "accel")]
+use stm32f4disc_demo::accel::ShakeDetector;
+use stm32f4disc_demo::ack::{Ack, AckStep};
+use stm32f4disc_demo::beacon::{self, Identity};
+#[cfg(not(feature = "pwm-timer"))]
+use stm32f4disc_demo::board::Led;
+#[cfg(feature = "pwm-timer")]
+use stm32f4disc_demo::board::LedChannel as Led;
+use stm32f4disc_demo::board::{self, AccelInterrupt, UserButton};
+use stm32f4disc_demo::button::{self, DEBOUNCE_MS};
+use stm32f4disc_demo::calibrate::Calibration;
+#[cfg(feature = "accel")]
+use stm32f4disc_demo::calibrate::{self, CalibrationStep};
+use stm32f4disc_demo::clocks;
+use stm32f4disc_demo::command::{Command, ParseError};
+use stm32f4disc_demo::demo::Demo;
+#[cfg(not(feature = "accel"))]
+use stm32f4disc_demo::diag::CheckResult;
+use stm32f4disc_demo::diag::{self, Check};
+use stm32f4disc_demo::features::{self, FEATURES};
+#[cfg(feature = "accel")]
+use stm32f4disc_demo::gesture::{self, GESTURES};
+// Like the resource types, the type of the gesture messages is checked without the `accel`
+// feature as well.
+use stm32f4disc_demo::gesture::Gesture;
+use stm32f4disc_demo::heartbeat::{self, FALLBACK_MODE};
+use stm32f4disc_demo::history::History;
+use stm32f4disc_demo::led_ring::{self, LedRing, Mode, PendingStates, StateSink, SWEEP_STEPS};
+use stm32f4disc_demo::map::write_map;
+use stm32f4disc_demo::party::{Party, PartyStep};
+use stm32f4disc_demo::rate_limit::{MessageClass, RateLimiter};
+use stm32f4disc_demo::reset::ResetCause;
+use stm32f4disc_demo::sequence::{Sequencer, Step};
+use stm32f4disc_demo::serial::{
+    clear_line, edit_line, write_hex, write_pong, BoundedWriter, Framing, Key, KeyDecoder,
+    LineEdit, PacketRead, PacketReader, Received, RxBuffer, TimeoutTx,
+};
+use stm32f4disc_demo::settings::{OutputKind, Settings};
+#[cfg(feature = "accel")]
+use stm32f4disc_demo::spi_bus::BusError;
+use stm32f4disc_demo::spin::{Spin, SpinStep};
+use stm32f4disc_demo::stack;
+use stm32f4disc_demo::tasks::{self, Outcome};
+use stm32f4disc_demo::timer;
+use stm32f4disc_demo::transition::Transition;
+use stm32f4disc_demo::ws2812::{self, Ws2812};
 
-type Accelerometer = hal::spi::Spi<SPI1, (Spi1Sck, Spi1Miso, Spi1Mosi)>;
+// The types of the accelerometer resources are also needed without the `accel` feature, since
+// the application checks that all resources can be sent between tasks.
+type Accelerometer = accel::Accelerometer<Spi1, AccelerometerCs>;
 type AccelerometerCs = hal::gpio::gpioe::PE3<Output<PushPull>>;
-type Led = hal::gpio::gpiod::PD<Output<PushPull>>;
-type SerialTx = hal::serial::Tx<USART2>;
+type LedStrip = Ws2812<Spi2>;
+type SerialTx = TimeoutTx<hal::serial::Tx<USART2>>;
 type SerialRx = hal::serial::Rx<USART2>;
+/// The number of received bytes that can be buffered until they are handled.
+type SerialRxLen = U32;
+type Spi1 = hal::spi::Spi<SPI1, (Spi1Sck, Spi1Miso, Spi1Mosi)>;
 type Spi1Sck = hal::gpio::gpioa::PA5<Alternate<AF5>>;
 type Spi1Miso = hal::gpio::gpioa::PA6<Alternate<AF5>>;
 type Spi1Mosi = hal::gpio::gpioa::PA7<Alternate<AF5>>;
-type UserButton = hal::gpio::gpioa::PA0<Input<Floating>>;
+type Spi2 = hal::spi::Spi<SPI2, (Spi2Sck, NoMiso, Spi2Mosi)>;
+type Spi2Sck = hal::gpio::gpiob::PB13<Alternate<AF5>>;
+type Spi2Mosi = hal::gpio::gpiob::PB15<Alternate<AF5>>;
+
+/// The version of the application, including the git hash it is built from.
+const VERSION: &str = concat!(
+    env!("CARGO_PKG_NAME"),
+    " ",
+    env!("CARGO_PKG_VERSION"),
+    " (",
+    env!("GIT_HASH"),
+    ")"
+);
+
+/// The sentinel sent via the serial interface once initialization has finished completely, so
+/// that a program can reliably detect that the board is ready.
+const READY_SENTINEL: &str = "=== READY ===";
+
+/// The prompt shown on the serial interface when it is interactive.
+const PROMPT: &str = "> ";
 
 /// The number of cycles between LED ring updates (used by tasks).
 const PERIOD: u32 = 8_000_000;
 
+/// The number of cycles between LED ring updates in cycle mode when the board is fully tilted
+/// (and the tilt controls the speed).
+#[cfg(feature = "accel")]
+const TILT_PERIOD: u32 = PERIOD / 8;
+
+/// The number of cycles between the on and off steps of the acknowledgment blinking.
+const ACK_PERIOD: u32 = PERIOD / 2;
+
+/// The number of cycles between the samples taken during calibration.
+#[cfg(feature = "accel")]
+const CALIBRATION_SAMPLE_PERIOD: u32 = PERIOD / 8;
+
+/// The number of cycles between the steps of the transition animation.
+const TRANSITION_PERIOD: u32 = PERIOD / 16;
+
+/// The number of cycles between the ticks of party mode (one second).
+const PARTY_TICK: u32 = 2 * PERIOD;
+
+/// The number of cycles after startup without serial input before the demo starts (five
+/// seconds).
+const DEMO_DELAY: u32 = 10 * PERIOD;
+
+/// The number of cycles between the checks of the heartbeat timeout (one second).
+const HEARTBEAT_CHECK_PERIOD: u32 = 2 * PERIOD;
+
+/// The number of cycles between ticks of the chasing dots.
+const CHASE_PERIOD: u32 = PERIOD / 4;
+
+/// The period (in cycles) between the steps of sparkle mode.
+const SPARKLE_PERIOD: u32 = PERIOD / 8;
+
+/// The period (in cycles) between the steps of counter mode.
+const COUNTER_PERIOD: u32 = PERIOD;
+
+/// The period (in cycles) between the steps of a breath in breathe mode (2 seconds per breath).
+const BREATHE_PERIOD: u32 = PERIOD / 8;
+
+/// The number of cycles the self-test sweep of the LED ring takes at startup.
+const SWEEP_DURATION: u32 = 16_000_000;
+
+/// The number of cycles between software PWM refreshes of the LED ring (at the default 16 MHz
+/// system clock).
+const PWM_PERIOD: u32 = led_ring::pwm_refresh_cycles(16_000_000);
+
+/// The number of cycles per millisecond (at the default 16 MHz system clock).
+const CYCLES_PER_MS: u32 = 16_000;
+
+/// The response to the commands that need the accelerometer when built without it.
+#[cfg(not(feature = "accel"))]
+const ACCEL_UNSUPPORTED: &str = "accel err: unsupported";
+
 #[app(device = hal::stm32, monotonic = rtfm::cyccnt::CYCCNT, peripherals = true)]
 const APP: () = {
     struct Resources {
         /// The on-board accelerometer.
+        #[cfg(feature = "accel")]
         accel: Accelerometer,
-        /// The control port for indicating data is being written to/read from the accelerometer.
-        accel_cs: AccelerometerCs,
+        /// The pin of the motion interrupt of the accelerometer (that wakes the board up).
+        #[cfg(feature = "accel")]
+        accel_int: AccelInterrupt,
+        /// Whether the accelerometer has been initialized successfully; if not (or if built
+        /// without the `accel` feature), accelerometer mode and the other commands that need it
+        /// are unavailable.
+        accel_ok: bool,
+        /// The acknowledgment blinking in progress (if any).
+        #[init(None)]
+        ack: Option<Ack>,
         /// The buffer used to capture incoming user commands via the serial inerface.
-        buffer: Vec<u8, U8>,
+        buffer: Vec<u8, U64>,
         /// The on-board blue user-controlled button.
         button: UserButton,
-        /// The interrupt controll for the EXTI interrupt (related to the user button).
+        /// The calibration of the accelerometer in progress (if any).
+        #[init(None)]
+        calibration: Option<Calibration>,
+        /// The frozen clock configuration.
+        clocks: Clocks,
+        /// The demo that plays automatically if the serial interface is not used after startup.
+        demo: Demo<Instant>,
+        /// Whether the user button (its interrupt) is enabled.
+        #[init(true)]
+        button_enabled: bool,
+        /// Whether the user button is held (as determined at its last edge).
+        #[init(false)]
+        button_held: bool,
+        /// The interrupt controll for the EXTI interrupts (related to the user button and the
+        /// accelerometer motion interrupt).
         exti_cntr: EXTI,
+        /// The history of commands received via the serial interface.
+        history: History,
+        /// The instant of the last activity of the host, i.e. the last byte received via the
+        /// serial interface.
+        last_activity: Instant,
+        /// The decoder for the special keys received via the serial interface.
+        #[init(KeyDecoder::new())]
+        keys: KeyDecoder,
         /// The "ring" formed by the four on-board leds.
-        led_ring: LedRing<Led>,
+        led_ring: LedRing<Led, PendingStates>,
+        /// The LED strip that mirrors the LED ring.
+        led_strip: LedStrip,
+        /// Whether the board was level at the previous accelerometer measurement.
+        #[cfg(feature = "accel")]
+        #[init(false)]
+        level: bool,
+        /// Whether the commands of the last line received via the serial interface are still
+        /// pending, in which case the next line has to wait.
+        #[init(false)]
+        line_pending: bool,
+        /// The reader of the commands received via the serial interface as packets.
+        #[init(PacketReader::new())]
+        packets: PacketReader,
+        /// The rate limiter for the asynchronous messages sent via the serial interface.
+        #[init(RateLimiter::new())]
+        rate_limiter: RateLimiter<Instant>,
+        /// The cause of the last reset (read at initialization).
+        reset_cause: ResetCause,
+        /// The buffer of the bytes received via the serial interface.
+        rx_buffer: RxBuffer<'static, SerialRxLen>,
+        /// The queue of the bytes received via the serial interface that have yet to be handled.
+        rx_queue: Consumer<'static, Received, SerialRxLen>,
         /// The receiving part of the serial interface.
         serial_rx: SerialRx,
         /// The transmitting part of the serial interface.
         serial_tx: SerialTx,
+        /// The sequencer of the sequence of commands (macro).
+        #[init(Sequencer::new())]
+        sequencer: Sequencer,
+        /// The detector of the board being shaken (from the accelerometer measurements).
+        #[cfg(feature = "accel")]
+        #[init(ShakeDetector::new())]
+        shake: ShakeDetector,
+        /// The settings that can be changed via the serial interface.
+        #[init(Settings::new())]
+        settings: Settings,
+        /// The party (rotating through effects) in progress (if any).
+        #[init(None)]
+        party: Option<Party>,
+        /// The spin (one full revolution) in progress (if any).
+        #[init(None)]
+        spin: Option<Spin>,
+        /// The cycle count at the last reset of the timer (see the timer module).
+        #[init(0)]
+        timer_reference: u32,
+        /// The transition to another mode in progress (if any).
+        #[init(None)]
+        transition: Option<Transition>,
     }
 
     /// Initializes the application by setting up the LED ring, user button, serial
     /// interface and accelerometer.
-    #[init(spawn = [accel_leds, cycle_leds])]
+    #[init(
+        resources = [settings],
+        schedule = [check_heartbeat, start_demo],
+        spawn = [pwm_leds, self_test]
+    )]
     fn init(mut cx: init::Context) -> init::LateResources {
+        static mut RX_QUEUE: Queue<Received, SerialRxLen> = Queue(i::Queue::new());
+
+        // Paint the unused part of the stack to be able to estimate the stack usage later on.
+        stack::paint();
+
         // Set up and enable the monotonic timer.
         cx.core.DCB.enable_trace();
         cx.core.DWT.enable_cycle_counter();
 
-        // Set up the LED ring and spawn the task corresponding to the mode.
-        let gpiod = cx.device.GPIOD.split();
-        let leds = [
-            gpiod.pd12.into_push_pull_output().downgrade(),
-            gpiod.pd13.into_push_pull_output().downgrade(),
-            gpiod.pd14.into_push_pull_output().downgrade(),
-            gpiod.pd15.into_push_pull_output().downgrade(),
-        ];
-        let led_ring = LedRing::from(leds);
-        if led_ring.is_mode_cycle() {
-            cx.spawn.cycle_leds().unwrap();
-        } else if led_ring.is_mode_accel() {
-            cx.spawn.accel_leds().unwrap();
-        }
+        // Start checking the heartbeat timeout (if enabled).
+        cx.schedule
+            .check_heartbeat(cx.start + HEARTBEAT_CHECK_PERIOD.cycles())
+            .unwrap();
 
-        // Set up the EXTI0 interrupt for the user button.
+        // Start the demo later on, unless the serial interface is used in the meantime.
+        cx.schedule
+            .start_demo(cx.start + DEMO_DELAY.cycles())
+            .unwrap();
+
+        // Set up the user button and its interrupt (see the board module for the pin).
         let mut exti_cntr = cx.device.EXTI;
         let gpioa = cx.device.GPIOA.split();
-        let mut button = gpioa.pa0.into_floating_input();
-        button.enable_interrupt(&mut exti_cntr);
-        button.trigger_on_edge(&mut exti_cntr, Edge::RISING);
+        let button = board::user_button(gpioa.pa0, &mut exti_cntr);
 
         // Set up the serial interface and the USART2 interrupt.
         let tx = gpioa.pa2.into_alternate_af7();
         let rx = gpioa.pa3.into_alternate_af7();
         let config = SerialConfig::default().baudrate(115_200.bps());
+        // Read the reset flags and clear them, so that they only reflect the next reset.
+        let reset_cause = ResetCause::from_flags(cx.device.RCC.csr.read().bits());
+        cx.device.RCC.csr.modify(|_, w| w.rmvf().set_bit());
+        // Enable the clock of the SYSCFG peripheral to route the EXTI line of the accelerometer
+        // interrupt (see the board module).
+        #[cfg(feature = "accel")]
+        cx.device.RCC.apb2enr.modify(|_, w| w.syscfgen().enabled());
+        // Enable the clock of the timer that dims the LEDs (see the board module).
+        #[cfg(feature = "pwm-timer")]
+        cx.device.RCC.apb1enr.modify(|_, w| w.tim4en().enabled());
         let rcc = cx.device.RCC.constrain();
         let clocks = rcc.cfgr.freeze();
         let mut serial = Serial::usart2(cx.device.USART2, (tx, rx), config, clocks).unwrap();
         serial.listen(serial::Event::Rxne);
-        let (mut serial_tx, serial_rx) = serial.split();
+        let (serial_tx, serial_rx) = serial.split();
+        // Writes give up instead of locking up the application if the UART never gets ready.
+        let mut serial_tx = TimeoutTx::new(serial_tx);
+
+        // Set up the LED ring (see the board module for the pins, and the timer with the
+        // pwm-timer feature) and start the self-test, after which the task corresponding to the
+        // mode is spawned.
+        #[cfg(not(feature = "pwm-timer"))]
+        let leds = board::led_pins(cx.device.GPIOD);
+        #[cfg(feature = "pwm-timer")]
+        let leds = board::led_channels(cx.device.GPIOD, cx.device.TIM4, clocks);
+        let led_ring = LedRing::from(leds);
+        cx.spawn.pwm_leds().unwrap();
+        cx.spawn.self_test(0).unwrap();
 
-        // Set up the serial interface command buffer.
+        // Set up the buffer of received bytes, and the serial interface command buffer and
+        // history.
+        let (rx_producer, rx_queue) = RX_QUEUE.split();
+        let rx_buffer = RxBuffer::new(rx_producer);
         let buffer = Vec::new();
+        let history = History::new();
 
-        // Set up the accelerometer.
-        let sck = gpioa.pa5.into_alternate_af5();
-        let miso = gpioa.pa6.into_alternate_af5();
-        let mosi = gpioa.pa7.into_alternate_af5();
-        let mode = Mode {
-            polarity: Polarity::IdleHigh,
-            phase: Phase::CaptureOnSecondTransition,
-        };
-        let mut accel = Spi::spi1(cx.device.SPI1, (sck, miso, mosi), mode, 100.hz(), clocks);
+        // Set up and initialize the accelerometer.  If initialization fails, the board is still
+        // useful without it, so the application continues (in cycle mode) with the
+        // accelerometer unavailable.
+        #[cfg(feature = "accel")]
+        let (accel, accel_int, accel_init) = {
+            let sck = gpioa.pa5.into_alternate_af5();
+            let miso = gpioa.pa6.into_alternate_af5();
+            let mosi = gpioa.pa7.into_alternate_af5();
+            let mode = spi::Mode {
+                polarity: Polarity::IdleHigh,
+                phase: Phase::CaptureOnSecondTransition,
+            };
+            let spi = Spi::spi1(cx.device.SPI1, (sck, miso, mosi), mode, 100.hz(), clocks);
+
+            let gpioe = cx.device.GPIOE.split();
+            let accel_cs = gpioe.pe3.into_push_pull_output();
+            let mut accel = Accelerometer::from(spi, accel_cs);
+            let mut syscfg = cx.device.SYSCFG;
+            let accel_int = board::accel_interrupt(gpioe.pe1, &mut exti_cntr, &mut syscfg);
+            let accel_init = accel.init();
 
-        let gpioe = cx.device.GPIOE.split();
-        let mut accel_cs = gpioe.pe3.into_push_pull_output();
+            (accel, accel_int, accel_init)
+        };
 
-        // Initialize the accelerometer.
-        accel_cs.set_low().unwrap();
-        let _ = accel.transfer(&mut [0x20, 0b01000111]).unwrap();
-        accel_cs.set_high().unwrap();
+        // Set up the LED strip that mirrors the LED ring (data line on PB15).
+        let gpiob = cx.device.GPIOB.split();
+        let sck = gpiob.pb13.into_alternate_af5();
+        let mosi = gpiob.pb15.into_alternate_af5();
+        let mode = spi::Mode {
+            polarity: Polarity::IdleLow,
+            phase: Phase::CaptureOnFirstTransition,
+        };
+        let freq = ws2812::SPI_FREQUENCY.hz();
+        let spi = Spi::spi2(cx.device.SPI2, (sck, NoMiso, mosi), mode, freq, clocks);
+        let led_strip = Ws2812::new(spi);
+        let led_ring = led_ring.with_sink(PendingStates::default());
 
-        // Output to the serial interface that initialization is finished.
-        writeln!(serial_tx, "init\r").unwrap();
+        // Output to the serial interface which firmware is running on which board and that
+        // initialization is finished (unless quiet).
+        if cx.resources.settings.allows(OutputKind::Event) {
+            let identity = Identity {
+                name: env!("CARGO_PKG_NAME"),
+                version: env!("CARGO_PKG_VERSION"),
+                git_hash: env!("GIT_HASH"),
+                uid: beacon::unique_id(),
+            };
+            beacon::write_beacon(&mut serial_tx, &identity).unwrap();
+            writeln!(serial_tx, "\r").unwrap();
+            #[cfg(feature = "accel")]
+            if let Err(error) = &accel_init {
+                writeln!(
+                    serial_tx,
+                    "warning: accel unavailable ({})\r",
+                    error.as_str()
+                )
+                .unwrap();
+            }
+            writeln!(serial_tx, "init\r").unwrap();
+            // This is the last line of initialization; only the prompt may follow.  Since
+            // initialization only happens after a reset, the sentinel is sent exactly once per
+            // reset (and not when waking up, for example).
+            writeln!(serial_tx, "{}\r", READY_SENTINEL).unwrap();
+        }
+        if cx.resources.settings.interactive {
+            write!(serial_tx, "{}", PROMPT).unwrap();
+        }
 
         init::LateResources {
+            #[cfg(feature = "accel")]
             accel: accel,
-            accel_cs: accel_cs,
+            #[cfg(feature = "accel")]
+            accel_int: accel_int,
+            #[cfg(feature = "accel")]
+            accel_ok: accel_init.is_ok(),
+            #[cfg(not(feature = "accel"))]
+            accel_ok: false,
             buffer: buffer,
             button: button,
+            clocks: clocks,
+            demo: Demo::new(cx.start),
             exti_cntr: exti_cntr,
+            history: history,
+            last_activity: cx.start,
             led_ring: led_ring,
+            led_strip: led_strip,
+            reset_cause: reset_cause,
+            rx_buffer: rx_buffer,
+            rx_queue: rx_queue,
             serial_rx: serial_rx,
             serial_tx: serial_tx,
         }
     }
 
     /// Task that advances the LED ring one step and schedules the next trigger (if enabled).
-    #[task(resources = [led_ring], schedule = [cycle_leds])]
+    ///
+    /// If the tilt controls the speed, the accelerometer is read to determine when the next
+    /// trigger is.
+    #[task(
+        resources = [accel, button_held, led_ring, serial_tx, settings],
+        schedule = [cycle_leds]
+    )]
     fn cycle_leds(mut cx: cycle_leds::Context) {
-        let reschedule = cx.resources.led_ring.lock(|led_ring| {
-            if led_ring.is_mode_cycle() {
-                led_ring.advance();
-                true
-            } else {
-                false
-            }
-        });
+        let settings = cx.resources.settings.lock(|settings| *settings);
+        let button_held = cx.resources.button_held.lock(|button_held| *button_held);
+        let reversed = settings.button_hold && button_held;
+        let outcome = cx
+            .resources
+            .led_ring
+            .lock(|led_ring| tasks::cycle_leds(led_ring, reversed));
+        report_anomaly(&outcome, &settings, &mut cx.resources.serial_tx);
 
-        if reschedule {
+        if outcome.reschedule {
+            #[cfg(feature = "accel")]
+            let period = if settings.tilt_speed {
+                let accel = &mut cx.resources.accel;
+                let (acc_x, acc_y) = accel.lock(|accel| read_accel(accel, &settings));
+                accel::tilt_period(accel::magnitude(acc_x, acc_y), PERIOD, TILT_PERIOD)
+            } else {
+                PERIOD
+            };
+            #[cfg(not(feature = "accel"))]
+            let period = PERIOD;
             cx.schedule
-                .cycle_leds(Instant::now() + PERIOD.cycles())
+                .cycle_leds(Instant::now() + period.cycles())
                 .unwrap();
         }
     }
 
+    /// Idle loop that polls the accelerometer and adjusts the LED ring accordingly (if
+    /// enabled), or otherwise sleeps until the next interrupt.
+    ///
+    /// Polling gives the lowest latency: the LEDs follow the accelerometer as soon as it has a
+    /// new sample (without the FIFO) instead of once per period.  The tradeoff is that the core
+    /// is kept busy all the time instead of sleeping between interrupts, and that the board
+    /// being level is not reported.
+    #[idle(resources = [accel, led_ring, settings])]
+    fn idle(cx: idle::Context) -> ! {
+        #[cfg(feature = "accel")]
+        let mut accel = cx.resources.accel;
+        let mut led_ring = cx.resources.led_ring;
+        let mut settings = cx.resources.settings;
+        // The level state while polling, which is not reported.
+        #[cfg(feature = "accel")]
+        let mut level = false;
+
+        loop {
+            let settings = settings.lock(|settings| *settings);
+            let polling =
+                settings.accel_polling && led_ring.lock(|led_ring| led_ring.is_mode_accel());
+
+            if polling {
+                // Without the accelerometer, polling cannot be enabled.
+                #[cfg(feature = "accel")]
+                poll_accel(&mut accel, &mut led_ring, &settings, &mut level);
+            } else {
+                wfi();
+            }
+        }
+    }
+
     /// Task that performs an accelerometers measurement and adjusts the LED ring accordingly
     /// and schedules the next trigger (if enabled).
-    #[task(resources = [accel, accel_cs, led_ring, serial_tx], schedule = [accel_leds])]
+    ///
+    /// The measurement is skipped if the accelerometer is being polled by the idle loop, but
+    /// the task keeps being scheduled so it can take over once polling is disabled.  The
+    /// measurements are also used to detect the board being shaken, which is handled as a
+    /// gesture.
+    #[cfg(feature = "accel")]
+    #[task(
+        resources = [accel, led_ring, level, rate_limiter, serial_tx, settings, shake],
+        schedule = [accel_leds],
+        spawn = [handle_gesture]
+    )]
     fn accel_leds(mut cx: accel_leds::Context) {
-        cx.resources.accel_cs.set_low().unwrap();
-        let read_command = (1 << 7) | (1 << 6) | 0x29;
-        let mut commands = [read_command, 0x0, 0x0, 0x0];
-        let result = cx.resources.accel.transfer(&mut commands[..]).unwrap();
-        let acc_x = result[1] as i8;
-        let acc_y = result[3] as i8;
-        cx.resources.accel_cs.set_high().unwrap();
-
-        if acc_x == 0 && acc_y == 0 {
-            cx.resources
-                .serial_tx
-                .lock(|serial_tx| writeln!(serial_tx, "level\r").unwrap());
+        let settings = cx.resources.settings.lock(|settings| *settings);
+        let measurement = if settings.accel_polling {
+            None
+        } else {
+            let accel = &mut cx.resources.accel;
+            Some(accel.lock(|accel| read_accel(accel, &settings)))
+        };
+
+        let level = cx.resources.level;
+        let outcome = cx
+            .resources
+            .led_ring
+            .lock(|led_ring| tasks::accel_leds(led_ring, measurement, &settings, level));
+
+        // Only consecutive measurements in accelerometer mode are used to detect shaking.
+        let shake = cx.resources.shake;
+        match measurement {
+            Some((acc_x, acc_y)) if outcome.reschedule => {
+                if shake.push(acc_x, acc_y) {
+                    cx.spawn.handle_gesture(Gesture::Shake).ok();
+                }
+            }
+            _ => shake.reset(),
         }
 
-        let reschedule = cx.resources.led_ring.lock(|led_ring| {
-            if led_ring.is_mode_accel() {
-                let directions = [acc_y < 0, acc_x < 0, acc_y > 0, acc_x > 0];
-                led_ring.specific_on(directions);
-                true
+        if let Some(message) = outcome
+            .message
+            .filter(|_| settings.allows(OutputKind::Event))
+        {
+            let interval = settings.rate_limit_ms as u32 * CYCLES_PER_MS;
+            if cx.resources.rate_limiter.allow(
+                MessageClass::Level,
+                Instant::now(),
+                interval.cycles(),
+            ) {
+                cx.resources
+                    .serial_tx
+                    .lock(|serial_tx| write_event(serial_tx, &settings, message));
             } else {
-                false
+                // Dropping the change would leave the host with the wrong state, so the level
+                // state is kept as reported: the change is detected (and reported) again at a
+                // later measurement, unless the board has returned in the meantime.
+                *level = !*level;
+            }
+        }
+
+        if outcome.reschedule {
+            let period = settings.accel_period_ms as u32 * CYCLES_PER_MS;
+            cx.schedule
+                .accel_leds(Instant::now() + period.cycles())
+                .unwrap();
+        }
+    }
+
+    /// Task that advances the chasing dots one tick and schedules the next trigger (if
+    /// enabled).
+    #[task(resources = [led_ring, serial_tx, settings], schedule = [chase_leds])]
+    fn chase_leds(mut cx: chase_leds::Context) {
+        let outcome = cx.resources.led_ring.lock(tasks::chase_leds);
+        let settings = cx.resources.settings.lock(|settings| *settings);
+        report_anomaly(&outcome, &settings, &mut cx.resources.serial_tx);
+
+        if outcome.reschedule {
+            cx.schedule
+                .chase_leds(Instant::now() + CHASE_PERIOD.cycles())
+                .unwrap();
+        }
+    }
+
+    /// Task that advances the breath of the LED ring one step and schedules the next trigger
+    /// (if enabled).
+    #[task(resources = [led_ring, serial_tx, settings], schedule = [breathe_leds])]
+    fn breathe_leds(mut cx: breathe_leds::Context) {
+        let outcome = cx.resources.led_ring.lock(tasks::breathe_leds);
+        let settings = cx.resources.settings.lock(|settings| *settings);
+        report_anomaly(&outcome, &settings, &mut cx.resources.serial_tx);
+
+        if outcome.reschedule {
+            cx.schedule
+                .breathe_leds(Instant::now() + BREATHE_PERIOD.cycles())
+                .unwrap();
+        }
+    }
+
+    /// Task that lights random LEDs of the LED ring and schedules the next trigger (if
+    /// enabled).
+    #[task(resources = [led_ring, serial_tx, settings], schedule = [sparkle_leds])]
+    fn sparkle_leds(mut cx: sparkle_leds::Context) {
+        let outcome = cx.resources.led_ring.lock(tasks::sparkle_leds);
+        let settings = cx.resources.settings.lock(|settings| *settings);
+        report_anomaly(&outcome, &settings, &mut cx.resources.serial_tx);
+
+        if outcome.reschedule {
+            cx.schedule
+                .sparkle_leds(Instant::now() + SPARKLE_PERIOD.cycles())
+                .unwrap();
+        }
+    }
+
+    /// Task that shows the next value of the binary count on the LED ring and schedules the
+    /// next trigger (if enabled).
+    #[task(resources = [led_ring, serial_tx, settings], schedule = [counter_leds])]
+    fn counter_leds(mut cx: counter_leds::Context) {
+        let outcome = cx.resources.led_ring.lock(tasks::counter_leds);
+        let settings = cx.resources.settings.lock(|settings| *settings);
+        report_anomaly(&outcome, &settings, &mut cx.resources.serial_tx);
+
+        if outcome.reschedule {
+            cx.schedule
+                .counter_leds(Instant::now() + COUNTER_PERIOD.cycles())
+                .unwrap();
+        }
+    }
+
+    /// Task that ramps the brightness of the LED ring one step and schedules the next trigger
+    /// (if enabled).
+    #[task(resources = [led_ring, serial_tx, settings], schedule = [ramp_leds])]
+    fn ramp_leds(mut cx: ramp_leds::Context) {
+        let (outcome, dwell) = cx
+            .resources
+            .led_ring
+            .lock(|led_ring| (tasks::ramp_leds(led_ring), led_ring.ramp().dwell()));
+        let settings = cx.resources.settings.lock(|settings| *settings);
+        report_anomaly(&outcome, &settings, &mut cx.resources.serial_tx);
+
+        if outcome.reschedule {
+            cx.schedule
+                .ramp_leds(Instant::now() + dwell.cycles())
+                .unwrap();
+        }
+    }
+
+    /// Task that refreshes the LED ring one step of the software PWM, mirrors pending state
+    /// changes onto the LED strip and schedules the next trigger (if not sleeping).
+    ///
+    /// The triggers are scheduled one period apart regardless of how long the steps take,
+    /// dropping the ones that were missed (see [`tasks::next_periodic`]).  The LED strip is
+    /// only written after the LED ring has been unlocked.
+    #[task(resources = [led_ring, led_strip], schedule = [pwm_leds])]
+    fn pwm_leds(mut cx: pwm_leds::Context) {
+        let (outcome, pending) = cx.resources.led_ring.lock(|led_ring| {
+            let outcome = tasks::pwm_leds(led_ring);
+            (outcome, led_ring.sink_mut().take())
+        });
+        if let Some(states) = pending {
+            cx.resources.led_strip.update(states);
+        }
+
+        if outcome.reschedule {
+            let next = tasks::next_periodic(cx.scheduled, Instant::now(), PWM_PERIOD.cycles());
+            cx.schedule.pwm_leds(next).unwrap();
+        }
+    }
+
+    /// Task that performs a step of the acknowledgment blinking and schedules the next step, or
+    /// restores the LED ring and resumes the mode when the blinking is done.
+    ///
+    /// The acknowledgment is cancelled if the mode is changed (or the LED ring is put to sleep)
+    /// in the meantime.
+    #[task(resources = [ack, led_ring], schedule = [ack_leds], spawn = [resume])]
+    fn ack_leds(mut cx: ack_leds::Context) {
+        let mut led_ring = cx.resources.led_ring;
+        let reschedule = cx.resources.ack.lock(|ack| {
+            led_ring.lock(|led_ring| {
+                if led_ring.mode() != Mode::Off || led_ring.is_sleeping() {
+                    *ack = None;
+                }
+
+                match ack.as_mut().map(Ack::advance) {
+                    Some(AckStep::On) => {
+                        led_ring.all_on();
+                        true
+                    }
+                    Some(AckStep::Off) => {
+                        led_ring.all_off();
+                        true
+                    }
+                    Some(AckStep::Restore {
+                        mode,
+                        direction,
+                        states,
+                    }) => {
+                        led_ring.set_mode(mode);
+                        led_ring.set_direction(direction);
+                        led_ring.specific_on(states);
+                        *ack = None;
+                        false
+                    }
+                    None => false,
+                }
+            })
+        });
+
+        if reschedule {
+            cx.schedule
+                .ack_leds(Instant::now() + ACK_PERIOD.cycles())
+                .unwrap();
+        } else {
+            // The task of the mode may still be pending; this is fine.
+            cx.spawn.resume().ok();
+        }
+    }
+
+    /// Task that performs a step of the calibration of the accelerometer and schedules the next
+    /// step, or stores the offset, restores the LED ring and resumes the mode when the
+    /// calibration is done.
+    ///
+    /// The calibration is cancelled if the mode is changed (or the LED ring is put to sleep) in
+    /// the meantime.
+    #[cfg(feature = "accel")]
+    #[task(
+        resources = [accel, calibration, led_ring, serial_tx, settings],
+        schedule = [calibrate_leds],
+        spawn = [resume]
+    )]
+    fn calibrate_leds(mut cx: calibrate_leds::Context) {
+        let mut led_ring = cx.resources.led_ring;
+        let step = cx.resources.calibration.lock(|calibration| {
+            led_ring.lock(|led_ring| {
+                if led_ring.mode() != Mode::Off || led_ring.is_sleeping() {
+                    *calibration = None;
+                }
+
+                let step = calibration.as_mut().map(Calibration::advance);
+                match step {
+                    Some(CalibrationStep::Countdown(states)) => led_ring.specific_on(states),
+                    Some(CalibrationStep::Done { mode, states, .. }) => {
+                        led_ring.set_mode(mode);
+                        led_ring.specific_on(states);
+                        *calibration = None;
+                    }
+                    Some(CalibrationStep::Sample) | None => {}
+                }
+                step
+            })
+        });
+
+        match step {
+            Some(CalibrationStep::Countdown(_)) => {
+                cx.schedule
+                    .calibrate_leds(Instant::now() + PERIOD.cycles())
+                    .unwrap();
+            }
+            Some(CalibrationStep::Sample) => {
+                let settings = cx.resources.settings.lock(|settings| *settings);
+                let (acc_x, acc_y) = cx
+                    .resources
+                    .accel
+                    .lock(|accel| read_raw_accel(accel, &settings));
+                cx.resources.calibration.lock(|calibration| {
+                    if let Some(calibration) = calibration {
+                        calibration.add_sample(acc_x, acc_y);
+                    }
+                });
+                cx.schedule
+                    .calibrate_leds(Instant::now() + CALIBRATION_SAMPLE_PERIOD.cycles())
+                    .unwrap();
+            }
+            Some(CalibrationStep::Done { offset, .. }) => {
+                cx.resources
+                    .settings
+                    .lock(|settings| settings.accel_offset = offset);
+                cx.resources.serial_tx.lock(|serial_tx| {
+                    writeln!(serial_tx, "calibrated: {} {}\r", offset.0, offset.1).unwrap()
+                });
+                // The task of the mode may still be pending; this is fine.
+                cx.spawn.resume().ok();
+            }
+            None => {
+                cx.spawn.resume().ok();
             }
+        }
+    }
+
+    /// Task that advances the LED ring one step of the spin and schedules the next step, or
+    /// restores the LED ring and resumes the mode when the revolution is done.
+    ///
+    /// The spin is cancelled if the mode is changed (or the LED ring is put to sleep) in the
+    /// meantime.
+    #[task(resources = [led_ring, spin], schedule = [spin_leds], spawn = [resume])]
+    fn spin_leds(mut cx: spin_leds::Context) {
+        let mut led_ring = cx.resources.led_ring;
+        let reschedule = cx.resources.spin.lock(|spin| {
+            led_ring.lock(|led_ring| {
+                if led_ring.mode() != Mode::Off || led_ring.is_sleeping() {
+                    *spin = None;
+                }
+
+                match spin.as_mut().map(Spin::advance) {
+                    Some(SpinStep::Advance) => {
+                        led_ring.advance();
+                        true
+                    }
+                    Some(SpinStep::Restore { mode, states }) => {
+                        led_ring.set_mode(mode);
+                        led_ring.specific_on(states);
+                        *spin = None;
+                        false
+                    }
+                    None => false,
+                }
+            })
+        });
+
+        if reschedule {
+            cx.schedule
+                .spin_leds(Instant::now() + PERIOD.cycles())
+                .unwrap();
+        } else {
+            // The task of the mode may still be pending; this is fine.
+            cx.spawn.resume().ok();
+        }
+    }
+
+    /// Task that advances the party one tick and schedules the next tick, switching the LED ring
+    /// to the next effect (and resuming it) when the time of the current effect is up.
+    ///
+    /// The party ends if the mode is changed in the meantime; while the LED ring is sleeping,
+    /// the party is paused.
+    #[task(resources = [led_ring, party], schedule = [party_leds], spawn = [resume])]
+    fn party_leds(mut cx: party_leds::Context) {
+        let mut led_ring = cx.resources.led_ring;
+        let (reschedule, switched) = cx.resources.party.lock(|party| {
+            led_ring.lock(|led_ring| {
+                let active = match party {
+                    Some(active) => active,
+                    None => return (false, false),
+                };
+                if led_ring.is_sleeping() {
+                    return (true, false);
+                }
+                if led_ring.mode() != active.effect() {
+                    *party = None;
+                    return (false, false);
+                }
+
+                match active.tick() {
+                    PartyStep::Stay => (true, false),
+                    PartyStep::Switch(mode) => {
+                        led_ring.set_mode(mode);
+                        led_ring.specific_on(mode.initial_states());
+                        (true, true)
+                    }
+                }
+            })
         });
 
+        if switched {
+            cx.spawn.resume().ok();
+        }
         if reschedule {
             cx.schedule
-                .accel_leds(Instant::now() + PERIOD.cycles())
+                .party_leds(Instant::now() + PARTY_TICK.cycles())
                 .unwrap();
         }
     }
 
-    /// Interrupt handler that writes that the button is pressed to the serial interface
-    /// and reverses the LED ring cycle direction.
-    #[task(binds = EXTI0, resources = [button, exti_cntr, led_ring, serial_tx])]
-    fn button_pressed(mut cx: button_pressed::Context) {
-        cx.resources.led_ring.lock(|led_ring| led_ring.reverse());
+    /// Task that starts the demo (party mode) if nothing has been received via the serial
+    /// interface since startup.
+    #[task(priority = 2, resources = [demo, last_activity], spawn = [run_command])]
+    fn start_demo(cx: start_demo::Context) {
+        if cx.resources.demo.check(*cx.resources.last_activity) {
+            cx.spawn.run_command(Command::Party, false).ok();
+        }
+    }
+
+    /// Task that shows a step of the transition animation and schedules the next step, or
+    /// enters the new mode and resumes it when the animation is done (see
+    /// `tasks::transition_leds`).
+    #[task(resources = [led_ring, transition], schedule = [transition_leds], spawn = [resume])]
+    fn transition_leds(mut cx: transition_leds::Context) {
+        let mut led_ring = cx.resources.led_ring;
+        let outcome = cx.resources.transition.lock(|transition| {
+            led_ring.lock(|led_ring| tasks::transition_leds(led_ring, transition))
+        });
+
+        if outcome.reschedule {
+            cx.schedule
+                .transition_leds(Instant::now() + TRANSITION_PERIOD.cycles())
+                .unwrap();
+        } else {
+            // The task of the mode may still be pending; this is fine.
+            cx.spawn.resume().ok();
+        }
+    }
+
+    /// Task that performs a step of the self-test sweep of the LED ring and schedules the next
+    /// step, or resumes the mode when the sweep is finished.
+    #[task(resources = [led_ring], schedule = [self_test], spawn = [resume])]
+    fn self_test(mut cx: self_test::Context, step: usize) {
+        let finished = cx.resources.led_ring.lock(|led_ring| led_ring.sweep(step));
 
-        // Write the fact that the button has been pressed to the serial port.
-        cx.resources
-            .serial_tx
-            .lock(|serial_tx| writeln!(serial_tx, "button\r").unwrap());
+        if finished {
+            cx.spawn.resume().unwrap();
+        } else {
+            let step_period = SWEEP_DURATION / SWEEP_STEPS as u32;
+            cx.schedule
+                .self_test(Instant::now() + step_period.cycles(), step + 1)
+                .unwrap();
+        }
+    }
+
+    /// Task that resumes the software PWM and the task corresponding to the mode after waking
+    /// up (or after the self-test at startup).
+    ///
+    /// While sleeping, no tasks are scheduled and the core waits for interrupts in the idle
+    /// loop.  Only the peripherals needed to wake up remain in use: the user button (EXTI0),
+    /// the serial interface (USART2) and, if motion wake is enabled, the accelerometer (EXTI1).
+    /// Whatever woke the board up, the motion interrupt is disabled again.
+    #[task(
+        resources = [accel, led_ring],
+        spawn = [
+            accel_leds, breathe_leds, chase_leds, counter_leds, cycle_leds, pwm_leds, ramp_leds,
+            sparkle_leds
+        ]
+    )]
+    fn resume(mut cx: resume::Context) {
+        #[cfg(feature = "accel")]
+        cx.resources.accel.lock(|accel| {
+            if accel.motion_interrupt().is_some() {
+                accel.enable_motion_interrupt(None).unwrap();
+            }
+        });
+
+        // The task of the mode that is still pending from before going to sleep continues by
+        // itself, in which case it is not claimed (and spawned) again.
+        let mode = cx.resources.led_ring.lock(|led_ring| {
+            let mode = led_ring.mode();
+            Some(mode).filter(|mode| led_ring.claim_task(*mode))
+        });
 
-        cx.resources
+        // The software PWM is not tracked, so spawning it fails if it is still scheduled;
+        // this is fine.
+        cx.spawn.pwm_leds().ok();
+        match mode {
+            Some(Mode::Cycle) => cx.spawn.cycle_leds().unwrap(),
+            #[cfg(feature = "accel")]
+            Some(Mode::Accelerometer) => cx.spawn.accel_leds().unwrap(),
+            Some(Mode::Ramp) => cx.spawn.ramp_leds().unwrap(),
+            Some(Mode::Chase) => cx.spawn.chase_leds().unwrap(),
+            Some(Mode::Breathe) => cx.spawn.breathe_leds().unwrap(),
+            Some(Mode::Sparkle) => cx.spawn.sparkle_leds().unwrap(),
+            Some(Mode::Counter) => cx.spawn.counter_leds().unwrap(),
+            Some(Mode::Off) | None => {}
+            // Without the accelerometer, accelerometer mode cannot be entered.
+            #[cfg(not(feature = "accel"))]
+            Some(Mode::Accelerometer) => {}
+        }
+    }
+
+    /// Interrupt handler that wakes the board up when the accelerometer detects motion while
+    /// sleeping (see the `motionwake` command).
+    ///
+    /// The motion interrupt is only enabled while sleeping; it stays latched until [`resume`]
+    /// disables it.  The bound interrupt has to match the EXTI line of the accelerometer
+    /// interrupt pin (see the board module).
+    #[task(binds = EXTI1, resources = [accel_int, exti_cntr, led_ring], spawn = [resume])]
+    fn motion_wake(mut cx: motion_wake::Context) {
+        // Without the accelerometer, its interrupt pin is not set up to trigger the interrupt.
+        #[cfg(feature = "accel")]
+        {
+            let accel_int = cx.resources.accel_int;
+            cx.resources
+                .exti_cntr
+                .lock(|exti_cntr| accel_int.clear_interrupt_pending_bit(exti_cntr));
+        }
+
+        if cx.resources.led_ring.lock(|led_ring| led_ring.wake()) {
+            cx.spawn.resume().ok();
+        }
+    }
+
+    /// Interrupt handler for the edges of the user button that schedules reading its state
+    /// once the contact has settled (see `button_debounced`).
+    ///
+    /// The handler is triggered on both edges; the edges that follow while a read is already
+    /// scheduled (e.g. due to bouncing) are covered by that read.
+    ///
+    /// The bound interrupt has to match the EXTI line of the button pin (see the board module).
+    #[task(binds = EXTI0, resources = [button, exti_cntr], schedule = [button_debounced])]
+    fn button_edge(mut cx: button_edge::Context) {
+        let mut exti_cntr = cx.resources.exti_cntr;
+        cx.resources.button.lock(|button| {
+            exti_cntr.lock(|exti_cntr| button.clear_interrupt_pending_bit(exti_cntr))
+        });
+
+        // Scheduling fails if a read is already scheduled, which is fine.
+        cx.schedule
+            .button_debounced(Instant::now() + (DEBOUNCE_MS * CYCLES_PER_MS).cycles())
+            .ok();
+    }
+
+    /// Task that reads the settled state of the user button, writes that the button is pressed
+    /// to the serial interface and runs the command for the button action, i.e. reverses the
+    /// LED ring cycle direction or switches to the next mode (or wakes up if sleeping).
+    ///
+    /// The task keeps track of whether the button is held; releasing the button has no further
+    /// effect, and neither has another read while the button is still held.  When holding the
+    /// button reverses the cycle direction, a press does not reverse it.
+    #[task(
+        resources = [
+            button, button_enabled, button_held, led_ring, rate_limiter, serial_tx, settings
+        ],
+        spawn = [resume, run_command]
+    )]
+    fn button_debounced(mut cx: button_debounced::Context) {
+        // The button may have been disabled after the edge.
+        if !cx
+            .resources
+            .button_enabled
+            .lock(|button_enabled| *button_enabled)
+        {
+            return;
+        }
+
+        let pressed = cx
+            .resources
             .button
-            .clear_interrupt_pending_bit(cx.resources.exti_cntr);
+            .lock(|button| button::is_pressed(button));
+        let was_held = cx
+            .resources
+            .button_held
+            .lock(|button_held| core::mem::replace(button_held, pressed));
+        if !button::is_new_press(was_held, pressed) {
+            return;
+        }
+
+        let settings = cx.resources.settings.lock(|settings| *settings);
+        let (outcome, command) = cx
+            .resources
+            .led_ring
+            .lock(|led_ring| tasks::button_pressed(led_ring, &settings));
+        if outcome.reschedule {
+            // The task may still be pending after a previous wake up.
+            cx.spawn.resume().ok();
+        }
+        if let Some(command) = command {
+            cx.spawn.run_command(command, false).ok();
+        }
+
+        // Write the fact that the button has been pressed to the serial port (unless quiet or
+        // it was pressed too soon after the previous time).
+        if let Some(message) = outcome
+            .message
+            .filter(|_| settings.allows(OutputKind::Event))
+        {
+            let interval = settings.rate_limit_ms as u32 * CYCLES_PER_MS;
+            if cx.resources.rate_limiter.allow(
+                MessageClass::Button,
+                Instant::now(),
+                interval.cycles(),
+            ) {
+                cx.resources
+                    .serial_tx
+                    .lock(|serial_tx| write_event(serial_tx, &settings, message));
+            }
+        }
     }
 
-    /// Interrupt handler that reads data from the serial connection and handles commands
-    /// once an appropriate command is in the buffer.
+    /// Interrupt handler that reads data from the serial connection into the buffer of
+    /// received bytes and has them handled.
+    ///
+    /// It has a higher priority than the tasks writing to the serial connection, so that no
+    /// bytes are lost while a (long) response is being written.
     #[task(
         binds = USART2,
+        priority = 3,
+        resources = [rx_buffer, serial_rx],
+        spawn = [handle_serial]
+    )]
+    fn receive_serial(cx: receive_serial::Context) {
+        cx.resources.rx_buffer.receive(cx.resources.serial_rx);
+        // If the task is still pending, it handles this byte as well.
+        cx.spawn.handle_serial().ok();
+    }
+
+    /// Task that handles the bytes received via the serial connection and handles commands
+    /// once an appropriate command is in the buffer.
+    ///
+    /// At most one line is handled at a time.  The bytes after it stay buffered until the last
+    /// command of the line has been run, which then spawns this task again.  (The task can be
+    /// spawned again by a received byte while it is running, so it does not suffice to spawn
+    /// the commands first.)
+    #[task(
         priority = 2,
-        resources = [buffer, led_ring, serial_rx, serial_tx],
-        spawn = [accel_leds, cycle_leds]
+        resources = [
+            buffer, demo, history, keys, last_activity, led_ring, line_pending, packets, rx_queue,
+            serial_tx, settings
+        ],
+        spawn = [handle_serial, resume, run_command]
     )]
     fn handle_serial(cx: handle_serial::Context) {
         let buffer = cx.resources.buffer;
+        let history = cx.resources.history;
+        let led_ring = cx.resources.led_ring;
+        let packets = cx.resources.packets;
+        let serial_tx = cx.resources.serial_tx;
+        let settings = cx.resources.settings;
+        let line_pending = cx.resources.line_pending;
 
-        // Read a byte from the serial port and write it back.
-        let byte = cx.resources.serial_rx.read().unwrap();
-        block!(cx.resources.serial_tx.write(byte)).unwrap();
-        //hprintln!("serial: {}", byte).unwrap();
+        // The next line waits until the commands of the previous one have been run.
+        if *line_pending {
+            return;
+        }
 
-        // Handle the command in the buffer for newline or backspace, otherwise append to the
-        // buffer.
-        if byte == b'\r' {
-            block!(cx.resources.serial_tx.write(b'\n')).unwrap();
-            match &buffer[..] {
-                b"flip" => {
-                    cx.resources.led_ring.reverse();
+        while let Some(received) = cx.resources.rx_queue.dequeue() {
+            // Errors (e.g. due to a wrong baud rate) are reported (unless quiet) and the byte is
+            // dropped.
+            let byte = match received {
+                Received::Byte(byte) => byte,
+                Received::Error(kind) => {
+                    if settings.allows(OutputKind::Event) {
+                        let message = format_args!("serial err: {}", kind);
+                        write_event(serial_tx, settings, message);
+                    }
+                    continue;
                 }
-                b"stop" => {
-                    cx.resources.led_ring.disable();
+            };
+            *cx.resources.last_activity = Instant::now();
+
+            // Flow control bytes pause or resume unsolicited output and are not part of a
+            // command.  Any byte can be part of a packet, so they are only recognized in between
+            // packets.
+            if !packets.is_partial() && settings.handle_flow_control(byte) {
+                continue;
+            }
+
+            // A packet is complete once all of its bytes are received; it is not edited or
+            // echoed.
+            let interactive = settings.interactive;
+            if settings.framing == Framing::Packet {
+                match packets.feed(buffer, byte) {
+                    PacketRead::Pending => continue,
+                    PacketRead::Oversized => {
+                        writeln!(serial_tx, "packet err: too long\r").unwrap();
+                        continue;
+                    }
+                    PacketRead::Complete => {}
                 }
-                b"cycle" => {
-                    cx.resources.led_ring.enable_cycle();
-                    cx.spawn.cycle_leds().unwrap();
+            } else {
+                // Decode the escape sequences of special keys, which are not echoed.  The up and
+                // down arrow keys recall commands from the history into the buffer.
+                let byte = match cx.resources.keys.feed(byte) {
+                    Some(Key::Byte(byte)) => byte,
+                    Some(key) => {
+                        let line = match key {
+                            Key::Up => history.older(),
+                            _ => history.newer(),
+                        };
+                        if let Some(line) = line {
+                            clear_line(buffer);
+                            buffer.extend_from_slice(line).unwrap();
+
+                            // Replace the line on the terminal by the recalled command.
+                            if settings.interactive {
+                                redraw_line(serial_tx, buffer);
+                            }
+                        }
+                        continue;
+                    }
+                    None => continue,
+                };
+                //hprintln!("serial: {}", byte).unwrap();
+
+                // Edit the line in the buffer and handle the command once it is complete.  If
+                // interactive, appended bytes are written back and editing redraws the line.
+                match edit_line(buffer, byte) {
+                    LineEdit::Submit => {}
+                    LineEdit::Append(byte) => {
+                        if interactive {
+                            serial_tx.write(byte).ok();
+                        }
+                        continue;
+                    }
+                    LineEdit::Full => {
+                        hprintln!("Serial read buffer full!").unwrap();
+                        continue;
+                    }
+                    LineEdit::Backspace | LineEdit::Clear => {
+                        if interactive {
+                            redraw_line(serial_tx, buffer);
+                        }
+                        continue;
+                    }
+                    LineEdit::Cancel => {
+                        if interactive {
+                            write!(serial_tx, "^C\r\n{}", PROMPT).unwrap();
+                        }
+                        continue;
+                    }
                 }
-                b"accel" => {
-                    cx.resources.led_ring.enable_accel();
-                    cx.spawn.accel_leds().unwrap();
+
+                // The line is complete, so finish it on the terminal and handle the command.
+                if interactive {
+                    write!(serial_tx, "\r\n").unwrap();
+                }
+            }
+
+            // An empty command line repeats the most recent one (including its prefix).
+            if settings.framing != Framing::Packet {
+                if let Some(line) = settings
+                    .prefix
+                    .strip(&buffer[..])
+                    .and_then(|command| history.repeat(command))
+                {
+                    clear_line(buffer);
+                    buffer.extend_from_slice(line).unwrap();
                 }
-                b"off" => {
-                    cx.resources.led_ring.disable();
-                    cx.resources.led_ring.all_off();
+            }
+
+            // A line without the command prefix is meant for someone else and is ignored.
+            let start = match settings.prefix.strip(&buffer[..]) {
+                Some(line) => buffer.len() - line.len(),
+                None => {
+                    clear_line(buffer);
+                    if interactive {
+                        write!(serial_tx, "{}", PROMPT).unwrap();
+                    }
+                    continue;
                 }
-                b"on" => {
-                    cx.resources.led_ring.disable();
-                    cx.resources.led_ring.all_on();
+            };
+
+            // Any command wakes up the LED ring if it is sleeping.
+            if led_ring.wake() {
+                // The task may still be pending after a previous wake up.
+                cx.spawn.resume().ok();
+            }
+
+            // Any command also stops the demo, before the command itself is run.
+            if cx.resources.demo.interrupt()
+                && cx.spawn.run_command(Command::PartyStop, false).is_err()
+            {
+                writeln!(serial_tx, "command err: busy\r").unwrap();
+            }
+
+            history.push(&buffer[..]);
+
+            // A valid command is run by a separate task, which also shows the prompt once the
+            // command has been handled completely.
+            let prompt = match Command::parse_line(&buffer[start..]) {
+                Ok(commands) => {
+                    // The commands are run in order; the last one shows the prompt and lets the
+                    // next line be handled once all have been handled.  If the commands cannot
+                    // all be spawned (because others are pending, e.g. from a sequence), the
+                    // rest of the line is dropped.
+                    let last = commands.len() - 1;
+                    *line_pending = true;
+                    for (index, command) in commands.iter().enumerate() {
+                        if cx.spawn.run_command(*command, index == last).is_err() {
+                            writeln!(serial_tx, "command err: busy\r").unwrap();
+                            *line_pending = false;
+                            break;
+                        }
+                    }
+                    !*line_pending
                 }
-                _ => {
-                    writeln!(cx.resources.serial_tx, "?\r").unwrap();
+                Err(ParseError::Empty) => true,
+                Err(_) => {
+                    if settings.debug_echo {
+                        // Show exactly which bytes were received.
+                        write!(serial_tx, "? ").unwrap();
+                        write_hex(serial_tx, &buffer[..]).unwrap();
+                        writeln!(serial_tx, "\r").unwrap();
+                    } else {
+                        writeln!(serial_tx, "?\r").unwrap();
+                    }
+                    true
                 }
+            };
+
+            clear_line(buffer);
+
+            if prompt && interactive {
+                write!(serial_tx, "{}", PROMPT).unwrap();
             }
+            //hprintln!("buffer: {:?}", buffer).unwrap();
 
-            buffer.clear();
-        } else if byte == 0x7F {
-            buffer.pop();
-            block!(cx.resources.serial_tx.write(b'\r')).unwrap();
-            for byte in buffer {
-                block!(cx.resources.serial_tx.write(*byte)).unwrap();
+            // The next line is handled once the commands have been run (see above), or right away
+            // if there are none.
+            if !*line_pending && cx.resources.rx_queue.ready() {
+                cx.spawn.handle_serial().ok();
             }
-        } else {
-            if buffer.push(byte).is_err() {
-                hprintln!("Serial read buffer full!").unwrap();
+            break;
+        }
+    }
+
+    /// Task that runs a command, given via the serial interface or by a gesture.
+    ///
+    /// After the last command of a line received via the serial interface (`line_end`), the
+    /// prompt is shown (if interactive) and the next line is handled.
+    ///
+    /// The capacity allows for all commands on a line (`MAX_LINE_COMMANDS`) plus one to stop
+    /// the demo and one from a gesture, the button or a sequence.
+    #[task(
+        capacity = 6,
+        priority = 2,
+        resources = [
+            accel, accel_ok, ack, button, button_enabled, button_held, calibration, clocks,
+            exti_cntr, led_ring, line_pending, party, reset_cause, sequencer, serial_tx, settings,
+            spin, timer_reference, transition
+        ],
+        schedule = [party_leds],
+        spawn = [
+            accel_leds, ack_leds, breathe_leds, calibrate_leds, chase_leds, counter_leds,
+            cycle_leds, handle_serial, ramp_leds, resume, run_sequence, sparkle_leds, spin_leds,
+            transition_leds
+        ]
+    )]
+    fn run_command(cx: run_command::Context, command: Command, line_end: bool) {
+        // The response is bounded; the prompt does not count.
+        let limit = cx.resources.settings.response_limit;
+        let serial_tx = &mut BoundedWriter::new(cx.resources.serial_tx, limit);
+
+        match command {
+            // Reporting the range needs the driver, even though it does not need the device.
+            #[cfg(not(feature = "accel"))]
+            command if command.needs_accel() || matches!(command, Command::AutoRange(None)) => {
+                writeln!(serial_tx, "{}\r", ACCEL_UNSUPPORTED).unwrap();
+            }
+            command if command.needs_accel() && !*cx.resources.accel_ok => {
+                writeln!(serial_tx, "accel err: unavailable\r").unwrap();
+            }
+            // The commands that only change a setting are applied by the settings themselves.
+            command @ (Command::AccelAnalog(Some(_))
+            | Command::AccelPeriod(Some(_))
+            | Command::AccelPoll(Some(_))
+            | Command::ButtonHold(Some(_))
+            | Command::ButtonMode(Some(_))
+            | Command::DebugEcho(Some(_))
+            | Command::Downhill(Some(_))
+            | Command::FlipAccel(Some(_))
+            | Command::FlowControl(Some(_))
+            | Command::Framing(Some(_))
+            | Command::Heartbeat(Some(_))
+            | Command::Interactive(Some(_))
+            | Command::LedCheck(Some(_))
+            | Command::Meter(Some(_))
+            | Command::MotionWake(Some(_))
+            | Command::Prefix(Some(_))
+            | Command::Quiet(Some(_))
+            | Command::RateLimit(Some(_))
+            | Command::ResponseLimit(Some(_))
+            | Command::SwapAxes(Some(_))
+            | Command::TiltSpeed(Some(_))
+            | Command::Timestamps(Some(_))
+            | Command::Transitions(Some(_))) => {
+                cx.resources.settings.apply(&command);
+            }
+            Command::SetAll(pairs) => {
+                // The settings are applied to a copy, which only replaces them once the FIFO has
+                // been reconfigured (if needed), so that nothing changes if that fails.
+                let mut updated = *cx.resources.settings;
+                updated.apply_all(&pairs);
+                #[cfg(feature = "accel")]
+                let applied = updated.accel_fifo == cx.resources.settings.accel_fifo
+                    || match cx.resources.accel.enable_fifo(updated.accel_fifo) {
+                        Ok(()) => true,
+                        Err(error) => {
+                            write_accel_error(serial_tx, error);
+                            false
+                        }
+                    };
+                #[cfg(not(feature = "accel"))]
+                let applied = true;
+                if applied {
+                    let settings = &mut *cx.resources.settings;
+                    *settings = updated;
+
+                    // The direction is set for the mode that is switched to (if any), so that it
+                    // also applies when the mode is entered after a transition.
+                    let led_ring = cx.resources.led_ring;
+                    let (mode, direction) = pairs.led_ring();
+                    if let Some(direction) = direction {
+                        led_ring.set_mode_direction(mode.unwrap_or(led_ring.mode()), direction);
+                    }
+                    if let Some(mode) = mode {
+                        set_mode(
+                            led_ring,
+                            cx.resources.transition,
+                            cx.resources.party,
+                            settings,
+                            cx.spawn,
+                            mode,
+                        );
+                    }
+                }
+            }
+            Command::GetAll => {
+                let led_ring = &*cx.resources.led_ring;
+                let (mode, direction) = (led_ring.mode(), led_ring.direction());
+                cx.resources
+                    .settings
+                    .write_all(serial_tx, mode, direction)
+                    .unwrap();
+                writeln!(serial_tx, "\r").unwrap();
+            }
+            Command::Ack(blinks) => {
+                let led_ring = cx.resources.led_ring;
+                match cx.resources.ack {
+                    // Restart the countdown, but keep the mode to return to.
+                    Some(ack) => ack.set_remaining_blinks(blinks),
+                    None => {
+                        *cx.resources.ack = Some(Ack::new(
+                            blinks,
+                            led_ring.mode(),
+                            led_ring.direction(),
+                            led_ring.states(),
+                        ));
+                        // Stop the task of the current mode.
+                        led_ring.disable();
+                        cx.spawn.ack_leds().unwrap();
+                    }
+                }
+            }
+            #[cfg(feature = "accel")]
+            Command::Calibrate => {
+                let led_ring = cx.resources.led_ring;
+                match cx.resources.calibration {
+                    // Restart the countdown, but keep the mode to return to.
+                    Some(calibration) => calibration.restart(),
+                    None => {
+                        *cx.resources.calibration =
+                            Some(Calibration::new(led_ring.mode(), led_ring.states()));
+                        // Stop the task of the current mode.
+                        led_ring.disable();
+                        cx.spawn.calibrate_leds().unwrap();
+                    }
+                }
+            }
+            Command::CalibrateReset => {
+                cx.resources.settings.accel_offset = (0, 0);
+            }
+            Command::ButtonMode(None) => {
+                let name = cx.resources.settings.button_action.as_name();
+                writeln!(serial_tx, "{}\r", name).unwrap();
+            }
+            Command::ButtonHold(None) => {
+                let state = on_off(cx.resources.settings.button_hold);
+                writeln!(serial_tx, "{}\r", state).unwrap();
+            }
+            Command::Button(Some(enable)) => {
+                let button = cx.resources.button;
+                let exti_cntr = cx.resources.exti_cntr;
+                if enable {
+                    // Clear a stale edge (that happened while disabled) before unmasking, so
+                    // that the interrupt does not fire right away.
+                    button.clear_interrupt_pending_bit(exti_cntr);
+                    NVIC::unpend(board::USER_BUTTON_INTERRUPT);
+                    button.enable_interrupt(exti_cntr);
+                } else {
+                    // Mask the interrupt before clearing a pending edge, so that no edge can
+                    // sneak in between.
+                    button.disable_interrupt(exti_cntr);
+                    button.clear_interrupt_pending_bit(exti_cntr);
+                    NVIC::unpend(board::USER_BUTTON_INTERRUPT);
+                }
+                // The next press is detected relative to the current state of the button.
+                *cx.resources.button_held = button::is_pressed(button);
+                *cx.resources.button_enabled = enable;
+            }
+            Command::Button(None) => {
+                let state = on_off(*cx.resources.button_enabled);
+                writeln!(serial_tx, "{}\r", state).unwrap();
+            }
+            Command::Buttons => {
+                let pressed = button::is_pressed(cx.resources.button);
+                writeln!(serial_tx, "user {}\r", button::state_name(pressed)).unwrap();
+            }
+            Command::CycleLead(Some(enable)) => {
+                cx.resources.led_ring.set_cycle_lead(enable);
+            }
+            Command::CycleLead(None) => {
+                let state = on_off(cx.resources.led_ring.cycle_lead());
+                writeln!(serial_tx, "{}\r", state).unwrap();
+            }
+            Command::Comet(Some(length)) => {
+                cx.resources.led_ring.set_comet_trail(length);
+            }
+            Command::Comet(None) => {
+                let length = cx.resources.led_ring.comet_trail();
+                writeln!(serial_tx, "{}\r", length).unwrap();
+            }
+            Command::Curve(Some(curve)) => {
+                cx.resources.led_ring.set_curve(curve);
+            }
+            Command::Curve(None) => {
+                let name = cx.resources.led_ring.curve().as_name();
+                writeln!(serial_tx, "{}\r", name).unwrap();
+            }
+            Command::CycleStyle(Some(style)) => {
+                cx.resources.led_ring.set_cycle_style(style);
+            }
+            Command::CycleStyle(None) => {
+                let name = cx.resources.led_ring.cycle_style().as_name();
+                writeln!(serial_tx, "{}\r", name).unwrap();
+            }
+            Command::Flip => {
+                cx.resources.led_ring.reverse();
+            }
+            Command::Direction(Some(direction)) => {
+                cx.resources.led_ring.set_direction(direction);
+            }
+            Command::Direction(None) => {
+                let name = cx.resources.led_ring.direction().as_name();
+                writeln!(serial_tx, "{}\r", name).unwrap();
+            }
+            Command::FlipAccel(None) => {
+                let flip = cx.resources.settings.accel_flip;
+                writeln!(serial_tx, "{}\r", flip.as_name()).unwrap();
+            }
+            Command::SwapAxes(None) => {
+                let state = on_off(cx.resources.settings.accel_swap);
+                writeln!(serial_tx, "{}\r", state).unwrap();
+            }
+            Command::Stop => {
+                *cx.resources.transition = None;
+                cx.resources.led_ring.disable();
+            }
+            Command::Cycle => {
+                set_mode(
+                    cx.resources.led_ring,
+                    cx.resources.transition,
+                    cx.resources.party,
+                    cx.resources.settings,
+                    cx.spawn,
+                    Mode::Cycle,
+                );
+            }
+            Command::CycleFrom(index) => match cx.resources.led_ring.set_index(index) {
+                Ok(()) => set_mode(
+                    cx.resources.led_ring,
+                    cx.resources.transition,
+                    cx.resources.party,
+                    cx.resources.settings,
+                    cx.spawn,
+                    Mode::Cycle,
+                ),
+                Err(_) => writeln!(serial_tx, "cycle err: invalid index\r").unwrap(),
+            },
+            #[cfg(feature = "accel")]
+            Command::Accel => {
+                set_mode(
+                    cx.resources.led_ring,
+                    cx.resources.transition,
+                    cx.resources.party,
+                    cx.resources.settings,
+                    cx.spawn,
+                    Mode::Accelerometer,
+                );
+            }
+            Command::Mode(Some(mode)) => {
+                set_mode(
+                    cx.resources.led_ring,
+                    cx.resources.transition,
+                    cx.resources.party,
+                    cx.resources.settings,
+                    cx.spawn,
+                    mode,
+                );
+            }
+            Command::NextMode => {
+                let mode = button::next_mode(cx.resources.led_ring.mode(), *cx.resources.accel_ok);
+                set_mode(
+                    cx.resources.led_ring,
+                    cx.resources.transition,
+                    cx.resources.party,
+                    cx.resources.settings,
+                    cx.spawn,
+                    mode,
+                );
+            }
+            Command::Mode(None) => {
+                let name = cx.resources.led_ring.mode().as_name();
+                writeln!(serial_tx, "{}\r", name).unwrap();
+            }
+            Command::Ramp { step, dwell } => {
+                let led_ring = cx.resources.led_ring;
+                if let Some(step) = step {
+                    led_ring.ramp_mut().set_step(step);
+                }
+                if let Some(dwell) = dwell {
+                    led_ring.ramp_mut().set_dwell(dwell);
+                }
+                set_mode(
+                    led_ring,
+                    cx.resources.transition,
+                    cx.resources.party,
+                    cx.resources.settings,
+                    cx.spawn,
+                    Mode::Ramp,
+                );
+            }
+            Command::Chase(rates) => {
+                let led_ring = cx.resources.led_ring;
+                if let Some(rates) = rates {
+                    led_ring.chase_mut().set_rates(rates);
+                }
+                set_mode(
+                    led_ring,
+                    cx.resources.transition,
+                    cx.resources.party,
+                    cx.resources.settings,
+                    cx.spawn,
+                    Mode::Chase,
+                );
+            }
+            Command::Breathe => {
+                set_mode(
+                    cx.resources.led_ring,
+                    cx.resources.transition,
+                    cx.resources.party,
+                    cx.resources.settings,
+                    cx.spawn,
+                    Mode::Breathe,
+                );
+            }
+            Command::Sparkle => {
+                set_mode(
+                    cx.resources.led_ring,
+                    cx.resources.transition,
+                    cx.resources.party,
+                    cx.resources.settings,
+                    cx.spawn,
+                    Mode::Sparkle,
+                );
+            }
+            Command::Count => {
+                set_mode(
+                    cx.resources.led_ring,
+                    cx.resources.transition,
+                    cx.resources.party,
+                    cx.resources.settings,
+                    cx.spawn,
+                    Mode::Counter,
+                );
+            }
+            Command::Seed(Some(seed)) => {
+                // The seed is non-zero, which is checked when parsing.
+                cx.resources.led_ring.sparkle_mut().set_seed(seed);
+            }
+            Command::Seed(None) => {
+                let seed = cx.resources.led_ring.sparkle().seed();
+                writeln!(serial_tx, "{}\r", seed).unwrap();
+            }
+            Command::BrightnessFloor(Some(floor)) => {
+                cx.resources.led_ring.set_brightness_floor(floor);
+            }
+            Command::BrightnessFloor(None) => {
+                let floor = cx.resources.led_ring.brightness_floor();
+                writeln!(serial_tx, "{}\r", floor).unwrap();
+            }
+            Command::NightMode(Some(enable)) => {
+                cx.resources.led_ring.set_night_mode(enable);
+            }
+            Command::NightMode(None) => {
+                let state = on_off(cx.resources.led_ring.is_night_mode());
+                writeln!(serial_tx, "{}\r", state).unwrap();
+            }
+            Command::Off => {
+                *cx.resources.transition = None;
+                cx.resources.led_ring.disable();
+                cx.resources.led_ring.all_off();
+            }
+            Command::Clear => {
+                cx.resources.led_ring.clear();
+            }
+            Command::On => {
+                *cx.resources.transition = None;
+                cx.resources.led_ring.disable();
+                cx.resources.led_ring.all_on();
+            }
+            Command::Macro(Some(sequence)) => {
+                cx.resources.sequencer.set_sequence(sequence);
+            }
+            Command::Macro(None) => {
+                // The steps are parsed from a command line, so they are ASCII.
+                for byte in cx.resources.sequencer.sequence().as_bytes() {
+                    serial_tx.write_char(*byte as char).unwrap();
+                }
+                writeln!(serial_tx, "\r").unwrap();
+            }
+            Command::MacroRun(looping) => match cx.resources.sequencer.start(looping) {
+                Ok(spawn) => {
+                    if spawn {
+                        cx.spawn.run_sequence().unwrap();
+                    }
+                }
+                Err(error) => {
+                    writeln!(serial_tx, "macro err: {}\r", error.as_str()).unwrap();
+                }
+            },
+            Command::MacroStop => {
+                cx.resources.sequencer.stop();
+            }
+            Command::Party => {
+                let led_ring = cx.resources.led_ring;
+                // A party in progress just continues.
+                if cx.resources.party.is_none() {
+                    let party = Party::new(led_ring.mode(), led_ring.states());
+                    let effect = party.effect();
+                    *cx.resources.party = Some(party);
+                    led_ring.set_mode(effect);
+                    led_ring.specific_on(effect.initial_states());
+                    // The tasks may still be pending from before; this is fine.
+                    cx.spawn.resume().ok();
+                    cx.schedule
+                        .party_leds(Instant::now() + PARTY_TICK.cycles())
+                        .ok();
+                }
+            }
+            Command::PartyStop => {
+                let led_ring = cx.resources.led_ring;
+                if let Some(party) = cx.resources.party.take() {
+                    // Only restore if the party has not ended already by changing the mode.
+                    if led_ring.mode() == party.effect() {
+                        let (mode, states) = party.restore();
+                        led_ring.set_mode(mode);
+                        led_ring.specific_on(states);
+                        cx.spawn.resume().ok();
+                    }
+                }
             }
+            #[cfg(feature = "accel")]
+            Command::Arrow => {
+                let (acc_x, acc_y) = read_accel(cx.resources.accel, cx.resources.settings);
+                writeln!(serial_tx, "{}\r", accel::arrow_from_accel(acc_x, acc_y)).unwrap();
+            }
+            #[cfg(feature = "accel")]
+            Command::RawAccel => match cx.resources.accel.read_xy_raw() {
+                Ok(result) => {
+                    write!(serial_tx, "rawaccel: ").unwrap();
+                    accel::write_raw_xy(serial_tx, &result).unwrap();
+                    writeln!(serial_tx, "\r").unwrap();
+                }
+                Err(error) => write_accel_error(serial_tx, error),
+            },
+            #[cfg(feature = "accel")]
+            Command::RegRead(address) => match accel::check_read(address) {
+                Ok(()) => match cx.resources.accel.read_register(address) {
+                    Ok(value) => {
+                        writeln!(serial_tx, "reg 0x{:02x}: 0x{:02x}\r", address, value).unwrap();
+                    }
+                    Err(error) => write_accel_error(serial_tx, error),
+                },
+                Err(error) => {
+                    writeln!(serial_tx, "reg err: {}\r", error.as_str()).unwrap();
+                }
+            },
+            #[cfg(feature = "accel")]
+            Command::RegWrite(address, value) => {
+                match accel::check_write(address) {
+                    Ok(()) => {
+                        let accel = cx.resources.accel;
+                        // Read back the value to show the result of the write.
+                        match accel
+                            .write_register(address, value)
+                            .and_then(|()| accel.read_register(address))
+                        {
+                            Ok(value) => {
+                                writeln!(serial_tx, "reg 0x{:02x}: 0x{:02x}\r", address, value)
+                                    .unwrap();
+                            }
+                            Err(error) => write_accel_error(serial_tx, error),
+                        }
+                    }
+                    Err(error) => {
+                        writeln!(serial_tx, "reg err: {}\r", error.as_str()).unwrap();
+                    }
+                }
+            }
+            Command::Reboot => {
+                // Make sure the message is sent completely before the serial interface is
+                // reset as well.
+                writeln!(serial_tx, "rebooting\r").unwrap();
+                serial_tx.inner().flush().ok();
+                SCB::sys_reset();
+            }
+            Command::Spin => {
+                let led_ring = cx.resources.led_ring;
+                match cx.resources.spin {
+                    // Restart the revolution, but keep the mode to return to.
+                    Some(spin) => spin.restart(),
+                    None => {
+                        *cx.resources.spin = Some(Spin::new(led_ring.mode(), led_ring.states()));
+                        // Stop the task of the current mode.
+                        led_ring.disable();
+                        cx.spawn.spin_leds().unwrap();
+                    }
+                }
+            }
+            Command::Map => {
+                let states = cx.resources.led_ring.states();
+                let settings = &*cx.resources.settings;
+                write_map(serial_tx, states, settings.accel_flip, settings.accel_swap).unwrap();
+            }
+            Command::Stack => {
+                let (used, size) = stack::usage();
+                writeln!(serial_tx, "stack: {} of {} bytes\r", used, size).unwrap();
+            }
+            Command::Timer => {
+                let count = DWT::cycle_count();
+                let running = DWT::cycle_counter_enabled();
+                let reference = *cx.resources.timer_reference;
+                timer::write_timer(serial_tx, count, running, reference).unwrap();
+                writeln!(serial_tx, "\r").unwrap();
+            }
+            Command::TimerReset => {
+                // The counter itself is left alone, since all scheduling is based on it.
+                *cx.resources.timer_reference = DWT::cycle_count();
+            }
+            Command::Factory => {
+                cx.resources.settings.restore_defaults();
+                writeln!(serial_tx, "factory settings\r").unwrap();
+            }
+            Command::Clocks => {
+                let clocks = cx.resources.clocks;
+                write!(serial_tx, "clocks: ").unwrap();
+                let frequencies = [
+                    ("sysclk", clocks.sysclk().0),
+                    ("hclk", clocks.hclk().0),
+                    ("pclk1", clocks.pclk1().0),
+                    ("pclk2", clocks.pclk2().0),
+                ];
+                clocks::write_clocks(serial_tx, &frequencies).unwrap();
+                writeln!(serial_tx, "\r").unwrap();
+            }
+            Command::ResetCause => {
+                let cause = cx.resources.reset_cause.as_str();
+                writeln!(serial_tx, "resetcause: {}\r", cause).unwrap();
+            }
+            Command::Diag => {
+                let leds = diag::check_led_sweep(cx.resources.led_ring);
+                #[cfg(feature = "accel")]
+                let (presence, reading) = {
+                    let accel = cx.resources.accel;
+                    let presence = diag::check_accel_presence(accel.is_present());
+                    (presence, diag::check_accel_read(accel.read_xy()))
+                };
+                #[cfg(not(feature = "accel"))]
+                let (presence, reading) = (CheckResult::Skip, CheckResult::Skip);
+                let checks = [
+                    Check {
+                        name: "leds",
+                        result: leds,
+                    },
+                    Check {
+                        name: "accel",
+                        result: presence,
+                    },
+                    Check {
+                        name: "accelread",
+                        result: reading,
+                    },
+                    Check {
+                        name: "loopback",
+                        result: diag::check_serial_loopback(),
+                    },
+                    Check {
+                        name: "txtimeout",
+                        result: diag::check_serial_timeouts(serial_tx.inner().timeouts()),
+                    },
+                ];
+                diag::write_summary(serial_tx, &checks).unwrap();
+            }
+            Command::Features => {
+                write!(serial_tx, "features: ").unwrap();
+                features::write_features(serial_tx, &FEATURES).unwrap();
+                writeln!(serial_tx, "\r").unwrap();
+            }
+            Command::Pin { index, state } => {
+                *cx.resources.transition = None;
+                cx.resources.led_ring.set_override(index as usize, state);
+            }
+            Command::Ping(sequence) => {
+                write_pong(serial_tx, sequence).unwrap();
+            }
+            Command::Version => {
+                writeln!(serial_tx, "{}\r", VERSION).unwrap();
+            }
+            Command::Sleep => {
+                cx.resources.led_ring.sleep();
+                #[cfg(feature = "accel")]
+                let threshold_mg = cx.resources.settings.motion_wake_mg;
+                #[cfg(feature = "accel")]
+                if threshold_mg > 0 && *cx.resources.accel_ok {
+                    let accel = cx.resources.accel;
+                    let threshold = accel::motion_threshold(threshold_mg, accel.scale());
+                    // The board still sleeps, but may not wake up on motion.
+                    if let Err(error) = accel.enable_motion_interrupt(Some(threshold)) {
+                        write_accel_error(serial_tx, error);
+                    }
+                }
+            }
+            Command::AccelAnalog(None) => {
+                let state = on_off(cx.resources.settings.accel_analog);
+                writeln!(serial_tx, "{}\r", state).unwrap();
+            }
+            Command::AccelPeriod(None) => {
+                let period_ms = cx.resources.settings.accel_period_ms;
+                writeln!(serial_tx, "{} ms\r", period_ms).unwrap();
+            }
+            Command::AccelPoll(None) => {
+                let state = on_off(cx.resources.settings.accel_polling);
+                writeln!(serial_tx, "{}\r", state).unwrap();
+            }
+            Command::Downhill(None) => {
+                let state = on_off(cx.resources.settings.accel_downhill);
+                writeln!(serial_tx, "{}\r", state).unwrap();
+            }
+            Command::Meter(None) => {
+                let state = on_off(cx.resources.settings.accel_meter);
+                writeln!(serial_tx, "{}\r", state).unwrap();
+            }
+            Command::LedCheck(None) => {
+                let state = on_off(cx.resources.settings.led_check);
+                writeln!(serial_tx, "{}\r", state).unwrap();
+            }
+            Command::DebugEcho(None) => {
+                let state = on_off(cx.resources.settings.debug_echo);
+                writeln!(serial_tx, "{}\r", state).unwrap();
+            }
+            #[cfg(feature = "accel")]
+            Command::AutoRange(Some(enable)) => {
+                if let Err(error) = cx.resources.accel.enable_auto_range(enable) {
+                    write_accel_error(serial_tx, error);
+                }
+            }
+            #[cfg(feature = "accel")]
+            Command::AutoRange(None) => match cx.resources.accel.auto_range() {
+                Some(scale) => writeln!(serial_tx, "on {}g\r", scale.as_g()).unwrap(),
+                None => writeln!(serial_tx, "off\r").unwrap(),
+            },
+            #[cfg(feature = "accel")]
+            Command::Fifo(Some(enable)) => match cx.resources.accel.enable_fifo(enable) {
+                Ok(()) => cx.resources.settings.accel_fifo = enable,
+                Err(error) => write_accel_error(serial_tx, error),
+            },
+            Command::Fifo(None) => {
+                let state = on_off(cx.resources.settings.accel_fifo);
+                writeln!(serial_tx, "{}\r", state).unwrap();
+            }
+            Command::FlowControl(None) => {
+                let state = on_off(cx.resources.settings.flow_control);
+                writeln!(serial_tx, "{}\r", state).unwrap();
+            }
+            Command::Framing(None) => {
+                let name = cx.resources.settings.framing.as_name();
+                writeln!(serial_tx, "{}\r", name).unwrap();
+            }
+            Command::Interactive(None) => {
+                let state = on_off(cx.resources.settings.interactive);
+                writeln!(serial_tx, "{}\r", state).unwrap();
+            }
+            Command::Quiet(None) => {
+                let state = on_off(cx.resources.settings.quiet);
+                writeln!(serial_tx, "{}\r", state).unwrap();
+            }
+            Command::Heartbeat(None) => {
+                let timeout_ms = cx.resources.settings.heartbeat_ms;
+                writeln!(serial_tx, "{} ms\r", timeout_ms).unwrap();
+            }
+            Command::MotionWake(None) => match cx.resources.settings.motion_wake_mg {
+                0 => writeln!(serial_tx, "off\r").unwrap(),
+                threshold_mg => writeln!(serial_tx, "{} mg\r", threshold_mg).unwrap(),
+            },
+            Command::Prefix(None) => {
+                let prefix = cx.resources.settings.prefix;
+                let prefix = if prefix.is_empty() {
+                    "off"
+                } else {
+                    prefix.as_str()
+                };
+                writeln!(serial_tx, "{}\r", prefix).unwrap();
+            }
+            Command::RateLimit(None) => {
+                let rate_limit_ms = cx.resources.settings.rate_limit_ms;
+                writeln!(serial_tx, "{} ms\r", rate_limit_ms).unwrap();
+            }
+            Command::ResponseLimit(None) => {
+                let limit = cx.resources.settings.response_limit;
+                writeln!(serial_tx, "{}\r", limit).unwrap();
+            }
+            Command::TiltSpeed(None) => {
+                let state = on_off(cx.resources.settings.tilt_speed);
+                writeln!(serial_tx, "{}\r", state).unwrap();
+            }
+            Command::Timestamps(None) => {
+                let state = on_off(cx.resources.settings.timestamps);
+                writeln!(serial_tx, "{}\r", state).unwrap();
+            }
+            Command::Transitions(None) => {
+                let state = on_off(cx.resources.settings.transitions);
+                writeln!(serial_tx, "{}\r", state).unwrap();
+            }
+            // The commands whose arms are left out without the accelerometer are answered by
+            // the arm for the commands that need it; guards do not count for exhaustiveness.
+            #[cfg(not(feature = "accel"))]
+            _ => unreachable!(),
+        }
+
+        if line_end {
+            if cx.resources.settings.interactive {
+                write!(serial_tx.inner(), "{}", PROMPT).unwrap();
+            }
+            *cx.resources.line_pending = false;
+            cx.spawn.handle_serial().ok();
+        }
+    }
+
+    /// Task that takes the next step of the sequence of commands (macro), i.e. runs the command
+    /// or waits, and schedules the next step until the sequence is finished or stopped.
+    #[task(resources = [sequencer], schedule = [run_sequence], spawn = [run_command])]
+    fn run_sequence(mut cx: run_sequence::Context) {
+        let step = cx.resources.sequencer.lock(Sequencer::advance);
+
+        if let Some(step) = step {
+            if let Step::Command(command) = step {
+                cx.spawn.run_command(command, false).ok();
+            }
+            let delay = step.delay_ms() as u32 * CYCLES_PER_MS;
+            cx.schedule
+                .run_sequence(Instant::now() + delay.cycles())
+                .unwrap();
         }
-        //hprintln!("buffer: {:?}", buffer).unwrap();
+    }
+
+    /// Task that handles a gesture by running the corresponding command from the gesture table.
+    ///
+    /// Like any command, a gesture wakes up the LED ring if it is sleeping.
+    #[cfg(feature = "accel")]
+    #[task(resources = [led_ring], spawn = [resume, run_command])]
+    fn handle_gesture(mut cx: handle_gesture::Context, gesture: Gesture) {
+        let (woken, mode) = cx
+            .resources
+            .led_ring
+            .lock(|led_ring| (led_ring.wake(), led_ring.mode()));
+        if woken {
+            // The task may still be pending after a previous wake up.
+            cx.spawn.resume().ok();
+        }
+
+        if let Some(command) = gesture::command_for(&GESTURES, gesture, mode) {
+            cx.spawn.run_command(command, false).ok();
+        }
+    }
+
+    /// Task that checks whether the heartbeat timed out, i.e. the host has not sent anything
+    /// for the configured time while it controls the LEDs, and if so, falls back to a safe
+    /// display (cycle mode) so that the board does not appear frozen.
+    ///
+    /// The task reschedules itself periodically, also when the timeout is disabled.
+    #[task(
+        resources = [
+            ack, calibration, last_activity, led_ring, serial_tx, settings, spin, transition
+        ],
+        schedule = [check_heartbeat],
+        spawn = [run_command]
+    )]
+    fn check_heartbeat(mut cx: check_heartbeat::Context) {
+        let settings = cx.resources.settings.lock(|settings| *settings);
+        let last_activity = cx
+            .resources
+            .last_activity
+            .lock(|last_activity| *last_activity);
+        let timeout = Some(settings.heartbeat_ms)
+            .filter(|&timeout_ms| timeout_ms > 0)
+            .map(|timeout_ms| (timeout_ms as u32 * CYCLES_PER_MS).cycles());
+
+        let (mode, sleeping) = cx
+            .resources
+            .led_ring
+            .lock(|led_ring| (led_ring.mode(), led_ring.is_sleeping()));
+        let overlay = cx.resources.ack.lock(|ack| ack.is_some())
+            || cx
+                .resources
+                .calibration
+                .lock(|calibration| calibration.is_some())
+            || cx.resources.spin.lock(|spin| spin.is_some())
+            || cx
+                .resources
+                .transition
+                .lock(|transition| transition.is_some());
+
+        let now = Instant::now();
+        if heartbeat::is_host_controlled(mode, sleeping, overlay)
+            && heartbeat::is_expired(timeout, last_activity, now)
+        {
+            if settings.allows(OutputKind::Event) {
+                cx.resources
+                    .serial_tx
+                    .lock(|serial_tx| write_event(serial_tx, &settings, "heartbeat timeout"));
+            }
+            cx.spawn
+                .run_command(Command::Mode(Some(FALLBACK_MODE)), false)
+                .ok();
+        }
+
+        cx.schedule
+            .check_heartbeat(now + HEARTBEAT_CHECK_PERIOD.cycles())
+            .unwrap();
     }
 
     extern "C" {
@@ -273,3 +2138,167 @@ const APP: () = {
         fn TIM3();
     }
 };
+
+/// Returns `"on"` or `"off"` for the given setting state.
+fn on_off(state: bool) -> &'static str {
+    if state {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+/// Polls the accelerometer and, if it has a new sample, adjusts the LED ring accordingly (in
+/// accelerometer mode); the level state is kept track of, but not reported.
+///
+/// Only new samples are used, as the data rate is much lower than the rate at which the
+/// accelerometer can be polled.
+#[cfg(feature = "accel")]
+fn poll_accel(
+    accel: &mut impl Mutex<T = Accelerometer>,
+    led_ring: &mut impl Mutex<T = LedRing<Led, PendingStates>>,
+    settings: &Settings,
+    level: &mut bool,
+) {
+    let sample = accel.lock(|accel| {
+        if settings.accel_fifo || accel.is_data_ready().unwrap() {
+            Some(read_accel(accel, settings))
+        } else {
+            None
+        }
+    });
+
+    // The mode may have changed during the measurement, in which case nothing is done (the
+    // task of accelerometer mode stops by itself).
+    if let Some(measurement) = sample {
+        led_ring.lock(|led_ring| {
+            if led_ring.is_mode_accel() {
+                tasks::accel_leds(led_ring, Some(measurement), settings, level);
+            }
+        });
+    }
+}
+
+/// Reads the X and Y acceleration according to the settings.
+///
+/// The offset (from calibration) is subtracted, and the axes are swapped and flipped if needed
+/// (see `accel::orient`).
+#[cfg(feature = "accel")]
+fn read_accel(accel: &mut Accelerometer, settings: &Settings) -> (i8, i8) {
+    let (acc_x, acc_y) = read_raw_accel(accel, settings);
+    let (acc_x, acc_y) = calibrate::calibrated(acc_x, acc_y, settings.accel_offset);
+
+    accel::orient(acc_x, acc_y, settings.accel_swap, settings.accel_flip)
+}
+
+/// Reads the X and Y acceleration as measured, i.e. without offset, swapping and flipping.
+///
+/// The samples in the FIFO are averaged if it is enabled.
+#[cfg(feature = "accel")]
+fn read_raw_accel(accel: &mut Accelerometer, settings: &Settings) -> (i8, i8) {
+    if settings.accel_fifo {
+        accel.read_xy_fifo().unwrap()
+    } else {
+        accel.read_xy().unwrap()
+    }
+}
+
+/// Writes the error of a transfer with the accelerometer in response to a command.
+#[cfg(feature = "accel")]
+fn write_accel_error<E>(serial_tx: &mut impl Write, error: BusError<E>) {
+    writeln!(serial_tx, "accel err: {}\r", error.as_str()).unwrap();
+}
+
+/// Writes the LED anomaly found by a step of an animation task (if any) to the serial interface,
+/// if checking the LEDs is enabled (and not quiet).
+fn report_anomaly(
+    outcome: &Outcome,
+    settings: &Settings,
+    serial_tx: &mut impl Mutex<T = SerialTx>,
+) {
+    if let Some(message) = outcome
+        .message
+        .filter(|_| settings.led_check && settings.allows(OutputKind::Event))
+    {
+        serial_tx.lock(|serial_tx| write_event(serial_tx, settings, message));
+    }
+}
+
+/// Writes an event (unsolicited output) as a line, prefixed with a timestamp if enabled.
+///
+/// Whether the event may be written at all (see `Settings::allows`) is up to the caller.
+fn write_event(serial_tx: &mut SerialTx, settings: &Settings, message: impl Display) {
+    if settings.timestamps {
+        timer::write_timestamp(serial_tx, DWT::cycle_count()).unwrap();
+    }
+    writeln!(serial_tx, "{}\r", message).unwrap();
+}
+
+/// Redraws the line being entered on the terminal: the prompt followed by the buffer.
+fn redraw_line(serial_tx: &mut SerialTx, buffer: &[u8]) {
+    write!(serial_tx, "\r\x1b[K{}", PROMPT).unwrap();
+    for byte in buffer {
+        serial_tx.write(*byte).ok();
+    }
+}
+
+/// Sets the mode of the LED ring and spawns the task corresponding to the mode.
+///
+/// The task is only spawned if it is not pending already (see `LedRing::claim_task`), so that
+/// a single task animates the mode.  If transitions are enabled, the transition animation is
+/// started instead, which enters the mode afterwards; a transition in progress is restarted to
+/// enter the new mode, or cancelled if transitions are disabled.
+///
+/// Setting the mode ends a party in progress (without restoring the mode from before it).
+fn set_mode(
+    led_ring: &mut LedRing<Led, PendingStates>,
+    transition: &mut Option<Transition>,
+    party: &mut Option<Party>,
+    settings: &Settings,
+    spawn: run_command::Spawn,
+    mode: Mode,
+) {
+    *party = None;
+    let changed = led_ring.mode() != mode;
+
+    if settings.transitions && (changed || transition.is_some()) {
+        match transition {
+            Some(transition) => transition.retarget(mode),
+            None => {
+                *transition = Some(Transition::new(mode));
+                // Stop the task of the current mode.
+                led_ring.disable();
+                spawn.transition_leds().unwrap();
+            }
+        }
+        return;
+    }
+
+    // A transition still in progress would enter its mode later on.
+    *transition = None;
+    led_ring.set_mode(mode);
+    match mode {
+        Mode::Off => led_ring.all_off(),
+        Mode::Ramp | Mode::Breathe if changed => led_ring.all_on(),
+        _ => {}
+    }
+
+    // The task of the mode is only spawned if it is not still pending from before the mode
+    // was left (see `LedRing::claim_task`), so that a single task animates the mode.
+    if led_ring.claim_task(mode) {
+        match mode {
+            Mode::Cycle => spawn.cycle_leds().unwrap(),
+            #[cfg(feature = "accel")]
+            Mode::Accelerometer => spawn.accel_leds().unwrap(),
+            Mode::Ramp => spawn.ramp_leds().unwrap(),
+            Mode::Chase => spawn.chase_leds().unwrap(),
+            Mode::Breathe => spawn.breathe_leds().unwrap(),
+            Mode::Sparkle => spawn.sparkle_leds().unwrap(),
+            Mode::Counter => spawn.counter_leds().unwrap(),
+            Mode::Off => {}
+            // Without the accelerometer, accelerometer mode cannot be entered.
+            #[cfg(not(feature = "accel"))]
+            Mode::Accelerometer => {}
+        }
+    }
+}
@@ -0,0 +1,99 @@
+//! Module for drawing the layout of the LED ring, as a debugging aid.
+//!
+//! The LEDs are drawn at their compass directions when looking at the board with the mini-USB
+//! port held down (south), together with the orientation that is assumed.
+
+use core::fmt::{self, Write};
+
+use crate::accel::AxisFlip;
+
+/// The character drawn for an LED that is on.
+const LIT: char = '*';
+
+/// The character drawn for an LED that is off.
+const UNLIT: char = '.';
+
+/// Returns the character drawn for an LED in the given state.
+fn led_char(on: bool) -> char {
+    if on {
+        LIT
+    } else {
+        UNLIT
+    }
+}
+
+/// Writes a diagram of the LED ring with the given (logical) states and the assumed orientation.
+///
+/// The states are in the order of [`LedRing::specific_on`]: `[east, south, west, north]`.  Each
+/// line is terminated by `\r\n`.  Swapped axes are only mentioned if enabled.
+///
+/// [`LedRing::specific_on`]: crate::led_ring::LedRing::specific_on
+pub fn write_map<W: Write>(
+    writer: &mut W,
+    states: [bool; 4],
+    flip: AxisFlip,
+    swap: bool,
+) -> fmt::Result {
+    let [east, south, west, north] = states;
+
+    writeln!(writer, "  N {}\r", led_char(north))?;
+    writeln!(writer, "W {}   E {}\r", led_char(west), led_char(east))?;
+    writeln!(writer, "  S {}\r", led_char(south))?;
+    write!(writer, "USB down, flipaccel {}", flip.as_name())?;
+    if swap {
+        writer.write_str(", swapaxes on")?;
+    }
+    writer.write_str("\r\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_map, AxisFlip};
+
+    #[test]
+    fn map_patterns() {
+        let mut output = String::new();
+        write_map(&mut output, [false; 4], AxisFlip::NONE, false).unwrap();
+        assert_eq!(
+            output,
+            "  N .\r\nW .   E .\r\n  S .\r\nUSB down, flipaccel none\r\n"
+        );
+
+        let mut output = String::new();
+        write_map(&mut output, [true; 4], AxisFlip::NONE, false).unwrap();
+        assert_eq!(
+            output,
+            "  N *\r\nW *   E *\r\n  S *\r\nUSB down, flipaccel none\r\n"
+        );
+
+        // Each LED is drawn at its own direction.
+        let expected = [
+            "  N .\r\nW .   E *\r\n  S .\r\n",
+            "  N .\r\nW .   E .\r\n  S *\r\n",
+            "  N .\r\nW *   E .\r\n  S .\r\n",
+            "  N *\r\nW .   E .\r\n  S .\r\n",
+        ];
+        for (index, expected) in expected.iter().enumerate() {
+            let mut states = [false; 4];
+            states[index] = true;
+            let mut output = String::new();
+            write_map(&mut output, states, AxisFlip::NONE, false).unwrap();
+            assert!(output.starts_with(expected), "LED {}: {:?}", index, output);
+        }
+    }
+
+    #[test]
+    fn map_orientation() {
+        let mut output = String::new();
+        let flip = AxisFlip { x: true, y: false };
+        write_map(&mut output, [true, false, true, false], flip, false).unwrap();
+        assert_eq!(
+            output,
+            "  N .\r\nW *   E *\r\n  S .\r\nUSB down, flipaccel x\r\n"
+        );
+
+        let mut output = String::new();
+        write_map(&mut output, [false; 4], flip, true).unwrap();
+        assert!(output.ends_with("USB down, flipaccel x, swapaxes on\r\n"));
+    }
+}
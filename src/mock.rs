@@ -0,0 +1,138 @@
+//! Module with the mock peripherals shared by the tests.
+
+use core::convert::Infallible;
+use hal::prelude::_embedded_hal_blocking_spi_Transfer as Transfer;
+use hal::prelude::_embedded_hal_blocking_spi_Write as Write;
+use hal::prelude::_embedded_hal_digital_v2_OutputPin as OutputPin;
+use hal::prelude::_embedded_hal_digital_v2_StatefulOutputPin as StatefulOutputPin;
+use std::cell::Cell;
+use std::rc::Rc;
+
+use crate::led_ring::LedRing;
+
+/// Mock output pin that keeps its state and counts the writes to it.
+///
+/// A pin can be stuck at a state, which it then keeps regardless of the writes.
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct MockOutputPin {
+    pub state: bool,
+    pub writes: usize,
+    pub stuck: Option<bool>,
+}
+
+impl MockOutputPin {
+    /// Returns four pins, for an LED ring.
+    pub fn get_4() -> [Self; 4] {
+        Default::default()
+    }
+}
+
+impl OutputPin for MockOutputPin {
+    type Error = Infallible;
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.state = self.stuck.unwrap_or(true);
+        self.writes += 1;
+        Ok(())
+    }
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.state = self.stuck.unwrap_or(false);
+        self.writes += 1;
+        Ok(())
+    }
+}
+
+impl StatefulOutputPin for MockOutputPin {
+    fn is_set_high(&self) -> Result<bool, Self::Error> {
+        Ok(self.state)
+    }
+
+    fn is_set_low(&self) -> Result<bool, Self::Error> {
+        Ok(!self.state)
+    }
+}
+
+/// Returns an LED ring of mock pins with the default settings.
+pub fn led_ring() -> LedRing<MockOutputPin> {
+    LedRing::from(MockOutputPin::get_4())
+}
+
+/// Mock chip select output that shares its state, so that the mock SPI can check which
+/// devices are selected.
+#[derive(Clone, Debug)]
+pub struct MockCs(Rc<Cell<bool>>);
+
+impl Default for MockCs {
+    /// Returns a new chip select output, with the device deselected.
+    fn default() -> MockCs {
+        MockCs(Rc::new(Cell::new(true)))
+    }
+}
+
+impl MockCs {
+    /// Returns whether the device is selected (i.e. the output is low).
+    pub fn is_selected(&self) -> bool {
+        !self.0.get()
+    }
+}
+
+impl OutputPin for MockCs {
+    type Error = Infallible;
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.0.set(true);
+        Ok(())
+    }
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.0.set(false);
+        Ok(())
+    }
+}
+
+/// Mock SPI that records the written bytes and replies with a fixed byte (or fails).
+///
+/// The first byte of a transfer (the address) is echoed.  For each written byte, it also
+/// records which of the attached devices are selected at the time.
+#[derive(Debug, Default)]
+pub struct MockSpi {
+    pub written: Vec<u8>,
+    pub selected: Vec<Vec<bool>>,
+    pub devices: Vec<MockCs>,
+    pub reply: u8,
+    pub fail: bool,
+}
+
+impl MockSpi {
+    /// Records the written bytes and the devices selected while writing them.
+    fn record(&mut self, words: &[u8]) -> Result<(), ()> {
+        if self.fail {
+            return Err(());
+        }
+        let selected: Vec<bool> = self.devices.iter().map(MockCs::is_selected).collect();
+        self.written.extend_from_slice(words);
+        self.selected.resize(self.written.len(), selected);
+        Ok(())
+    }
+}
+
+impl Transfer<u8> for MockSpi {
+    type Error = ();
+
+    fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Self::Error> {
+        self.record(words)?;
+        for word in words.iter_mut().skip(1) {
+            *word = self.reply;
+        }
+        Ok(words)
+    }
+}
+
+impl Write<u8> for MockSpi {
+    type Error = ();
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        self.record(words)
+    }
+}
@@ -0,0 +1,133 @@
+//! Module for party mode, which rotates through several effects as a hands-off showcase.
+//!
+//! Party mode is not a mode of the LED ring itself: it switches the LED ring between the modes
+//! of its effects, and restores the mode from before the party when it is stopped.
+
+use crate::led_ring::Mode;
+
+/// The effects shown by party mode (in order): spinning, chasing dots, fading in and out
+/// (breathing) and random LEDs.
+pub const PARTY_EFFECTS: [Mode; 4] = [Mode::Cycle, Mode::Chase, Mode::Breathe, Mode::Sparkle];
+
+/// The number of ticks each effect is shown for.
+pub const PARTY_DWELL_TICKS: u8 = 5;
+
+/// A step of the party.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PartyStep {
+    /// Keep showing the current effect.
+    Stay,
+    /// Switch to the effect with the given mode.
+    Switch(Mode),
+}
+
+/// A party that rotates through the effects, switching to the next one every
+/// [`PARTY_DWELL_TICKS`] ticks.
+///
+/// The mode and LED states of the LED ring from before the party are kept so that they can be
+/// restored afterwards.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Party {
+    /// The index of the current effect.
+    effect: usize,
+    /// The number of ticks the current effect has been shown for.
+    elapsed: u8,
+    /// The mode to restore.
+    mode: Mode,
+    /// The LED states to restore.
+    states: [bool; 4],
+}
+
+impl Party {
+    /// Sets up a party starting with the first effect, that restores the given mode and LED
+    /// states when stopped.
+    pub fn new(mode: Mode, states: [bool; 4]) -> Party {
+        Party {
+            effect: 0,
+            elapsed: 0,
+            mode,
+            states,
+        }
+    }
+
+    /// Returns the mode of the current effect.
+    pub fn effect(&self) -> Mode {
+        PARTY_EFFECTS[self.effect]
+    }
+
+    /// Returns the number of ticks the current effect has been shown for.
+    pub fn elapsed(&self) -> u8 {
+        self.elapsed
+    }
+
+    /// Returns the mode and LED states to restore when the party is stopped.
+    pub fn restore(&self) -> (Mode, [bool; 4]) {
+        (self.mode, self.states)
+    }
+
+    /// Advances the party one tick and returns what to do.
+    ///
+    /// After the last effect, the party starts over with the first one.
+    pub fn tick(&mut self) -> PartyStep {
+        self.elapsed += 1;
+        if self.elapsed < PARTY_DWELL_TICKS {
+            return PartyStep::Stay;
+        }
+
+        self.elapsed = 0;
+        self.effect = (self.effect + 1) % PARTY_EFFECTS.len();
+        PartyStep::Switch(self.effect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mode, Party, PartyStep, PARTY_DWELL_TICKS, PARTY_EFFECTS};
+
+    #[test]
+    fn party_init() {
+        let party = Party::new(Mode::Accelerometer, [true, false, false, true]);
+
+        assert_eq!(party.effect(), PARTY_EFFECTS[0]);
+        assert_eq!(party.elapsed(), 0);
+        assert_eq!(
+            party.restore(),
+            (Mode::Accelerometer, [true, false, false, true])
+        );
+    }
+
+    #[test]
+    fn party_rotation() {
+        let mut party = Party::new(Mode::Off, [false; 4]);
+
+        // Each effect is shown for the dwell time, after the last one it starts over.
+        for next in PARTY_EFFECTS
+            .iter()
+            .cycle()
+            .skip(1)
+            .take(PARTY_EFFECTS.len() + 1)
+        {
+            for elapsed in 1..PARTY_DWELL_TICKS {
+                assert_eq!(party.tick(), PartyStep::Stay);
+                assert_eq!(party.elapsed(), elapsed);
+            }
+            assert_eq!(party.tick(), PartyStep::Switch(*next));
+            assert_eq!(party.effect(), *next);
+            assert_eq!(party.elapsed(), 0);
+        }
+        assert_eq!(party.effect(), PARTY_EFFECTS[1]);
+
+        // The state to restore is kept.
+        assert_eq!(party.restore(), (Mode::Off, [false; 4]));
+    }
+
+    #[test]
+    fn party_effects() {
+        // The effects are animations that differ from each other.
+        for (index, effect) in PARTY_EFFECTS.iter().enumerate() {
+            assert_ne!(*effect, Mode::Off);
+            assert_ne!(*effect, Mode::Accelerometer);
+            assert!(!PARTY_EFFECTS[index + 1..].contains(effect));
+        }
+    }
+}
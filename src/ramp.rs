@@ -0,0 +1,153 @@
+//! Module for ramping the brightness of the LED ring up and down.
+
+/// The default brightness increase/decrease per ramp step.
+pub const DEFAULT_STEP: u8 = 5;
+
+/// The default number of cycles to dwell at each brightness level.
+pub const DEFAULT_DWELL: u32 = 200_000;
+
+/// A brightness ramp.
+///
+/// The ramp continuously goes from zero to full brightness and back again, changing the
+/// brightness level by the step size at each step.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Ramp {
+    /// The current brightness level.
+    level: u8,
+    /// Whether the brightness level is currently rising.
+    rising: bool,
+    /// The brightness increase/decrease per step.
+    step: u8,
+    /// The number of cycles to dwell at each brightness level.
+    dwell: u32,
+}
+
+impl Ramp {
+    /// Sets up a ramp starting at zero brightness with the given step size and dwell.
+    ///
+    /// A step size of zero is treated as one.
+    pub fn new(step: u8, dwell: u32) -> Ramp {
+        Ramp {
+            level: 0,
+            rising: true,
+            step: step.max(1),
+            dwell,
+        }
+    }
+
+    /// Returns the current brightness level.
+    pub fn level(&self) -> u8 {
+        self.level
+    }
+
+    /// Returns the brightness increase/decrease per step.
+    pub fn step(&self) -> u8 {
+        self.step
+    }
+
+    /// Sets the brightness increase/decrease per step.
+    ///
+    /// A step size of zero is treated as one.
+    pub fn set_step(&mut self, step: u8) {
+        self.step = step.max(1);
+    }
+
+    /// Returns the number of cycles to dwell at each brightness level.
+    pub fn dwell(&self) -> u32 {
+        self.dwell
+    }
+
+    /// Sets the number of cycles to dwell at each brightness level.
+    pub fn set_dwell(&mut self, dwell: u32) {
+        self.dwell = dwell;
+    }
+
+    /// Advances the ramp one step and returns the new brightness level.
+    ///
+    /// The ramp turns around when it hits zero or full brightness.
+    pub fn advance(&mut self) -> u8 {
+        if self.rising {
+            self.level = self.level.saturating_add(self.step);
+            if self.level == u8::MAX {
+                self.rising = false;
+            }
+        } else {
+            self.level = self.level.saturating_sub(self.step);
+            if self.level == 0 {
+                self.rising = true;
+            }
+        }
+
+        self.level
+    }
+}
+
+impl Default for Ramp {
+    fn default() -> Ramp {
+        Ramp::new(DEFAULT_STEP, DEFAULT_DWELL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Ramp, DEFAULT_DWELL, DEFAULT_STEP};
+
+    #[test]
+    fn ramp_init() {
+        let ramp = Ramp::default();
+
+        assert_eq!(ramp.level(), 0);
+        assert_eq!(ramp.step(), DEFAULT_STEP);
+        assert_eq!(ramp.dwell(), DEFAULT_DWELL);
+
+        let ramp = Ramp::new(0, 1000);
+        assert_eq!(ramp.step(), 1);
+        assert_eq!(ramp.dwell(), 1000);
+    }
+
+    #[test]
+    fn ramp_advance() {
+        let mut ramp = Ramp::new(100, 1000);
+
+        assert_eq!(ramp.advance(), 100);
+        assert_eq!(ramp.advance(), 200);
+        assert_eq!(ramp.advance(), 255);
+        assert_eq!(ramp.advance(), 155);
+        assert_eq!(ramp.advance(), 55);
+        assert_eq!(ramp.advance(), 0);
+        assert_eq!(ramp.advance(), 100);
+    }
+
+    #[test]
+    fn ramp_advance_full_period() {
+        let mut ramp = Ramp::new(DEFAULT_STEP, DEFAULT_DWELL);
+
+        // With a step size that divides the full range, the ramp takes an equal number of
+        // steps up and down and is periodic.
+        let steps = 2 * (u8::MAX / DEFAULT_STEP) as usize;
+        let mut prev = ramp.level();
+        for _ in 0..steps {
+            let level = ramp.advance();
+            let diff = (level as i16 - prev as i16).abs();
+            assert_eq!(diff, DEFAULT_STEP as i16);
+            prev = level;
+        }
+        assert_eq!(ramp.level(), 0);
+        assert_eq!(ramp.advance(), DEFAULT_STEP);
+    }
+
+    #[test]
+    fn ramp_set_step_dwell() {
+        let mut ramp = Ramp::default();
+
+        ramp.set_step(50);
+        assert_eq!(ramp.step(), 50);
+        assert_eq!(ramp.advance(), 50);
+        ramp.set_step(0);
+        assert_eq!(ramp.step(), 1);
+        assert_eq!(ramp.advance(), 51);
+
+        ramp.set_dwell(42);
+        assert_eq!(ramp.dwell(), 42);
+    }
+}
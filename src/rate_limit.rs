@@ -0,0 +1,152 @@
+//! Module for limiting the rate of asynchronous messages sent via the serial interface.
+//!
+//! The rate limiter is generic over the instant type of the clock, so that it can be used with
+//! the CYCCNT-based instants of the scheduler, which wrap around and are therefore compared
+//! relative to each other.
+
+use core::ops::Add;
+
+/// The default minimum interval between messages of the same class (in milliseconds).
+pub const DEFAULT_INTERVAL_MS: u16 = 100;
+
+/// The class of an asynchronous message.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MessageClass {
+    /// The board has become level or is no longer level (in accelerometer mode).
+    Level,
+    /// The user button has been pressed.
+    Button,
+}
+
+impl MessageClass {
+    /// All message classes.
+    pub const ALL: [MessageClass; 2] = [MessageClass::Level, MessageClass::Button];
+
+    /// Returns the index of the message class in the list of all message classes.
+    fn index(&self) -> usize {
+        match self {
+            MessageClass::Level => 0,
+            MessageClass::Button => 1,
+        }
+    }
+}
+
+/// Returns whether a message may be sent at the current instant given the instant the previous
+/// message of the same class was sent (if any) and the minimum interval.
+///
+/// Because the CYCCNT-based instants wrap around, a message is also allowed if the previous one
+/// was sent so long ago that it appears to be in the future (about 134 seconds at 16 MHz).
+pub fn allow<I, D>(last: Option<I>, now: I, interval: D) -> bool
+where
+    I: Add<D, Output = I> + Copy + Ord,
+{
+    match last {
+        Some(last) => now >= last + interval || now < last,
+        None => true,
+    }
+}
+
+/// A rate limiter that drops messages that follow the previous message of the same class too
+/// soon.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RateLimiter<I> {
+    /// The instant the last message of each class was sent.
+    last: [Option<I>; MessageClass::ALL.len()],
+}
+
+impl<I: Copy> RateLimiter<I> {
+    /// Sets up a rate limiter for which no messages have been sent yet.
+    pub const fn new() -> RateLimiter<I> {
+        RateLimiter {
+            last: [None; MessageClass::ALL.len()],
+        }
+    }
+
+    /// Returns whether a message of the given class may be sent at the current instant with
+    /// the given minimum interval.
+    ///
+    /// If so, the message is assumed to be sent at the current instant.
+    pub fn allow<D>(&mut self, class: MessageClass, now: I, interval: D) -> bool
+    where
+        I: Add<D, Output = I> + Ord,
+    {
+        let last = &mut self.last[class.index()];
+        if allow(*last, now, interval) {
+            *last = Some(now);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl<I: Copy> Default for RateLimiter<I> {
+    fn default() -> RateLimiter<I> {
+        RateLimiter::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{allow, Add, MessageClass, RateLimiter};
+    use std::cmp::Ordering;
+
+    /// Mock instant that wraps around like the CYCCNT-based instants.
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    struct MockInstant(i32);
+
+    impl Add<u32> for MockInstant {
+        type Output = MockInstant;
+
+        fn add(self, cycles: u32) -> MockInstant {
+            MockInstant(self.0.wrapping_add(cycles as i32))
+        }
+    }
+
+    impl Ord for MockInstant {
+        fn cmp(&self, other: &MockInstant) -> Ordering {
+            self.0.wrapping_sub(other.0).cmp(&0)
+        }
+    }
+
+    impl PartialOrd for MockInstant {
+        fn partial_cmp(&self, other: &MockInstant) -> Option<Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    #[test]
+    fn allow_decision() {
+        let at = MockInstant;
+
+        assert!(allow(None, at(0), 100));
+        assert!(!allow(Some(at(1000)), at(1000), 100));
+        assert!(!allow(Some(at(1000)), at(1099), 100));
+        assert!(allow(Some(at(1000)), at(1100), 100));
+
+        // The cycle counter wraps around.
+        assert!(!allow(Some(at(i32::MAX - 10)), at(i32::MIN + 50), 100));
+        assert!(allow(Some(at(i32::MAX - 10)), at(i32::MIN + 89), 100));
+        // A message sent so long ago that it appears to be in the future is not in the way.
+        assert!(allow(Some(at(1000)), at(1000 + i32::MIN + 50), 100));
+
+        // A zero interval disables the rate limit.
+        assert!(allow(Some(at(1000)), at(1000), 0));
+    }
+
+    #[test]
+    fn rate_limiter() {
+        let at = MockInstant;
+        let mut limiter = RateLimiter::default();
+
+        assert!(limiter.allow(MessageClass::Level, at(0), 100));
+        assert!(!limiter.allow(MessageClass::Level, at(50), 100));
+        // The classes are limited independently.
+        assert!(limiter.allow(MessageClass::Button, at(50), 100));
+        assert!(!limiter.allow(MessageClass::Button, at(60), 100));
+        // Dropped messages do not postpone the next message.
+        assert!(limiter.allow(MessageClass::Level, at(100), 100));
+        assert!(!limiter.allow(MessageClass::Level, at(150), 100));
+        assert!(limiter.allow(MessageClass::Button, at(150), 100));
+    }
+}
@@ -0,0 +1,143 @@
+//! Module for determining why the board was last reset.
+//!
+//! The reset flags are kept in the RCC control/status register (CSR) across resets until they
+//! are cleared by setting its RMVF bit, which is why they are read and cleared once at
+//! initialization.  Some resets set several flags: a power-on reset also sets the brownout and
+//! pin reset flags, and any internal reset (software, watchdog or low-power) also sets the pin
+//! reset flag, as it drives the reset pin.  The flags are therefore decoded from the most
+//! specific to the least specific.
+
+/// The low-power reset flag.
+pub const LPWRRSTF: u32 = 1 << 31;
+/// The window watchdog reset flag.
+pub const WWDGRSTF: u32 = 1 << 30;
+/// The independent watchdog reset flag.
+pub const IWDGRSTF: u32 = 1 << 29;
+/// The software reset flag.
+pub const SFTRSTF: u32 = 1 << 28;
+/// The power-on/power-down reset flag.
+pub const PORRSTF: u32 = 1 << 27;
+/// The pin reset flag.
+pub const PINRSTF: u32 = 1 << 26;
+/// The brownout reset flag.
+pub const BORRSTF: u32 = 1 << 25;
+
+/// The cause of the last reset.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ResetCause {
+    /// The core entered a low-power mode it was not allowed to enter.
+    LowPower,
+    /// The window watchdog expired or was refreshed outside of its window.
+    WindowWatchdog,
+    /// The independent watchdog expired.
+    Watchdog,
+    /// Reset by software (e.g. the `reboot` command).
+    Software,
+    /// The board was powered on.
+    PowerOn,
+    /// The supply voltage dropped below the brownout level.
+    Brownout,
+    /// The reset pin was pulled low (e.g. by the reset button or the debugger).
+    Pin,
+    /// No reset flag was set.
+    Unknown,
+}
+
+impl ResetCause {
+    /// Decodes the cause from the reset flags of the RCC CSR register.
+    pub fn from_flags(csr: u32) -> ResetCause {
+        let causes = [
+            (LPWRRSTF, ResetCause::LowPower),
+            (WWDGRSTF, ResetCause::WindowWatchdog),
+            (IWDGRSTF, ResetCause::Watchdog),
+            (SFTRSTF, ResetCause::Software),
+            (PORRSTF, ResetCause::PowerOn),
+            (BORRSTF, ResetCause::Brownout),
+            (PINRSTF, ResetCause::Pin),
+        ];
+
+        causes
+            .iter()
+            .find(|(flag, _)| csr & flag != 0)
+            .map_or(ResetCause::Unknown, |(_, cause)| *cause)
+    }
+
+    /// Returns the human-readable description of the cause.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ResetCause::LowPower => "low-power",
+            ResetCause::WindowWatchdog => "window watchdog",
+            ResetCause::Watchdog => "watchdog",
+            ResetCause::Software => "software",
+            ResetCause::PowerOn => "power-on",
+            ResetCause::Brownout => "brownout",
+            ResetCause::Pin => "pin",
+            ResetCause::Unknown => "unknown",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ResetCause, BORRSTF, IWDGRSTF, LPWRRSTF, PINRSTF, PORRSTF, SFTRSTF, WWDGRSTF};
+
+    #[test]
+    fn reset_cause_single_flag() {
+        assert_eq!(ResetCause::from_flags(LPWRRSTF), ResetCause::LowPower);
+        assert_eq!(ResetCause::from_flags(WWDGRSTF), ResetCause::WindowWatchdog);
+        assert_eq!(ResetCause::from_flags(IWDGRSTF), ResetCause::Watchdog);
+        assert_eq!(ResetCause::from_flags(SFTRSTF), ResetCause::Software);
+        assert_eq!(ResetCause::from_flags(PORRSTF), ResetCause::PowerOn);
+        assert_eq!(ResetCause::from_flags(BORRSTF), ResetCause::Brownout);
+        assert_eq!(ResetCause::from_flags(PINRSTF), ResetCause::Pin);
+        assert_eq!(ResetCause::from_flags(0), ResetCause::Unknown);
+    }
+
+    #[test]
+    fn reset_cause_combined_flags() {
+        // The flags as they are set by the hardware for each kind of reset.
+        assert_eq!(
+            ResetCause::from_flags(PORRSTF | PINRSTF | BORRSTF),
+            ResetCause::PowerOn
+        );
+        assert_eq!(
+            ResetCause::from_flags(SFTRSTF | PINRSTF),
+            ResetCause::Software
+        );
+        assert_eq!(
+            ResetCause::from_flags(IWDGRSTF | PINRSTF),
+            ResetCause::Watchdog
+        );
+        assert_eq!(
+            ResetCause::from_flags(WWDGRSTF | PINRSTF),
+            ResetCause::WindowWatchdog
+        );
+        assert_eq!(
+            ResetCause::from_flags(LPWRRSTF | PINRSTF),
+            ResetCause::LowPower
+        );
+        assert_eq!(
+            ResetCause::from_flags(BORRSTF | PINRSTF),
+            ResetCause::Brownout
+        );
+    }
+
+    #[test]
+    fn reset_cause_other_bits() {
+        // The bits of the register that are not reset flags (RMVF and the LSI bits) are ignored.
+        let other = (1 << 24) | 0b11;
+        assert_eq!(ResetCause::from_flags(other), ResetCause::Unknown);
+        assert_eq!(
+            ResetCause::from_flags(other | SFTRSTF),
+            ResetCause::Software
+        );
+    }
+
+    #[test]
+    fn reset_cause_names() {
+        assert_eq!(ResetCause::PowerOn.as_str(), "power-on");
+        assert_eq!(ResetCause::Software.as_str(), "software");
+        assert_eq!(ResetCause::Watchdog.as_str(), "watchdog");
+        assert_eq!(ResetCause::Pin.as_str(), "pin");
+    }
+}
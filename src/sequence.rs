@@ -0,0 +1,395 @@
+//! Module for sequences of commands (macros) that are replayed with timed waits in between.
+//!
+//! A sequence is given as its steps separated by `;`, where each step is either a command (as
+//! given via the serial interface) or `wait MS` to wait the given number of milliseconds
+//! (1-65535) before the next step, for example: `cycle; wait 2000; flip; wait 2000; off`.
+//! Sequences cannot contain macro commands themselves.
+
+use core::str;
+
+use crate::command::{self, Command, ParseError};
+
+/// The maximum length of a sequence (in bytes, including the separators).
+pub const MAX_SEQUENCE_LEN: usize = 64;
+
+/// A step of a sequence.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Step {
+    /// Run the command.
+    Command(Command),
+    /// Wait the given number of milliseconds.
+    Wait(u16),
+}
+
+impl Step {
+    /// Parses a step from its text.
+    pub fn parse(text: &[u8]) -> Result<Step, ParseError> {
+        let mut words = text
+            .split(|byte| byte.is_ascii_whitespace())
+            .filter(|word| !word.is_empty());
+
+        if words.next() == Some(b"wait") {
+            let ms = words
+                .next()
+                .and_then(|word| str::from_utf8(word).ok())
+                .and_then(|word| word.parse().ok())
+                .filter(|ms| *ms > 0)
+                .ok_or(ParseError::InvalidArgument)?;
+            if words.next().is_some() {
+                return Err(ParseError::TooManyArguments);
+            }
+            return Ok(Step::Wait(ms));
+        }
+
+        match Command::parse(text)? {
+            Command::Macro(_) | Command::MacroRun(_) | Command::MacroStop => {
+                Err(ParseError::InvalidArgument)
+            }
+            command => Ok(Step::Command(command)),
+        }
+    }
+
+    /// Returns the delay (in milliseconds) before the next step can be taken.
+    pub fn delay_ms(&self) -> u16 {
+        match self {
+            Step::Command(_) => 0,
+            Step::Wait(ms) => *ms,
+        }
+    }
+}
+
+/// The error that occurs when a sequence cannot be started.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SequenceError {
+    /// There is no sequence.
+    Empty,
+    /// The sequence is repeated but does not wait, so it would keep the core busy.
+    NoWait,
+}
+
+impl SequenceError {
+    /// Returns a short description of the error.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SequenceError::Empty => "empty",
+            SequenceError::NoWait => "no wait",
+        }
+    }
+}
+
+/// A sequence of steps, kept as its (validated) text.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Sequence {
+    /// The text of the sequence.
+    text: [u8; MAX_SEQUENCE_LEN],
+    /// The length of the text.
+    len: u8,
+}
+
+impl Sequence {
+    /// Returns the empty sequence (without any steps).
+    pub const fn empty() -> Sequence {
+        Sequence {
+            text: [0; MAX_SEQUENCE_LEN],
+            len: 0,
+        }
+    }
+
+    /// Parses a sequence from its text, checking that all steps are valid.
+    ///
+    /// Empty steps are ignored, but the sequence must contain at least one step.
+    pub fn parse(text: &[u8]) -> Result<Sequence, ParseError> {
+        let text = command::trim(text);
+        if text.len() > MAX_SEQUENCE_LEN {
+            return Err(ParseError::InvalidArgument);
+        }
+
+        let mut sequence = Sequence::empty();
+        sequence.text[..text.len()].copy_from_slice(text);
+        sequence.len = text.len() as u8;
+
+        for step in sequence.step_texts() {
+            Step::parse(step)?;
+        }
+        if sequence.is_empty() {
+            return Err(ParseError::InvalidArgument);
+        }
+
+        Ok(sequence)
+    }
+
+    /// Returns the text of the sequence.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.text[..self.len as usize]
+    }
+
+    /// Returns the number of steps.
+    pub fn len(&self) -> usize {
+        self.step_texts().count()
+    }
+
+    /// Returns whether the sequence has no steps.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the step with the given index.
+    pub fn step(&self, index: usize) -> Option<Step> {
+        self.step_texts()
+            .nth(index)
+            .and_then(|step| Step::parse(step).ok())
+    }
+
+    /// Returns the texts of the (non-empty) steps.
+    fn step_texts(&self) -> impl Iterator<Item = &[u8]> {
+        self.as_bytes()
+            .split(|byte| *byte == b';')
+            .filter(|step| !command::trim(step).is_empty())
+    }
+}
+
+impl Default for Sequence {
+    fn default() -> Sequence {
+        Sequence::empty()
+    }
+}
+
+/// The sequencer that steps through a sequence, once or repeatedly.
+///
+/// The steps are taken by a task that keeps scheduling itself while the sequence is running.
+/// The sequencer keeps track of whether that task is still pending, so that starting the
+/// sequence again does not lead to a second task taking steps as well.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Sequencer {
+    /// The sequence.
+    sequence: Sequence,
+    /// The index of the next step.
+    next: usize,
+    /// Whether the sequence is repeated.
+    looping: bool,
+    /// Whether the sequence is running.
+    running: bool,
+    /// Whether the task taking the steps is pending.
+    pending: bool,
+}
+
+impl Sequencer {
+    /// Sets up a sequencer with an empty sequence.
+    pub const fn new() -> Sequencer {
+        Sequencer {
+            sequence: Sequence::empty(),
+            next: 0,
+            looping: false,
+            running: false,
+            pending: false,
+        }
+    }
+
+    /// Returns the sequence.
+    pub fn sequence(&self) -> &Sequence {
+        &self.sequence
+    }
+
+    /// Replaces the sequence, which stops it if it is running.
+    pub fn set_sequence(&mut self, sequence: Sequence) {
+        self.sequence = sequence;
+        self.stop();
+    }
+
+    /// Returns whether the sequence is running.
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Starts the sequence from the first step, once or repeatedly, and returns whether the
+    /// task taking the steps needs to be spawned.
+    ///
+    /// If the task is still pending (e.g. waiting), it continues with the first step once it is
+    /// done waiting.  A repeated sequence needs to wait somewhere to give the rest of the
+    /// application a chance.
+    pub fn start(&mut self, looping: bool) -> Result<bool, SequenceError> {
+        if self.sequence.is_empty() {
+            return Err(SequenceError::Empty);
+        }
+        if looping && !self.sequence_waits() {
+            return Err(SequenceError::NoWait);
+        }
+
+        self.next = 0;
+        self.looping = looping;
+        self.running = true;
+        let spawn = !self.pending;
+        self.pending = true;
+        Ok(spawn)
+    }
+
+    /// Stops the sequence.
+    ///
+    /// A pending task takes no further steps.
+    pub fn stop(&mut self) {
+        self.running = false;
+    }
+
+    /// Advances the sequence and returns the next step.
+    ///
+    /// Returns nothing if the sequence is finished (and not repeated) or stopped, in which case
+    /// the task taking the steps should not be scheduled anymore; otherwise it should be
+    /// scheduled after the delay of the step.
+    pub fn advance(&mut self) -> Option<Step> {
+        if self.running && self.next >= self.sequence.len() {
+            if self.looping {
+                self.next = 0;
+            } else {
+                self.running = false;
+            }
+        }
+        if !self.running {
+            self.pending = false;
+            return None;
+        }
+
+        let step = self.sequence.step(self.next);
+        self.next += 1;
+        step
+    }
+
+    /// Returns whether any step of the sequence waits.
+    fn sequence_waits(&self) -> bool {
+        (0..self.sequence.len())
+            .filter_map(|index| self.sequence.step(index))
+            .any(|step| step.delay_ms() > 0)
+    }
+}
+
+impl Default for Sequencer {
+    fn default() -> Sequencer {
+        Sequencer::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Command, ParseError, Sequence, SequenceError, Sequencer, Step, MAX_SEQUENCE_LEN};
+
+    #[test]
+    fn parse_steps() {
+        assert_eq!(Step::parse(b"flip"), Ok(Step::Command(Command::Flip)));
+        assert_eq!(Step::parse(b" wait 2000 "), Ok(Step::Wait(2000)));
+        assert_eq!(Step::parse(b"wait 65535"), Ok(Step::Wait(u16::MAX)));
+        assert_eq!(Step::parse(b"wait"), Err(ParseError::InvalidArgument));
+        assert_eq!(Step::parse(b"wait 0"), Err(ParseError::InvalidArgument));
+        assert_eq!(Step::parse(b"wait 65536"), Err(ParseError::InvalidArgument));
+        assert_eq!(Step::parse(b"wait 1 2"), Err(ParseError::TooManyArguments));
+        assert_eq!(Step::parse(b"fly"), Err(ParseError::Unknown));
+
+        // Sequences cannot be nested.
+        assert_eq!(Step::parse(b"macro run"), Err(ParseError::InvalidArgument));
+        assert_eq!(Step::parse(b"macro"), Err(ParseError::InvalidArgument));
+    }
+
+    #[test]
+    fn parse_sequence() {
+        let sequence = Sequence::parse(b" cycle; wait 2000; flip; wait 2000; off ").unwrap();
+        assert_eq!(
+            sequence.as_bytes(),
+            b"cycle; wait 2000; flip; wait 2000; off"
+        );
+        assert_eq!(sequence.len(), 5);
+        assert_eq!(sequence.step(0), Some(Step::Command(Command::Cycle)));
+        assert_eq!(sequence.step(1), Some(Step::Wait(2000)));
+        assert_eq!(sequence.step(4), Some(Step::Command(Command::Off)));
+        assert_eq!(sequence.step(5), None);
+
+        // Empty steps are ignored.
+        let sequence = Sequence::parse(b";on;; ;off;").unwrap();
+        assert_eq!(sequence.len(), 2);
+        assert_eq!(sequence.step(1), Some(Step::Command(Command::Off)));
+
+        assert_eq!(Sequence::parse(b""), Err(ParseError::InvalidArgument));
+        assert_eq!(Sequence::parse(b" ; ;"), Err(ParseError::InvalidArgument));
+        assert_eq!(Sequence::parse(b"on; fly"), Err(ParseError::Unknown));
+        assert_eq!(Sequence::empty().len(), 0);
+
+        // The length is bounded.
+        let long = [b'x'; MAX_SEQUENCE_LEN + 1];
+        assert_eq!(Sequence::parse(&long), Err(ParseError::InvalidArgument));
+        let mut max = [b' '; MAX_SEQUENCE_LEN];
+        max[..3].copy_from_slice(b"off");
+        max[MAX_SEQUENCE_LEN - 3..].copy_from_slice(b";on");
+        assert_eq!(Sequence::parse(&max).map(|sequence| sequence.len()), Ok(2));
+    }
+
+    #[test]
+    fn sequencer_once() {
+        let mut sequencer = Sequencer::new();
+        assert_eq!(sequencer.start(false), Err(SequenceError::Empty));
+
+        let sequence = Sequence::parse(b"cycle; wait 2000; flip; wait 2000; off").unwrap();
+        sequencer.set_sequence(sequence);
+        assert_eq!(sequencer.start(false), Ok(true));
+        assert!(sequencer.is_running());
+
+        let steps: Vec<Step> = core::iter::from_fn(|| sequencer.advance()).collect();
+        assert_eq!(
+            steps,
+            vec![
+                Step::Command(Command::Cycle),
+                Step::Wait(2000),
+                Step::Command(Command::Flip),
+                Step::Wait(2000),
+                Step::Command(Command::Off),
+            ]
+        );
+        assert!(!sequencer.is_running());
+
+        // Commands follow each other directly, only the waits take time.
+        let delays: Vec<u16> = steps.iter().map(Step::delay_ms).collect();
+        assert_eq!(delays, vec![0, 2000, 0, 2000, 0]);
+
+        // The task has ended, so it needs to be spawned again.
+        assert_eq!(sequencer.start(false), Ok(true));
+    }
+
+    #[test]
+    fn sequencer_loop() {
+        let mut sequencer = Sequencer::new();
+        sequencer.set_sequence(Sequence::parse(b"on; off").unwrap());
+        assert_eq!(sequencer.start(true), Err(SequenceError::NoWait));
+
+        sequencer.set_sequence(Sequence::parse(b"on; wait 500").unwrap());
+        assert_eq!(sequencer.start(true), Ok(true));
+        for _ in 0..3 {
+            assert_eq!(sequencer.advance(), Some(Step::Command(Command::On)));
+            assert_eq!(sequencer.advance(), Some(Step::Wait(500)));
+        }
+
+        sequencer.stop();
+        assert!(!sequencer.is_running());
+        assert_eq!(sequencer.advance(), None);
+    }
+
+    #[test]
+    fn sequencer_restart() {
+        let mut sequencer = Sequencer::new();
+        sequencer.set_sequence(Sequence::parse(b"on; wait 500; off").unwrap());
+
+        assert_eq!(sequencer.start(false), Ok(true));
+        assert_eq!(sequencer.advance(), Some(Step::Command(Command::On)));
+        assert_eq!(sequencer.advance(), Some(Step::Wait(500)));
+
+        // While the task is waiting, restarting does not need another task; the pending task
+        // starts over.
+        assert_eq!(sequencer.start(false), Ok(false));
+        assert_eq!(sequencer.advance(), Some(Step::Command(Command::On)));
+
+        // The same holds when stopping and starting again before the task ends.
+        sequencer.stop();
+        assert_eq!(sequencer.start(false), Ok(false));
+        assert_eq!(sequencer.advance(), Some(Step::Command(Command::On)));
+
+        // Replacing the sequence stops it.
+        sequencer.set_sequence(Sequence::parse(b"off").unwrap());
+        assert_eq!(sequencer.advance(), None);
+        assert_eq!(sequencer.start(false), Ok(true));
+    }
+}
@@ -0,0 +1,1034 @@
+//! Module with helpers for the serial interface.
+
+use core::fmt::{self, Write};
+use hal::nb;
+use hal::prelude::{
+    _embedded_hal_serial_Read as SerialRead, _embedded_hal_serial_Write as SerialWrite,
+};
+use hal::serial::Error;
+use heapless::{spsc::Producer, ArrayLength, Vec};
+
+/// The byte received for the backspace key.
+const BACKSPACE: u8 = 0x7F;
+
+/// The byte received for Ctrl-C.
+const CTRL_C: u8 = 0x03;
+
+/// The byte received for Ctrl-U.
+const CTRL_U: u8 = 0x15;
+
+/// The flow control byte that resumes output (Ctrl-Q).
+pub const XON: u8 = 0x11;
+
+/// The flow control byte that pauses output (Ctrl-S).
+pub const XOFF: u8 = 0x13;
+
+/// The number of attempts to write a byte (or flush) before giving up.
+///
+/// At 115200 baud a byte takes less than 1500 cycles at 16 MHz, whereas this many attempts
+/// take considerably longer, so a write only times out if the UART never becomes ready.
+pub const WRITE_SPINS: u32 = 10_000;
+
+/// The error that occurs when a write does not complete in time or fails.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TimeoutError<E> {
+    /// The write did not complete within the number of attempts.
+    Timeout,
+    /// The write failed.
+    Other(E),
+}
+
+/// Retries the non-blocking operation until it completes or fails, but at most the given number
+/// of times.
+pub fn block_timeout<T, E, F>(spins: u32, mut operation: F) -> Result<T, TimeoutError<E>>
+where
+    F: FnMut() -> nb::Result<T, E>,
+{
+    for _ in 0..spins {
+        match operation() {
+            Ok(value) => return Ok(value),
+            Err(nb::Error::Other(error)) => return Err(TimeoutError::Other(error)),
+            Err(nb::Error::WouldBlock) => {}
+        }
+    }
+
+    Err(TimeoutError::Timeout)
+}
+
+/// Writes the byte, giving up after [`WRITE_SPINS`] attempts instead of blocking forever.
+pub fn write_timeout<W>(writer: &mut W, byte: u8) -> Result<(), TimeoutError<W::Error>>
+where
+    W: SerialWrite<u8>,
+{
+    block_timeout(WRITE_SPINS, || writer.write(byte))
+}
+
+/// A serial transmitter whose writes give up instead of blocking forever, see
+/// [`write_timeout`].
+///
+/// Formatted output stops at the first byte that times out (or fails), since the rest would
+/// time out as well; formatting itself still succeeds, so that the caller carries on.  The
+/// number of timeouts is counted.
+pub struct TimeoutTx<TX> {
+    /// The underlying serial transmitter.
+    tx: TX,
+    /// The number of writes that timed out (saturating).
+    timeouts: u32,
+}
+
+impl<TX> TimeoutTx<TX>
+where
+    TX: SerialWrite<u8>,
+{
+    /// Wraps the serial transmitter.
+    pub fn new(tx: TX) -> TimeoutTx<TX> {
+        TimeoutTx { tx, timeouts: 0 }
+    }
+
+    /// Writes the byte, giving up after [`WRITE_SPINS`] attempts.
+    pub fn write(&mut self, byte: u8) -> Result<(), TimeoutError<TX::Error>> {
+        let result = write_timeout(&mut self.tx, byte);
+        self.count(&result);
+        result
+    }
+
+    /// Waits until all written bytes have been sent, giving up after [`WRITE_SPINS`] attempts.
+    pub fn flush(&mut self) -> Result<(), TimeoutError<TX::Error>> {
+        let tx = &mut self.tx;
+        let result = block_timeout(WRITE_SPINS, || tx.flush());
+        self.count(&result);
+        result
+    }
+
+    /// Returns the number of writes that timed out.
+    pub fn timeouts(&self) -> u32 {
+        self.timeouts
+    }
+
+    /// Counts the result if it is a timeout.
+    fn count<E>(&mut self, result: &Result<(), TimeoutError<E>>) {
+        if let Err(TimeoutError::Timeout) = result {
+            self.timeouts = self.timeouts.saturating_add(1);
+        }
+    }
+}
+
+impl<TX> Write for TimeoutTx<TX>
+where
+    TX: SerialWrite<u8>,
+{
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            if self.write(byte).is_err() {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The marker (with line terminator) written instead of the rest of a truncated response.
+pub const TRUNCATION_MARKER: &str = "...\r\n";
+
+/// A writer that bounds the length of a response.
+///
+/// At most the limit number of bytes (including line terminators) of the response is written;
+/// if the response is longer, the rest is replaced by [`TRUNCATION_MARKER`], so that a truncated
+/// response is still terminated.  A line terminator is not split by the truncation, since a
+/// terminal would show the marker over the line instead of after it.  A limit of 0 means no
+/// limit.
+pub struct BoundedWriter<'a, W> {
+    /// The underlying writer.
+    writer: &'a mut W,
+    /// The number of bytes that can still be written, or `None` if unlimited.
+    remaining: Option<usize>,
+    /// Whether the response has been truncated.
+    truncated: bool,
+    /// Whether the last byte written is a carriage return (which the line feed of a line
+    /// terminator follows).
+    after_cr: bool,
+}
+
+impl<'a, W> BoundedWriter<'a, W>
+where
+    W: Write,
+{
+    /// Wraps the writer for a response of at most the given number of bytes (0 for no limit).
+    pub fn new(writer: &'a mut W, limit: u16) -> BoundedWriter<'a, W> {
+        BoundedWriter {
+            writer,
+            remaining: Some(limit as usize).filter(|limit| *limit > 0),
+            truncated: false,
+            after_cr: false,
+        }
+    }
+
+    /// Returns whether the response has been truncated.
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Returns the underlying writer, e.g. to write something that does not belong to the
+    /// response.
+    pub fn inner(&mut self) -> &mut W {
+        self.writer
+    }
+}
+
+impl<W> Write for BoundedWriter<'_, W>
+where
+    W: Write,
+{
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.truncated {
+            return Ok(());
+        }
+
+        let remaining = match self.remaining {
+            Some(remaining) => remaining,
+            None => return self.writer.write_str(s),
+        };
+        if s.len() <= remaining {
+            self.remaining = Some(remaining - s.len());
+            if !s.is_empty() {
+                self.after_cr = s.ends_with('\r');
+            }
+            return self.writer.write_str(s);
+        }
+
+        // Only write the part that fits, cut at a character boundary and before a carriage
+        // return without its line feed.  If the carriage return has been written already, the
+        // line terminator is completed instead.
+        let mut end = remaining;
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        let part = s[..end].trim_end_matches('\r');
+        self.truncated = true;
+        self.remaining = Some(0);
+        self.writer.write_str(part)?;
+        if part.is_empty() && self.after_cr {
+            self.writer.write_str("\n")?;
+        }
+        self.writer.write_str(TRUNCATION_MARKER)
+    }
+}
+
+/// Returns a short name describing the kind of serial error.
+pub fn error_kind(error: Error) -> &'static str {
+    match error {
+        Error::Framing => "framing",
+        Error::Noise => "noise",
+        Error::Overrun => "overrun",
+        Error::Parity => "parity",
+        _ => "unknown",
+    }
+}
+
+/// A byte received via the serial interface, or the kind of error (see [`error_kind`]) that
+/// occurred instead.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Received {
+    /// A received byte.
+    Byte(u8),
+    /// A receive error, or `"rx full"` if bytes were lost because the buffer was full.
+    Error(&'static str),
+}
+
+/// The buffer of the bytes received via the serial interface, which is filled as soon as a byte
+/// arrives and emptied when the bytes are handled.
+///
+/// This way, no bytes are lost while the handling is held up, for example by a long response
+/// being written.  If the buffer is full nevertheless, the bytes are dropped and an error is
+/// buffered as soon as there is room again.
+pub struct RxBuffer<'a, N>
+where
+    N: ArrayLength<Received>,
+{
+    /// The producing end of the queue of received bytes.
+    queue: Producer<'a, Received, N>,
+    /// Whether bytes were dropped since the last buffered byte.
+    overflow: bool,
+}
+
+impl<'a, N> RxBuffer<'a, N>
+where
+    N: ArrayLength<Received>,
+{
+    /// Sets up the buffer filling the given queue.
+    pub fn new(queue: Producer<'a, Received, N>) -> RxBuffer<'a, N> {
+        RxBuffer {
+            queue,
+            overflow: false,
+        }
+    }
+
+    /// Reads the received byte (or error) from the serial receiver into the buffer, if any.
+    pub fn receive<RX>(&mut self, rx: &mut RX)
+    where
+        RX: SerialRead<u8, Error = Error>,
+    {
+        match rx.read() {
+            Ok(byte) => self.push(Received::Byte(byte)),
+            Err(nb::Error::Other(error)) => self.push(Received::Error(error_kind(error))),
+            Err(nb::Error::WouldBlock) => {}
+        }
+    }
+
+    /// Stores the received byte (or error) in the buffer, or drops it if the buffer is full.
+    pub fn push(&mut self, received: Received) {
+        if self.overflow {
+            // Report the dropped bytes before anything that is received later.
+            if self.queue.enqueue(Received::Error("rx full")).is_err() {
+                return;
+            }
+            self.overflow = false;
+        }
+
+        if self.queue.enqueue(received).is_err() {
+            self.overflow = true;
+        }
+    }
+}
+
+/// A key received via the serial interface.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Key {
+    /// A plain byte (character).
+    Byte(u8),
+    /// The up arrow key.
+    Up,
+    /// The down arrow key.
+    Down,
+}
+
+/// The state of the key decoder.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum DecoderState {
+    /// No escape sequence is being received.
+    Ground,
+    /// The escape byte has been received.
+    Escape,
+    /// The control sequence introducer (`ESC [` or `ESC O`) has been received.
+    Sequence,
+}
+
+/// Decoder for the escape sequences that terminals send for special keys.
+///
+/// Only the up and down arrow keys are recognized, the bytes of all other escape sequences are
+/// dropped.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct KeyDecoder {
+    state: DecoderState,
+}
+
+impl KeyDecoder {
+    /// Sets up a decoder that is not receiving an escape sequence.
+    pub const fn new() -> KeyDecoder {
+        KeyDecoder {
+            state: DecoderState::Ground,
+        }
+    }
+
+    /// Feeds a received byte to the decoder.
+    ///
+    /// Returns the key if it is complete, or `None` if the byte is part of an escape sequence.
+    pub fn feed(&mut self, byte: u8) -> Option<Key> {
+        match self.state {
+            DecoderState::Ground if byte == 0x1B => {
+                self.state = DecoderState::Escape;
+                None
+            }
+            DecoderState::Ground => Some(Key::Byte(byte)),
+            DecoderState::Escape if byte == b'[' || byte == b'O' => {
+                self.state = DecoderState::Sequence;
+                None
+            }
+            DecoderState::Escape => {
+                self.state = DecoderState::Ground;
+                None
+            }
+            // Parameter and intermediate bytes precede the final byte of the sequence.
+            DecoderState::Sequence if (0x20..0x40).contains(&byte) => None,
+            DecoderState::Sequence => {
+                self.state = DecoderState::Ground;
+                match byte {
+                    b'A' => Some(Key::Up),
+                    b'B' => Some(Key::Down),
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+impl Default for KeyDecoder {
+    fn default() -> KeyDecoder {
+        KeyDecoder::new()
+    }
+}
+
+/// The result of editing the line in the buffer with a received byte.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LineEdit {
+    /// The line is complete and should be handled as a command (the buffer is left alone).
+    Submit,
+    /// The byte is appended to the line.
+    Append(u8),
+    /// The buffer is full, so the byte is dropped.
+    Full,
+    /// The last byte is removed from the line (backspace).
+    Backspace,
+    /// The line is discarded without handling it (Ctrl-C).
+    Cancel,
+    /// The line is cleared to start over (Ctrl-U).
+    Clear,
+}
+
+/// Edits the line in the buffer with a received byte.
+///
+/// Carriage return completes the line.  Backspace removes the last byte, Ctrl-C discards the
+/// line and Ctrl-U clears it; all other bytes are appended.
+pub fn edit_line<N>(buffer: &mut Vec<u8, N>, byte: u8) -> LineEdit
+where
+    N: ArrayLength<u8>,
+{
+    match byte {
+        b'\r' => LineEdit::Submit,
+        BACKSPACE => {
+            buffer.pop();
+            LineEdit::Backspace
+        }
+        CTRL_C => {
+            clear_line(buffer);
+            LineEdit::Cancel
+        }
+        CTRL_U => {
+            clear_line(buffer);
+            LineEdit::Clear
+        }
+        _ => match buffer.push(byte) {
+            Ok(()) => LineEdit::Append(byte),
+            Err(_) => LineEdit::Full,
+        },
+    }
+}
+
+/// Clears the line in the buffer.
+///
+/// The bytes are popped one by one, since `Vec::clear` of this version of heapless trips the
+/// debug assertions of the standard library when the buffer is not empty.
+pub fn clear_line<N>(buffer: &mut Vec<u8, N>)
+where
+    N: ArrayLength<u8>,
+{
+    while buffer.pop().is_some() {}
+}
+
+/// The framing of the commands received via the serial interface.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Framing {
+    /// Each command line is terminated by a carriage return (and can be edited).
+    Line,
+    /// Each command line is a packet: a length byte followed by that many bytes.
+    Packet,
+}
+
+impl Framing {
+    /// Returns the framing with the given name.
+    pub fn from_name(name: &[u8]) -> Option<Framing> {
+        match name {
+            b"line" => Some(Framing::Line),
+            b"packet" => Some(Framing::Packet),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the framing (as used by the serial interface).
+    pub fn as_name(&self) -> &'static str {
+        match self {
+            Framing::Line => "line",
+            Framing::Packet => "packet",
+        }
+    }
+}
+
+/// The result of reading a packet with a received byte.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PacketRead {
+    /// The packet is not complete yet.
+    Pending,
+    /// The packet is complete and its payload is in the buffer (which may be empty).
+    Complete,
+    /// The payload of the packet did not fit in the buffer and has been skipped completely.
+    Oversized,
+}
+
+/// The state of the packet reader.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum PacketState {
+    /// Waiting for the length byte of the next packet.
+    Length,
+    /// Receiving the given number of remaining payload bytes into the buffer.
+    Payload(u8),
+    /// Skipping the given number of remaining payload bytes, which do not fit in the buffer.
+    Skip(u8),
+}
+
+/// Reader for length-prefixed packets, i.e. a length byte followed by that many payload bytes.
+///
+/// Since the length is known up front, no byte has a special meaning and no terminator is
+/// needed.  A packet that does not fit in the buffer is skipped as a whole, so that the reader
+/// stays in step with the packets that follow.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PacketReader {
+    state: PacketState,
+}
+
+impl PacketReader {
+    /// Sets up a reader that is waiting for the length byte of a packet.
+    pub const fn new() -> PacketReader {
+        PacketReader {
+            state: PacketState::Length,
+        }
+    }
+
+    /// Feeds a received byte to the reader, which collects the payload in the buffer.
+    ///
+    /// The buffer is cleared when a new packet starts; once the packet is complete, it is left
+    /// alone until then.
+    pub fn feed<N>(&mut self, buffer: &mut Vec<u8, N>, byte: u8) -> PacketRead
+    where
+        N: ArrayLength<u8>,
+    {
+        let (state, read) = match self.state {
+            PacketState::Length => {
+                clear_line(buffer);
+                match byte {
+                    0 => (PacketState::Length, PacketRead::Complete),
+                    length if length as usize > buffer.capacity() => {
+                        (PacketState::Skip(length), PacketRead::Pending)
+                    }
+                    length => (PacketState::Payload(length), PacketRead::Pending),
+                }
+            }
+            PacketState::Payload(remaining) => {
+                // The length has been checked against the capacity, so the byte fits.
+                buffer.push(byte).ok();
+                match remaining - 1 {
+                    0 => (PacketState::Length, PacketRead::Complete),
+                    remaining => (PacketState::Payload(remaining), PacketRead::Pending),
+                }
+            }
+            PacketState::Skip(remaining) => match remaining - 1 {
+                0 => (PacketState::Length, PacketRead::Oversized),
+                remaining => (PacketState::Skip(remaining), PacketRead::Pending),
+            },
+        };
+        self.state = state;
+
+        read
+    }
+
+    /// Returns whether a packet is partially received.
+    pub fn is_partial(&self) -> bool {
+        self.state != PacketState::Length
+    }
+}
+
+impl Default for PacketReader {
+    fn default() -> PacketReader {
+        PacketReader::new()
+    }
+}
+
+/// Writes the bytes as space-separated hexadecimal numbers.
+pub fn write_hex<W: Write>(writer: &mut W, bytes: &[u8]) -> fmt::Result {
+    for (i, byte) in bytes.iter().enumerate() {
+        if i > 0 {
+            writer.write_char(' ')?;
+        }
+        write!(writer, "{:02x}", byte)?;
+    }
+
+    Ok(())
+}
+
+/// Writes the reply to a ping with the given sequence number.
+pub fn write_pong<W: Write>(writer: &mut W, sequence: u32) -> fmt::Result {
+    writeln!(writer, "pong {}\r", sequence)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        block_timeout, clear_line, edit_line, error_kind, nb, write_hex, write_pong, write_timeout,
+        BoundedWriter, Error, Framing, Key, KeyDecoder, LineEdit, PacketRead, PacketReader,
+        Received, RxBuffer, SerialRead, SerialWrite, TimeoutError, TimeoutTx, Vec, Write,
+        WRITE_SPINS,
+    };
+    use heapless::consts::{U16, U4};
+    use heapless::spsc::Queue;
+
+    #[test]
+    fn error_kinds() {
+        assert_eq!(error_kind(Error::Framing), "framing");
+        assert_eq!(error_kind(Error::Noise), "noise");
+        assert_eq!(error_kind(Error::Overrun), "overrun");
+        assert_eq!(error_kind(Error::Parity), "parity");
+    }
+
+    #[test]
+    fn hex() {
+        let mut output = String::new();
+        write_hex(&mut output, b"").unwrap();
+        assert_eq!(output, "");
+
+        let mut output = String::new();
+        write_hex(&mut output, b"a").unwrap();
+        assert_eq!(output, "61");
+
+        let mut output = String::new();
+        write_hex(&mut output, b"on\x1b[A\x00\xff").unwrap();
+        assert_eq!(output, "6f 6e 1b 5b 41 00 ff");
+    }
+
+    /// Mock serial transmitter that is busy for a number of attempts per byte (or forever).
+    #[derive(Debug, Default)]
+    struct MockTx {
+        written: Vec<u8, U4>,
+        busy: u32,
+        attempts: u32,
+        stuck: bool,
+    }
+
+    impl SerialWrite<u8> for MockTx {
+        type Error = ();
+
+        fn write(&mut self, byte: u8) -> nb::Result<(), ()> {
+            self.attempts += 1;
+            if self.stuck || self.attempts <= self.busy {
+                return Err(nb::Error::WouldBlock);
+            }
+            self.attempts = 0;
+            self.written.push(byte).map_err(|_| nb::Error::Other(()))
+        }
+
+        fn flush(&mut self) -> nb::Result<(), ()> {
+            if self.stuck {
+                Err(nb::Error::WouldBlock)
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    #[test]
+    fn timeout_counting() {
+        // The operation is attempted exactly the given number of times.
+        let mut attempts = 0;
+        let result = block_timeout(5, || -> nb::Result<(), ()> {
+            attempts += 1;
+            Err(nb::Error::WouldBlock)
+        });
+        assert_eq!(result, Err(TimeoutError::Timeout));
+        assert_eq!(attempts, 5);
+
+        // It completes as soon as the operation does, or fails when the operation fails.
+        let mut attempts = 0;
+        let result = block_timeout(5, || -> nb::Result<u32, ()> {
+            attempts += 1;
+            if attempts < 5 {
+                Err(nb::Error::WouldBlock)
+            } else {
+                Ok(attempts)
+            }
+        });
+        assert_eq!(result, Ok(5));
+        let result = block_timeout(5, || -> nb::Result<(), u8> { Err(nb::Error::Other(7)) });
+        assert_eq!(result, Err(TimeoutError::Other(7)));
+        assert_eq!(
+            block_timeout(0, || -> nb::Result<(), ()> { Ok(()) }),
+            Err(TimeoutError::Timeout)
+        );
+    }
+
+    #[test]
+    fn write_timeouts() {
+        let mut tx = MockTx {
+            busy: WRITE_SPINS - 1,
+            ..MockTx::default()
+        };
+        assert_eq!(write_timeout(&mut tx, b'o'), Ok(()));
+        assert_eq!(&tx.written[..], b"o");
+
+        tx.busy = WRITE_SPINS;
+        assert_eq!(write_timeout(&mut tx, b'n'), Err(TimeoutError::Timeout));
+        assert_eq!(tx.attempts, WRITE_SPINS);
+        assert_eq!(&tx.written[..], b"o");
+    }
+
+    #[test]
+    fn timeout_tx() {
+        let mut tx = TimeoutTx::new(MockTx {
+            busy: 3,
+            ..MockTx::default()
+        });
+        write!(tx, "on").unwrap();
+        assert_eq!(tx.flush(), Ok(()));
+        assert_eq!(&tx.tx.written[..], b"on");
+        assert_eq!(tx.timeouts(), 0);
+
+        // Formatting gives up at the first timeout, but does not fail.
+        tx.tx.stuck = true;
+        write!(tx, "off").unwrap();
+        assert_eq!(tx.timeouts(), 1);
+        assert_eq!(tx.write(b'!'), Err(TimeoutError::Timeout));
+        assert_eq!(tx.flush(), Err(TimeoutError::Timeout));
+        assert_eq!(tx.timeouts(), 3);
+        assert_eq!(&tx.tx.written[..], b"on");
+
+        // A failing write is not counted as a timeout.
+        tx.tx.stuck = false;
+        tx.tx.attempts = 0;
+        write!(tx, "abc").unwrap();
+        assert_eq!(tx.write(b'd'), Err(TimeoutError::Other(())));
+        assert_eq!(tx.timeouts(), 3);
+        assert_eq!(&tx.tx.written[..], b"onab");
+    }
+
+    #[test]
+    fn pong() {
+        let mut output = String::new();
+        write_pong(&mut output, 0).unwrap();
+        assert_eq!(output, "pong 0\r\n");
+
+        let mut output = String::new();
+        write_pong(&mut output, u32::MAX).unwrap();
+        assert_eq!(output, "pong 4294967295\r\n");
+    }
+
+    #[test]
+    fn edit_lines() {
+        let mut buffer = Vec::<u8, U4>::new();
+
+        assert_eq!(edit_line(&mut buffer, b'o'), LineEdit::Append(b'o'));
+        assert_eq!(edit_line(&mut buffer, b'x'), LineEdit::Append(b'x'));
+        assert_eq!(edit_line(&mut buffer, 0x7F), LineEdit::Backspace);
+        assert_eq!(edit_line(&mut buffer, b'n'), LineEdit::Append(b'n'));
+        assert_eq!(&buffer[..], b"on");
+        assert_eq!(edit_line(&mut buffer, b'\r'), LineEdit::Submit);
+        assert_eq!(&buffer[..], b"on");
+
+        // Backspace on an empty line does nothing.
+        clear_line(&mut buffer);
+        assert_eq!(edit_line(&mut buffer, 0x7F), LineEdit::Backspace);
+        assert!(buffer.is_empty());
+
+        // Bytes that do not fit are dropped.
+        for byte in b"ramp".iter() {
+            edit_line(&mut buffer, *byte);
+        }
+        assert_eq!(edit_line(&mut buffer, b'x'), LineEdit::Full);
+        assert_eq!(&buffer[..], b"ramp");
+    }
+
+    #[test]
+    fn edit_lines_control_bytes() {
+        let mut buffer = Vec::<u8, U4>::new();
+
+        // Ctrl-C discards the line.
+        buffer.extend_from_slice(b"ofx").unwrap();
+        assert_eq!(edit_line(&mut buffer, 0x03), LineEdit::Cancel);
+        assert!(buffer.is_empty());
+
+        // Ctrl-U clears the line, after which editing continues.
+        buffer.extend_from_slice(b"ofx").unwrap();
+        assert_eq!(edit_line(&mut buffer, 0x15), LineEdit::Clear);
+        assert!(buffer.is_empty());
+        assert_eq!(edit_line(&mut buffer, b'o'), LineEdit::Append(b'o'));
+        assert_eq!(&buffer[..], b"o");
+
+        // Control bytes on an empty line are harmless.
+        clear_line(&mut buffer);
+        assert_eq!(edit_line(&mut buffer, 0x03), LineEdit::Cancel);
+        assert_eq!(edit_line(&mut buffer, 0x15), LineEdit::Clear);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn decode_keys() {
+        let mut decoder = KeyDecoder::new();
+
+        assert_eq!(decoder.feed(b'a'), Some(Key::Byte(b'a')));
+        assert_eq!(decoder.feed(b'\r'), Some(Key::Byte(b'\r')));
+
+        for (bytes, key) in [
+            (b"\x1b[A", Key::Up),
+            (b"\x1b[B", Key::Down),
+            (b"\x1bOA", Key::Up),
+        ]
+        .iter()
+        {
+            assert_eq!(decoder.feed(bytes[0]), None);
+            assert_eq!(decoder.feed(bytes[1]), None);
+            assert_eq!(decoder.feed(bytes[2]), Some(*key));
+        }
+    }
+
+    #[test]
+    fn decode_keys_unknown_sequence() {
+        let mut decoder = KeyDecoder::new();
+
+        // The bytes of other escape sequences (e.g. the right arrow key and page down) are
+        // dropped.
+        for byte in b"\x1b[C\x1b[6~\x1bx".iter() {
+            assert_eq!(decoder.feed(*byte), None);
+        }
+        assert_eq!(decoder.feed(b'A'), Some(Key::Byte(b'A')));
+    }
+
+    #[test]
+    fn framing_names() {
+        for framing in [Framing::Line, Framing::Packet].iter() {
+            assert_eq!(
+                Framing::from_name(framing.as_name().as_bytes()),
+                Some(*framing)
+            );
+        }
+        assert_eq!(Framing::from_name(b"slip"), None);
+    }
+
+    #[test]
+    fn read_packets() {
+        let mut reader = PacketReader::new();
+        let mut buffer = Vec::<u8, U4>::new();
+
+        // The length byte is followed by the payload.
+        assert_eq!(reader.feed(&mut buffer, 2), PacketRead::Pending);
+        assert_eq!(reader.feed(&mut buffer, b'o'), PacketRead::Pending);
+        assert!(reader.is_partial());
+        assert_eq!(reader.feed(&mut buffer, b'n'), PacketRead::Complete);
+        assert!(!reader.is_partial());
+        assert_eq!(&buffer[..], b"on");
+
+        // No byte is special in the payload, and a payload that fills the buffer fits.
+        for byte in [4, b'\r', 0x03, 0x00].iter() {
+            assert_eq!(reader.feed(&mut buffer, *byte), PacketRead::Pending);
+        }
+        assert_eq!(reader.feed(&mut buffer, 0x7F), PacketRead::Complete);
+        assert_eq!(&buffer[..], b"\r\x03\x00\x7f");
+
+        // An empty packet is complete right away.
+        assert_eq!(reader.feed(&mut buffer, 0), PacketRead::Complete);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn read_partial_packets() {
+        let mut reader = PacketReader::new();
+        let mut buffer = Vec::<u8, U4>::new();
+
+        // The packet is completed by later bytes, however they are split up.
+        assert_eq!(reader.feed(&mut buffer, 3), PacketRead::Pending);
+        assert_eq!(reader.feed(&mut buffer, b'o'), PacketRead::Pending);
+        assert_eq!(&buffer[..], b"o");
+        assert_eq!(reader.feed(&mut buffer, b'f'), PacketRead::Pending);
+        assert_eq!(reader.feed(&mut buffer, b'f'), PacketRead::Complete);
+        assert_eq!(&buffer[..], b"off");
+
+        // The next packet starts with a clear buffer.
+        assert_eq!(reader.feed(&mut buffer, 1), PacketRead::Pending);
+        assert!(buffer.is_empty());
+        assert_eq!(reader.feed(&mut buffer, b'x'), PacketRead::Complete);
+        assert_eq!(&buffer[..], b"x");
+    }
+
+    #[test]
+    fn read_oversized_packets() {
+        let mut reader = PacketReader::new();
+        let mut buffer = Vec::<u8, U4>::new();
+
+        // The payload of a packet that does not fit is skipped as a whole.
+        assert_eq!(reader.feed(&mut buffer, 5), PacketRead::Pending);
+        for byte in b"ramp".iter() {
+            assert_eq!(reader.feed(&mut buffer, *byte), PacketRead::Pending);
+            assert!(buffer.is_empty());
+        }
+        assert_eq!(reader.feed(&mut buffer, b's'), PacketRead::Oversized);
+        assert!(buffer.is_empty());
+        assert!(!reader.is_partial());
+
+        // The reader is in step with the next packet.
+        assert_eq!(reader.feed(&mut buffer, 2), PacketRead::Pending);
+        assert_eq!(reader.feed(&mut buffer, b'o'), PacketRead::Pending);
+        assert_eq!(reader.feed(&mut buffer, b'n'), PacketRead::Complete);
+        assert_eq!(&buffer[..], b"on");
+
+        // Even the longest packet can be skipped.
+        assert_eq!(reader.feed(&mut buffer, 255), PacketRead::Pending);
+        for _ in 0..254 {
+            assert_eq!(reader.feed(&mut buffer, b'x'), PacketRead::Pending);
+        }
+        assert_eq!(reader.feed(&mut buffer, b'x'), PacketRead::Oversized);
+    }
+
+    #[test]
+    fn bounded_writer() {
+        // Without a limit, everything is written.
+        let mut output = String::new();
+        let mut writer = BoundedWriter::new(&mut output, 0);
+        write!(writer, "{}\r\n", "x".repeat(1000)).unwrap();
+        assert!(!writer.is_truncated());
+        assert_eq!(output.len(), 1002);
+
+        // A response of exactly the limit is not truncated, also when written in parts.
+        let mut output = String::new();
+        let mut writer = BoundedWriter::new(&mut output, 9);
+        write!(writer, "fifo").unwrap();
+        write!(writer, "=on\r\n").unwrap();
+        assert!(!writer.is_truncated());
+        // Writing nothing more is fine too.
+        write!(writer, "").unwrap();
+        assert!(!writer.is_truncated());
+        assert_eq!(output, "fifo=on\r\n");
+    }
+
+    #[test]
+    fn bounded_writer_truncation() {
+        // One byte more than the limit truncates the response, without splitting the line
+        // terminator.
+        let mut output = String::new();
+        let mut writer = BoundedWriter::new(&mut output, 9);
+        write!(writer, "fifo=off\r\n").unwrap();
+        assert!(writer.is_truncated());
+        assert_eq!(output, "fifo=off...\r\n");
+
+        // A line terminator split over two parts is completed instead.
+        let mut output = String::new();
+        let mut writer = BoundedWriter::new(&mut output, 9);
+        write!(writer, "fifo=off\r").unwrap();
+        assert!(!writer.is_truncated());
+        write!(writer, "\nnext\r\n").unwrap();
+        assert!(writer.is_truncated());
+        assert_eq!(output, "fifo=off\r\n...\r\n");
+
+        // The limit is reached exactly by one part, so the next one is dropped completely.
+        let mut output = String::new();
+        let mut writer = BoundedWriter::new(&mut output, 4);
+        write!(writer, "fifo").unwrap();
+        assert!(!writer.is_truncated());
+        write!(writer, "=on").unwrap();
+        write!(writer, "\r\n").unwrap();
+        assert!(writer.is_truncated());
+        assert_eq!(output, "fifo...\r\n");
+
+        // Multi-byte characters are not split.
+        let mut output = String::new();
+        let mut writer = BoundedWriter::new(&mut output, 2);
+        write!(writer, "a\u{b0}b").unwrap();
+        assert_eq!(output, "a...\r\n");
+
+        // Writing to the underlying writer bypasses the limit.
+        let mut writer = BoundedWriter::new(&mut output, 1);
+        write!(writer.inner(), "> ").unwrap();
+        assert_eq!(output, "a...\r\n> ");
+    }
+
+    /// Mock serial receiver that returns the given bytes one by one, or a noise error for
+    /// `None`.
+    struct MockRx<'a> {
+        received: core::slice::Iter<'a, Option<u8>>,
+    }
+
+    impl SerialRead<u8> for MockRx<'_> {
+        type Error = Error;
+
+        fn read(&mut self) -> nb::Result<u8, Error> {
+            match self.received.next() {
+                Some(Some(byte)) => Ok(*byte),
+                Some(None) => Err(nb::Error::Other(Error::Noise)),
+                None => Err(nb::Error::WouldBlock),
+            }
+        }
+    }
+
+    #[test]
+    fn rx_buffer_receive() {
+        let mut queue = Queue::<Received, U4>::new();
+        let (producer, mut consumer) = queue.split();
+        let mut rx_buffer = RxBuffer::new(producer);
+
+        let received = [Some(b'o'), None];
+        let mut rx = MockRx {
+            received: received.iter(),
+        };
+        // Nothing is buffered once there is nothing to read.
+        for _ in 0..3 {
+            rx_buffer.receive(&mut rx);
+        }
+        assert_eq!(consumer.dequeue(), Some(Received::Byte(b'o')));
+        assert_eq!(consumer.dequeue(), Some(Received::Error("noise")));
+        assert_eq!(consumer.dequeue(), None);
+    }
+
+    #[test]
+    fn rx_buffer_interleaved() {
+        let mut queue = Queue::<Received, U16>::new();
+        let (producer, mut consumer) = queue.split();
+        let mut rx_buffer = RxBuffer::new(producer);
+        let mut buffer = Vec::<u8, U16>::new();
+        let mut lines = Vec::<Vec<u8, U16>, U4>::new();
+
+        // Handles the buffered bytes like the serial handler, collecting the complete lines.
+        let mut handle = |consumer: &mut heapless::spsc::Consumer<Received, U16>| {
+            while let Some(Received::Byte(byte)) = consumer.dequeue() {
+                if edit_line(&mut buffer, byte) == LineEdit::Submit {
+                    lines.push(buffer.clone()).unwrap();
+                    clear_line(&mut buffer);
+                }
+            }
+        };
+
+        // The first command is partially handled when the rest arrives, followed by the next
+        // commands while the response to the first is being written.
+        for byte in b"fl".iter() {
+            rx_buffer.push(Received::Byte(*byte));
+        }
+        handle(&mut consumer);
+        for byte in b"ip\rcycle\ron\r".iter() {
+            rx_buffer.push(Received::Byte(*byte));
+        }
+        handle(&mut consumer);
+
+        assert_eq!(lines.len(), 3);
+        assert_eq!(&lines[0][..], b"flip");
+        assert_eq!(&lines[1][..], b"cycle");
+        assert_eq!(&lines[2][..], b"on");
+    }
+
+    #[test]
+    fn rx_buffer_overflow() {
+        let mut queue = Queue::<Received, U4>::new();
+        let (producer, mut consumer) = queue.split();
+        let mut rx_buffer = RxBuffer::new(producer);
+
+        for byte in b"abcdef".iter() {
+            rx_buffer.push(Received::Byte(*byte));
+        }
+        // Once there is room, the dropped bytes are reported first; without room for the next
+        // byte as well, that byte is dropped too.
+        assert_eq!(consumer.dequeue(), Some(Received::Byte(b'a')));
+        rx_buffer.push(Received::Byte(b'g'));
+        for byte in b"bc".iter() {
+            assert_eq!(consumer.dequeue(), Some(Received::Byte(*byte)));
+        }
+        rx_buffer.push(Received::Byte(b'h'));
+        assert_eq!(consumer.dequeue(), Some(Received::Byte(b'd')));
+        assert_eq!(consumer.dequeue(), Some(Received::Error("rx full")));
+        assert_eq!(consumer.dequeue(), Some(Received::Error("rx full")));
+        assert_eq!(consumer.dequeue(), Some(Received::Byte(b'h')));
+        assert_eq!(consumer.dequeue(), None);
+    }
+}
@@ -0,0 +1,747 @@
+//! Module for the settings of the demo application.
+
+use core::fmt::{self, Write};
+
+use crate::accel::AxisFlip;
+use crate::button::ButtonAction;
+use crate::command::{self, Command, CommandPrefix, ParseError};
+use crate::led_ring::{Direction, Mode};
+use crate::rate_limit::DEFAULT_INTERVAL_MS;
+use crate::serial::{Framing, XOFF, XON};
+
+/// The default period (in milliseconds) between accelerometer samples in accelerometer mode.
+pub const DEFAULT_ACCEL_PERIOD_MS: u16 = 500;
+
+/// The shortest period (in milliseconds) between accelerometer samples.
+pub const MIN_ACCEL_PERIOD_MS: u16 = 10;
+
+/// The longest period (in milliseconds) between accelerometer samples.
+pub const MAX_ACCEL_PERIOD_MS: u16 = 10_000;
+
+/// The maximum length of the `key=value` pairs of settings that are applied at once (in bytes,
+/// including the separators).
+pub const MAX_PAIRS_LEN: usize = 64;
+
+/// The kind of output sent via the serial interface.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OutputKind {
+    /// A response to a command (including errors and reports of queries).
+    Response,
+    /// Unsolicited output, i.e. the startup lines and events like `button` and `level`.
+    Event,
+}
+
+/// The settings that can be changed via the serial interface.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Settings {
+    /// Whether accelerometer mode shows the tilt by the brightness of the LEDs instead of just
+    /// turning them on or off.
+    pub accel_analog: bool,
+    /// Whether accelerometer mode shows the downhill direction instead of the sides that are
+    /// pointing down.
+    pub accel_downhill: bool,
+    /// Whether accelerometer mode shows how far the board is tilted by the number of LEDs that
+    /// are on (like a meter) instead of the sides that are pointing down.
+    pub accel_meter: bool,
+    /// The period (in milliseconds) between accelerometer samples in accelerometer mode,
+    /// independent of the period of the other animations.
+    pub accel_period_ms: u16,
+    /// Which axes of the accelerometer are flipped before determining the directions.
+    pub accel_flip: AxisFlip,
+    /// Whether the X and Y axes of the accelerometer are swapped (before flipping them).
+    pub accel_swap: bool,
+    /// Whether the FIFO of the accelerometer is used to average several samples per reading.
+    pub accel_fifo: bool,
+    /// Whether the accelerometer is polled continuously (in the idle loop) instead of read
+    /// periodically (by a timer-driven task).
+    pub accel_polling: bool,
+    /// The offset of the X and Y acceleration (as determined by calibration) that is
+    /// subtracted from each reading.
+    pub accel_offset: (i8, i8),
+    /// The action performed when the user button is pressed.
+    pub button_action: ButtonAction,
+    /// Whether the cycle direction is reversed while the user button is held, instead of each
+    /// press of the button reversing it.
+    pub button_hold: bool,
+    /// Whether to include a hex dump of the received bytes when a command is not recognized.
+    pub debug_echo: bool,
+    /// Whether software flow control (XON/XOFF) is used for unsolicited output.
+    pub flow_control: bool,
+    /// Whether unsolicited output is paused by the host (using XOFF) until it is resumed.
+    pub flow_paused: bool,
+    /// The framing of the commands received via the serial interface.
+    pub framing: Framing,
+    /// The time (in milliseconds) without activity of the host after which the board falls back
+    /// from host-controlled LEDs to a safe display, or 0 if disabled.
+    pub heartbeat_ms: u16,
+    /// The acceleration (in mg) that counts as motion and wakes the board up while sleeping, or
+    /// 0 if disabled.
+    pub motion_wake_mg: u16,
+    /// The prefix that lines received via the serial interface have to start with (empty to
+    /// accept all lines).
+    pub prefix: CommandPrefix,
+    /// Whether the serial interface is used interactively, i.e. received bytes are echoed and
+    /// a prompt is shown.
+    pub interactive: bool,
+    /// Whether the animations check that the tracked LED states are consistent and report an
+    /// anomaly otherwise.
+    pub led_check: bool,
+    /// Whether unsolicited output is suppressed, so that only responses to commands are sent.
+    pub quiet: bool,
+    /// The minimum interval (in milliseconds) between asynchronous messages of the same class;
+    /// messages that follow too soon are dropped.
+    pub rate_limit_ms: u16,
+    /// The maximum length (in bytes) of a response to a command, after which it is truncated
+    /// (0 for no limit).
+    pub response_limit: u16,
+    /// Whether the tilt of the board controls the speed of cycle mode.
+    pub tilt_speed: bool,
+    /// Whether unsolicited output is prefixed with a timestamp (the count of the cycle counter).
+    pub timestamps: bool,
+    /// Whether a short animation is shown when switching to another mode.
+    pub transitions: bool,
+}
+
+impl Settings {
+    /// Returns the default settings.
+    pub const fn new() -> Settings {
+        Settings {
+            accel_analog: false,
+            accel_downhill: false,
+            accel_meter: false,
+            accel_period_ms: DEFAULT_ACCEL_PERIOD_MS,
+            accel_flip: AxisFlip::NONE,
+            accel_swap: false,
+            accel_fifo: false,
+            accel_polling: false,
+            accel_offset: (0, 0),
+            button_action: ButtonAction::Direction,
+            button_hold: false,
+            debug_echo: false,
+            flow_control: false,
+            flow_paused: false,
+            framing: Framing::Line,
+            heartbeat_ms: 0,
+            motion_wake_mg: 0,
+            prefix: CommandPrefix::NONE,
+            interactive: true,
+            led_check: false,
+            quiet: false,
+            rate_limit_ms: DEFAULT_INTERVAL_MS,
+            response_limit: 0,
+            tilt_speed: false,
+            timestamps: false,
+            transitions: false,
+        }
+    }
+}
+
+impl Settings {
+    /// Sets the period (in milliseconds) between accelerometer samples.
+    ///
+    /// The period is clamped between [`MIN_ACCEL_PERIOD_MS`] and [`MAX_ACCEL_PERIOD_MS`].
+    pub fn set_accel_period_ms(&mut self, period_ms: u16) {
+        self.accel_period_ms = period_ms.clamp(MIN_ACCEL_PERIOD_MS, MAX_ACCEL_PERIOD_MS);
+    }
+
+    /// Restores the default settings.
+    ///
+    /// The settings are not persisted, so these are also the settings after the next reset.
+    pub fn restore_defaults(&mut self) {
+        *self = Settings::new();
+    }
+
+    /// Enables or disables software flow control.
+    ///
+    /// Disabling it resumes unsolicited output if it was paused.
+    pub fn set_flow_control(&mut self, enable: bool) {
+        self.flow_control = enable;
+        self.flow_paused &= enable;
+    }
+
+    /// Handles a received byte in case it is a flow control byte (if flow control is enabled):
+    /// XOFF pauses unsolicited output and XON resumes it.
+    ///
+    /// Returns whether the byte was handled, in which case it is not part of a command.
+    pub fn handle_flow_control(&mut self, byte: u8) -> bool {
+        if !self.flow_control {
+            return false;
+        }
+
+        match byte {
+            XOFF => self.flow_paused = true,
+            XON => self.flow_paused = false,
+            _ => return false,
+        }
+        true
+    }
+
+    /// Applies a command that only changes a setting, and returns whether it was one.
+    ///
+    /// Queries and other commands are left alone, as are the commands that also reconfigure
+    /// the hardware (like `fifo`).
+    pub fn apply(&mut self, command: &Command) -> bool {
+        match *command {
+            Command::AccelAnalog(Some(enable)) => self.accel_analog = enable,
+            Command::AccelPeriod(Some(period_ms)) => self.set_accel_period_ms(period_ms),
+            Command::AccelPoll(Some(enable)) => self.accel_polling = enable,
+            Command::ButtonHold(Some(enable)) => self.button_hold = enable,
+            Command::ButtonMode(Some(action)) => self.button_action = action,
+            Command::DebugEcho(Some(enable)) => self.debug_echo = enable,
+            Command::Downhill(Some(enable)) => self.accel_downhill = enable,
+            Command::FlipAccel(Some(flip)) => self.accel_flip = flip,
+            Command::SwapAxes(Some(enable)) => self.accel_swap = enable,
+            Command::FlowControl(Some(enable)) => self.set_flow_control(enable),
+            Command::Framing(Some(framing)) => self.framing = framing,
+            Command::Heartbeat(Some(timeout_ms)) => self.heartbeat_ms = timeout_ms,
+            Command::Interactive(Some(enable)) => self.interactive = enable,
+            Command::LedCheck(Some(enable)) => self.led_check = enable,
+            Command::Meter(Some(enable)) => self.accel_meter = enable,
+            Command::MotionWake(Some(threshold_mg)) => self.motion_wake_mg = threshold_mg,
+            Command::Prefix(Some(prefix)) => self.prefix = prefix,
+            Command::Quiet(Some(enable)) => self.quiet = enable,
+            Command::RateLimit(Some(rate_limit_ms)) => self.rate_limit_ms = rate_limit_ms,
+            Command::ResponseLimit(Some(limit)) => self.response_limit = limit,
+            Command::TiltSpeed(Some(enable)) => self.tilt_speed = enable,
+            Command::Timestamps(Some(enable)) => self.timestamps = enable,
+            Command::Transitions(Some(enable)) => self.transitions = enable,
+            _ => return false,
+        }
+        true
+    }
+
+    /// Applies the commands of all `key=value` pairs of settings at once.
+    ///
+    /// Since the pairs are validated when they are parsed, either all of them are applied or
+    /// (if they could not be parsed) none of them.  Unlike [`Settings::apply`], this also
+    /// changes whether the FIFO is used; reconfiguring the accelerometer accordingly is up to
+    /// the caller, as is applying the mode and direction of the LED ring (see
+    /// [`SettingPairs::led_ring`]).
+    pub fn apply_all(&mut self, pairs: &SettingPairs) {
+        for command in pairs.commands() {
+            match command {
+                Command::Fifo(Some(enable)) => self.accel_fifo = enable,
+                command => {
+                    self.apply(&command);
+                }
+            }
+        }
+    }
+
+    /// Writes all settings as `key=value` pairs on a single line, separated by spaces (without
+    /// line terminator), including the given mode and cycle direction of the LED ring.
+    ///
+    /// The key is the name of the command that changes the setting and the value is its
+    /// argument, so each pair is turned into that command by replacing the `=` by a space.
+    /// The calibration offset and whether output is paused by flow control are left out, since
+    /// they are state rather than settings and no command sets them directly.
+    pub fn write_all<W: Write>(
+        &self,
+        writer: &mut W,
+        mode: Mode,
+        direction: Direction,
+    ) -> fmt::Result {
+        let prefix = if self.prefix.is_empty() {
+            "off"
+        } else {
+            self.prefix.as_str()
+        };
+        let pairs = [
+            ("accelanalog", Value::Flag(self.accel_analog)),
+            ("accelperiod", Value::Number(self.accel_period_ms)),
+            ("accelpoll", Value::Flag(self.accel_polling)),
+            ("buttonhold", Value::Flag(self.button_hold)),
+            ("buttonmode", Value::Name(self.button_action.as_name())),
+            ("debugecho", Value::Flag(self.debug_echo)),
+            ("direction", Value::Name(direction.as_name())),
+            ("downhill", Value::Flag(self.accel_downhill)),
+            ("fifo", Value::Flag(self.accel_fifo)),
+            ("flipaccel", Value::Name(self.accel_flip.as_name())),
+            ("flowcontrol", Value::Flag(self.flow_control)),
+            ("framing", Value::Name(self.framing.as_name())),
+            ("heartbeat", Value::Number(self.heartbeat_ms)),
+            ("interactive", Value::Flag(self.interactive)),
+            ("ledcheck", Value::Flag(self.led_check)),
+            ("meter", Value::Flag(self.accel_meter)),
+            ("mode", Value::Name(mode.as_name())),
+            ("motionwake", Value::Number(self.motion_wake_mg)),
+            ("prefix", Value::Name(prefix)),
+            ("quiet", Value::Flag(self.quiet)),
+            ("ratelimit", Value::Number(self.rate_limit_ms)),
+            ("responselimit", Value::Number(self.response_limit)),
+            ("swapaxes", Value::Flag(self.accel_swap)),
+            ("tiltspeed", Value::Flag(self.tilt_speed)),
+            ("timestamps", Value::Flag(self.timestamps)),
+            ("transitions", Value::Flag(self.transitions)),
+        ];
+
+        for (index, (key, value)) in pairs.iter().enumerate() {
+            if index > 0 {
+                writer.write_str(" ")?;
+            }
+            write!(writer, "{}=", key)?;
+            match value {
+                Value::Flag(true) => writer.write_str("on")?,
+                Value::Flag(false) => writer.write_str("off")?,
+                Value::Number(number) => write!(writer, "{}", number)?,
+                Value::Name(name) => writer.write_str(name)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether output of the given kind may be sent.
+    ///
+    /// When quiet or paused by flow control, only responses to commands are sent.
+    pub fn allows(&self, output: OutputKind) -> bool {
+        match output {
+            OutputKind::Response => true,
+            OutputKind::Event => !self.quiet && !self.flow_paused,
+        }
+    }
+}
+
+/// The `key=value` pairs of settings (as written by [`Settings::write_all`]) to apply at once,
+/// kept as their (validated) text.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SettingPairs {
+    /// The text of the pairs.
+    text: [u8; MAX_PAIRS_LEN],
+    /// The length of the text.
+    len: u8,
+}
+
+impl SettingPairs {
+    /// Parses the pairs from their text, separated by whitespace, checking that all of them
+    /// are valid settings.
+    ///
+    /// There has to be at least one pair; a later pair for the same key overrides an earlier
+    /// one.
+    pub fn parse(text: &[u8]) -> Result<SettingPairs, ParseError> {
+        let text = command::trim(text);
+        if text.is_empty() || text.len() > MAX_PAIRS_LEN {
+            return Err(ParseError::InvalidArgument);
+        }
+
+        let mut pairs = SettingPairs {
+            text: [0; MAX_PAIRS_LEN],
+            len: text.len() as u8,
+        };
+        pairs.text[..text.len()].copy_from_slice(text);
+
+        for pair in pairs.pair_texts() {
+            parse_pair(pair)?;
+        }
+        Ok(pairs)
+    }
+
+    /// Returns the text of the pairs.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.text[..self.len as usize]
+    }
+
+    /// Returns the commands that apply the pairs (in order).
+    pub fn commands(&self) -> impl Iterator<Item = Command> + '_ {
+        self.pair_texts().filter_map(|pair| parse_pair(pair).ok())
+    }
+
+    /// Returns the mode and the cycle direction of the LED ring given by the pairs (if any).
+    pub fn led_ring(&self) -> (Option<Mode>, Option<Direction>) {
+        let mut led_ring = (None, None);
+        for command in self.commands() {
+            match command {
+                Command::Mode(mode) => led_ring.0 = mode,
+                Command::Direction(direction) => led_ring.1 = direction,
+                _ => {}
+            }
+        }
+        led_ring
+    }
+
+    /// Returns the texts of the pairs.
+    fn pair_texts(&self) -> impl Iterator<Item = &[u8]> {
+        self.as_bytes()
+            .split(|byte| byte.is_ascii_whitespace())
+            .filter(|pair| !pair.is_empty())
+    }
+}
+
+/// Parses a `key=value` pair into the command that applies it, i.e. `key value`, which has to
+/// change a setting (or the mode or direction of the LED ring).
+fn parse_pair(pair: &[u8]) -> Result<Command, ParseError> {
+    let separator = pair.iter().position(|byte| *byte == b'=');
+    let (key, value) = match separator {
+        Some(index) => (&pair[..index], &pair[index + 1..]),
+        None => return Err(ParseError::InvalidArgument),
+    };
+    if key.is_empty() || value.is_empty() {
+        return Err(ParseError::InvalidArgument);
+    }
+
+    let mut line = [b' '; MAX_PAIRS_LEN];
+    line[..key.len()].copy_from_slice(key);
+    line[key.len() + 1..pair.len()].copy_from_slice(value);
+    let command = Command::parse(&line[..pair.len()])?;
+
+    let is_setting = matches!(
+        command,
+        Command::Direction(Some(_)) | Command::Fifo(Some(_)) | Command::Mode(Some(_))
+    ) || Settings::new().apply(&command);
+    if !is_setting {
+        return Err(ParseError::InvalidArgument);
+    }
+    Ok(command)
+}
+
+/// The value of a setting as written by [`Settings::write_all`].
+enum Value<'a> {
+    /// An on/off setting.
+    Flag(bool),
+    /// A numeric setting.
+    Number(u16),
+    /// A setting that is written by name.
+    Name(&'a str),
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        AxisFlip, ButtonAction, Command, CommandPrefix, Direction, Framing, Mode, OutputKind,
+        ParseError, SettingPairs, Settings, DEFAULT_ACCEL_PERIOD_MS, DEFAULT_INTERVAL_MS,
+        MAX_ACCEL_PERIOD_MS, MAX_PAIRS_LEN, MIN_ACCEL_PERIOD_MS, XOFF, XON,
+    };
+
+    #[test]
+    fn settings_default() {
+        let settings = Settings::default();
+
+        assert!(!settings.accel_analog);
+        assert!(!settings.accel_downhill);
+        assert!(!settings.accel_meter);
+        assert_eq!(settings.accel_period_ms, DEFAULT_ACCEL_PERIOD_MS);
+        assert_eq!(settings.accel_flip, AxisFlip::NONE);
+        assert!(!settings.accel_swap);
+        assert!(!settings.accel_fifo);
+        assert!(!settings.accel_polling);
+        assert_eq!(settings.accel_offset, (0, 0));
+        assert_eq!(settings.button_action, ButtonAction::Direction);
+        assert!(!settings.button_hold);
+        assert!(!settings.debug_echo);
+        assert!(!settings.flow_control);
+        assert!(!settings.flow_paused);
+        assert_eq!(settings.framing, Framing::Line);
+        assert_eq!(settings.heartbeat_ms, 0);
+        assert_eq!(settings.motion_wake_mg, 0);
+        assert!(settings.prefix.is_empty());
+        assert!(settings.interactive);
+        assert!(!settings.led_check);
+        assert!(!settings.quiet);
+        assert_eq!(settings.rate_limit_ms, DEFAULT_INTERVAL_MS);
+        assert_eq!(settings.response_limit, 0);
+        assert!(!settings.tilt_speed);
+        assert!(!settings.timestamps);
+        assert!(!settings.transitions);
+    }
+
+    #[test]
+    fn settings_accel_period() {
+        let mut settings = Settings::default();
+
+        settings.set_accel_period_ms(100);
+        assert_eq!(settings.accel_period_ms, 100);
+        settings.set_accel_period_ms(MIN_ACCEL_PERIOD_MS);
+        assert_eq!(settings.accel_period_ms, MIN_ACCEL_PERIOD_MS);
+
+        // Periods that are too short or too long are clamped.
+        settings.set_accel_period_ms(1);
+        assert_eq!(settings.accel_period_ms, MIN_ACCEL_PERIOD_MS);
+        settings.set_accel_period_ms(u16::MAX);
+        assert_eq!(settings.accel_period_ms, MAX_ACCEL_PERIOD_MS);
+    }
+
+    #[test]
+    fn settings_restore_defaults() {
+        let mut settings = Settings {
+            accel_flip: AxisFlip { x: true, y: false },
+            button_action: ButtonAction::Mode,
+            interactive: false,
+            quiet: true,
+            ..Settings::default()
+        };
+        settings.set_accel_period_ms(100);
+
+        settings.restore_defaults();
+        assert_eq!(settings, Settings::default());
+
+        // Restoring the defaults again changes nothing.
+        settings.restore_defaults();
+        assert_eq!(settings, Settings::default());
+    }
+
+    #[test]
+    fn settings_write_all() {
+        let mut line = String::new();
+        Settings::default()
+            .write_all(&mut line, Mode::Cycle, Direction::Clockwise)
+            .unwrap();
+        assert_eq!(
+            line,
+            "accelanalog=off accelperiod=500 accelpoll=off buttonhold=off buttonmode=direction \
+             debugecho=off direction=cw downhill=off fifo=off flipaccel=none flowcontrol=off \
+             framing=line heartbeat=0 interactive=on ledcheck=off meter=off mode=cycle \
+             motionwake=0 prefix=off quiet=off ratelimit=100 responselimit=0 swapaxes=off \
+             tiltspeed=off timestamps=off transitions=off"
+        );
+    }
+
+    #[test]
+    fn settings_round_trip() {
+        let settings = Settings {
+            accel_analog: true,
+            accel_downhill: true,
+            accel_meter: true,
+            accel_period_ms: 250,
+            accel_flip: AxisFlip { x: true, y: true },
+            accel_swap: true,
+            accel_fifo: true,
+            accel_polling: true,
+            button_action: ButtonAction::Mode,
+            button_hold: true,
+            debug_echo: true,
+            flow_control: true,
+            framing: Framing::Packet,
+            heartbeat_ms: 5000,
+            motion_wake_mg: 200,
+            prefix: CommandPrefix::from_bytes(b"led:").unwrap(),
+            interactive: false,
+            led_check: true,
+            quiet: true,
+            rate_limit_ms: 0,
+            response_limit: 256,
+            tilt_speed: true,
+            timestamps: true,
+            transitions: true,
+            ..Settings::default()
+        };
+        let cases = [
+            (settings, Mode::Chase, Direction::CounterClockwise),
+            (Settings::default(), Mode::Cycle, Direction::Clockwise),
+        ];
+        for (settings, mode, direction) in cases.iter() {
+            let mut line = String::new();
+            settings.write_all(&mut line, *mode, *direction).unwrap();
+
+            // Each pair is turned into the command that restores the setting (or the mode and
+            // direction of the LED ring).
+            let mut restored = Settings::default();
+            let mut restored_mode = None;
+            let mut restored_direction = None;
+            for pair in line.split(' ') {
+                let command = Command::parse(pair.replacen('=', " ", 1).as_bytes()).unwrap();
+                match command {
+                    Command::Fifo(Some(enable)) => restored.accel_fifo = enable,
+                    Command::Mode(mode) => restored_mode = mode,
+                    Command::Direction(direction) => restored_direction = direction,
+                    command => assert!(restored.apply(&command), "{}", pair),
+                }
+            }
+            assert_eq!(restored, *settings);
+            assert_eq!(restored_mode, Some(*mode));
+            assert_eq!(restored_direction, Some(*direction));
+        }
+    }
+
+    #[test]
+    fn settings_apply() {
+        let mut settings = Settings::default();
+
+        assert!(settings.apply(&Command::Quiet(Some(true))));
+        assert!(settings.quiet);
+        assert!(settings.apply(&Command::AccelPeriod(Some(1))));
+        assert_eq!(settings.accel_period_ms, MIN_ACCEL_PERIOD_MS);
+
+        // Queries, other commands and those that reconfigure the hardware are not applied.
+        let unchanged = settings;
+        for command in [
+            Command::Quiet(None),
+            Command::Cycle,
+            Command::Fifo(Some(true)),
+        ]
+        .iter()
+        {
+            assert!(!settings.apply(command), "{:?}", command);
+        }
+        assert_eq!(settings, unchanged);
+    }
+
+    #[test]
+    fn settings_apply_all() {
+        let mut settings = Settings::default();
+        let pairs = SettingPairs::parse(b"quiet=on heartbeat=5000  fifo=on flipaccel=xy").unwrap();
+        assert_eq!(pairs.commands().count(), 4);
+
+        settings.apply_all(&pairs);
+        assert_eq!(
+            settings,
+            Settings {
+                quiet: true,
+                heartbeat_ms: 5000,
+                accel_fifo: true,
+                accel_flip: AxisFlip { x: true, y: true },
+                ..Settings::default()
+            }
+        );
+
+        // A later pair for the same key overrides an earlier one; values are validated (and
+        // clamped) like the command would.
+        let pairs = SettingPairs::parse(b"quiet=off accelperiod=1 quiet=on").unwrap();
+        settings.apply_all(&pairs);
+        assert!(settings.quiet);
+        assert_eq!(settings.accel_period_ms, MIN_ACCEL_PERIOD_MS);
+        assert_eq!(pairs.led_ring(), (None, None));
+    }
+
+    #[test]
+    fn settings_apply_all_led_ring() {
+        // The mode and direction are left to the caller, the other pairs are applied.
+        let mut settings = Settings::default();
+        let pairs = SettingPairs::parse(b"mode=cycle quiet=on direction=ccw").unwrap();
+        settings.apply_all(&pairs);
+        assert_eq!(
+            settings,
+            Settings {
+                quiet: true,
+                ..Settings::default()
+            }
+        );
+        assert_eq!(
+            pairs.led_ring(),
+            (Some(Mode::Cycle), Some(Direction::CounterClockwise))
+        );
+
+        // A later pair overrides an earlier one here as well.
+        let pairs = SettingPairs::parse(b"mode=chase direction=cw mode=off").unwrap();
+        assert_eq!(
+            pairs.led_ring(),
+            (Some(Mode::Off), Some(Direction::Clockwise))
+        );
+        let pairs = SettingPairs::parse(b"mode=chase").unwrap();
+        assert_eq!(pairs.led_ring(), (Some(Mode::Chase), None));
+
+        // The output of getall is accepted (in parts, due to the length of a line).
+        let mut line = String::new();
+        Settings::default()
+            .write_all(&mut line, Mode::Chase, Direction::CounterClockwise)
+            .unwrap();
+        let mut restored = (None, None);
+        for part in line.split(' ').collect::<Vec<_>>().chunks(4) {
+            let pairs = SettingPairs::parse(part.join(" ").as_bytes()).unwrap();
+            let (mode, direction) = pairs.led_ring();
+            restored = (mode.or(restored.0), direction.or(restored.1));
+        }
+        assert_eq!(
+            restored,
+            (Some(Mode::Chase), Some(Direction::CounterClockwise))
+        );
+    }
+
+    #[test]
+    fn settings_apply_all_invalid() {
+        // A single invalid pair rejects all pairs, so that none of them is applied.
+        let partially_invalid: [&[u8]; 6] = [
+            b"quiet=on heartbeat=70000",
+            b"quiet=on ratelimit",
+            b"quiet=on =on",
+            b"quiet=on meter=",
+            b"quiet=on mode=next",
+            b"quiet=on fifo",
+        ];
+        for text in partially_invalid.iter() {
+            assert_eq!(
+                SettingPairs::parse(text),
+                Err(ParseError::InvalidArgument),
+                "{}",
+                String::from_utf8_lossy(text)
+            );
+        }
+
+        // Unknown keys are rejected like unknown commands.
+        assert_eq!(
+            SettingPairs::parse(b"quiet=on speed=4000000"),
+            Err(ParseError::Unknown)
+        );
+        assert_eq!(
+            SettingPairs::parse(b"quiet=on quiet=on=off"),
+            Err(ParseError::InvalidArgument)
+        );
+
+        let fully_invalid: [&[u8]; 4] = [b"", b"   ", b"quiet", b"fly=high cycle=on"];
+        for text in fully_invalid.iter() {
+            assert!(SettingPairs::parse(text).is_err());
+        }
+
+        // Queries and other commands are not settings.
+        assert_eq!(
+            SettingPairs::parse(b"ack=3"),
+            Err(ParseError::InvalidArgument)
+        );
+
+        let too_long = b"quiet=on ".repeat(MAX_PAIRS_LEN / 9 + 1);
+        assert_eq!(
+            SettingPairs::parse(&too_long),
+            Err(ParseError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn settings_quiet() {
+        let mut settings = Settings::default();
+        assert!(settings.allows(OutputKind::Response));
+        assert!(settings.allows(OutputKind::Event));
+
+        // Queries are still answered, but events are suppressed.
+        settings.quiet = true;
+        assert!(settings.allows(OutputKind::Response));
+        assert!(!settings.allows(OutputKind::Event));
+    }
+
+    #[test]
+    fn settings_flow_control() {
+        let mut settings = Settings::default();
+
+        // Without flow control, the bytes are not handled.
+        assert!(!settings.handle_flow_control(XOFF));
+        assert!(settings.allows(OutputKind::Event));
+
+        // XOFF pauses events (but not responses) until XON resumes them.
+        settings.set_flow_control(true);
+        assert!(settings.handle_flow_control(XOFF));
+        assert!(settings.flow_paused);
+        assert!(settings.allows(OutputKind::Response));
+        assert!(!settings.allows(OutputKind::Event));
+        assert!(settings.handle_flow_control(XOFF));
+        assert!(!settings.allows(OutputKind::Event));
+        assert!(settings.handle_flow_control(XON));
+        assert!(!settings.flow_paused);
+        assert!(settings.allows(OutputKind::Event));
+        assert!(settings.handle_flow_control(XON));
+        assert!(settings.allows(OutputKind::Event));
+
+        // Other bytes are not handled.
+        assert!(!settings.handle_flow_control(b'o'));
+        assert!(!settings.handle_flow_control(0x03));
+
+        // Disabling flow control resumes events.
+        settings.handle_flow_control(XOFF);
+        settings.set_flow_control(false);
+        assert!(!settings.flow_paused);
+        assert!(settings.allows(OutputKind::Event));
+    }
+}
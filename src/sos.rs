@@ -0,0 +1,92 @@
+//! Module for signalling SOS in Morse code with the LED ring.
+//!
+//! With the `panic-sos` feature enabled, this module also provides the panic handler that
+//! blinks all LEDs in the SOS pattern forever (instead of the semihosting panic handler).
+
+/// The SOS pattern in Morse code as a sequence of LED states and their duration in units.
+///
+/// A dot lasts one unit and a dash three units.  The parts of a letter are separated by one
+/// unit, letters by three units and the repetitions of the pattern by seven units.
+pub const SOS: [(bool, u8); 18] = [
+    // S
+    (true, 1),
+    (false, 1),
+    (true, 1),
+    (false, 1),
+    (true, 1),
+    (false, 3),
+    // O
+    (true, 3),
+    (false, 1),
+    (true, 3),
+    (false, 1),
+    (true, 3),
+    (false, 3),
+    // S
+    (true, 1),
+    (false, 1),
+    (true, 1),
+    (false, 1),
+    (true, 1),
+    (false, 7),
+];
+
+/// The number of cycles of a unit of the SOS pattern (200 ms at the default 16 MHz system
+/// clock).
+pub const UNIT_CYCLES: u32 = 3_200_000;
+
+/// The panic handler that blinks the LED ring in the SOS pattern.
+///
+/// The LED pins are taken over by reinitializing their GPIO port, regardless of what the
+/// application was doing with them.  Unlike the semihosting panic handler, the panic message is
+/// lost, but a panic is visible without a debugger attached.
+#[cfg(all(feature = "panic-sos", not(test)))]
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    use crate::board;
+    use cortex_m::{asm, interrupt};
+    use hal::{prelude::*, stm32};
+
+    interrupt::disable();
+
+    // NOTE(unsafe) The application is not running anymore, so it cannot use the peripherals.
+    let device = unsafe { stm32::Peripherals::steal() };
+    let mut leds = board::led_pins(device.GPIOD);
+
+    loop {
+        for (on, units) in SOS.iter() {
+            for led in leds.iter_mut() {
+                if *on {
+                    led.set_high().unwrap();
+                } else {
+                    led.set_low().unwrap();
+                }
+            }
+            asm::delay(*units as u32 * UNIT_CYCLES);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SOS;
+
+    #[test]
+    fn sos_pattern() {
+        // The pattern alternates between on and off.
+        for (index, (on, _units)) in SOS.iter().enumerate() {
+            assert_eq!(*on, index % 2 == 0);
+        }
+
+        // Three dots, three dashes and three dots.
+        let signals: Vec<u8> = SOS
+            .iter()
+            .filter(|(on, _units)| *on)
+            .map(|(_on, units)| *units)
+            .collect();
+        assert_eq!(signals, vec![1, 1, 1, 3, 3, 3, 1, 1, 1]);
+
+        let total: u32 = SOS.iter().map(|(_on, units)| *units as u32).sum();
+        assert_eq!(total, 34);
+    }
+}
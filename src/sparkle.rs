@@ -0,0 +1,163 @@
+//! Module for letting the LED ring sparkle, i.e. light random LEDs.
+
+/// The default seed of the pseudo-random number generator.
+pub const DEFAULT_SEED: u32 = 0x2545_F491;
+
+/// The number of LEDs in the ring that sparkle.
+const NUM_LEDS: usize = 4;
+
+/// A pseudo-random number generator (xorshift32).
+///
+/// The generator is fast and small, which is all that is needed for lighting LEDs, but not
+/// suitable for anything that needs real randomness.  The same seed always gives the same
+/// sequence of numbers.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Rng {
+    /// The state of the generator, which is never zero.
+    state: u32,
+}
+
+impl Rng {
+    /// Sets up the generator with the given seed.
+    ///
+    /// A seed of zero would only ever give zero, and is not allowed.
+    pub fn new(seed: u32) -> Option<Rng> {
+        if seed == 0 {
+            None
+        } else {
+            Some(Rng { state: seed })
+        }
+    }
+
+    /// Returns the next pseudo-random number.
+    pub fn next_u32(&mut self) -> u32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+
+        self.state
+    }
+}
+
+/// Sparkling LEDs, where each step lights each LED with a chance of one in four.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Sparkle {
+    /// The seed the generator was last seeded with.
+    seed: u32,
+    /// The pseudo-random number generator.
+    rng: Rng,
+}
+
+impl Sparkle {
+    /// Sets up sparkling LEDs with the generator seeded with the given seed.
+    ///
+    /// A seed of zero is not allowed (see [`Rng::new`]).
+    pub fn new(seed: u32) -> Option<Sparkle> {
+        Rng::new(seed).map(|rng| Sparkle { seed, rng })
+    }
+
+    /// Returns the seed the generator was last seeded with.
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+
+    /// Reseeds the generator, so that the same sequence of LEDs is lit again for the same
+    /// seed.
+    ///
+    /// Returns whether the seed is allowed; otherwise, nothing is changed.
+    pub fn set_seed(&mut self, seed: u32) -> bool {
+        match Sparkle::new(seed) {
+            Some(sparkle) => {
+                *self = sparkle;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Advances the sparkle one step and returns which LEDs are lit.
+    pub fn advance(&mut self) -> [bool; NUM_LEDS] {
+        let bits = self.rng.next_u32();
+        let mut pattern = [false; NUM_LEDS];
+        for (index, on) in pattern.iter_mut().enumerate() {
+            *on = (bits >> (2 * index)) & 0b11 == 0;
+        }
+
+        pattern
+    }
+}
+
+impl Default for Sparkle {
+    fn default() -> Sparkle {
+        Sparkle::new(DEFAULT_SEED).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Rng, Sparkle, DEFAULT_SEED};
+
+    #[test]
+    fn rng() {
+        assert_eq!(Rng::new(0), None);
+
+        // The reference sequence of xorshift32 for seed 1.
+        let mut rng = Rng::new(1).unwrap();
+        assert_eq!(rng.next_u32(), 270_369);
+        assert_eq!(rng.next_u32(), 67_634_689);
+        assert_eq!(rng.next_u32(), 2_647_435_461);
+
+        // The generator never gets stuck at zero.
+        let mut rng = Rng::new(DEFAULT_SEED).unwrap();
+        assert!((0..1000).all(|_| rng.next_u32() != 0));
+    }
+
+    #[test]
+    fn sparkle_init() {
+        assert_eq!(Sparkle::default(), Sparkle::new(DEFAULT_SEED).unwrap());
+        assert_eq!(Sparkle::default().seed(), DEFAULT_SEED);
+        assert_eq!(Sparkle::new(0), None);
+    }
+
+    #[test]
+    fn sparkle_deterministic() {
+        let run = |seed| {
+            let mut sparkle = Sparkle::new(seed).unwrap();
+            (0..64).map(|_| sparkle.advance()).collect::<Vec<_>>()
+        };
+
+        // Two runs with the same seed light the same LEDs, but another seed does not.
+        assert_eq!(run(42), run(42));
+        assert_ne!(run(42), run(43));
+
+        // Reseeding starts the same sequence over.
+        let mut sparkle = Sparkle::default();
+        for _ in 0..10 {
+            sparkle.advance();
+        }
+        assert!(sparkle.set_seed(42));
+        assert_eq!(sparkle.seed(), 42);
+        let rerun: Vec<_> = (0..64).map(|_| sparkle.advance()).collect();
+        assert_eq!(rerun, run(42));
+
+        // An invalid seed is ignored.
+        assert!(!sparkle.set_seed(0));
+        assert_eq!(sparkle.seed(), 42);
+    }
+
+    #[test]
+    fn sparkle_chance() {
+        let mut sparkle = Sparkle::default();
+
+        // Each LED is lit about one in four steps, and all LEDs sparkle.
+        let mut lit = [0; 4];
+        for _ in 0..4000 {
+            for (count, on) in lit.iter_mut().zip(sparkle.advance().iter()) {
+                *count += *on as usize;
+            }
+        }
+        for count in lit.iter() {
+            assert!((800..1200).contains(count), "{:?}", lit);
+        }
+    }
+}
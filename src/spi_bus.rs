@@ -0,0 +1,231 @@
+//! Module for sharing an SPI bus between several devices.
+//!
+//! Each device on the bus has its own chip select output.  The bus lends the SPI peripheral to
+//! one transaction at a time, during which the device of the transaction is selected, so that
+//! the transfers of different devices cannot interleave.  The accelerometer is the only device
+//! on its bus on this board, but runs its transfers as transactions as well, so that another
+//! device only needs its own chip select output.
+
+use core::cell::RefCell;
+use core::convert::Infallible;
+
+use hal::prelude::_embedded_hal_blocking_spi_Transfer as Transfer;
+use hal::prelude::_embedded_hal_digital_v2_OutputPin as OutputPin;
+
+/// A guard that keeps a device selected, i.e. its chip select output low, while it exists.
+///
+/// The chip select output is set high again when the guard is dropped, also when returning
+/// early (e.g. due to a failed transfer).  Since dropping cannot fail, setting it high is best
+/// effort: an error is ignored.
+pub struct Selected<'a, CS: OutputPin> {
+    /// The chip select output of the selected device.
+    cs: &'a mut CS,
+}
+
+impl<'a, CS: OutputPin> Selected<'a, CS> {
+    /// Selects the device by setting the chip select output low.
+    pub fn new(cs: &'a mut CS) -> Selected<'a, CS> {
+        cs.set_low().ok();
+
+        Selected { cs }
+    }
+}
+
+impl<'a, CS: OutputPin> Drop for Selected<'a, CS> {
+    fn drop(&mut self) {
+        self.cs.set_high().ok();
+    }
+}
+
+/// The error that occurs when using a shared SPI bus.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BusError<E> {
+    /// The bus is lent to another transaction that is still in progress.
+    Busy,
+    /// The SPI transfer failed.
+    Transfer(E),
+}
+
+impl<E> BusError<E> {
+    /// Returns a short description of the error.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BusError::Busy => "busy",
+            BusError::Transfer(_) => "transfer failed",
+        }
+    }
+}
+
+/// An SPI bus that is shared between several devices.
+pub struct SpiBus<SPI> {
+    /// The SPI peripheral, lent to one transaction at a time.
+    spi: RefCell<SPI>,
+}
+
+impl<SPI, E> SpiBus<SPI>
+where
+    SPI: Transfer<u8, Error = E>,
+{
+    /// Sets up the shared bus using the SPI peripheral.
+    pub fn new(spi: SPI) -> SpiBus<SPI> {
+        SpiBus {
+            spi: RefCell::new(spi),
+        }
+    }
+
+    /// Releases the SPI peripheral.
+    pub fn release(self) -> SPI {
+        self.spi.into_inner()
+    }
+
+    /// Returns the SPI peripheral, which is not lent to a transaction since the bus is borrowed
+    /// mutably.
+    pub fn spi_mut(&mut self) -> &mut SPI {
+        self.spi.get_mut()
+    }
+
+    /// Runs a transaction with the device that has the given chip select output, i.e. selects
+    /// the device and lends the SPI peripheral to the transaction, and deselects the device
+    /// afterwards.
+    ///
+    /// Returns [`BusError::Busy`] without selecting the device if another transaction is still
+    /// in progress (e.g. when nested).
+    pub fn transaction<CS, R>(
+        &self,
+        cs: &mut CS,
+        transaction: impl FnOnce(&mut SPI) -> R,
+    ) -> Result<R, BusError<E>>
+    where
+        CS: OutputPin<Error = Infallible>,
+    {
+        let mut spi = self.spi.try_borrow_mut().map_err(|_| BusError::Busy)?;
+        let _selected = Selected::new(cs);
+
+        Ok(transaction(&mut spi))
+    }
+
+    /// Transfers the words to and from the device that has the given chip select output in a
+    /// single transaction.
+    pub fn transfer<'w, CS>(
+        &self,
+        cs: &mut CS,
+        words: &'w mut [u8],
+    ) -> Result<&'w [u8], BusError<E>>
+    where
+        CS: OutputPin<Error = Infallible>,
+    {
+        self.transaction(cs, move |spi| spi.transfer(words))?
+            .map_err(BusError::Transfer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BusError, SpiBus, Transfer};
+    use crate::mock::{MockCs, MockSpi};
+
+    /// Sets up a bus with two devices, returning their chip select outputs.
+    fn setup() -> (SpiBus<MockSpi>, [MockCs; 2]) {
+        let devices = [MockCs::default(), MockCs::default()];
+        let spi = MockSpi {
+            devices: devices.to_vec(),
+            reply: 0xA5,
+            ..MockSpi::default()
+        };
+
+        (SpiBus::new(spi), devices)
+    }
+
+    /// Returns the log of transferred words with which devices were selected at the time.
+    fn log(bus: &mut SpiBus<MockSpi>) -> Vec<(u8, Vec<bool>)> {
+        let spi = bus.spi_mut();
+        spi.written
+            .iter()
+            .copied()
+            .zip(spi.selected.clone())
+            .collect()
+    }
+
+    /// Transfers a copy of the words via the bus and returns the received words.
+    fn transfer(
+        bus: &SpiBus<MockSpi>,
+        cs: &mut MockCs,
+        words: &[u8],
+    ) -> Result<Vec<u8>, BusError<()>> {
+        let mut words = words.to_vec();
+        bus.transfer(cs, &mut words).map(|words| words.to_vec())
+    }
+
+    #[test]
+    fn spi_bus_two_devices() {
+        let (mut bus, [mut cs_a, mut cs_b]) = setup();
+
+        assert_eq!(
+            transfer(&bus, &mut cs_a, &[0x01, 0x02]),
+            Ok(vec![0x01, 0xA5])
+        );
+        assert_eq!(transfer(&bus, &mut cs_b, &[0x03]), Ok(vec![0x03]));
+        assert_eq!(transfer(&bus, &mut cs_a, &[0x04]), Ok(vec![0x04]));
+
+        // Only the device of each transfer is selected during it, and none afterwards.
+        assert_eq!(
+            log(&mut bus),
+            [
+                (0x01, vec![true, false]),
+                (0x02, vec![true, false]),
+                (0x03, vec![false, true]),
+                (0x04, vec![true, false])
+            ]
+        );
+        assert!(!cs_a.is_selected());
+        assert!(!cs_b.is_selected());
+    }
+
+    #[test]
+    fn spi_bus_serialized() {
+        let (mut bus, [mut cs_a, mut cs_b]) = setup();
+
+        // A transaction cannot start while another one is in progress.
+        let nested = bus.transaction(&mut cs_a, |spi| {
+            spi.transfer(&mut [0x01]).unwrap();
+            let nested = transfer(&bus, &mut cs_b, &[0x02]);
+            spi.transfer(&mut [0x03]).unwrap();
+            nested
+        });
+        assert_eq!(nested, Ok(Err(BusError::Busy)));
+        assert_eq!(
+            log(&mut bus),
+            [(0x01, vec![true, false]), (0x03, vec![true, false])]
+        );
+        assert!(!cs_a.is_selected());
+        assert!(!cs_b.is_selected());
+
+        // The bus is available again afterwards.
+        assert_eq!(
+            transfer(&bus, &mut cs_b, &[0x02, 0x00]),
+            Ok(vec![0x02, 0xA5])
+        );
+    }
+
+    #[test]
+    fn spi_bus_failure() {
+        let (bus, [mut cs_a, _cs_b]) = setup();
+        let mut spi = bus.release();
+        spi.fail = true;
+        let mut bus = SpiBus::new(spi);
+
+        assert_eq!(
+            transfer(&bus, &mut cs_a, &[0x01]),
+            Err(BusError::Transfer(()))
+        );
+        // The device is also deselected after a failing transfer.
+        assert!(!cs_a.is_selected());
+        assert!(log(&mut bus).is_empty());
+    }
+
+    #[test]
+    fn spi_bus_error_names() {
+        assert_eq!(BusError::<()>::Busy.as_str(), "busy");
+        assert_eq!(BusError::Transfer(()).as_str(), "transfer failed");
+    }
+}
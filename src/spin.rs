@@ -0,0 +1,111 @@
+//! Module for spinning the LED ring one full revolution.
+
+use crate::led_ring::Mode;
+
+/// The number of steps of a full revolution (one per LED).
+pub const SPIN_STEPS: u8 = 4;
+
+/// A step of the spin.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SpinStep {
+    /// Advance the ring one step.
+    Advance,
+    /// The revolution is done; restore the given mode and LED states.
+    Restore { mode: Mode, states: [bool; 4] },
+}
+
+/// A spin that advances the LED ring one full revolution.
+///
+/// The mode and LED states of the LED ring from before the spin are kept so that they can be
+/// restored afterwards.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Spin {
+    /// The number of remaining steps.
+    remaining: u8,
+    /// The mode to restore.
+    mode: Mode,
+    /// The LED states to restore.
+    states: [bool; 4],
+}
+
+impl Spin {
+    /// Sets up a spin that restores the given mode and LED states after a full revolution.
+    pub fn new(mode: Mode, states: [bool; 4]) -> Spin {
+        Spin {
+            remaining: SPIN_STEPS,
+            mode,
+            states,
+        }
+    }
+
+    /// Returns the number of steps that remain.
+    pub fn remaining_steps(&self) -> u8 {
+        self.remaining
+    }
+
+    /// Restarts the revolution, keeping the state to restore.
+    pub fn restart(&mut self) {
+        self.remaining = SPIN_STEPS;
+    }
+
+    /// Advances the spin one step and returns what to do.
+    ///
+    /// After the last step the state to restore is returned (repeatedly).
+    pub fn advance(&mut self) -> SpinStep {
+        if self.remaining == 0 {
+            return SpinStep::Restore {
+                mode: self.mode,
+                states: self.states,
+            };
+        }
+
+        self.remaining -= 1;
+        SpinStep::Advance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mode, Spin, SpinStep, SPIN_STEPS};
+
+    #[test]
+    fn spin_countdown() {
+        let states = [true, false, false, true];
+        let mut spin = Spin::new(Mode::Off, states);
+
+        assert_eq!(spin.remaining_steps(), SPIN_STEPS);
+        for remaining in (0..SPIN_STEPS).rev() {
+            assert_eq!(spin.advance(), SpinStep::Advance);
+            assert_eq!(spin.remaining_steps(), remaining);
+        }
+
+        let restore = SpinStep::Restore {
+            mode: Mode::Off,
+            states,
+        };
+        assert_eq!(spin.advance(), restore);
+        assert_eq!(spin.advance(), restore);
+    }
+
+    #[test]
+    fn spin_restart() {
+        let mut spin = Spin::new(Mode::Cycle, [false; 4]);
+
+        assert_eq!(spin.advance(), SpinStep::Advance);
+        assert_eq!(spin.advance(), SpinStep::Advance);
+        spin.restart();
+        assert_eq!(spin.remaining_steps(), SPIN_STEPS);
+
+        let steps = core::iter::repeat_with(|| spin.advance())
+            .take_while(|step| *step == SpinStep::Advance)
+            .count();
+        assert_eq!(steps, SPIN_STEPS as usize);
+        assert_eq!(
+            spin.advance(),
+            SpinStep::Restore {
+                mode: Mode::Cycle,
+                states: [false; 4]
+            }
+        );
+    }
+}
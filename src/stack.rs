@@ -0,0 +1,110 @@
+//! Module for estimating the stack usage (high-water mark) by painting the stack.
+//!
+//! At startup, the unused part of the stack is filled with a known pattern.  Later on, the
+//! number of words that still contain the pattern (counting from the far end of the stack)
+//! gives the part of the stack that has never been used.
+//!
+//! This is only an estimate: a word that happens to be written with the pattern looks unused,
+//! and the part of the stack that was in use when painting always counts as used.
+
+/// The pattern the unused part of the stack is painted with.
+pub const PAINT: u32 = 0xDEAD_BEEF;
+
+/// The number of bytes below the stack pointer that are left alone when painting, so that the
+/// painting itself does not overwrite the stack that is in use.
+pub const PAINT_MARGIN: usize = 256;
+
+/// Returns the number of words at the start of the stack region (i.e. its far end, since the
+/// stack grows downwards) that still contain the paint pattern.
+pub fn unused_words<I>(words: I) -> usize
+where
+    I: IntoIterator<Item = u32>,
+{
+    words.into_iter().take_while(|word| *word == PAINT).count()
+}
+
+/// Returns the stack usage (in bytes) given the size of the stack (in bytes) and the number of
+/// unused words.
+pub fn used_bytes(size: usize, unused_words: usize) -> usize {
+    size.saturating_sub(unused_words * 4)
+}
+
+/// Returns the lowest and the highest address (exclusive) of the stack.
+///
+/// There is no heap, so the stack can grow down from the end of RAM until the end of the static
+/// variables.
+#[cfg(not(test))]
+fn bounds() -> (usize, usize) {
+    extern "C" {
+        static __sheap: u32;
+        static _stack_start: u32;
+    }
+
+    // NOTE(unsafe) Only the addresses of the symbols provided by the linker script are used.
+    unsafe {
+        (
+            &__sheap as *const u32 as usize,
+            &_stack_start as *const u32 as usize,
+        )
+    }
+}
+
+/// Paints the part of the stack below the stack pointer (minus [`PAINT_MARGIN`]) with the
+/// paint pattern.
+///
+/// This should be called once at startup, when the stack is hardly used.
+#[cfg(not(test))]
+#[inline(never)]
+pub fn paint() {
+    let (start, _end) = bounds();
+    let top = cortex_m::register::msp::read() as usize - PAINT_MARGIN;
+
+    for address in (start..top).step_by(4) {
+        // NOTE(unsafe) This part of the stack is not in use (yet), and the writes are volatile
+        // so that they are not optimized away.
+        unsafe { core::ptr::write_volatile(address as *mut u32, PAINT) };
+    }
+}
+
+/// Returns the stack usage since painting and the size of the stack (both in bytes).
+///
+/// Only the part of the stack below the stack pointer is scanned, the rest is in use anyway.
+#[cfg(not(test))]
+pub fn usage() -> (usize, usize) {
+    let (start, end) = bounds();
+    let top = cortex_m::register::msp::read() as usize;
+
+    let words = (start..top).step_by(4).map(|address| {
+        // NOTE(unsafe) Reading the unused part of the stack is harmless, and the reads are
+        // volatile since interrupts may use it in the meantime.
+        unsafe { core::ptr::read_volatile(address as *const u32) }
+    });
+    let size = end - start;
+
+    (used_bytes(size, unused_words(words)), size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{unused_words, used_bytes, PAINT};
+
+    #[test]
+    fn count_unused_words() {
+        assert_eq!(unused_words(vec![]), 0);
+        assert_eq!(unused_words(vec![PAINT; 8]), 8);
+        assert_eq!(unused_words(vec![0; 8]), 0);
+
+        // Only the painted words up to the first used word count, a word with the pattern
+        // further up the stack is in use.
+        let words = vec![PAINT, PAINT, PAINT, 0x1234, PAINT, 0];
+        assert_eq!(unused_words(words), 3);
+    }
+
+    #[test]
+    fn stack_usage() {
+        assert_eq!(used_bytes(1024, 0), 1024);
+        assert_eq!(used_bytes(1024, 200), 224);
+        assert_eq!(used_bytes(1024, 256), 0);
+        assert_eq!(used_bytes(1024, 300), 0);
+    }
+}
@@ -0,0 +1,979 @@
+//! Module with the logic of the tasks of the demo application.
+//!
+//! Each function performs one step of the task with the same name on the LED ring and decides
+//! whether the task should be scheduled again, independent of the hardware and the scheduler.
+//! The tasks that animate a mode also check that nothing else changed the LED states in
+//! between their steps.
+
+use core::ops::Add;
+
+use crate::accel::{
+    directions_from_accel, downhill_from_accel, duties_from_accel, magnitude, meter_count,
+    meter_pattern,
+};
+use crate::button::ButtonAction;
+use crate::command::Command;
+use crate::led_ring::{Direction, LedOutput, LedRing, Mode, StateSink};
+use crate::settings::Settings;
+use crate::transition::{Transition, TransitionStep};
+
+/// The outcome of a step of a task.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Outcome {
+    /// Whether the task should be scheduled again (or, for the button, whether the tasks of
+    /// the mode should be resumed).
+    pub reschedule: bool,
+    /// The message to write to the serial interface (if any).
+    pub message: Option<&'static str>,
+}
+
+impl Outcome {
+    /// Returns the outcome with the given reschedule decision and no message.
+    fn reschedule(reschedule: bool) -> Outcome {
+        Outcome {
+            reschedule,
+            message: None,
+        }
+    }
+}
+
+/// The message reported when the LED states are inconsistent (see [`animate`]).
+pub const LED_ANOMALY: &str = "led anomaly";
+
+/// Takes a step of the animation of the current mode and reports an anomaly if the LED states
+/// were changed by something else since the previous step.
+///
+/// This is a sanity check of the tracked LED states only (see
+/// [`LedRing::states_as_expected`]), there is no feedback of what the LEDs actually do.
+fn animate<LED, SINK, F>(led_ring: &mut LedRing<LED, SINK>, step: F) -> Outcome
+where
+    LED: LedOutput,
+    SINK: StateSink,
+    F: FnOnce(&mut LedRing<LED, SINK>),
+{
+    let consistent = led_ring.states_as_expected();
+    step(led_ring);
+    led_ring.expect_states();
+
+    Outcome {
+        reschedule: true,
+        message: if consistent { None } else { Some(LED_ANOMALY) },
+    }
+}
+
+/// Stops the task of the given mode because the LED ring is no longer in that mode.
+///
+/// The task is released in the same step in which the mode is checked, so that entering the
+/// mode again either finds the task still pending (and lets it continue) or spawns a new one,
+/// but never ends up with two tasks animating the mode.
+fn stop<LED, SINK>(led_ring: &mut LedRing<LED, SINK>, mode: Mode) -> Outcome
+where
+    LED: LedOutput,
+    SINK: StateSink,
+{
+    led_ring.release_task(mode);
+    Outcome::reschedule(false)
+}
+
+/// Returns the direction to cycle in, given the normal direction and whether it is reversed
+/// (e.g. while the button is held).
+pub fn cycle_direction(normal: Direction, reversed: bool) -> Direction {
+    if reversed {
+        normal.flip()
+    } else {
+        normal
+    }
+}
+
+/// Advances the LED ring one step if it is in cycle mode.
+///
+/// If reversed, the step is taken in the opposite direction without changing the direction of
+/// the LED ring, so that it resumes the normal direction once no longer reversed.
+pub fn cycle_leds<LED, SINK>(led_ring: &mut LedRing<LED, SINK>, reversed: bool) -> Outcome
+where
+    LED: LedOutput,
+    SINK: StateSink,
+{
+    if led_ring.is_mode_cycle() {
+        animate(led_ring, |led_ring| {
+            let direction = led_ring.direction();
+            led_ring.set_direction(cycle_direction(direction, reversed));
+            led_ring.advance();
+            led_ring.set_direction(direction);
+        })
+    } else {
+        stop(led_ring, Mode::Cycle)
+    }
+}
+
+/// Shows the X and Y acceleration of the measurement on the LED ring if it is in accelerometer
+/// mode.
+///
+/// Depending on the settings, the sides pointing down, the downhill direction, the tilt by the
+/// number of LEDs that are on (as a meter) or (if analog) the tilt by the brightness of the
+/// LEDs is shown.  The meter continues from the number of LEDs that are on.  Without a
+/// measurement (e.g. when the accelerometer is being polled elsewhere), the LEDs are left alone
+/// but the task is still rescheduled.
+///
+/// The board becoming level or no longer being level is reported, where the level state keeps
+/// track of whether the board was level at the previous measurement.  It is reset when not in
+/// accelerometer mode, so that a level board is reported again when the mode is re-entered.
+pub fn accel_leds<LED, SINK>(
+    led_ring: &mut LedRing<LED, SINK>,
+    measurement: Option<(i8, i8)>,
+    settings: &Settings,
+    level: &mut bool,
+) -> Outcome
+where
+    LED: LedOutput,
+    SINK: StateSink,
+{
+    if !led_ring.is_mode_accel() {
+        *level = false;
+        return stop(led_ring, Mode::Accelerometer);
+    }
+
+    match measurement {
+        Some((acc_x, acc_y)) => {
+            if settings.accel_analog {
+                led_ring.specific_duties(duties_from_accel(acc_x, acc_y));
+            } else if settings.accel_downhill {
+                led_ring.specific_on(downhill_from_accel(acc_x, acc_y));
+            } else if settings.accel_meter {
+                let previous = led_ring.states().iter().take_while(|on| **on).count();
+                let count = meter_count(magnitude(acc_x, acc_y), previous as u8);
+                led_ring.specific_on(meter_pattern(count));
+            } else {
+                led_ring.specific_on(directions_from_accel(acc_x, acc_y));
+            }
+
+            let was_level = *level;
+            *level = acc_x == 0 && acc_y == 0;
+            Outcome {
+                reschedule: true,
+                message: match (was_level, *level) {
+                    (false, true) => Some("level"),
+                    (true, false) => Some("unlevel"),
+                    _ => None,
+                },
+            }
+        }
+        None => Outcome::reschedule(true),
+    }
+}
+
+/// Advances the chasing dots one tick if the LED ring is in chase mode.
+pub fn chase_leds<LED, SINK>(led_ring: &mut LedRing<LED, SINK>) -> Outcome
+where
+    LED: LedOutput,
+    SINK: StateSink,
+{
+    if led_ring.is_mode_chase() {
+        animate(led_ring, LedRing::advance_chase)
+    } else {
+        stop(led_ring, Mode::Chase)
+    }
+}
+
+/// Ramps the brightness one step if the LED ring is in ramp mode.
+///
+/// The task should be rescheduled after the dwell of the ramp.
+pub fn ramp_leds<LED, SINK>(led_ring: &mut LedRing<LED, SINK>) -> Outcome
+where
+    LED: LedOutput,
+    SINK: StateSink,
+{
+    if led_ring.is_mode_ramp() {
+        animate(led_ring, |led_ring| {
+            led_ring.ramp_mut().advance();
+        })
+    } else {
+        stop(led_ring, Mode::Ramp)
+    }
+}
+
+/// Advances the breath one step if the LED ring is in breathe mode.
+pub fn breathe_leds<LED, SINK>(led_ring: &mut LedRing<LED, SINK>) -> Outcome
+where
+    LED: LedOutput,
+    SINK: StateSink,
+{
+    if led_ring.is_mode_breathe() {
+        animate(led_ring, |led_ring| {
+            led_ring.breathe_mut().advance();
+        })
+    } else {
+        stop(led_ring, Mode::Breathe)
+    }
+}
+
+/// Lights random LEDs if the LED ring is in sparkle mode.
+pub fn sparkle_leds<LED, SINK>(led_ring: &mut LedRing<LED, SINK>) -> Outcome
+where
+    LED: LedOutput,
+    SINK: StateSink,
+{
+    if led_ring.is_mode_sparkle() {
+        animate(led_ring, LedRing::advance_sparkle)
+    } else {
+        stop(led_ring, Mode::Sparkle)
+    }
+}
+
+/// Shows the next value of the binary count if the LED ring is in counter mode.
+pub fn counter_leds<LED, SINK>(led_ring: &mut LedRing<LED, SINK>) -> Outcome
+where
+    LED: LedOutput,
+    SINK: StateSink,
+{
+    if led_ring.is_mode_counter() {
+        animate(led_ring, LedRing::advance_counter)
+    } else {
+        stop(led_ring, Mode::Counter)
+    }
+}
+
+/// Returns the instant to schedule the next run of a periodic task at, given the instant the
+/// current run was scheduled at and the current instant.
+///
+/// The next run is scheduled one period after the current one was due, so that the period does
+/// not drift by the time it took to start and run the task.  If that instant has already passed
+/// (because higher priority tasks kept the task from running), the missed runs are dropped and
+/// the next run is scheduled one period from now instead, so that the task does not run back to
+/// back to catch up and starve the other tasks at its priority.
+pub fn next_periodic<I, D>(scheduled: I, now: I, period: D) -> I
+where
+    I: Add<D, Output = I> + Copy + Ord,
+    D: Copy,
+{
+    let next = scheduled + period;
+    if next < now {
+        now + period
+    } else {
+        next
+    }
+}
+
+/// Refreshes the LED ring one step of the software PWM, unless it is sleeping.
+pub fn pwm_leds<LED, SINK>(led_ring: &mut LedRing<LED, SINK>) -> Outcome
+where
+    LED: LedOutput,
+    SINK: StateSink,
+{
+    led_ring.refresh();
+    Outcome::reschedule(!led_ring.is_sleeping())
+}
+
+/// Shows a step of the transition animation, or enters the new mode when the animation is done.
+///
+/// The transition is cancelled if the mode is changed (or the LED ring is put to sleep) in the
+/// meantime; whatever sets the mode without a transition or takes control of the LEDs clears
+/// the transition itself.  The task of the new mode should be resumed once the transition is
+/// done or cancelled.
+pub fn transition_leds<LED, SINK>(
+    led_ring: &mut LedRing<LED, SINK>,
+    transition: &mut Option<Transition>,
+) -> Outcome
+where
+    LED: LedOutput,
+    SINK: StateSink,
+{
+    if led_ring.mode() != Mode::Off || led_ring.is_sleeping() {
+        *transition = None;
+    }
+
+    match transition.as_mut().map(Transition::advance) {
+        Some(TransitionStep::Show(states)) => {
+            led_ring.specific_on(states);
+            Outcome::reschedule(true)
+        }
+        Some(TransitionStep::Enter { mode, states }) => {
+            led_ring.set_mode(mode);
+            led_ring.specific_on(states);
+            *transition = None;
+            Outcome::reschedule(false)
+        }
+        None => Outcome::reschedule(false),
+    }
+}
+
+/// Handles a press of the user button: wakes the LED ring up if it is sleeping, or otherwise
+/// returns the command for the configured button action (if any).
+///
+/// The tasks of the mode should be resumed if the LED ring was woken up.  The press is always
+/// reported.  When holding the button reverses the direction, a press does not reverse it.
+///
+/// Waking up is the only change made here; the command is run later, after any commands that
+/// were given before it (see [`ButtonAction::command`]).
+pub fn button_pressed<LED, SINK>(
+    led_ring: &mut LedRing<LED, SINK>,
+    settings: &Settings,
+) -> (Outcome, Option<Command>)
+where
+    LED: LedOutput,
+    SINK: StateSink,
+{
+    let woken = led_ring.wake();
+    let command = match settings.button_action {
+        _ if woken => None,
+        ButtonAction::Direction if settings.button_hold => None,
+        action => Some(action.command()),
+    };
+
+    let outcome = Outcome {
+        reschedule: woken,
+        message: Some("button"),
+    };
+    (outcome, command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        accel_leds, breathe_leds, button_pressed, chase_leds, counter_leds, cycle_direction,
+        cycle_leds, next_periodic, pwm_leds, ramp_leds, sparkle_leds, transition_leds,
+        ButtonAction, Command, LedRing, Outcome, Transition, LED_ANOMALY,
+    };
+    use crate::button::next_mode;
+    use crate::led_ring::{counter_pattern, CycleStyle, Direction, Mode, PWM_STEPS};
+    use crate::mock::{led_ring, MockOutputPin};
+    use crate::settings::Settings;
+
+    const RESCHEDULE: Outcome = Outcome {
+        reschedule: true,
+        message: None,
+    };
+
+    const STOP: Outcome = Outcome {
+        reschedule: false,
+        message: None,
+    };
+
+    #[test]
+    fn task_cycle_leds() {
+        let mut led_ring = led_ring();
+
+        led_ring.enable_cycle();
+        assert_eq!(cycle_leds(&mut led_ring, false), RESCHEDULE);
+        assert_eq!(led_ring.states(), [true, false, false, false]);
+
+        led_ring.disable();
+        assert_eq!(cycle_leds(&mut led_ring, false), STOP);
+        assert_eq!(led_ring.states(), [true, false, false, false]);
+    }
+
+    #[test]
+    fn cycle_directions() {
+        use Direction::*;
+
+        assert_eq!(cycle_direction(Clockwise, false), Clockwise);
+        assert_eq!(cycle_direction(Clockwise, true), CounterClockwise);
+        assert_eq!(cycle_direction(CounterClockwise, false), CounterClockwise);
+        assert_eq!(cycle_direction(CounterClockwise, true), Clockwise);
+    }
+
+    #[test]
+    fn task_cycle_leds_reversed() {
+        let mut led_ring = led_ring();
+        led_ring.enable_cycle();
+        led_ring.set_cycle_style(CycleStyle::Single);
+
+        // Released for two steps, held for two steps, and released again.
+        let held = [false, false, true, true, false, false];
+        let expected = [0, 1, 2, 1, 0, 1];
+        for (held, index) in held.iter().zip(expected.iter()) {
+            assert_eq!(cycle_leds(&mut led_ring, *held), RESCHEDULE);
+            let mut states = [false; 4];
+            states[*index] = true;
+            assert_eq!(led_ring.states(), states, "held: {}", held);
+            // The direction itself does not change.
+            assert_eq!(led_ring.direction(), Direction::Clockwise);
+        }
+    }
+
+    #[test]
+    fn task_accel_leds() {
+        let mut led_ring = led_ring();
+        let settings = Settings::new();
+        let mut level = false;
+
+        led_ring.enable_accel();
+        assert_eq!(
+            accel_leds(&mut led_ring, Some((-5, 3)), &settings, &mut level),
+            RESCHEDULE
+        );
+        assert_eq!(led_ring.states(), [false, true, true, false]);
+
+        // Without a measurement (while polling), the LEDs are left alone.
+        assert_eq!(
+            accel_leds(&mut led_ring, None, &settings, &mut level),
+            RESCHEDULE
+        );
+        assert_eq!(led_ring.states(), [false, true, true, false]);
+
+        let level_outcome = Outcome {
+            reschedule: true,
+            message: Some("level"),
+        };
+        assert_eq!(
+            accel_leds(&mut led_ring, Some((0, 0)), &settings, &mut level),
+            level_outcome
+        );
+        assert_eq!(led_ring.states(), [false; 4]);
+
+        led_ring.enable_cycle();
+        assert_eq!(
+            accel_leds(&mut led_ring, Some((0, 0)), &settings, &mut level),
+            STOP
+        );
+    }
+
+    #[test]
+    fn task_accel_leds_level_edges() {
+        let mut led_ring = led_ring();
+        let settings = Settings::new();
+        let mut level = false;
+
+        led_ring.enable_accel();
+        let readings = [
+            ((3, 1), None),
+            ((0, 0), Some("level")),
+            ((0, 0), None),
+            ((0, 0), None),
+            ((0, -1), Some("unlevel")),
+            ((2, 2), None),
+            ((0, 0), Some("level")),
+            ((1, 0), Some("unlevel")),
+        ];
+        for (measurement, message) in readings.iter() {
+            let outcome = accel_leds(&mut led_ring, Some(*measurement), &settings, &mut level);
+            assert_eq!(outcome.message, *message, "measurement {:?}", measurement);
+        }
+
+        // Missing measurements do not change the level state.
+        accel_leds(&mut led_ring, Some((0, 0)), &settings, &mut level);
+        assert_eq!(
+            accel_leds(&mut led_ring, None, &settings, &mut level),
+            RESCHEDULE
+        );
+        assert!(level);
+
+        // Leaving accelerometer mode resets the level state, so it is reported again.
+        led_ring.enable_cycle();
+        accel_leds(&mut led_ring, Some((0, 0)), &settings, &mut level);
+        assert!(!level);
+        led_ring.enable_accel();
+        let outcome = accel_leds(&mut led_ring, Some((0, 0)), &settings, &mut level);
+        assert_eq!(outcome.message, Some("level"));
+    }
+
+    #[test]
+    fn task_accel_leds_analog() {
+        let mut led_ring = led_ring();
+        let settings = Settings::new();
+        let analog = Settings {
+            accel_analog: true,
+            ..Settings::new()
+        };
+        let mut level = false;
+
+        led_ring.enable_accel();
+        assert_eq!(
+            accel_leds(&mut led_ring, Some((-32, 64)), &analog, &mut level),
+            RESCHEDULE
+        );
+        assert_eq!(led_ring.states(), [false, true, true, false]);
+        assert_eq!(led_ring.duties(), [0, PWM_STEPS / 2, PWM_STEPS, 0]);
+
+        assert_eq!(
+            accel_leds(&mut led_ring, Some((-32, 64)), &settings, &mut level),
+            RESCHEDULE
+        );
+        assert_eq!(led_ring.duties(), [PWM_STEPS; 4]);
+    }
+
+    #[test]
+    fn task_accel_leds_downhill() {
+        let mut led_ring = led_ring();
+        let downhill = Settings {
+            accel_downhill: true,
+            ..Settings::new()
+        };
+        let mut level = false;
+
+        led_ring.enable_accel();
+        assert_eq!(
+            accel_leds(&mut led_ring, Some((20, 3)), &downhill, &mut level),
+            RESCHEDULE
+        );
+        assert_eq!(led_ring.states(), [false, false, false, true]);
+
+        accel_leds(&mut led_ring, Some((20, 15)), &downhill, &mut level);
+        assert_eq!(led_ring.states(), [false, false, true, true]);
+
+        // Analog display takes precedence.
+        let analog = Settings {
+            accel_analog: true,
+            ..downhill
+        };
+        accel_leds(&mut led_ring, Some((-32, 64)), &analog, &mut level);
+        assert_eq!(led_ring.duties(), [0, PWM_STEPS / 2, PWM_STEPS, 0]);
+    }
+
+    #[test]
+    fn task_accel_leds_meter() {
+        let mut led_ring = led_ring();
+        let meter = Settings {
+            accel_meter: true,
+            ..Settings::new()
+        };
+        let mut level = false;
+
+        led_ring.enable_accel();
+        assert_eq!(
+            accel_leds(&mut led_ring, Some((0, -40)), &meter, &mut level),
+            RESCHEDULE
+        );
+        assert_eq!(led_ring.states(), [true, true, false, false]);
+
+        // The meter does not drop right away when the tilt decreases just below a threshold.
+        accel_leds(&mut led_ring, Some((30, 0)), &meter, &mut level);
+        assert_eq!(led_ring.states(), [true, true, false, false]);
+        accel_leds(&mut led_ring, Some((20, -10)), &meter, &mut level);
+        assert_eq!(led_ring.states(), [true, false, false, false]);
+        accel_leds(&mut led_ring, Some((-64, 64)), &meter, &mut level);
+        assert_eq!(led_ring.states(), [true; 4]);
+
+        // Downhill display takes precedence.
+        let downhill = Settings {
+            accel_downhill: true,
+            ..meter
+        };
+        accel_leds(&mut led_ring, Some((20, 3)), &downhill, &mut level);
+        assert_eq!(led_ring.states(), [false, false, false, true]);
+    }
+
+    #[test]
+    fn task_chase_leds() {
+        let mut led_ring = led_ring();
+
+        led_ring.enable_chase();
+        led_ring.chase_mut().set_rates([1, 1]);
+        assert_eq!(chase_leds(&mut led_ring), RESCHEDULE);
+        assert_eq!(led_ring.states(), [false, true, false, true]);
+
+        led_ring.set_mode(Mode::Off);
+        assert_eq!(chase_leds(&mut led_ring), STOP);
+    }
+
+    #[test]
+    fn task_ramp_leds() {
+        let mut led_ring = led_ring();
+
+        led_ring.enable_ramp();
+        assert_eq!(ramp_leds(&mut led_ring), RESCHEDULE);
+        assert_eq!(led_ring.ramp().level(), led_ring.ramp().step());
+
+        led_ring.enable_accel();
+        assert_eq!(ramp_leds(&mut led_ring), STOP);
+        assert_eq!(led_ring.ramp().level(), led_ring.ramp().step());
+    }
+
+    #[test]
+    fn task_breathe_leds() {
+        let mut led_ring = led_ring();
+
+        led_ring.enable_breathe();
+        assert_eq!(breathe_leds(&mut led_ring), RESCHEDULE);
+        let level = led_ring.breathe().level();
+        assert!(level > 0);
+
+        led_ring.enable_ramp();
+        assert_eq!(breathe_leds(&mut led_ring), STOP);
+        assert_eq!(led_ring.breathe().level(), level);
+    }
+
+    #[test]
+    fn task_led_anomaly_normal_operation() {
+        let mut led_ring = led_ring();
+        let settings = Settings::default();
+        let mut level = false;
+
+        for mode in Mode::ALL.iter().chain(Mode::ALL.iter()) {
+            led_ring.set_mode(*mode);
+            if *mode == Mode::Ramp || *mode == Mode::Breathe {
+                led_ring.all_on();
+            }
+
+            for step in 0..20 {
+                // Things that are done in between steps that leave the LED states alone.
+                match step % 5 {
+                    0 => led_ring.reverse(),
+                    1 => led_ring.set_cycle_style(CycleStyle::Single),
+                    2 => led_ring.set_night_mode(step % 2 == 0),
+                    3 => {
+                        led_ring.sleep();
+                        led_ring.wake();
+                    }
+                    _ => led_ring.set_cycle_style(CycleStyle::Pair),
+                }
+
+                let outcomes = [
+                    cycle_leds(&mut led_ring, step % 3 == 0),
+                    chase_leds(&mut led_ring),
+                    ramp_leds(&mut led_ring),
+                    breathe_leds(&mut led_ring),
+                    sparkle_leds(&mut led_ring),
+                    counter_leds(&mut led_ring),
+                    accel_leds(&mut led_ring, Some((step, -step)), &settings, &mut level),
+                ];
+                for outcome in outcomes.iter() {
+                    assert_ne!(outcome.message, Some(LED_ANOMALY));
+                }
+            }
+
+            // Blinking (or spinning) and returning to the mode afterwards.
+            let states = led_ring.states();
+            led_ring.disable();
+            led_ring.all_on();
+            led_ring.advance();
+            led_ring.set_mode(*mode);
+            led_ring.specific_on(states);
+            assert_ne!(cycle_leds(&mut led_ring, false).message, Some(LED_ANOMALY));
+            assert_ne!(chase_leds(&mut led_ring).message, Some(LED_ANOMALY));
+        }
+    }
+
+    #[test]
+    fn task_led_anomaly() {
+        let mut led_ring = led_ring();
+        let anomaly = Outcome {
+            reschedule: true,
+            message: Some(LED_ANOMALY),
+        };
+
+        assert_eq!(cycle_leds(&mut led_ring, false), RESCHEDULE);
+        led_ring.all_off();
+        assert_eq!(cycle_leds(&mut led_ring, false), anomaly);
+        // The anomaly is only reported once.
+        assert_eq!(cycle_leds(&mut led_ring, false), RESCHEDULE);
+
+        led_ring.enable_breathe();
+        led_ring.all_on();
+        assert_eq!(breathe_leds(&mut led_ring), RESCHEDULE);
+        led_ring.specific_on([true, false, true, true]);
+        assert_eq!(breathe_leds(&mut led_ring), anomaly);
+
+        led_ring.enable_chase();
+        assert_eq!(chase_leds(&mut led_ring), RESCHEDULE);
+        led_ring.advance();
+        assert_eq!(chase_leds(&mut led_ring), anomaly);
+    }
+
+    #[test]
+    fn task_sparkle_leds() {
+        let (mut led_ring, mut other) = (led_ring(), led_ring());
+
+        // The same seed lights the same LEDs.
+        led_ring.enable_sparkle();
+        other.enable_sparkle();
+        led_ring.sparkle_mut().set_seed(7);
+        other.sparkle_mut().set_seed(7);
+        for _ in 0..16 {
+            assert_eq!(sparkle_leds(&mut led_ring), RESCHEDULE);
+            assert_eq!(sparkle_leds(&mut other), RESCHEDULE);
+            assert_eq!(led_ring.states(), other.states());
+        }
+
+        led_ring.enable_cycle();
+        let states = led_ring.states();
+        assert_eq!(sparkle_leds(&mut led_ring), STOP);
+        assert_eq!(led_ring.states(), states);
+    }
+
+    #[test]
+    fn task_counter_leds() {
+        let mut led_ring = led_ring();
+
+        led_ring.enable_counter();
+        for value in 0..20 {
+            assert_eq!(counter_leds(&mut led_ring), RESCHEDULE);
+            assert_eq!(led_ring.states(), counter_pattern(value % 16));
+        }
+
+        led_ring.enable_cycle();
+        let states = led_ring.states();
+        assert_eq!(counter_leds(&mut led_ring), STOP);
+        assert_eq!(led_ring.states(), states);
+    }
+
+    /// Takes a step of the task of the given mode.
+    fn step_task(led_ring: &mut LedRing<MockOutputPin>, mode: Mode) -> Outcome {
+        let settings = Settings::default();
+        let mut level = false;
+        match mode {
+            Mode::Off => unreachable!("off mode has no task"),
+            Mode::Cycle => cycle_leds(led_ring, false),
+            Mode::Accelerometer => accel_leds(led_ring, Some((0, 0)), &settings, &mut level),
+            Mode::Ramp => ramp_leds(led_ring),
+            Mode::Chase => chase_leds(led_ring),
+            Mode::Breathe => breathe_leds(led_ring),
+            Mode::Sparkle => sparkle_leds(led_ring),
+            Mode::Counter => counter_leds(led_ring),
+        }
+    }
+
+    /// Enters the given mode and queues its task if it needs to be spawned (like the
+    /// application does when setting the mode).
+    fn enter_mode(led_ring: &mut LedRing<MockOutputPin>, queue: &mut Vec<Mode>, mode: Mode) {
+        led_ring.set_mode(mode);
+        if led_ring.claim_task(mode) {
+            // Each task can only be pending once, so spawning it again would fail.
+            assert!(!queue.contains(&mode), "{:?} task spawned twice", mode);
+            queue.push(mode);
+        }
+    }
+
+    /// Runs each queued task once, queues those that are rescheduled again and returns the
+    /// modes of the tasks that took a step.
+    fn run_queue(led_ring: &mut LedRing<MockOutputPin>, queue: &mut Vec<Mode>) -> Vec<Mode> {
+        let mut stepped = Vec::new();
+        for mode in core::mem::take(queue) {
+            if step_task(led_ring, mode).reschedule {
+                stepped.push(mode);
+                queue.push(mode);
+            }
+        }
+
+        stepped
+    }
+
+    #[test]
+    fn task_reschedule_single_task_per_mode() {
+        let mut led_ring = led_ring();
+        let mut queue = Vec::new();
+
+        // The modes entered in between the runs of the tasks.
+        let switches: [&[Mode]; 7] = [
+            &[Mode::Cycle],
+            &[Mode::Accelerometer, Mode::Cycle],
+            &[Mode::Chase, Mode::Ramp, Mode::Chase],
+            &[Mode::Off, Mode::Sparkle, Mode::Counter, Mode::Breathe],
+            &[Mode::Cycle, Mode::Breathe, Mode::Cycle, Mode::Breathe],
+            &[Mode::Accelerometer],
+            &[Mode::Counter, Mode::Off],
+        ];
+        for modes in switches.iter() {
+            for mode in modes.iter() {
+                enter_mode(&mut led_ring, &mut queue, *mode);
+            }
+
+            // Only the task of the current mode steps, the others stop without touching the
+            // LEDs and are no longer pending.
+            let current: &[Mode] = match led_ring.mode() {
+                Mode::Off => &[],
+                _ => &modes[modes.len() - 1..],
+            };
+            assert_eq!(run_queue(&mut led_ring, &mut queue), current);
+            assert_eq!(queue, current);
+            for mode in Mode::ALL.iter() {
+                assert_eq!(led_ring.is_task_pending(*mode), current.contains(mode));
+            }
+        }
+    }
+
+    #[test]
+    fn task_reschedule_reentered_mode() {
+        let (mut led_ring, mut reference) = (led_ring(), led_ring());
+        let mut queue = Vec::new();
+
+        enter_mode(&mut led_ring, &mut queue, Mode::Cycle);
+        assert_eq!(run_queue(&mut led_ring, &mut queue), [Mode::Cycle]);
+        cycle_leds(&mut reference, false);
+
+        // Leaving cycle mode and entering it again before its task runs lets that task
+        // continue instead of spawning a second one, so the LEDs advance only one step.
+        enter_mode(&mut led_ring, &mut queue, Mode::Accelerometer);
+        enter_mode(&mut led_ring, &mut queue, Mode::Cycle);
+        assert_eq!(queue, [Mode::Cycle, Mode::Accelerometer]);
+        assert_eq!(run_queue(&mut led_ring, &mut queue), [Mode::Cycle]);
+        cycle_leds(&mut reference, false);
+        assert_eq!(led_ring.states(), reference.states());
+        assert_eq!(queue, [Mode::Cycle]);
+
+        // Once the task has stopped, entering the mode spawns it again.
+        enter_mode(&mut led_ring, &mut queue, Mode::Off);
+        assert_eq!(run_queue(&mut led_ring, &mut queue), []);
+        assert!(queue.is_empty());
+        enter_mode(&mut led_ring, &mut queue, Mode::Cycle);
+        assert_eq!(queue, [Mode::Cycle]);
+    }
+
+    #[test]
+    fn task_pwm_leds() {
+        let mut led_ring = led_ring();
+
+        assert_eq!(pwm_leds(&mut led_ring), RESCHEDULE);
+        led_ring.sleep();
+        assert_eq!(pwm_leds(&mut led_ring), STOP);
+    }
+
+    #[test]
+    fn task_next_periodic() {
+        // On time or late within the period, the next run keeps to the period.
+        assert_eq!(next_periodic(1000u32, 1000, 100), 1100);
+        assert_eq!(next_periodic(1000u32, 1060, 100), 1100);
+        assert_eq!(next_periodic(1000u32, 1100, 100), 1100);
+        // Later than that, the missed runs are dropped.
+        assert_eq!(next_periodic(1000u32, 1101, 100), 1201);
+        assert_eq!(next_periodic(1000u32, 1550, 100), 1650);
+    }
+
+    #[test]
+    fn task_transition() {
+        let mut led_ring = led_ring();
+        let mut transition = Some(Transition::new(Mode::Chase));
+        led_ring.disable();
+
+        // The animation sweeps a single LED around and then enters the mode.
+        assert_eq!(transition_leds(&mut led_ring, &mut transition), RESCHEDULE);
+        assert_eq!(led_ring.states(), [true, false, false, false]);
+        while transition_leds(&mut led_ring, &mut transition).reschedule {}
+        assert_eq!(transition, None);
+        assert_eq!(led_ring.mode(), Mode::Chase);
+        assert_eq!(led_ring.states(), Mode::Chase.initial_states());
+    }
+
+    #[test]
+    fn task_transition_cancelled() {
+        let mut led_ring = led_ring();
+        let mut transition = Some(Transition::new(Mode::Chase));
+        led_ring.disable();
+        assert_eq!(transition_leds(&mut led_ring, &mut transition), RESCHEDULE);
+
+        // Setting another mode in the meantime cancels the transition.
+        led_ring.set_mode(Mode::Cycle);
+        assert_eq!(transition_leds(&mut led_ring, &mut transition), STOP);
+        assert_eq!(transition, None);
+        assert_eq!(led_ring.mode(), Mode::Cycle);
+
+        // So does putting the LED ring to sleep.
+        let mut transition = Some(Transition::new(Mode::Chase));
+        led_ring.disable();
+        led_ring.sleep();
+        assert_eq!(transition_leds(&mut led_ring, &mut transition), STOP);
+        assert_eq!(transition, None);
+        led_ring.wake();
+
+        // Turning all LEDs off (or on) leaves the mode off, so that clears the transition
+        // instead; a step that is still pending then leaves the LEDs and the mode alone.
+        let mut transition = Some(Transition::new(Mode::Chase));
+        led_ring.disable();
+        assert_eq!(transition_leds(&mut led_ring, &mut transition), RESCHEDULE);
+        transition = None;
+        led_ring.disable();
+        led_ring.all_off();
+        assert_eq!(transition_leds(&mut led_ring, &mut transition), STOP);
+        assert_eq!(led_ring.mode(), Mode::Off);
+        assert_eq!(led_ring.states(), [false; 4]);
+    }
+
+    #[test]
+    fn task_button_pressed() {
+        let mut led_ring = led_ring();
+        let mut settings = Settings::default();
+        let pressed = Outcome {
+            reschedule: false,
+            message: Some("button"),
+        };
+
+        // By default, a press reverses the direction.
+        assert_eq!(
+            button_pressed(&mut led_ring, &settings),
+            (pressed, Some(Command::Flip))
+        );
+
+        // The press leaves the direction alone when holding reverses.
+        settings.button_hold = true;
+        assert_eq!(button_pressed(&mut led_ring, &settings), (pressed, None));
+
+        // Pressing the button while sleeping wakes up (and resumes) without reversing.
+        settings.button_hold = false;
+        led_ring.sleep();
+        let woken = Outcome {
+            reschedule: true,
+            message: Some("button"),
+        };
+        assert_eq!(button_pressed(&mut led_ring, &settings), (woken, None));
+        assert!(!led_ring.is_sleeping());
+        assert_eq!(led_ring.mode(), Mode::Cycle);
+        assert_eq!(led_ring.direction(), Direction::Clockwise);
+    }
+
+    #[test]
+    fn task_button_pressed_mode() {
+        let mut led_ring = led_ring();
+        let mut settings = Settings {
+            button_action: ButtonAction::Mode,
+            ..Settings::default()
+        };
+        let pressed = Outcome {
+            reschedule: false,
+            message: Some("button"),
+        };
+
+        // A press switches to the next mode, regardless of whether holding reverses.
+        let next = Some(Command::NextMode);
+        assert_eq!(button_pressed(&mut led_ring, &settings), (pressed, next));
+        settings.button_hold = true;
+        assert_eq!(button_pressed(&mut led_ring, &settings), (pressed, next));
+
+        // The press does not switch the mode itself.
+        let last = Mode::ALL[Mode::ALL.len() - 1];
+        led_ring.set_mode(last);
+        assert_eq!(button_pressed(&mut led_ring, &settings), (pressed, next));
+        assert_eq!(led_ring.mode(), last);
+
+        // Pressing the button while sleeping only wakes up.
+        led_ring.sleep();
+        let woken = Outcome {
+            reschedule: true,
+            message: Some("button"),
+        };
+        assert_eq!(button_pressed(&mut led_ring, &settings), (woken, None));
+        assert_eq!(led_ring.mode(), last);
+    }
+
+    #[test]
+    fn task_button_pressed_with_command() {
+        let mut led_ring = led_ring();
+        let mut settings = Settings {
+            button_action: ButtonAction::Mode,
+            ..Settings::default()
+        };
+
+        // A command given via the serial interface between the press and the command of the
+        // press is taken into account: the next mode follows the mode it switched to.
+        let (_, command) = button_pressed(&mut led_ring, &settings);
+        assert_eq!(command, Some(Command::NextMode));
+        led_ring.set_mode(Mode::Chase);
+        led_ring.set_mode(next_mode(led_ring.mode(), true));
+        assert_eq!(led_ring.mode(), Mode::Breathe);
+
+        // Likewise, a press reverses the direction of the mode it runs in, and only that one.
+        settings.button_action = ButtonAction::Direction;
+        led_ring.set_mode(Mode::Cycle);
+        let (_, command) = button_pressed(&mut led_ring, &settings);
+        assert_eq!(command, Some(Command::Flip));
+        led_ring.set_mode(Mode::Chase);
+        led_ring.reverse();
+        assert_eq!(led_ring.direction(), Direction::CounterClockwise);
+        led_ring.set_mode(Mode::Cycle);
+        assert_eq!(led_ring.direction(), Direction::Clockwise);
+
+        // Cycling continues from the index and in the direction of the mode when it runs.
+        led_ring.set_index(2).unwrap();
+        assert_eq!(cycle_leds(&mut led_ring, false), RESCHEDULE);
+        assert_eq!(led_ring.states(), [false, false, true, false]);
+    }
+}
@@ -0,0 +1,84 @@
+//! Module for reporting the cycle counter that serves as the monotonic timer.
+//!
+//! The cycle counter (DWT CYCCNT) counts the cycles of the core clock, and all scheduling is
+//! based on it: each scheduled task is due at an instant of the counter.  Writing or stopping
+//! the counter would move or freeze all of these instants at once, delaying the scheduled
+//! tasks (the animations, the software PWM and the heartbeat check) by up to a full
+//! wrap-around of the counter (about 268 s at 16 MHz).  The counter itself is therefore never
+//! written; resetting the timer records the current count as a reference instead, and the
+//! cycles elapsed since then are reported relative to it.
+
+use core::fmt::{self, Write};
+
+/// Returns the number of cycles elapsed from the reference to the count, taking a
+/// wrap-around of the counter in between into account.
+pub fn elapsed(count: u32, reference: u32) -> u32 {
+    count.wrapping_sub(reference)
+}
+
+/// Writes the timestamp that prefixes an event line, i.e. the count of the cycle counter in
+/// brackets followed by a space, e.g. `[12345] `.
+pub fn write_timestamp<W: Write>(writer: &mut W, count: u32) -> fmt::Result {
+    write!(writer, "[{}] ", count)
+}
+
+/// Writes the state of the cycle counter and the cycles elapsed since the reference on a
+/// single line (without line terminator), e.g. `timer: 1234567 cycles (running), 4567 since
+/// reset`.
+pub fn write_timer<W: Write>(
+    writer: &mut W,
+    count: u32,
+    running: bool,
+    reference: u32,
+) -> fmt::Result {
+    let state = if running { "running" } else { "stopped" };
+
+    write!(
+        writer,
+        "timer: {} cycles ({}), {} since reset",
+        count,
+        state,
+        elapsed(count, reference)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{elapsed, write_timer, write_timestamp};
+
+    #[test]
+    fn timer_elapsed() {
+        assert_eq!(elapsed(1000, 0), 1000);
+        assert_eq!(elapsed(1000, 400), 600);
+        assert_eq!(elapsed(1000, 1000), 0);
+        // The counter wrapped around after the reference.
+        assert_eq!(elapsed(100, u32::MAX - 99), 200);
+    }
+
+    #[test]
+    fn timer_timestamp() {
+        let mut line = String::new();
+        write_timestamp(&mut line, 12345).unwrap();
+        line.push_str("button");
+        assert_eq!(line, "[12345] button");
+
+        let mut line = String::new();
+        write_timestamp(&mut line, 0).unwrap();
+        assert_eq!(line, "[0] ");
+
+        let mut line = String::new();
+        write_timestamp(&mut line, u32::MAX).unwrap();
+        assert_eq!(line, "[4294967295] ");
+    }
+
+    #[test]
+    fn timer_format() {
+        let mut line = String::new();
+        write_timer(&mut line, 1_234_567, true, 1_230_000).unwrap();
+        assert_eq!(line, "timer: 1234567 cycles (running), 4567 since reset");
+
+        let mut line = String::new();
+        write_timer(&mut line, 0, false, 0).unwrap();
+        assert_eq!(line, "timer: 0 cycles (stopped), 0 since reset");
+    }
+}
@@ -0,0 +1,133 @@
+//! Module for the short animation shown when switching the LED ring to another mode.
+
+use crate::led_ring::Mode;
+
+/// The number of steps of the animation (one per LED).
+pub const TRANSITION_STEPS: u8 = 4;
+
+/// A step of the transition.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TransitionStep {
+    /// Show the given LED states.
+    Show([bool; 4]),
+    /// The animation is done; enter the given mode starting with the given LED states.
+    Enter { mode: Mode, states: [bool; 4] },
+}
+
+/// A transition that sweeps a single LED around the ring once before entering a mode.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Transition {
+    /// The number of steps taken.
+    step: u8,
+    /// The mode to enter.
+    mode: Mode,
+}
+
+impl Transition {
+    /// Sets up a transition that enters the given mode after the animation.
+    pub fn new(mode: Mode) -> Transition {
+        Transition { step: 0, mode }
+    }
+
+    /// Returns the mode to enter.
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Restarts the animation, entering the given mode afterwards instead.
+    pub fn retarget(&mut self, mode: Mode) {
+        *self = Transition::new(mode);
+    }
+
+    /// Advances the transition one step and returns what to do.
+    ///
+    /// After the animation, the mode is entered with the LED states it starts with (see
+    /// [`Mode::initial_states`]).  This is returned repeatedly.
+    pub fn advance(&mut self) -> TransitionStep {
+        if self.step == TRANSITION_STEPS {
+            return TransitionStep::Enter {
+                mode: self.mode,
+                states: self.mode.initial_states(),
+            };
+        }
+
+        let mut states = [false; 4];
+        states[self.step as usize] = true;
+        self.step += 1;
+        TransitionStep::Show(states)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mode, Transition, TransitionStep, TRANSITION_STEPS};
+
+    #[test]
+    fn transition_sequence() {
+        let mut transition = Transition::new(Mode::Accelerometer);
+
+        assert_eq!(transition.mode(), Mode::Accelerometer);
+        for index in 0..TRANSITION_STEPS as usize {
+            let mut states = [false; 4];
+            states[index] = true;
+            assert_eq!(transition.advance(), TransitionStep::Show(states));
+        }
+
+        let enter = TransitionStep::Enter {
+            mode: Mode::Accelerometer,
+            states: [false; 4],
+        };
+        assert_eq!(transition.advance(), enter);
+        assert_eq!(transition.advance(), enter);
+    }
+
+    #[test]
+    fn transition_entry_states() {
+        let enter = |mode| {
+            let mut transition = Transition::new(mode);
+            core::iter::repeat_with(|| transition.advance())
+                .find(|step| matches!(step, TransitionStep::Enter { .. }))
+                .unwrap()
+        };
+
+        // The ring is left ready for the mode.
+        for mode in Mode::ALL.iter() {
+            let on = *mode == Mode::Ramp || *mode == Mode::Breathe;
+            assert_eq!(
+                enter(*mode),
+                TransitionStep::Enter {
+                    mode: *mode,
+                    states: [on; 4]
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn transition_retarget() {
+        let mut transition = Transition::new(Mode::Cycle);
+
+        assert_eq!(
+            transition.advance(),
+            TransitionStep::Show([true, false, false, false])
+        );
+        assert_eq!(
+            transition.advance(),
+            TransitionStep::Show([false, true, false, false])
+        );
+        transition.retarget(Mode::Ramp);
+        assert_eq!(transition.mode(), Mode::Ramp);
+
+        let steps = core::iter::repeat_with(|| transition.advance())
+            .take_while(|step| matches!(step, TransitionStep::Show(_)))
+            .count();
+        assert_eq!(steps, TRANSITION_STEPS as usize);
+        assert_eq!(
+            transition.advance(),
+            TransitionStep::Enter {
+                mode: Mode::Ramp,
+                states: [true; 4]
+            }
+        );
+    }
+}
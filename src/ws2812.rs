@@ -0,0 +1,169 @@
+//! Module for mirroring the LED ring onto an addressable RGB LED strip (WS2812).
+//!
+//! The strip is driven by the MOSI line of an SPI peripheral running at 4 MHz: each bit of the
+//! WS2812 protocol is sent as four SPI bits, i.e. a 250 ns high pulse for a zero and a 750 ns
+//! high pulse for a one.
+
+use hal::prelude::_embedded_hal_blocking_spi_Write as Write;
+
+use crate::led_ring::StateSink;
+
+/// The SPI frequency (in Hz) the encoding of the WS2812 bits is based on.
+pub const SPI_FREQUENCY: u32 = 4_000_000;
+
+/// The number of zero bytes that are sent to latch the colors (at least 50 µs low).
+const RESET_BYTES: usize = 32;
+
+/// The SPI bits (nibble) that encode a zero bit.
+const ZERO_BITS: u8 = 0b1000;
+
+/// The SPI bits (nibble) that encode a one bit.
+const ONE_BITS: u8 = 0b1110;
+
+/// An RGB color.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Rgb {
+    /// The red component.
+    pub r: u8,
+    /// The green component.
+    pub g: u8,
+    /// The blue component.
+    pub b: u8,
+}
+
+impl Rgb {
+    /// The color of an LED that is off.
+    pub const OFF: Rgb = Rgb { r: 0, g: 0, b: 0 };
+}
+
+/// The default colors of the LEDs of the strip, matching those of the on-board LEDs (green,
+/// orange, red and blue).
+pub const DEFAULT_COLORS: [Rgb; 4] = [
+    Rgb { r: 0, g: 255, b: 0 },
+    Rgb {
+        r: 255,
+        g: 128,
+        b: 0,
+    },
+    Rgb { r: 255, g: 0, b: 0 },
+    Rgb { r: 0, g: 0, b: 255 },
+];
+
+/// Encodes the color into the SPI bytes for a WS2812 LED.
+///
+/// The LED expects the green, red and blue components in that order, most significant bit first.
+pub fn encode(color: Rgb) -> [u8; 12] {
+    let mut bytes = [0; 12];
+    for (index, component) in [color.g, color.r, color.b].iter().enumerate() {
+        for pair in 0..4 {
+            let bits = |bit: u8| {
+                if component & (1 << bit) != 0 {
+                    ONE_BITS
+                } else {
+                    ZERO_BITS
+                }
+            };
+            let high_bit = 7 - 2 * pair;
+            bytes[index * 4 + pair as usize] = bits(high_bit) << 4 | bits(high_bit - 1);
+        }
+    }
+
+    bytes
+}
+
+/// An addressable RGB LED strip (of at least four WS2812 LEDs) connected to an SPI peripheral.
+///
+/// The strip mirrors the LED ring, where each LED of the ring is mapped to a configurable color.
+pub struct Ws2812<SPI> {
+    /// The SPI peripheral the data line of the strip is connected to.
+    spi: SPI,
+    /// The colors of the LEDs when they are on.
+    colors: [Rgb; 4],
+}
+
+impl<SPI, E> Ws2812<SPI>
+where
+    SPI: Write<u8, Error = E>,
+{
+    /// Sets up the strip using the SPI peripheral (which must run at 4 MHz) with the default
+    /// colors.
+    pub fn new(spi: SPI) -> Ws2812<SPI> {
+        Ws2812 {
+            spi,
+            colors: DEFAULT_COLORS,
+        }
+    }
+
+    /// Returns the colors of the LEDs when they are on.
+    pub fn colors(&self) -> [Rgb; 4] {
+        self.colors
+    }
+
+    /// Sets the color of the LED with the given index when it is on.
+    ///
+    /// The change is only visible after the next update.
+    pub fn set_color(&mut self, index: usize, color: Rgb) {
+        self.colors[index] = color;
+    }
+
+    /// Writes the colors for the given LED states to the strip.
+    pub fn write(&mut self, states: [bool; 4]) -> Result<(), E> {
+        for (state, color) in states.iter().zip(self.colors.iter()) {
+            let color = if *state { *color } else { Rgb::OFF };
+            self.spi.write(&encode(color))?;
+        }
+
+        self.spi.write(&[0; RESET_BYTES])
+    }
+}
+
+impl<SPI, E> StateSink for Ws2812<SPI>
+where
+    SPI: Write<u8, Error = E>,
+{
+    fn update(&mut self, states: [bool; 4]) {
+        // A failed write only affects the strip until the next update.
+        self.write(states).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode, Rgb, StateSink, Ws2812, DEFAULT_COLORS, RESET_BYTES};
+    use crate::mock::MockSpi;
+
+    #[test]
+    fn encode_colors() {
+        assert_eq!(encode(Rgb::OFF), [0x88; 12]);
+        assert_eq!(
+            encode(Rgb {
+                r: 0xFF,
+                g: 0x00,
+                b: 0x81
+            }),
+            [0x88, 0x88, 0x88, 0x88, 0xEE, 0xEE, 0xEE, 0xEE, 0xE8, 0x88, 0x88, 0x8E]
+        );
+    }
+
+    #[test]
+    fn update_strip() {
+        let mut strip = Ws2812::new(MockSpi::default());
+        assert_eq!(strip.colors(), DEFAULT_COLORS);
+
+        let white = Rgb {
+            r: 255,
+            g: 255,
+            b: 255,
+        };
+        strip.set_color(3, white);
+        strip.update([true, false, false, true]);
+
+        let written = &strip.spi.written;
+        assert_eq!(written.len(), 4 * 12 + RESET_BYTES);
+        assert_eq!(written[0..12], encode(DEFAULT_COLORS[0]));
+        assert_eq!(written[12..24], encode(Rgb::OFF));
+        assert_eq!(written[24..36], encode(Rgb::OFF));
+        assert_eq!(written[36..48], encode(white));
+        assert!(written[48..].iter().all(|byte| *byte == 0));
+    }
+}